@@ -1,20 +1,45 @@
 use bytes::Bytes;
 use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use datafusion::{physical_plan::ColumnarValue, scalar::ScalarValue};
 use futures::stream::StreamExt;
 use itertools::Itertools;
 use parquet::{
     arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder},
     file::{reader::FileReader, serialized_reader::SerializedFileReader},
 };
-use std::{env, fs, time::Duration};
+use std::{env, fs, sync::OnceLock, time::Duration};
 use tokio::runtime::Runtime;
 
-use zn_perf::match_udf;
+use zn_perf::{
+    bench::{search_sql, SqlOp},
+    match_udf,
+    str::Substring,
+    testdata::LogSpec,
+};
+
+/// Whether the DataFusion benchmarks run with the optimized session; add
+/// `true` to compare both.
+const OPTIMIZED: &[bool] = &[false];
 
+/// The file named by the `FILE` environment variable, or else a generated one.
 fn parquet_sample_path() -> String {
-    env::var("FILE").expect("Set FILE environment variable")
+    static PATH: OnceLock<String> = OnceLock::new();
+    PATH.get_or_init(|| {
+        env::var("FILE").unwrap_or_else(|_| {
+            let path = env::temp_dir().join("zn-perf-bench.parquet");
+            let spec = LogSpec {
+                rows: 1_000_000,
+                label_columns: 8,
+                ..LogSpec::default()
+            };
+            spec.write(&path).unwrap();
+            path.to_str().unwrap().to_owned()
+        })
+    })
+    .clone()
 }
 
+#[allow(dead_code)] // used by benchmarks that are disabled in `criterion_group!`
 fn new_parquet_file_reader() -> SerializedFileReader<Bytes> {
     let buf = fs::read(parquet_sample_path()).unwrap(); // load the entire file into memory
     SerializedFileReader::new(buf.into()).unwrap()
@@ -40,10 +65,12 @@ async fn new_datafusion_session_context(
     ctx
 }
 
+#[allow(dead_code)]
 fn bench_file_search(c: &mut Criterion) {
     let parquet_reader = new_parquet_file_reader();
 
-    let size = zn_perf::file::byte_array_columns_uncompressed_size(parquet_reader.metadata());
+    let size =
+        zn_perf::file::byte_array_columns_uncompressed_size(parquet_reader.metadata()).unwrap();
 
     let mut group = c.benchmark_group("file-search");
     group
@@ -58,7 +85,6 @@ fn bench_file_search(c: &mut Criterion) {
 
 fn bench_arrow_search(c: &mut Criterion) {
     let size: usize = new_parquet_arrow_reader(4096)
-        .into_iter()
         .map(|batch| batch.unwrap().get_array_memory_size())
         .sum();
 
@@ -81,6 +107,39 @@ fn bench_arrow_search(c: &mut Criterion) {
     group.finish();
 }
 
+/// Compares `count_occurrences`, which searches the values buffer of a column
+/// without nulls at once, to `count_matches` with the same needle, which
+/// calls the matcher once per value.
+fn bench_arrow_scan(c: &mut Criterion) {
+    let size: usize = new_parquet_arrow_reader(8192)
+        .map(|batch| batch.unwrap().get_array_memory_size())
+        .sum();
+
+    let mut group = c.benchmark_group("arrow-scan");
+    group
+        .measurement_time(Duration::from_secs(8))
+        .throughput(Throughput::Bytes(size as u64));
+
+    let needle = "search_string";
+    group.bench_function("single-buffer", |b| {
+        b.iter_batched(
+            || new_parquet_arrow_reader(8192),
+            |parquet_reader| zn_perf::arrow::count_occurrences(parquet_reader, needle).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+    let matcher = Substring::new(needle.as_bytes());
+    group.bench_function("per-value", |b| {
+        b.iter_batched(
+            || new_parquet_arrow_reader(8192),
+            |parquet_reader| zn_perf::arrow::count_matches(parquet_reader, &matcher).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+#[allow(dead_code)]
 fn bench_datafusion_queries(c: &mut Criterion) {
     const QUERIES: &[&str] = &[
         "select * from tbl",
@@ -96,7 +155,7 @@ fn bench_datafusion_queries(c: &mut Criterion) {
     let rt = Runtime::new().unwrap();
     for query in QUERIES {
         for batch_size in [1024, 4096, 8192] {
-            for optimized_p in [false] {
+            for &optimized_p in OPTIMIZED {
                 group.bench_function(
                     BenchmarkId::from_parameter(format!(
                         "{batch_size}-O{}/{query}",
@@ -141,25 +200,16 @@ fn bench_datafusion_search(c: &mut Criterion) {
     group.throughput(Throughput::Bytes(total_size));
 
     for batch_size in [1024, 4096, 8192] {
-        for op in ["like", "strpos"] {
-            for optimized_p in [false] {
-                let where_clause = text_columns
-                    .iter()
-                    .map(|column| {
-                        if op == "like" {
-                            format!("\"{column}\" like '%k8s%'")
-                        } else {
-                            format!("strpos(\"{column}\", 'k8s') > 0")
-                        }
-                    })
-                    .join(" or ");
-                let sql = format!("select * from tbl where {where_clause}");
+        for op in [SqlOp::Like, SqlOp::Strpos] {
+            for &optimized_p in OPTIMIZED {
+                let sql = search_sql("tbl", &text_columns, op, "k8s");
 
                 let rt = Runtime::new().unwrap();
                 group.bench_function(
                     BenchmarkId::from_parameter(format!(
-                        "{batch_size}-O{}/{op}",
-                        optimized_p as u8
+                        "{batch_size}-O{}/{}",
+                        optimized_p as u8,
+                        op.name()
                     )),
                     |b| {
                         b.to_async(&rt).iter(|| async {
@@ -200,30 +250,20 @@ fn bench_datafusion_search_memchr(c: &mut Criterion) {
     group.throughput(Throughput::Bytes(total_size));
 
     for batch_size in [1024, 4096, 8192] {
-        for op in ["like", "str_match"] {
-            for optimized_p in [false] {
-                let where_clause = text_columns
-                    .iter()
-                    .map(|column| {
-                        if op == "like" {
-                            format!("\"{column}\" like '%k8s%'")
-                        } else {
-                            format!("str_match(\"{column}\", 'k8s') ")
-                        }
-                    })
-                    .join(" or ");
-                let sql = format!("select * from tbl where {where_clause}");
+        for op in [SqlOp::Like, SqlOp::StrMatch, SqlOp::MatchAllColumns] {
+            for &optimized_p in OPTIMIZED {
+                let sql = search_sql("tbl", &text_columns, op, "k8s");
 
                 let rt = Runtime::new().unwrap();
                 group.bench_function(
                     BenchmarkId::from_parameter(format!(
-                        "{batch_size}-O{}/{op}",
-                        optimized_p as u8
+                        "{batch_size}-O{}/{}",
+                        optimized_p as u8,
+                        op.name()
                     )),
                     |b| {
                         b.to_async(&rt).iter(|| async {
                             let ctx = new_datafusion_session_context(batch_size, optimized_p).await;
-                            ctx.register_udf(match_udf::MATCH_UDF.clone());
                             let df = ctx.sql(&sql).await.unwrap();
                             let mut stream = df.execute_stream().await.unwrap();
                             while let Some(batch) = stream.next().await {
@@ -238,12 +278,39 @@ fn bench_datafusion_search_memchr(c: &mut Criterion) {
     group.finish();
 }
 
+/// Compares `str_match` with a literal needle, which is searched with a
+/// precompiled finder, to a needle column of the same needle, which is
+/// searched for row by row.
+fn bench_match_udf(c: &mut Criterion) {
+    let batch = new_parquet_arrow_reader(8192).next().unwrap().unwrap();
+    let log = batch
+        .column(batch.schema().index_of("log").unwrap())
+        .clone();
+    let needle = "search_string";
+
+    let mut group = c.benchmark_group("match-udf");
+    group.throughput(Throughput::Bytes(log.get_array_memory_size() as u64));
+    let str_match = match_udf::match_expr_impl(false);
+    let literal = ColumnarValue::Scalar(ScalarValue::Utf8(Some(needle.to_owned())));
+    let column = ColumnarValue::Array(
+        ScalarValue::Utf8(Some(needle.to_owned())).to_array_of_size(log.len()),
+    );
+    for (name, needle) in [("literal", literal), ("column", column)] {
+        group.bench_function(name, |b| {
+            b.iter(|| str_match(&[ColumnarValue::Array(log.clone()), needle.clone()]).unwrap())
+        });
+    }
+    group.finish();
+}
+
 criterion_group!(
     benches,
     // bench_file_search,
     bench_arrow_search,
+    bench_arrow_scan,
     // bench_datafusion_queries,
     bench_datafusion_search,
     bench_datafusion_search_memchr,
+    bench_match_udf,
 );
 criterion_main!(benches);
@@ -2,69 +2,2444 @@
 //!
 //! [`parquet::arrow`]: https://docs.rs/parquet/latest/parquet/arrow/index.html
 
-use crate::ZnResult;
-use arrow_array::cast;
-use arrow_schema::DataType;
+use crate::{
+    bloom::{filter_of, fnv1a, BloomFilter, NgramBloom},
+    cancel::CancelToken,
+    file::{byte_array_columns_uncompressed_size, has_offset_index, is_byte_array, CountMode},
+    kernel,
+    metrics::{registry, SearchPath},
+    storage::{RangeChunkReader, RangeReader},
+    str::{is_char_boundary, IgnoreAsciiCase, Matcher},
+    tune, ZnError, ZnResult,
+};
+use aho_corasick::AhoCorasick;
+use arrow::{
+    compute::{self, or},
+    datatypes::ArrowNativeType,
+};
+use arrow_array::{
+    builder::StringBuilder,
+    cast, downcast_dictionary_array,
+    types::{ByteArrayType, Float64Type, Int64Type},
+    Array, ArrayRef, BooleanArray, GenericByteArray, GenericListArray, LargeStringArray,
+    OffsetSizeTrait, RecordBatch, RecordBatchReader,
+};
+use arrow_schema::{DataType, Field, Schema};
 use memchr::memmem;
-use parquet::arrow::arrow_reader::ParquetRecordBatchReader;
+use once_cell::sync::OnceCell;
+#[cfg(feature = "native")]
+use parquet::{
+    arrow::async_reader::{AsyncFileReader, ParquetRecordBatchStream},
+    file::reader::ChunkReader,
+};
+use parquet::{
+    arrow::{
+        arrow_reader::{
+            ArrowReaderOptions, ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder,
+            RowSelection, RowSelector,
+        },
+        ProjectionMask,
+    },
+    file::metadata::ParquetMetaData,
+};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, HashSet},
+    fmt::{Display, Write},
+    ops::Range,
+    sync::Arc,
+};
+
+pub mod kernels;
 
 /// Counts the number of cells (intersections of column and row) that contain
-/// the `needle`, taking only [`DataType::Utf8`] columns into account.
+/// the `needle`, taking only text and binary columns, i.e.
+/// [`DataType::Utf8`], [`DataType::LargeUtf8`], [`DataType::Binary`], and
+/// [`DataType::LargeBinary`] ones, into account, like the [byte array]
+/// columns of [`crate::file::count_occurrences`], dictionary columns of
+/// such values, and the text and binary fields nested in list and struct
+/// columns, e.g. arrays of tags, where a row of a list matches if one of its
+/// elements does and each nested field counts as a column.  Columns large
+/// enough are matched by the [offloaded](crate::kernel) kernel, if any; the
+/// values of dictionaries are matched once per batch rather than once per
+/// row, which pays off on label columns of low cardinality.  The arrow
+/// version in use has no string view type, so there are no view columns to
+/// match.
+///
+/// [byte array]: crate::file
 ///
-/// # Panics
+/// # Errors
 ///
-/// Panics if the `needle` is empty.
+/// Returns [`ZnError::EmptyNeedle`] if the `needle` is empty.
 pub fn count_occurrences(haystack: ParquetRecordBatchReader, needle: &str) -> ZnResult<usize> {
-    assert!(!needle.is_empty());
+    count_occurrences_with_cancel(haystack, needle, &CancelToken::new())
+}
 
-    let mut count = 0;
+/// Like [`count_occurrences`], but checks the `cancel` token before every
+/// batch.
+///
+/// # Errors
+///
+/// Returns [`ZnError::Cancelled`] if the token is cancelled, and the errors
+/// of [`count_occurrences`].
+pub fn count_occurrences_with_cancel(
+    haystack: ParquetRecordBatchReader,
+    needle: &str,
+    cancel: &CancelToken,
+) -> ZnResult<usize> {
+    count_occurrences_impl(haystack, needle, &SearchOptions::default(), cancel)
+}
+
+/// Settings of [`count_occurrences_with_options`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchOptions {
+    /// Ignores the case of ASCII letters, comparing the values with the
+    /// needle [as they are](IgnoreAsciiCase) instead of
+    /// lowercasing copies of them.  Values are then never matched by the
+    /// [offloaded](crate::kernel) kernel.
+    pub case_insensitive: bool,
+    /// Names of the only columns to match, as with
+    /// [`count_occurrences_in_columns`]; all of them if `None`.
+    pub columns: Option<Vec<String>>,
+    /// What to count, the matching cells by default.  Occurrences are only
+    /// counted in text, binary and dictionary columns, not in values nested
+    /// in lists or structs.
+    pub count: CountMode,
+    /// Also matches the Int64, Float64 and Boolean columns, formatted as
+    /// text, e.g. `404`, `0.5` and `true`, so that searching for `404` finds
+    /// it in a numeric `status` column.
+    pub numbers: bool,
+}
+
+/// Like [`count_occurrences`], but searches as the `options` say.
+///
+/// # Errors
+///
+/// Returns the errors of [`count_occurrences_in_columns`].
+pub fn count_occurrences_with_options(
+    haystack: ParquetRecordBatchReader,
+    needle: &str,
+    options: &SearchOptions,
+) -> ZnResult<usize> {
+    count_occurrences_impl(haystack, needle, options, &CancelToken::new())
+}
+
+/// Like [`count_occurrences`], but only matches the `columns` of the
+/// reader's schema with these names, if any, e.g. just `log` instead of it
+/// and every label column.  Named columns that are not text or binary ones
+/// are not matched either.
+///
+/// # Errors
+///
+/// Returns [`ZnError::InvalidArgument`] if the reader has no column with one
+/// of the names, e.g. because it was projected out, and the errors of
+/// [`count_occurrences`].
+pub fn count_occurrences_in_columns(
+    haystack: ParquetRecordBatchReader,
+    needle: &str,
+    columns: Option<&[&str]>,
+) -> ZnResult<usize> {
+    let options = SearchOptions {
+        columns: columns.map(|names| names.iter().map(|&name| name.to_owned()).collect()),
+        ..SearchOptions::default()
+    };
+    count_occurrences_impl(haystack, needle, &options, &CancelToken::new())
+}
+
+fn count_occurrences_impl(
+    haystack: ParquetRecordBatchReader,
+    needle: &str,
+    options: &SearchOptions,
+    cancel: &CancelToken,
+) -> ZnResult<usize> {
+    if needle.is_empty() {
+        return Err(ZnError::empty_needle());
+    }
+    let needle = match options.case_insensitive {
+        true => Needle::IgnoreAsciiCase(IgnoreAsciiCase::new(needle.as_bytes())),
+        false => Needle::Exact(needle.as_bytes()),
+    };
+    count_needle(haystack, &needle, options, cancel)
+}
+
+/// Counts the matches of the `needle` as the `options` say, which it has
+/// been built from; the batch loop shared by the searches for a needle and
+/// for a regex.
+fn count_needle(
+    haystack: ParquetRecordBatchReader,
+    needle: &Needle,
+    options: &SearchOptions,
+    cancel: &CancelToken,
+) -> ZnResult<usize> {
+    let schema = haystack.schema();
+    let projection = options
+        .columns
+        .as_deref()
+        .map(|names| {
+            names
+                .iter()
+                .map(|name| {
+                    schema
+                        .index_of(name)
+                        .map_err(|_| ZnError::invalid_argument(format!("no column named {name:?}")))
+                })
+                .collect::<ZnResult<Vec<_>>>()
+        })
+        .transpose()?;
+
+    let _timer = registry().query_latency(SearchPath::Arrow).start_timer();
+
+    let mut counts = BatchCounts::default();
+    let mut buf = String::new();
+    for batch in haystack {
+        cancel.check()?;
+        let mut batch = match &projection {
+            Some(projection) => batch?.project(projection)?,
+            None => batch?,
+        };
+        if options.numbers {
+            batch = format_numbers(batch, &mut buf)?;
+        }
+        counts += match options.count {
+            CountMode::Cells | CountMode::Rows => count_batch(&batch, needle),
+            CountMode::Occurrences => count_batch_occurrences(&batch, needle),
+        };
+    }
+    registry().bytes_scanned().inc_by(counts.bytes_scanned);
+    registry().rows_matched().inc_by(counts.rows_matched);
+    Ok(match options.count {
+        CountMode::Cells | CountMode::Occurrences => counts.count,
+        CountMode::Rows => counts.rows_matched as usize,
+    })
+}
+
+/// Replaces the Int64, Float64 and Boolean columns of `batch` with text ones
+/// of their values, formatting each value into the reusable `buf`.
+fn format_numbers(batch: RecordBatch, buf: &mut String) -> ZnResult<RecordBatch> {
+    fn format_values<T: Display>(
+        values: impl Iterator<Item = Option<T>>,
+        buf: &mut String,
+    ) -> ArrayRef {
+        let mut builder = StringBuilder::new();
+        for value in values {
+            match value {
+                Some(value) => {
+                    buf.clear();
+                    write!(buf, "{value}").expect("format into a String");
+                    builder.append_value(&*buf);
+                }
+                None => builder.append_null(),
+            }
+        }
+        Arc::new(builder.finish())
+    }
+
+    let formatted =
+        |t: &DataType| matches!(t, DataType::Int64 | DataType::Float64 | DataType::Boolean);
+    if !batch
+        .columns()
+        .iter()
+        .any(|array| formatted(array.data_type()))
+    {
+        return Ok(batch);
+    }
+    let schema = batch.schema();
+    let mut fields = Vec::with_capacity(batch.num_columns());
+    let mut columns = Vec::with_capacity(batch.num_columns());
+    for (field, array) in schema.fields().iter().zip(batch.columns()) {
+        let array = match array.data_type() {
+            DataType::Int64 => {
+                format_values(cast::as_primitive_array::<Int64Type>(array).iter(), buf)
+            }
+            DataType::Float64 => {
+                format_values(cast::as_primitive_array::<Float64Type>(array).iter(), buf)
+            }
+            DataType::Boolean => format_values(cast::as_boolean_array(array).iter(), buf),
+            _ => {
+                fields.push(field.clone());
+                columns.push(array.clone());
+                continue;
+            }
+        };
+        fields.push(Field::new(
+            field.name(),
+            DataType::Utf8,
+            field.is_nullable(),
+        ));
+        columns.push(array);
+    }
+    Ok(RecordBatch::try_new(
+        Arc::new(Schema::new(fields)),
+        columns,
+    )?)
+}
+
+/// Like [`count_occurrences`], but reads the batches from the asynchronous
+/// `haystack`, e.g. built with [`ParquetRecordBatchStreamBuilder`] over a
+/// [`RangeChunkReader`] of an object store, which fetches the column chunks
+/// as they are decoded rather than the whole file up front.
+///
+/// [`ParquetRecordBatchStreamBuilder`]: parquet::arrow::ParquetRecordBatchStreamBuilder
+///
+/// # Errors
+///
+/// Returns the errors of [`count_occurrences`].
+#[cfg(feature = "native")]
+pub async fn count_occurrences_async<T>(
+    mut haystack: ParquetRecordBatchStream<T>,
+    needle: &str,
+) -> ZnResult<usize>
+where
+    T: AsyncFileReader + Unpin + Send + 'static,
+{
+    use futures::StreamExt;
+
+    if needle.is_empty() {
+        return Err(ZnError::empty_needle());
+    }
+    let needle = Needle::Exact(needle.as_bytes());
+
+    let _timer = registry().query_latency(SearchPath::Arrow).start_timer();
+
+    let mut counts = BatchCounts::default();
+    while let Some(batch) = haystack.next().await {
+        counts += count_batch(&batch?, &needle);
+    }
+    registry().bytes_scanned().inc_by(counts.bytes_scanned);
+    registry().rows_matched().inc_by(counts.rows_matched);
+    Ok(counts.count)
+}
+
+/// Like [`count_occurrences`], but pipelines the work on the blocking threads
+/// of the [current](crate::runtime::current) runtime, e.g. tokio's blocking
+/// pool: the row groups of `haystack` are decoded in order on one thread,
+/// while the batches already decoded are searched on others, so that
+/// decompression and matching overlap.  At most `in_flight` batches are
+/// decoded ahead of being searched and searched at the same time, which
+/// bounds the memory held by the pipeline.
+///
+/// # Errors
+///
+/// Returns [`ZnError::InvalidArgument`] if `in_flight` is zero, and the
+/// errors of [`count_occurrences`].
+#[cfg(feature = "native")]
+pub async fn count_occurrences_pipelined<T: ChunkReader + 'static>(
+    haystack: T,
+    needle: &str,
+    in_flight: usize,
+) -> ZnResult<usize> {
+    use futures::{channel::mpsc, SinkExt, StreamExt, TryStreamExt};
+
+    if needle.is_empty() {
+        return Err(ZnError::empty_needle());
+    }
+    if in_flight == 0 {
+        return Err(ZnError::invalid_argument(
+            "in-flight batch limit must be positive",
+        ));
+    }
+    let needle: Arc<[u8]> = needle.as_bytes().into();
+    let runtime = crate::runtime::current();
+
+    let _timer = registry().query_latency(SearchPath::Arrow).start_timer();
+
+    let (mut decoded, batches) = mpsc::channel::<ZnResult<RecordBatch>>(in_flight);
+    let decode = crate::runtime::spawn_blocking(&*runtime, move || {
+        let reader = match ParquetRecordBatchReaderBuilder::try_new(haystack)
+            .and_then(ParquetRecordBatchReaderBuilder::build)
+        {
+            Ok(reader) => reader,
+            Err(e) => {
+                let _ = futures::executor::block_on(decoded.send(Err(e.into())));
+                return;
+            }
+        };
+        for batch in reader {
+            // Fails once the search has stopped on an error.
+            if futures::executor::block_on(decoded.send(batch.map_err(Into::into))).is_err() {
+                return;
+            }
+        }
+    });
+    let search = batches
+        .map(|batch| {
+            let needle = needle.clone();
+            let runtime = runtime.clone();
+            async move {
+                let batch = batch?;
+                crate::runtime::spawn_blocking(&*runtime, move || {
+                    count_batch(&batch, &Needle::Exact(&needle))
+                })
+                .await
+            }
+        })
+        .buffer_unordered(in_flight)
+        .try_fold(
+            BatchCounts::default(),
+            |mut counts, batch_counts| async move {
+                counts += batch_counts;
+                Ok(counts)
+            },
+        );
+    let (counts, ()) = futures::try_join!(search, decode)?;
+    registry().bytes_scanned().inc_by(counts.bytes_scanned);
+    registry().rows_matched().inc_by(counts.rows_matched);
+    Ok(counts.count)
+}
+
+/// Counts the number of cells of the text and binary columns, as
+/// [`count_occurrences`] takes them into account, with a match of the
+/// [`regex`] `pattern`, e.g. `status=5\d\d`.  Values are matched as bytes,
+/// so binary values need not be UTF-8.
+///
+/// # Errors
+///
+/// Returns [`ZnError::InvalidArgument`] if the pattern is not a valid regex.
+#[cfg(feature = "regex")]
+pub fn count_regex_matches(haystack: ParquetRecordBatchReader, pattern: &str) -> ZnResult<usize> {
+    let regex = regex::bytes::Regex::new(pattern)
+        .map_err(|e| ZnError::invalid_argument(format!("invalid regex {pattern:?}: {e}")))?;
+    count_needle(
+        haystack,
+        &Needle::Regex(regex),
+        &SearchOptions::default(),
+        &CancelToken::new(),
+    )
+}
+
+/// Like [`count_occurrences`], but matches the batches on `threads` threads
+/// as the reader decodes them, and sums their counts, so that large files
+/// keep more than one core busy.  With 0 threads, the batches are matched on
+/// the [scan pool](crate::pool).
+///
+/// # Errors
+///
+/// Returns [`ZnError::InvalidArgument`] if the threads cannot be spawned,
+/// and the errors of [`count_occurrences`].
+#[cfg(feature = "native")]
+pub fn count_occurrences_parallel(
+    haystack: ParquetRecordBatchReader,
+    needle: &str,
+    threads: usize,
+) -> ZnResult<usize> {
+    use rayon::prelude::*;
+
+    if needle.is_empty() {
+        return Err(ZnError::empty_needle());
+    }
+
+    let needle = Needle::Exact(needle.as_bytes());
+
+    let _timer = registry().query_latency(SearchPath::Arrow).start_timer();
+
+    let scan = || {
+        haystack
+            .par_bridge()
+            .map(|batch| -> ZnResult<_> { Ok(count_batch(&batch?, &needle)) })
+            .try_reduce(BatchCounts::default, |mut a, b| {
+                a += b;
+                Ok(a)
+            })
+    };
+    let counts = match threads {
+        0 => crate::pool::install(scan)??,
+        threads => crate::pool::PoolOptions {
+            threads,
+            ..crate::pool::PoolOptions::default()
+        }
+        .build()?
+        .install(scan)?,
+    };
+    registry().bytes_scanned().inc_by(counts.bytes_scanned);
+    registry().rows_matched().inc_by(counts.rows_matched);
+    Ok(counts.count)
+}
+
+/// What [`count_batch`] found in a batch.
+#[derive(Debug, Default, Clone, Copy)]
+struct BatchCounts {
+    count: usize,
+    bytes_scanned: u64,
+    rows_matched: u64,
+}
+
+impl std::ops::AddAssign for BatchCounts {
+    fn add_assign(&mut self, other: Self) {
+        self.count += other.count;
+        self.bytes_scanned += other.bytes_scanned;
+        self.rows_matched += other.rows_matched;
+    }
+}
+
+/// What the arrow search matches values with: a non-empty needle, or a
+/// regex.
+enum Needle<'a> {
+    Exact(&'a [u8]),
+    IgnoreAsciiCase(IgnoreAsciiCase),
+    #[cfg(feature = "regex")]
+    Regex(regex::bytes::Regex),
+}
+
+impl Needle<'_> {
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        match self {
+            Needle::Exact(needle) => memmem::find(haystack, needle).is_some(),
+            Needle::IgnoreAsciiCase(needle) => needle.find(haystack).is_some(),
+            #[cfg(feature = "regex")]
+            Needle::Regex(regex) => regex.is_match(haystack),
+        }
+    }
+
+    /// Returns the number of non-overlapping matches in `haystack`.
+    fn occurrences(&self, haystack: &[u8]) -> usize {
+        match self {
+            Needle::Exact(needle) => memmem::find_iter(haystack, needle).count(),
+            Needle::IgnoreAsciiCase(needle) => {
+                let mut count = 0;
+                let mut start = 0;
+                while let Some(i) = needle.find(&haystack[start..]) {
+                    count += 1;
+                    start += i + needle.needle().len();
+                }
+                count
+            }
+            #[cfg(feature = "regex")]
+            Needle::Regex(regex) => regex.find_iter(haystack).count(),
+        }
+    }
+}
+
+/// Counts the cells of the text and binary columns of `batch` that contain
+/// the `needle`.
+fn count_batch(batch: &RecordBatch, needle: &Needle) -> BatchCounts {
+    match_batch(batch, needle, &mut vec![false; batch.num_rows()])
+}
+
+/// Like [`count_batch`], but counts the occurrences of the `needle` in the
+/// text, binary and dictionary columns of `batch`, leaving out values nested
+/// in lists and structs.
+fn count_batch_occurrences(batch: &RecordBatch, needle: &Needle) -> BatchCounts {
+    let mut counts = BatchCounts::default();
+    let mut matched = vec![false; batch.num_rows()];
+    for array in batch.columns() {
+        let occurrences = match byte_values(array) {
+            Some((bytes, values)) => Some((
+                bytes,
+                values
+                    .map(|s| s.map_or(0, |s| needle.occurrences(s)))
+                    .collect(),
+            )),
+            None => dictionary_occurrences(array, needle),
+        };
+        let Some((bytes, occurrences)) = occurrences else {
+            continue;
+        };
+        counts.bytes_scanned += bytes;
+        for (row, n) in occurrences.into_iter().enumerate() {
+            counts.count += n;
+            matched[row] |= n > 0;
+        }
+    }
+    counts.rows_matched = matched.iter().filter(|&&m| m).count() as u64;
+    counts
+}
+
+/// Like [`count_batch`], but also sets the flags in `matched` of the rows
+/// with such a cell.
+fn match_batch(batch: &RecordBatch, needle: &Needle, matched: &mut [bool]) -> BatchCounts {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("match_batch", rows = batch.num_rows()).entered();
+    let mut counts = BatchCounts::default();
+    for array in batch.columns() {
+        if let Some((bytes, count)) = match_array(array, needle, matched) {
+            counts.bytes_scanned += bytes;
+            counts.count += count;
+        }
+    }
+    counts.rows_matched = matched.iter().filter(|&&m| m).count() as u64;
+    counts
+}
+
+/// Sets the flags in `matched` of the rows whose value in `array` contains
+/// the `needle`; returns the size of the values and the number of matching
+/// cells, or `None` if the array has no text or binary values to match.
+fn match_array(array: &ArrayRef, needle: &Needle, matched: &mut [bool]) -> Option<(u64, usize)> {
+    match array.data_type() {
+        DataType::Utf8 => {
+            let array = cast::as_string_array(array);
+            Some((value_bytes(array), match_column(array, needle, matched)))
+        }
+        DataType::LargeUtf8 => {
+            let array = as_large_string_array(array);
+            Some((value_bytes(array), match_column(array, needle, matched)))
+        }
+        DataType::Binary => {
+            let array = cast::as_generic_binary_array::<i32>(array);
+            Some((value_bytes(array), match_column(array, needle, matched)))
+        }
+        DataType::LargeBinary => {
+            let array = cast::as_generic_binary_array::<i64>(array);
+            Some((value_bytes(array), match_column(array, needle, matched)))
+        }
+        DataType::Dictionary(_, _) => match_dictionary(array, needle, matched),
+        DataType::List(_) | DataType::LargeList(_) | DataType::Struct(_) => {
+            let (bytes, leaves) = match_nested(array, needle);
+            if leaves.is_empty() {
+                return None;
+            }
+            let mut count = 0;
+            for hits in leaves {
+                for (row, hit) in hits.into_iter().enumerate() {
+                    if hit {
+                        count += 1;
+                        matched[row] = true;
+                    }
+                }
+            }
+            Some((bytes, count))
+        }
+        DataType::Null
+        | DataType::Boolean
+        | DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32
+        | DataType::UInt64
+        | DataType::Float16
+        | DataType::Float32
+        | DataType::Float64
+        | DataType::Timestamp(_, _)
+        | DataType::Date32
+        | DataType::Date64
+        | DataType::Time32(_)
+        | DataType::Time64(_)
+        | DataType::Duration(_)
+        | DataType::Interval(_)
+        | DataType::FixedSizeBinary(_)
+        | DataType::FixedSizeList(_, _)
+        | DataType::Union(_, _, _)
+        | DataType::Decimal128(_, _)
+        | DataType::Decimal256(_, _)
+        | DataType::Map(_, _) => None,
+    }
+}
+
+/// Returns the indices of the rows of each batch read from `haystack` with a
+/// text or binary value containing the `needle`, as pairs of the index of
+/// the batch and the ascending row indices within it, leaving out batches
+/// without matches, so that consumers can decode other columns of the
+/// matched rows only later, e.g. with a [`row_selection`] of their
+/// positions in the file.
+///
+/// # Errors
+///
+/// Returns [`ZnError::EmptyNeedle`] if the `needle` is empty.
+pub fn matching_rows(
+    haystack: ParquetRecordBatchReader,
+    needle: &str,
+) -> ZnResult<Vec<(usize, Vec<u32>)>> {
+    if needle.is_empty() {
+        return Err(ZnError::empty_needle());
+    }
+
+    let _timer = registry().query_latency(SearchPath::Arrow).start_timer();
+
+    let mut rows = Vec::new();
+    let mut counts = BatchCounts::default();
+    for (batch_idx, batch) in haystack.enumerate() {
+        let batch = batch?;
+        let mut matched = vec![false; batch.num_rows()];
+        counts += match_batch(&batch, &Needle::Exact(needle.as_bytes()), &mut matched);
+        let batch_rows: Vec<_> = matched
+            .iter()
+            .enumerate()
+            .filter(|&(_, &m)| m)
+            .map(|(row, _)| row as u32)
+            .collect();
+        if !batch_rows.is_empty() {
+            rows.push((batch_idx, batch_rows));
+        }
+    }
+    registry().bytes_scanned().inc_by(counts.bytes_scanned);
+    registry().rows_matched().inc_by(counts.rows_matched);
+    Ok(rows)
+}
+
+/// Counts the cells containing the `needle` per column, by column name, so
+/// that the columns holding a term, e.g. the log body rather than the labels,
+/// can be told apart in one pass.  The columns are those that
+/// [`count_occurrences`] matches, with the matches of fields nested in list
+/// and struct columns counted for their top-level column; columns without
+/// matches are mapped to 0.
+///
+/// # Errors
+///
+/// Returns the errors of [`count_occurrences`].
+pub fn count_occurrences_by_column(
+    haystack: ParquetRecordBatchReader,
+    needle: &str,
+) -> ZnResult<HashMap<String, u64>> {
+    if needle.is_empty() {
+        return Err(ZnError::empty_needle());
+    }
+    let needle = Needle::Exact(needle.as_bytes());
+    let schema = haystack.schema();
+
+    let _timer = registry().query_latency(SearchPath::Arrow).start_timer();
+
+    let mut columns = HashMap::new();
+    let mut bytes_scanned = 0;
+    let mut rows_matched = 0;
     for batch in haystack {
         let batch = batch?;
+        let mut matched = vec![false; batch.num_rows()];
+        for (field, array) in schema.fields().iter().zip(batch.columns()) {
+            if let Some((bytes, count)) = match_array(array, &needle, &mut matched) {
+                bytes_scanned += bytes;
+                *columns.entry(field.name().clone()).or_default() += count as u64;
+            }
+        }
+        rows_matched += matched.iter().filter(|&&m| m).count() as u64;
+    }
+    registry().bytes_scanned().inc_by(bytes_scanned);
+    registry().rows_matched().inc_by(rows_matched);
+    Ok(columns)
+}
+
+/// A cell containing the needle, found by [`search_hits`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchHit {
+    /// Number of the row among those read, counted from 0.
+    pub row: u64,
+    /// Name of the column.
+    pub column: String,
+    /// The value, with invalid UTF-8, e.g. in binary values, replaced.
+    pub value: String,
+    /// Byte ranges of the non-overlapping occurrences of the needle in the
+    /// value as stored, e.g. to highlight them.
+    pub offsets: Vec<Range<usize>>,
+}
+
+/// Returns the first `limit` cells of the text and binary columns
+/// containing the `needle`, row by row and in column order within a row, with
+/// the positions of the needle in them, as a search UI shows them.  Reading
+/// stops at the batch holding the last hit.
+///
+/// # Errors
+///
+/// Returns [`ZnError::EmptyNeedle`] if the `needle` is empty.
+pub fn search_hits(
+    haystack: ParquetRecordBatchReader,
+    needle: &str,
+    limit: usize,
+) -> ZnResult<Vec<SearchHit>> {
+    if needle.is_empty() {
+        return Err(ZnError::empty_needle());
+    }
+    let finder = memmem::Finder::new(needle.as_bytes());
+    let schema = haystack.schema();
+
+    let _timer = registry().query_latency(SearchPath::Arrow).start_timer();
+
+    let mut hits = Vec::new();
+    let mut first_row = 0;
+    let mut bytes_scanned = 0;
+    let mut rows_matched = 0;
+    for batch in haystack {
+        if hits.len() >= limit {
+            break;
+        }
+        let batch = batch?;
+        let mut columns: Vec<_> = schema
+            .fields()
+            .iter()
+            .zip(batch.columns())
+            .filter_map(|(field, array)| Some((field.name(), byte_values(array)?)))
+            .collect();
+        bytes_scanned += columns.iter().map(|(_, (bytes, _))| bytes).sum::<u64>();
+        for row in 0..batch.num_rows() {
+            let mut row_matched = false;
+            for (column, (_, values)) in &mut columns {
+                let Some(Some(value)) = values.next() else {
+                    continue;
+                };
+                let offsets: Vec<_> = finder
+                    .find_iter(value)
+                    .map(|start| start..start + needle.len())
+                    .collect();
+                if offsets.is_empty() || hits.len() >= limit {
+                    continue;
+                }
+                row_matched = true;
+                hits.push(SearchHit {
+                    row: first_row + row as u64,
+                    column: column.to_string(),
+                    value: String::from_utf8_lossy(value).into_owned(),
+                    offsets,
+                });
+            }
+            rows_matched += u64::from(row_matched);
+        }
+        first_row += batch.num_rows() as u64;
+    }
+    registry().bytes_scanned().inc_by(bytes_scanned);
+    registry().rows_matched().inc_by(rows_matched);
+    Ok(hits)
+}
+
+/// An occurrence of the needle with its surroundings, found by [`snippets`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    /// Number of the row among those read, counted from 0.
+    pub row: u64,
+    /// Name of the column.
+    pub column: String,
+    /// The part of the value around the occurrence, with invalid UTF-8
+    /// replaced.
+    pub text: String,
+    /// Byte range of the occurrence in `text`, if the value is valid UTF-8.
+    pub highlight: Range<usize>,
+    /// Whether the value goes on before and after `text`, e.g. to show
+    /// ellipses.
+    pub truncated: (bool, bool),
+}
+
+/// Returns every non-overlapping occurrence of the `needle` in the text and
+/// binary columns, in the order of [`search_hits`], with up to
+/// `context_bytes` of its value on either side, cut at character boundaries,
+/// so that search previews need not carry whole log lines.
+///
+/// # Errors
+///
+/// Returns [`ZnError::EmptyNeedle`] if the `needle` is empty.
+pub fn snippets(
+    haystack: ParquetRecordBatchReader,
+    needle: &str,
+    context_bytes: usize,
+) -> ZnResult<Vec<Snippet>> {
+    if needle.is_empty() {
+        return Err(ZnError::empty_needle());
+    }
+    let finder = memmem::Finder::new(needle.as_bytes());
+    let schema = haystack.schema();
+
+    let _timer = registry().query_latency(SearchPath::Arrow).start_timer();
+
+    let mut snippets = Vec::new();
+    let mut first_row = 0;
+    let mut bytes_scanned = 0;
+    let mut rows_matched = 0;
+    for batch in haystack {
+        let batch = batch?;
+        let mut columns: Vec<_> = schema
+            .fields()
+            .iter()
+            .zip(batch.columns())
+            .filter_map(|(field, array)| Some((field.name(), byte_values(array)?)))
+            .collect();
+        bytes_scanned += columns.iter().map(|(_, (bytes, _))| bytes).sum::<u64>();
+        for row in 0..batch.num_rows() {
+            let mut row_matched = false;
+            for (column, (_, values)) in &mut columns {
+                let Some(Some(value)) = values.next() else {
+                    continue;
+                };
+                for start in finder.find_iter(value) {
+                    let end = start + needle.len();
+                    let mut from = start.saturating_sub(context_bytes);
+                    while !is_char_boundary(value, from) {
+                        from += 1;
+                    }
+                    let mut to = (end + context_bytes).min(value.len());
+                    while !is_char_boundary(value, to) {
+                        to -= 1;
+                    }
+                    row_matched = true;
+                    snippets.push(Snippet {
+                        row: first_row + row as u64,
+                        column: column.to_string(),
+                        text: String::from_utf8_lossy(&value[from..to]).into_owned(),
+                        highlight: start - from..end - from,
+                        truncated: (from > 0, to < value.len()),
+                    });
+                }
+            }
+            rows_matched += u64::from(row_matched);
+        }
+        first_row += batch.num_rows() as u64;
+    }
+    registry().bytes_scanned().inc_by(bytes_scanned);
+    registry().rows_matched().inc_by(rows_matched);
+    Ok(snippets)
+}
+
+/// Returns the `k` newest rows with a text or binary cell containing the
+/// `needle`, newest first, by their value in the `timestamp_column`, e.g.
+/// `@timestamp`, an Int64 or timestamp one, as for "latest 100 hits".  Only
+/// the newest `k` matching rows seen so far are kept while reading, in a
+/// bounded heap.  Rows without a timestamp are skipped; of rows with the same
+/// timestamp, later ones count as newer.
+///
+/// # Errors
+///
+/// Returns [`ZnError::EmptyNeedle`] if the `needle` is empty,
+/// [`ZnError::InvalidArgument`] if there is no `timestamp_column`, and
+/// [`ZnError::UnsupportedType`] if it is of another type.
+pub fn latest_matches(
+    haystack: ParquetRecordBatchReader,
+    needle: &str,
+    timestamp_column: &str,
+    k: usize,
+) -> ZnResult<RecordBatch> {
+    if needle.is_empty() {
+        return Err(ZnError::empty_needle());
+    }
+    let schema = haystack.schema();
+    let column = schema.index_of(timestamp_column).map_err(|_| {
+        ZnError::invalid_argument(format!("no timestamp column {timestamp_column:?}"))
+    })?;
+    match schema.field(column).data_type() {
+        DataType::Int64 | DataType::Timestamp(_, _) => (),
+        t => {
+            return Err(ZnError::unsupported_type(format!(
+                "timestamp column {timestamp_column:?} is of type {t}"
+            )))
+        }
+    }
+    let needle = Needle::Exact(needle.as_bytes());
+
+    let _timer = registry().query_latency(SearchPath::Arrow).start_timer();
+
+    let mut newest = BinaryHeap::new();
+    let mut first_row = 0;
+    let mut counts = BatchCounts::default();
+    for batch in haystack {
+        let batch = batch?;
+        let mut matched = vec![false; batch.num_rows()];
+        counts += match_batch(&batch, &needle, &mut matched);
+        let times = compute::cast(batch.column(column), &DataType::Int64)?;
+        let times = cast::as_primitive_array::<Int64Type>(&times);
+        for (row, time) in times.iter().enumerate() {
+            let Some(time) = time.filter(|_| matched[row]) else {
+                continue;
+            };
+            let key = (time, first_row + row as u64);
+            if newest.len() == k {
+                if newest
+                    .peek()
+                    .is_none_or(|Reverse(oldest): &Reverse<Candidate>| oldest.key >= key)
+                {
+                    continue;
+                }
+                newest.pop();
+            }
+            newest.push(Reverse(Candidate {
+                key,
+                batch: batch.slice(row, 1),
+            }));
+        }
+        first_row += batch.num_rows() as u64;
+    }
+    registry().bytes_scanned().inc_by(counts.bytes_scanned);
+    registry().rows_matched().inc_by(counts.rows_matched);
+    let rows: Vec<_> = newest
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Reverse(candidate)| candidate.batch)
+        .collect();
+    Ok(compute::concat_batches(&schema, &rows)?)
+}
+
+/// A matching row kept by [`latest_matches`], ordered by time and then
+/// position.
+struct Candidate {
+    /// The time and the number of the row among those read.
+    key: (i64, u64),
+    /// The row itself, sliced out of its batch.
+    batch: RecordBatch,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+/// Counts, for each of the `needles`, the number of cells of the text and
+/// binary columns that contain it, like [`count_occurrences`] would for
+/// each, in a single pass over the data: the needles are searched for at
+/// once with an Aho-Corasick automaton.
+///
+/// # Errors
+///
+/// Returns [`ZnError::EmptyNeedle`] if one of the `needles` is empty.
+pub fn count_occurrences_multi(
+    haystack: ParquetRecordBatchReader,
+    needles: &[&str],
+) -> ZnResult<Vec<usize>> {
+    if needles.iter().any(|needle| needle.is_empty()) {
+        return Err(ZnError::empty_needle());
+    }
+    let automaton = AhoCorasick::new(needles);
+
+    let _timer = registry().query_latency(SearchPath::Arrow).start_timer();
+
+    let mut counts = vec![0; needles.len()];
+    let mut found = vec![false; needles.len()];
+    let mut bytes_scanned = 0;
+    let mut rows_matched = 0;
+    for batch in haystack {
+        let batch = batch?;
+        let mut matched = vec![false; batch.num_rows()];
         for array in batch.columns() {
-            match array.data_type() {
+            let Some((bytes, values)) = byte_values(array) else {
+                continue;
+            };
+            bytes_scanned += bytes;
+            for (row, s) in values.enumerate() {
+                let Some(s) = s else {
+                    continue;
+                };
+                // Overlapping matches, so that a needle inside or across
+                // another one's match is found too.
+                found.fill(false);
+                for m in automaton.find_overlapping_iter(s) {
+                    found[m.pattern()] = true;
+                }
+                for (count, _) in counts.iter_mut().zip(&found).filter(|(_, &f)| f) {
+                    *count += 1;
+                    matched[row] = true;
+                }
+            }
+        }
+        rows_matched += matched.iter().filter(|&&m| m).count() as u64;
+    }
+    registry().bytes_scanned().inc_by(bytes_scanned);
+    registry().rows_matched().inc_by(rows_matched);
+    Ok(counts)
+}
+
+/// Record batches kept in memory to be searched repeatedly, e.g. the latest
+/// ones of a stream, each with a bloom filter over the byte trigrams of its
+/// text and binary values (nested or not), built when it is first searched.
+/// Later searches skip the batches whose filter rules out the needle.
+/// Searches without case use [lowercased](kernels::ascii_lowercase) copies
+/// of the text and binary columns instead, made when first needed.
+#[derive(Debug, Default)]
+pub struct CachedBatches {
+    batches: Vec<RecordBatch>,
+    filters: Vec<OnceCell<BloomFilter>>,
+    /// The lowercased copy of each column, if it is a text or binary one.
+    lowercase: Vec<OnceCell<Vec<Option<ArrayRef>>>>,
+}
+
+impl CachedBatches {
+    pub fn new(batches: Vec<RecordBatch>) -> Self {
+        let filters = batches.iter().map(|_| OnceCell::new()).collect();
+        let lowercase = batches.iter().map(|_| OnceCell::new()).collect();
+        Self {
+            batches,
+            filters,
+            lowercase,
+        }
+    }
+
+    pub fn batches(&self) -> &[RecordBatch] {
+        &self.batches
+    }
+
+    /// Returns the number of batches whose filter has been built.
+    pub fn num_filters(&self) -> usize {
+        self.filters.iter().filter(|f| f.get().is_some()).count()
+    }
+
+    /// Like [`count_occurrences`], but over the cached batches, building
+    /// the missing filters and skipping the batches ruled out by theirs.
+    /// Needles shorter than a trigram never rule out a batch.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::EmptyNeedle`] if the `needle` is empty.
+    pub fn count_occurrences(&self, needle: &str) -> ZnResult<usize> {
+        if needle.is_empty() {
+            return Err(ZnError::empty_needle());
+        }
+        let hashes: Vec<_> = needle
+            .as_bytes()
+            .windows(NgramBloom::DEFAULT_N)
+            .map(fnv1a)
+            .collect();
+        let needle = Needle::Exact(needle.as_bytes());
+
+        let _timer = registry().query_latency(SearchPath::Arrow).start_timer();
+
+        let mut counts = BatchCounts::default();
+        for (batch, filter) in self.batches.iter().zip(&self.filters) {
+            let filter = filter.get_or_init(|| batch_filter(batch));
+            if hashes.iter().all(|&h| filter.contains_hash(h)) {
+                counts += count_batch(batch, &needle);
+            }
+        }
+        registry().bytes_scanned().inc_by(counts.bytes_scanned);
+        registry().rows_matched().inc_by(counts.rows_matched);
+        Ok(counts.count)
+    }
+
+    /// Like [`count_occurrences`](Self::count_occurrences), but ignores the
+    /// case of ASCII letters, as [`SearchOptions::case_insensitive`] does.
+    /// The lowercased copies of the text and binary columns are searched for
+    /// the lowercased needle; other columns, e.g. dictionaries, are matched
+    /// without case as they are.  No batch is skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::EmptyNeedle`] if the `needle` is empty.
+    pub fn count_occurrences_ignore_ascii_case(&self, needle: &str) -> ZnResult<usize> {
+        if needle.is_empty() {
+            return Err(ZnError::empty_needle());
+        }
+        let lowercase_needle = needle.to_ascii_lowercase();
+        let exact = Needle::Exact(lowercase_needle.as_bytes());
+        let ignore_case = Needle::IgnoreAsciiCase(IgnoreAsciiCase::new(needle.as_bytes()));
+
+        let _timer = registry().query_latency(SearchPath::Arrow).start_timer();
+
+        let mut counts = BatchCounts::default();
+        for (batch, lowercase) in self.batches.iter().zip(&self.lowercase) {
+            let lowercase = lowercase.get_or_init(|| lowercase_columns(batch));
+            let mut matched = vec![false; batch.num_rows()];
+            for (array, lowercase) in batch.columns().iter().zip(lowercase) {
+                let found = match lowercase {
+                    Some(lowercase) => match_array(lowercase, &exact, &mut matched),
+                    None => match_array(array, &ignore_case, &mut matched),
+                };
+                if let Some((bytes, count)) = found {
+                    counts.bytes_scanned += bytes;
+                    counts.count += count;
+                }
+            }
+            counts.rows_matched += matched.iter().filter(|&&m| m).count() as u64;
+        }
+        registry().bytes_scanned().inc_by(counts.bytes_scanned);
+        registry().rows_matched().inc_by(counts.rows_matched);
+        Ok(counts.count)
+    }
+}
+
+/// Returns the lowercased copy of each text and binary column of `batch`.
+fn lowercase_columns(batch: &RecordBatch) -> Vec<Option<ArrayRef>> {
+    batch
+        .columns()
+        .iter()
+        .map(|array| {
+            Some(match array.data_type() {
                 DataType::Utf8 => {
-                    let array = cast::as_string_array(array);
-                    count += array
-                        .iter()
-                        .flatten()
-                        .filter_map(|s| memmem::find(s.as_bytes(), needle.as_bytes()))
-                        .count();
-                }
-                DataType::Int64 => (),
-                DataType::Null
-                | DataType::Boolean
-                | DataType::Int8
-                | DataType::Int16
-                | DataType::Int32
-                | DataType::UInt8
-                | DataType::UInt16
-                | DataType::UInt32
-                | DataType::UInt64
-                | DataType::Float16
-                | DataType::Float32
-                | DataType::Float64
-                | DataType::Timestamp(_, _)
-                | DataType::Date32
-                | DataType::Date64
-                | DataType::Time32(_)
-                | DataType::Time64(_)
-                | DataType::Duration(_)
-                | DataType::Interval(_)
-                | DataType::Binary
-                | DataType::FixedSizeBinary(_)
-                | DataType::LargeBinary
-                | DataType::LargeUtf8
-                | DataType::List(_)
-                | DataType::FixedSizeList(_, _)
-                | DataType::LargeList(_)
-                | DataType::Struct(_)
-                | DataType::Union(_, _, _)
-                | DataType::Dictionary(_, _)
-                | DataType::Decimal128(_, _)
-                | DataType::Decimal256(_, _)
-                | DataType::Map(_, _) => todo!("{:#?}", array.data_type()),
+                    Arc::new(kernels::ascii_lowercase(cast::as_string_array(array))) as ArrayRef
+                }
+                DataType::LargeUtf8 => {
+                    Arc::new(kernels::ascii_lowercase(as_large_string_array(array)))
+                }
+                DataType::Binary => Arc::new(kernels::ascii_lowercase(
+                    cast::as_generic_binary_array::<i32>(array),
+                )),
+                DataType::LargeBinary => Arc::new(kernels::ascii_lowercase(
+                    cast::as_generic_binary_array::<i64>(array),
+                )),
+                _ => return None,
+            })
+        })
+        .collect()
+}
+
+/// Builds the bloom filter over the byte trigrams of the values of `batch`
+/// that [`count_batch`] matches, or more.
+fn batch_filter(batch: &RecordBatch) -> BloomFilter {
+    // The values of sliced lists are not sliced, so only their part in the
+    // batch is added; the children of sliced structs are.
+    fn elements<O: OffsetSizeTrait>(array: &GenericListArray<O>) -> ArrayRef {
+        let offsets = array.value_offsets();
+        let start = offsets[0].as_usize();
+        array
+            .values()
+            .slice(start, offsets[array.len()].as_usize() - start)
+    }
+    fn add(array: &ArrayRef, hashes: &mut HashSet<u64>) {
+        if let Some((_, values)) = byte_values(array) {
+            for s in values.flatten() {
+                hashes.extend(s.windows(NgramBloom::DEFAULT_N).map(fnv1a));
             }
+            return;
+        }
+        match array.data_type() {
+            DataType::Dictionary(_, _) => downcast_dictionary_array!(
+                array => add(array.values(), hashes),
+                _ => {}
+            ),
+            DataType::List(_) => add(&elements(cast::as_list_array(array)), hashes),
+            DataType::LargeList(_) => add(&elements(cast::as_large_list_array(array)), hashes),
+            DataType::Struct(_) => {
+                for column in cast::as_struct_array(array).columns() {
+                    add(column, hashes);
+                }
+            }
+            _ => {}
         }
     }
+
+    let mut hashes = HashSet::new();
+    for array in batch.columns() {
+        add(array, &mut hashes);
+    }
+    filter_of(&hashes, NgramBloom::DEFAULT_FALSE_POSITIVE_RATE)
+}
+
+/// Counts the number of cells of the text and binary columns that the
+/// `matcher`, e.g. a [registered](crate::plugins) one, matches.
+pub fn count_matches(haystack: ParquetRecordBatchReader, matcher: &dyn Matcher) -> ZnResult<usize> {
+    let _timer = registry().query_latency(SearchPath::Arrow).start_timer();
+
+    let mut count = 0;
+    let mut bytes_scanned = 0;
+    let mut rows_matched = 0;
+    for batch in haystack {
+        let batch = batch?;
+        let mut matched = vec![false; batch.num_rows()];
+        for array in batch.columns() {
+            let Some((bytes, values)) = byte_values(array) else {
+                continue;
+            };
+            bytes_scanned += bytes;
+            for (row, s) in values.enumerate() {
+                if s.is_some_and(|s| matcher.is_match(s)) {
+                    count += 1;
+                    matched[row] = true;
+                }
+            }
+        }
+        rows_matched += matched.iter().filter(|&&m| m).count() as u64;
+    }
+    registry().bytes_scanned().inc_by(bytes_scanned);
+    registry().rows_matched().inc_by(rows_matched);
     Ok(count)
 }
+
+/// Sets the flags in `matched` of the rows whose value in `array` contains
+/// the `needle`; returns the number of such values.
+fn match_column<T: ByteArrayType>(
+    array: &GenericByteArray<T>,
+    needle: &Needle,
+    matched: &mut [bool],
+) -> usize {
+    if let Needle::Exact(needle) = needle {
+        return match_finder(array, &memmem::Finder::new(needle), matched);
+    }
+    let mut count = 0;
+    for (row, s) in array.iter().enumerate() {
+        let s: Option<&[u8]> = s.map(AsRef::as_ref);
+        if s.is_some_and(|s| needle.is_match(s)) {
+            count += 1;
+            matched[row] = true;
+        }
+    }
+    count
+}
+
+/// Like [`match_column`] with the non-empty exact needle of the `finder`,
+/// which callers matching several columns build once: with the
+/// [offloaded](crate::kernel) kernel if the values are large enough.
+fn match_finder<T: ByteArrayType>(
+    array: &GenericByteArray<T>,
+    finder: &memmem::Finder,
+    matched: &mut [bool],
+) -> usize {
+    let mut count = 0;
+    if let Some(kernel) = kernel::offload_for(value_bytes(array) as usize) {
+        let base = array.value_offsets()[0].as_usize();
+        let offsets: Vec<_> = array
+            .value_offsets()
+            .iter()
+            .map(|o| o.as_usize() - base)
+            .collect();
+        let values = &array.value_data()[base..];
+        let mut column = vec![false; offsets.len() - 1];
+        kernel::match_values_or_cpu(&*kernel, values, &offsets, finder.needle(), &mut column);
+        for (row, hit) in column.into_iter().enumerate() {
+            // The kernel sees the bytes of null values too, which may
+            // contain the needle.
+            if hit && array.is_valid(row) {
+                count += 1;
+                matched[row] = true;
+            }
+        }
+        return count;
+    }
+    if array.null_count() == 0 {
+        return match_values_buffer(array, finder, matched);
+    }
+    for (row, s) in array.iter().enumerate() {
+        let s: Option<&[u8]> = s.map(AsRef::as_ref);
+        if s.is_some_and(|s| finder.find(s).is_some()) {
+            count += 1;
+            matched[row] = true;
+        }
+    }
+    count
+}
+
+/// Like [`match_finder`], for an `array` without nulls: searches the
+/// contiguous buffer of its values at once, mapping each hit to its row by a
+/// binary search of the offsets, so that the searcher is started once per
+/// hit rather than once per value.
+fn match_values_buffer<T: ByteArrayType>(
+    array: &GenericByteArray<T>,
+    finder: &memmem::Finder,
+    matched: &mut [bool],
+) -> usize {
+    let offsets = array.value_offsets();
+    let values = &array.value_data()[..offsets[offsets.len() - 1].as_usize()];
+    let needle = finder.needle();
+    let mut count = 0;
+    let mut pos = offsets[0].as_usize();
+    while let Some(i) = finder.find(&values[pos..]) {
+        let start = pos + i;
+        // The last row starting at or before the hit, which passes over
+        // empty values.
+        let row = offsets.partition_point(|o| o.as_usize() <= start) - 1;
+        let end = offsets[row + 1].as_usize();
+        if start + needle.len() <= end {
+            count += 1;
+            matched[row] = true;
+        }
+        // Either the row matched, or the hit straddles it and the next one,
+        // which may still contain the needle from its start.
+        pos = end;
+    }
+    count
+}
+
+/// Sets the flags in `matched` of the rows whose value in the dictionary
+/// `array` contains the `needle`, matching each value of the
+/// dictionary once; returns the size of the dictionary values and the number
+/// of such rows, or `None` if the values are neither text nor binary.
+fn match_dictionary(
+    array: &ArrayRef,
+    needle: &Needle,
+    matched: &mut [bool],
+) -> Option<(u64, usize)> {
+    downcast_dictionary_array!(
+        array => {
+            let (bytes, values) = byte_values(array.values())?;
+            let hits: Vec<_> = values.map(|s| s.is_some_and(|s| needle.is_match(s))).collect();
+            let mut count = 0;
+            for (row, key) in array.keys_iter().enumerate() {
+                if key.is_some_and(|key| hits[key]) {
+                    count += 1;
+                    matched[row] = true;
+                }
+            }
+            Some((bytes, count))
+        },
+        _ => None,
+    )
+}
+
+/// Returns the size of the dictionary values of `array` and the number of
+/// occurrences of the `needle` in the value of each row, counting each value
+/// of the dictionary once, or `None` if the values are neither text nor
+/// binary.
+fn dictionary_occurrences(array: &ArrayRef, needle: &Needle) -> Option<(u64, Vec<usize>)> {
+    downcast_dictionary_array!(
+        array => {
+            let (bytes, values) = byte_values(array.values())?;
+            let occurrences: Vec<_> = values.map(|s| s.map_or(0, |s| needle.occurrences(s))).collect();
+            let rows = array.keys_iter().map(|key| key.map_or(0, |key| occurrences[key])).collect();
+            Some((bytes, rows))
+        },
+        _ => None,
+    )
+}
+
+/// Returns the size of the text and binary values in `array`, nested in
+/// lists and structs or not, and, for each of these leaf columns, which rows
+/// of `array` have a value in it containing the `needle`: a list
+/// row if one of its elements does, a struct row if it is not null and its
+/// field does.
+fn match_nested(array: &ArrayRef, needle: &Needle) -> (u64, Vec<Vec<bool>>) {
+    fn leaf<T: ByteArrayType>(
+        array: &GenericByteArray<T>,
+        needle: &Needle,
+    ) -> (u64, Vec<Vec<bool>>) {
+        let mut hits = vec![false; array.len()];
+        match_column(array, needle, &mut hits);
+        (value_bytes(array), vec![hits])
+    }
+    fn list<O: OffsetSizeTrait>(
+        array: &GenericListArray<O>,
+        needle: &Needle,
+    ) -> (u64, Vec<Vec<bool>>) {
+        let (bytes, leaves) = match_nested(&array.values(), needle);
+        let offsets = array.value_offsets();
+        let leaves = leaves
+            .into_iter()
+            .map(|hits| {
+                (0..array.len())
+                    .map(|row| {
+                        let elements = offsets[row].as_usize()..offsets[row + 1].as_usize();
+                        array.is_valid(row) && hits[elements].contains(&true)
+                    })
+                    .collect()
+            })
+            .collect();
+        (bytes, leaves)
+    }
+
+    match array.data_type() {
+        DataType::Utf8 => leaf(cast::as_string_array(array), needle),
+        DataType::LargeUtf8 => leaf(as_large_string_array(array), needle),
+        DataType::Binary => leaf(cast::as_generic_binary_array::<i32>(array), needle),
+        DataType::LargeBinary => leaf(cast::as_generic_binary_array::<i64>(array), needle),
+        DataType::Dictionary(_, _) => {
+            let mut hits = vec![false; array.len()];
+            match match_dictionary(array, needle, &mut hits) {
+                Some((bytes, _)) => (bytes, vec![hits]),
+                None => (0, Vec::new()),
+            }
+        }
+        DataType::List(_) => list(cast::as_list_array(array), needle),
+        DataType::LargeList(_) => list(cast::as_large_list_array(array), needle),
+        DataType::Struct(_) => {
+            let array = cast::as_struct_array(array);
+            let mut bytes = 0;
+            let mut leaves = Vec::new();
+            for field in array.columns() {
+                let (field_bytes, field_leaves) = match_nested(field, needle);
+                bytes += field_bytes;
+                leaves.extend(field_leaves.into_iter().map(|mut hits| {
+                    for (row, hit) in hits.iter_mut().enumerate() {
+                        *hit &= array.is_valid(row);
+                    }
+                    hits
+                }));
+            }
+            (bytes, leaves)
+        }
+        _ => (0, Vec::new()),
+    }
+}
+
+type ByteValues<'a> = Box<dyn Iterator<Item = Option<&'a [u8]>> + 'a>;
+
+/// Returns the size of the values of a text or binary `array` and the
+/// values as bytes, or `None` if the array is neither.
+fn byte_values(array: &ArrayRef) -> Option<(u64, ByteValues<'_>)> {
+    fn bytes<T: ByteArrayType>(array: &GenericByteArray<T>) -> (u64, ByteValues<'_>) {
+        (
+            value_bytes(array),
+            Box::new(array.iter().map(|s| s.map(AsRef::as_ref))),
+        )
+    }
+    match array.data_type() {
+        DataType::Utf8 => Some(bytes(cast::as_string_array(array))),
+        DataType::LargeUtf8 => Some(bytes(as_large_string_array(array))),
+        DataType::Binary => Some(bytes(cast::as_generic_binary_array::<i32>(array))),
+        DataType::LargeBinary => Some(bytes(cast::as_generic_binary_array::<i64>(array))),
+        _ => None,
+    }
+}
+
+fn as_large_string_array(array: &ArrayRef) -> &LargeStringArray {
+    array
+        .as_any()
+        .downcast_ref::<LargeStringArray>()
+        .expect("LargeUtf8 array")
+}
+
+/// Size of the values of the (possibly sliced) `array`.
+fn value_bytes<T: ByteArrayType>(array: &GenericByteArray<T>) -> u64 {
+    let offsets = array.value_offsets();
+    (offsets[offsets.len() - 1] - offsets[0]).as_usize() as u64
+}
+
+/// Returns which rows of the `batch` contain the `needle` in some text or
+/// binary column.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(rows = batch.num_rows()))
+)]
+pub(crate) fn match_mask(batch: &RecordBatch, needle: &str) -> ZnResult<BooleanArray> {
+    let finder = memmem::Finder::new(needle.as_bytes());
+    let mut mask = BooleanArray::from(vec![false; batch.num_rows()]);
+    for array in batch.columns() {
+        let Some((bytes, values)) = byte_values(array) else {
+            continue;
+        };
+        registry().bytes_scanned().inc_by(bytes);
+        let column: BooleanArray = values
+            .map(|s| Some(s.is_some_and(|s| finder.find(s).is_some())))
+            .collect();
+        mask = or(&mask, &column)?;
+    }
+    registry().rows_matched().inc_by(mask.true_count() as u64);
+    Ok(mask)
+}
+
+/// Converts sorted row numbers (counted from the start of a file with
+/// `num_rows` rows) into a selection for
+/// [`ParquetRecordBatchReaderBuilder::with_row_selection`].
+///
+/// [`ParquetRecordBatchReaderBuilder::with_row_selection`]: parquet::arrow::arrow_reader::ArrowReaderBuilder::with_row_selection
+pub fn row_selection(rows: &[u64], num_rows: u64) -> RowSelection {
+    let mut selectors = Vec::new();
+    let mut next = 0; // first row not covered by `selectors`
+    for &row in rows {
+        if row > next {
+            selectors.push(RowSelector::skip((row - next) as usize));
+        }
+        match selectors.last_mut() {
+            Some(last) if !last.skip && row == next => last.row_count += 1,
+            _ => selectors.push(RowSelector::select(1)),
+        }
+        next = row + 1;
+    }
+    if num_rows > next {
+        selectors.push(RowSelector::skip((num_rows - next) as usize));
+    }
+    selectors.into()
+}
+
+/// Settings of [`tuned_reader`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TunedReaderOptions {
+    /// Bytes of text per batch; by default, those of a [tuned](crate::tune)
+    /// batch of 128-byte rows.
+    pub target_batch_bytes: Option<u64>,
+    /// Decodes the byte array columns only, those the searches match in,
+    /// unless the file has none.
+    pub text_columns_only: bool,
+}
+
+impl Default for TunedReaderOptions {
+    fn default() -> Self {
+        Self {
+            target_batch_bytes: None,
+            text_columns_only: true,
+        }
+    }
+}
+
+/// Returns the number of rows whose byte array values take about
+/// `target_batch_bytes` in the file described by `metadata`, in powers of
+/// two between 1024 and 65536.
+///
+/// # Errors
+///
+/// Returns [`ZnError::InvalidMetadata`] if the metadata reports a negative
+/// column chunk size.
+pub fn tuned_batch_size(metadata: &ParquetMetaData, target_batch_bytes: u64) -> ZnResult<usize> {
+    let rows = metadata.file_metadata().num_rows().max(1) as u64;
+    let row_bytes = byte_array_columns_uncompressed_size(metadata)? / rows;
+    Ok(tune::batch_rows(target_batch_bytes, row_bytes))
+}
+
+/// Returns a reader of the parquet file read by `reader`, on any
+/// [storage](crate::storage) backend, e.g. [`bytes::Bytes`] or a
+/// [`File`](std::fs::File), with a batch size [tuned](tuned_batch_size) to
+/// the average size of its text values and, by default, only its text
+/// columns.
+pub fn tuned_reader<R: RangeReader + ?Sized + 'static>(
+    reader: Arc<R>,
+    options: &TunedReaderOptions,
+) -> ZnResult<ParquetRecordBatchReader> {
+    Ok(tuned_builder(reader, options)?.build()?)
+}
+
+/// Returns a builder of readers of the parquet file read by `reader`,
+/// configured as recommended for searches:
+///
+/// - the batch size and projection of [`tuned_reader`] with the default
+///   [options](TunedReaderOptions);
+/// - the page index loaded if the file has one, so that row selections skip
+///   whole pages instead of decoding and discarding them.
+///
+/// Further settings are applied to the builder, typically the rows of an
+/// [index](crate::index) lookup with
+/// `with_row_selection(row_selection(&rows, num_rows))` or a predicate with
+/// `with_row_filter`.  Text columns decode to [`DataType::Utf8`]: the
+/// arrow version in use has no string view type to prefer.
+pub fn reader_builder<R: RangeReader + ?Sized + 'static>(
+    reader: Arc<R>,
+) -> ZnResult<ParquetRecordBatchReaderBuilder<RangeChunkReader<R>>> {
+    tuned_builder(reader, &TunedReaderOptions::default())
+}
+
+fn tuned_builder<R: RangeReader + ?Sized + 'static>(
+    reader: Arc<R>,
+    options: &TunedReaderOptions,
+) -> ZnResult<ParquetRecordBatchReaderBuilder<RangeChunkReader<R>>> {
+    let builder =
+        ParquetRecordBatchReaderBuilder::try_new(RangeChunkReader::try_new(reader.clone())?)?;
+    // Loading the page index of a file without one fails on the first read,
+    // so the footer is parsed again only when every column chunk has one.
+    let builder = if has_offset_index(builder.metadata()) {
+        ParquetRecordBatchReaderBuilder::try_new_with_options(
+            RangeChunkReader::try_new(reader)?,
+            ArrowReaderOptions::new().with_page_index(true),
+        )?
+    } else {
+        builder
+    };
+    let metadata = builder.metadata().clone();
+    let target_batch_bytes = options
+        .target_batch_bytes
+        .unwrap_or_else(|| tune::tuning().batch_size as u64 * tune::ROW_BYTES);
+    let mut builder = builder.with_batch_size(tuned_batch_size(&metadata, target_batch_bytes)?);
+    let schema = metadata.file_metadata().schema_descr();
+    let text_columns: Vec<_> = (0..schema.num_columns())
+        .filter(|&i| is_byte_array(schema.column(i).physical_type()))
+        .collect();
+    if options.text_columns_only && !text_columns.is_empty() {
+        builder = builder.with_projection(ProjectionMask::leaves(schema, text_columns));
+    }
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util::parquet_bytes, testdata::LogSpec};
+    use arrow::{array::ArrayData, buffer::Buffer};
+    use arrow_array::{
+        builder::ListBuilder,
+        types::{Int32Type, Int8Type},
+        BinaryArray, DictionaryArray, Float64Array, Int64Array, LargeBinaryArray, StringArray,
+        StructArray,
+    };
+    use bytes::Bytes;
+    use parquet::arrow::ArrowWriter;
+
+    #[test]
+    fn test_tuned_reader() {
+        let spec = LogSpec {
+            rows: 5000,
+            label_columns: 1,
+            ..LogSpec::default()
+        };
+        let generated = spec.generate().unwrap();
+        let data = Arc::new(generated.data);
+        let small = TunedReaderOptions {
+            target_batch_bytes: Some(1),
+            ..TunedReaderOptions::default()
+        };
+        let mut reader = tuned_reader(data.clone(), &small).unwrap();
+        let batch = reader.next().unwrap().unwrap();
+        assert_eq!(batch.num_rows(), 1024);
+        // `_timestamp` is not decoded.
+        assert_eq!(batch.num_columns(), 2);
+
+        let large = TunedReaderOptions {
+            target_batch_bytes: Some(1 << 30),
+            text_columns_only: false,
+        };
+        let reader = tuned_reader(data.clone(), &large).unwrap();
+        let batches: Vec<_> = reader.map(Result::unwrap).collect();
+        assert_eq!(batches[0].num_columns(), 3);
+        assert_eq!(batches[0].num_rows(), 5000);
+        assert_eq!(
+            count_occurrences(tuned_reader(data, &small).unwrap(), "search_string").unwrap(),
+            generated.matching_rows
+        );
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn test_count_occurrences_parallel() {
+        let generated = LogSpec {
+            rows: 10000,
+            needle_density: 0.1,
+            ..LogSpec::default()
+        }
+        .generate()
+        .unwrap();
+        let data = Arc::new(generated.data);
+        let small = TunedReaderOptions {
+            target_batch_bytes: Some(1),
+            ..TunedReaderOptions::default()
+        };
+        let reader = || tuned_reader(data.clone(), &small).unwrap();
+        for threads in [0, 1, 3] {
+            assert_eq!(
+                count_occurrences_parallel(reader(), "search_string", threads).unwrap(),
+                generated.matching_rows
+            );
+        }
+        assert!(matches!(
+            count_occurrences_parallel(reader(), "", 2),
+            Err(ZnError::EmptyNeedle)
+        ));
+    }
+
+    #[test]
+    fn test_count_occurrences_in_columns() {
+        let batch = RecordBatch::try_from_iter([
+            (
+                "log",
+                Arc::new(StringArray::from(vec!["k8s pod", "node", "k8s"])) as ArrayRef,
+            ),
+            (
+                "label",
+                Arc::new(StringArray::from(vec!["k8s", "k8s", "vm"])) as ArrayRef,
+            ),
+            ("id", Arc::new(Int64Array::from(vec![1, 2, 3])) as ArrayRef),
+        ])
+        .unwrap();
+        let mut data = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut data, batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        let reader = || {
+            ParquetRecordBatchReaderBuilder::try_new(Bytes::from(data.clone()))
+                .unwrap()
+                .build()
+                .unwrap()
+        };
+        let count =
+            |columns: Option<&[&str]>| count_occurrences_in_columns(reader(), "k8s", columns);
+        assert_eq!(count(None).unwrap(), 4);
+        assert_eq!(count(Some(&["log"])).unwrap(), 2);
+        assert_eq!(count(Some(&["label", "id"])).unwrap(), 2);
+        assert_eq!(count(Some(&[])).unwrap(), 0);
+        assert!(matches!(
+            count(Some(&["missing"])),
+            Err(ZnError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_dictionary() {
+        let pods: DictionaryArray<Int32Type> =
+            vec![Some("k8s-web"), None, Some("vm"), Some("k8s-web")]
+                .into_iter()
+                .collect();
+        let nodes: DictionaryArray<Int8Type> = vec!["node", "k8s-node", "node", "node"]
+            .into_iter()
+            .collect();
+        let batch = RecordBatch::try_from_iter([
+            ("pod", Arc::new(pods) as ArrayRef),
+            ("node", Arc::new(nodes) as ArrayRef),
+        ])
+        .unwrap();
+        let mut data = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut data, batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        let reader = || {
+            ParquetRecordBatchReaderBuilder::try_new(Bytes::from(data.clone()))
+                .unwrap()
+                .build()
+                .unwrap()
+        };
+        assert!(matches!(
+            reader().schema().field(0).data_type(),
+            DataType::Dictionary(_, _)
+        ));
+        assert_eq!(count_occurrences(reader(), "k8s").unwrap(), 3);
+        assert_eq!(count_occurrences(reader(), "node").unwrap(), 4);
+        assert_eq!(count_occurrences(reader(), "missing").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_nested() {
+        let mut tags = ListBuilder::new(StringBuilder::new());
+        for row in [
+            Some(vec!["k8s", "web"]),
+            Some(vec![]),
+            None,
+            Some(vec!["vm", "k8s-node"]),
+        ] {
+            match row {
+                Some(values) => {
+                    values
+                        .into_iter()
+                        .for_each(|v| tags.values().append_value(v));
+                    tags.append(true);
+                }
+                None => tags.append(false),
+            }
+        }
+        let meta = StructArray::from((
+            vec![
+                (
+                    Field::new("pod", DataType::Utf8, true),
+                    Arc::new(StringArray::from(vec!["k8s-a", "b", "k8s-c", "k8s-d"])) as ArrayRef,
+                ),
+                (
+                    Field::new("node", DataType::Utf8, true),
+                    Arc::new(StringArray::from(vec!["n1", "k8s", "n3", "n4"])) as ArrayRef,
+                ),
+            ],
+            Buffer::from([0b0111u8]),
+        ));
+        let batch = RecordBatch::try_from_iter([
+            ("tags", Arc::new(tags.finish()) as ArrayRef),
+            ("meta", Arc::new(meta) as ArrayRef),
+        ])
+        .unwrap();
+        let counts = count_batch(&batch, &Needle::Exact(b"k8s"));
+        assert_eq!(counts.count, 5);
+        assert_eq!(counts.rows_matched, 4);
+        assert_eq!(count_batch(&batch, &Needle::Exact(b"web")).count, 1);
+        assert_eq!(count_batch(&batch, &Needle::Exact(b"k8s-d")).count, 0);
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let data = parquet_bytes(&["K8s pod", "node", "k8S", "K8", "kubernetes"], 2);
+        let reader = || {
+            ParquetRecordBatchReaderBuilder::try_new(data.clone())
+                .unwrap()
+                .build()
+                .unwrap()
+        };
+        let options = SearchOptions {
+            case_insensitive: true,
+            ..SearchOptions::default()
+        };
+        assert_eq!(count_occurrences(reader(), "k8s").unwrap(), 0);
+        assert_eq!(
+            count_occurrences_with_options(reader(), "k8s", &options).unwrap(),
+            2
+        );
+        assert_eq!(
+            count_occurrences_with_options(reader(), "KUBERNETES", &options).unwrap(),
+            1
+        );
+        let options = SearchOptions {
+            columns: Some(vec!["id".to_owned()]),
+            ..options
+        };
+        assert_eq!(
+            count_occurrences_with_options(reader(), "k8s", &options).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_cached_batches() {
+        let logs = StringArray::from(vec!["k8s pod", "node", "vm", "k8s", "host", "vm"]);
+        let mut labels = ListBuilder::new(StringBuilder::new());
+        for label in ["a", "b", "c", "d", "kube-system", "e"] {
+            labels.values().append_value(label);
+            labels.append(true);
+        }
+        let namespaces = StringArray::from(vec!["a", "b", "c", "d", "e", "prod"]);
+        let kubernetes = StructArray::from(vec![(
+            Field::new("namespace", DataType::Utf8, false),
+            Arc::new(namespaces) as ArrayRef,
+        )]);
+        let batch = RecordBatch::try_from_iter([
+            ("log", Arc::new(logs) as ArrayRef),
+            ("labels", Arc::new(labels.finish()) as ArrayRef),
+            ("kubernetes", Arc::new(kubernetes) as ArrayRef),
+        ])
+        .unwrap();
+        let cached = CachedBatches::new(vec![
+            batch.slice(0, 2),
+            batch.slice(2, 2),
+            batch.slice(4, 2),
+        ]);
+        assert_eq!(cached.num_filters(), 0);
+        assert_eq!(cached.count_occurrences("k8s").unwrap(), 2);
+        assert_eq!(cached.num_filters(), 3);
+        assert_eq!(cached.count_occurrences("kube").unwrap(), 1);
+        assert_eq!(cached.count_occurrences("node").unwrap(), 1);
+        assert_eq!(cached.count_occurrences("vm").unwrap(), 2);
+        assert_eq!(cached.count_occurrences("prod").unwrap(), 1);
+        assert_eq!(cached.count_occurrences("missing").unwrap(), 0);
+        assert!(cached.count_occurrences("").is_err());
+
+        let filters: Vec<_> = cached.filters.iter().map(|f| f.get().unwrap()).collect();
+        let may_contain = |filter: &BloomFilter, needle: &[u8]| {
+            needle.windows(3).all(|w| filter.contains_hash(fnv1a(w)))
+        };
+        assert!(may_contain(filters[0], b"k8s pod"));
+        assert!(may_contain(filters[2], b"kube-system"));
+        assert!(!may_contain(filters[1], b"kube-system"));
+        assert!(may_contain(filters[2], b"prod"));
+        assert!(!may_contain(filters[0], b"prod"));
+        assert!(!may_contain(filters[1], b"prod"));
+
+        assert_eq!(cached.count_occurrences("K8S").unwrap(), 0);
+        assert_eq!(
+            cached.count_occurrences_ignore_ascii_case("K8S").unwrap(),
+            2
+        );
+        assert_eq!(
+            cached.count_occurrences_ignore_ascii_case("Kube").unwrap(),
+            1
+        );
+        assert_eq!(cached.count_occurrences_ignore_ascii_case("VM").unwrap(), 2);
+        assert!(cached.count_occurrences_ignore_ascii_case("").is_err());
+    }
+
+    #[test]
+    fn test_numbers() {
+        let batch = RecordBatch::try_from_iter([
+            (
+                "log",
+                Arc::new(StringArray::from(vec![
+                    "GET /a 404",
+                    "GET /b",
+                    "GET /c",
+                    "GET /d",
+                ])) as ArrayRef,
+            ),
+            (
+                "status",
+                Arc::new(Int64Array::from(vec![
+                    Some(404),
+                    Some(200),
+                    None,
+                    Some(404),
+                ])) as ArrayRef,
+            ),
+            (
+                "latency",
+                Arc::new(Float64Array::from(vec![0.5, 404.25, 1.0, 2.0])) as ArrayRef,
+            ),
+            (
+                "cached",
+                Arc::new(BooleanArray::from(vec![true, false, true, false])) as ArrayRef,
+            ),
+        ])
+        .unwrap();
+        let mut data = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut data, batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        let data = Bytes::from(data);
+        let count = |needle, options: &SearchOptions| {
+            let reader = ParquetRecordBatchReaderBuilder::try_new(data.clone())
+                .unwrap()
+                .build()
+                .unwrap();
+            count_occurrences_with_options(reader, needle, options).unwrap()
+        };
+        let numbers = SearchOptions {
+            numbers: true,
+            ..SearchOptions::default()
+        };
+
+        assert_eq!(count("404", &SearchOptions::default()), 1);
+        assert_eq!(count("404", &numbers), 4);
+        assert_eq!(count("0.5", &numbers), 1);
+        assert_eq!(count("1", &numbers), 1);
+        assert_eq!(count("true", &numbers), 2);
+        let rows = SearchOptions {
+            count: CountMode::Rows,
+            ..numbers.clone()
+        };
+        assert_eq!(count("404", &rows), 3);
+        let status = SearchOptions {
+            columns: Some(vec!["status".to_owned()]),
+            ..numbers
+        };
+        assert_eq!(count("404", &status), 2);
+    }
+
+    #[test]
+    fn test_match_values_buffer() {
+        let array = StringArray::from(vec!["ab", "cab", "", "abc", "xa", "bc", "", "abcabc", "a"]);
+        for needle in ["abc", "ab", "a", "b", "c", "bca", "cabc", "xabc"] {
+            for offset in 0..array.len() {
+                for len in 0..=array.len() - offset {
+                    let array = array.slice(offset, len);
+                    let array = cast::as_string_array(&array);
+                    let expected: Vec<_> =
+                        array.iter().map(|s| s.unwrap().contains(needle)).collect();
+                    let mut matched = vec![false; len];
+                    let count =
+                        match_values_buffer(array, &memmem::Finder::new(needle), &mut matched);
+                    assert_eq!(matched, expected, "{needle} {offset} {len}");
+                    assert_eq!(count, expected.iter().filter(|&&m| m).count());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_offloaded_nulls() {
+        // The null value has bytes, which contain the needle.
+        let data = ArrayData::builder(DataType::Utf8)
+            .len(3)
+            .add_buffer(Buffer::from_slice_ref([0i32, 3, 6, 9]))
+            .add_buffer(Buffer::from_slice_ref(b"k8sk8spod"))
+            .null_bit_buffer(Some(Buffer::from([0b101])))
+            .build()
+            .unwrap();
+        let array = StringArray::from(data);
+        let finder = memmem::Finder::new("k8s");
+        let mut cpu = vec![false; 3];
+        assert_eq!(match_finder(&array, &finder, &mut cpu), 1);
+        kernel::set_offload(Arc::new(kernel::CpuKernel), 0);
+        let mut offloaded = vec![false; 3];
+        let count = match_finder(&array, &finder, &mut offloaded);
+        kernel::clear_offload();
+        assert_eq!(count, 1);
+        assert_eq!(offloaded, [true, false, false]);
+        assert_eq!(offloaded, cpu);
+    }
+
+    #[test]
+    fn test_count_mode() {
+        let batch = RecordBatch::try_from_iter([
+            (
+                "log",
+                Arc::new(StringArray::from(vec!["k8s pod k8s", "node", "k8s", "pod"])) as ArrayRef,
+            ),
+            (
+                "labels",
+                Arc::new(DictionaryArray::<Int32Type>::from_iter([
+                    "k8s", "a", "k8s k8s", "k8s",
+                ])) as ArrayRef,
+            ),
+        ])
+        .unwrap();
+        let mut data = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut data, batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        let data = Bytes::from(data);
+        let reader = || {
+            ParquetRecordBatchReaderBuilder::try_new(data.clone())
+                .unwrap()
+                .build()
+                .unwrap()
+        };
+        let count = |needle, count, case_insensitive| {
+            let options = SearchOptions {
+                case_insensitive,
+                count,
+                ..SearchOptions::default()
+            };
+            count_occurrences_with_options(reader(), needle, &options).unwrap()
+        };
+        let file = crate::file::open_bytes(data.clone()).unwrap();
+        for (mode, expected) in [
+            (CountMode::Cells, 5),
+            (CountMode::Rows, 3),
+            (CountMode::Occurrences, 7),
+        ] {
+            assert_eq!(count("k8s", mode, false), expected, "{mode:?}");
+            assert_eq!(count("K8S", mode, true), expected, "{mode:?}");
+            assert_eq!(
+                crate::file::count_occurrences_with_mode(&file, b"k8s", mode).unwrap(),
+                expected,
+                "{mode:?}"
+            );
+        }
+        assert_eq!(count("k8s k8s", CountMode::Occurrences, false), 1);
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_count_regex_matches() {
+        let data = parquet_bytes(&["status=500 k8s", "status=200", "status=503", "k8s"], 2);
+        let reader = || {
+            ParquetRecordBatchReaderBuilder::try_new(data.clone())
+                .unwrap()
+                .build()
+                .unwrap()
+        };
+        assert_eq!(count_regex_matches(reader(), r"status=5\d\d").unwrap(), 2);
+        assert_eq!(count_regex_matches(reader(), "^k8s$").unwrap(), 1);
+        assert_eq!(count_regex_matches(reader(), "").unwrap(), 4);
+        assert!(matches!(
+            count_regex_matches(reader(), "("),
+            Err(ZnError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_count_occurrences_multi() {
+        let data = parquet_bytes(&["k8s pod", "node", "k8s", "pod k8s-node", ""], 2);
+        let reader = || {
+            ParquetRecordBatchReaderBuilder::try_new(data.clone())
+                .unwrap()
+                .build()
+                .unwrap()
+        };
+        let needles = ["k8s", "pod", "node", "s pod", "missing"];
+        let counts = count_occurrences_multi(reader(), &needles).unwrap();
+        assert_eq!(counts, [3, 2, 2, 1, 0]);
+        for (needle, count) in needles.iter().zip(counts) {
+            assert_eq!(count_occurrences(reader(), needle).unwrap(), count);
+        }
+        assert!(count_occurrences_multi(reader(), &[]).unwrap().is_empty());
+        assert!(count_occurrences_multi(reader(), &["k8s", ""]).is_err());
+    }
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn test_count_occurrences_async() {
+        use parquet::arrow::ParquetRecordBatchStreamBuilder;
+
+        let data = Arc::new(parquet_bytes(&["k8s pod", "node", "k8s", "pod"], 2));
+        let stream = || async {
+            ParquetRecordBatchStreamBuilder::new(RangeChunkReader::try_new(data.clone()).unwrap())
+                .await
+                .unwrap()
+                .with_batch_size(1)
+                .build()
+                .unwrap()
+        };
+        assert_eq!(
+            count_occurrences_async(stream().await, "k8s")
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            count_occurrences_async(stream().await, "pod")
+                .await
+                .unwrap(),
+            2
+        );
+        assert!(count_occurrences_async(stream().await, "").await.is_err());
+    }
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn test_count_occurrences_pipelined() {
+        let data = parquet_bytes(&["k8s pod", "node", "k8s", "pod", "k8s k8s"], 1);
+        for in_flight in [1, 2, 8] {
+            assert_eq!(
+                count_occurrences_pipelined(data.clone(), "k8s", in_flight)
+                    .await
+                    .unwrap(),
+                3
+            );
+            assert_eq!(
+                count_occurrences_pipelined(data.clone(), "vm", in_flight)
+                    .await
+                    .unwrap(),
+                0
+            );
+        }
+        assert!(count_occurrences_pipelined(data.clone(), "", 1)
+            .await
+            .is_err());
+        assert!(count_occurrences_pipelined(data.clone(), "k8s", 0)
+            .await
+            .is_err());
+        assert!(
+            count_occurrences_pipelined(Bytes::from_static(b"not parquet"), "k8s", 1)
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_count_occurrences_by_column() {
+        let batch = RecordBatch::try_from_iter([
+            (
+                "log",
+                Arc::new(StringArray::from(vec!["k8s pod", "node", "k8s"])) as ArrayRef,
+            ),
+            (
+                "label",
+                Arc::new(StringArray::from(vec!["k8s", "vm", "vm"])) as ArrayRef,
+            ),
+            (
+                "host",
+                Arc::new(StringArray::from(vec!["a", "b", "c"])) as ArrayRef,
+            ),
+            ("id", Arc::new(Int64Array::from(vec![1, 2, 3])) as ArrayRef),
+        ])
+        .unwrap();
+        let mut data = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut data, batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(data))
+            .unwrap()
+            .with_batch_size(2)
+            .build()
+            .unwrap();
+        let columns = count_occurrences_by_column(reader, "k8s").unwrap();
+        let expected =
+            [("log", 2), ("label", 1), ("host", 0)].map(|(name, count)| (name.to_owned(), count));
+        assert_eq!(columns, HashMap::from(expected));
+    }
+
+    #[test]
+    fn test_search_hits() {
+        let batch = RecordBatch::try_from_iter([
+            (
+                "log",
+                Arc::new(StringArray::from(vec![
+                    Some("k8s pod on k8s"),
+                    None,
+                    Some("node"),
+                    Some("k8s"),
+                ])) as ArrayRef,
+            ),
+            (
+                "payload",
+                Arc::new(BinaryArray::from(vec![&b"\xffk8s"[..], b"k8s", b"", b""])) as ArrayRef,
+            ),
+        ])
+        .unwrap();
+        let mut data = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut data, batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        let reader = || {
+            ParquetRecordBatchReaderBuilder::try_new(Bytes::from(data.clone()))
+                .unwrap()
+                .with_batch_size(2)
+                .build()
+                .unwrap()
+        };
+        let hit = |row, column: &str, value: &str, offsets: &[(usize, usize)]| SearchHit {
+            row,
+            column: column.to_owned(),
+            value: value.to_owned(),
+            offsets: offsets.iter().map(|&(start, end)| start..end).collect(),
+        };
+        assert_eq!(
+            search_hits(reader(), "k8s", 10).unwrap(),
+            [
+                hit(0, "log", "k8s pod on k8s", &[(0, 3), (11, 14)]),
+                hit(0, "payload", "\u{fffd}k8s", &[(1, 4)]),
+                hit(1, "payload", "k8s", &[(0, 3)]),
+                hit(3, "log", "k8s", &[(0, 3)]),
+            ]
+        );
+        assert_eq!(search_hits(reader(), "k8s", 3).unwrap().len(), 3);
+        assert!(search_hits(reader(), "k8s", 0).unwrap().is_empty());
+        assert!(search_hits(reader(), "missing", 10).unwrap().is_empty());
+        assert!(search_hits(reader(), "", 10).is_err());
+    }
+
+    #[test]
+    fn test_snippets() {
+        let data = parquet_bytes(
+            &[
+                "GET /api/k8s/pods 200 took 12ms",
+                "node",
+                "k8s k8s",
+                "été k8s ça",
+            ],
+            2,
+        );
+        let reader = || {
+            ParquetRecordBatchReaderBuilder::try_new(data.clone())
+                .unwrap()
+                .build()
+                .unwrap()
+        };
+        let snippet = |row, text: &str, highlight, truncated| Snippet {
+            row,
+            column: "log".to_owned(),
+            text: text.to_owned(),
+            highlight,
+            truncated,
+        };
+        assert_eq!(
+            snippets(reader(), "k8s", 5).unwrap(),
+            [
+                snippet(0, "/api/k8s/pods", 5..8, (true, true)),
+                snippet(2, "k8s k8s", 0..3, (false, false)),
+                snippet(2, "k8s k8s", 4..7, (false, false)),
+                snippet(3, "té k8s ça", 4..7, (true, false)),
+            ]
+        );
+        // Cut after `é` and before `ç`, which span two bytes.
+        assert_eq!(
+            snippets(reader(), "k8s", 2).unwrap()[3],
+            snippet(3, " k8s ", 1..4, (true, true))
+        );
+        assert_eq!(
+            snippets(reader(), "k8s", 100).unwrap()[0],
+            snippet(0, "GET /api/k8s/pods 200 took 12ms", 9..12, (false, false))
+        );
+        assert_eq!(snippets(reader(), "k8s", 0).unwrap()[0].text, "k8s");
+        assert!(snippets(reader(), "", 5).is_err());
+    }
+
+    #[test]
+    fn test_latest_matches() {
+        let batch = RecordBatch::try_from_iter([
+            (
+                "log",
+                Arc::new(StringArray::from(vec![
+                    "error a", "info b", "error c", "error d", "error e", "error f",
+                ])) as ArrayRef,
+            ),
+            (
+                "@timestamp",
+                Arc::new(Int64Array::from(vec![
+                    Some(30),
+                    Some(90),
+                    None,
+                    Some(10),
+                    Some(50),
+                    Some(30),
+                ])) as ArrayRef,
+            ),
+        ])
+        .unwrap();
+        let mut data = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut data, batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        let reader = || {
+            ParquetRecordBatchReaderBuilder::try_new(Bytes::from(data.clone()))
+                .unwrap()
+                .with_batch_size(2)
+                .build()
+                .unwrap()
+        };
+        let logs = |batch: RecordBatch| {
+            cast::as_string_array(batch.column(0))
+                .iter()
+                .map(|s| s.unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        let newest = latest_matches(reader(), "error", "@timestamp", 3).unwrap();
+        assert_eq!(newest.num_columns(), 2);
+        assert_eq!(logs(newest), ["error e", "error f", "error a"]);
+        assert_eq!(
+            logs(latest_matches(reader(), "error", "@timestamp", 10).unwrap()),
+            ["error e", "error f", "error a", "error d"]
+        );
+        assert_eq!(
+            latest_matches(reader(), "error", "@timestamp", 0)
+                .unwrap()
+                .num_rows(),
+            0
+        );
+        assert!(latest_matches(reader(), "", "@timestamp", 3).is_err());
+        assert!(latest_matches(reader(), "error", "time", 3).is_err());
+        assert!(latest_matches(reader(), "error", "log", 3).is_err());
+    }
+
+    #[test]
+    fn test_matching_rows() {
+        let data = parquet_bytes(&["k8s pod", "node", "k8s", "node", "pod", "k8s"], 2);
+        let reader = || {
+            ParquetRecordBatchReaderBuilder::try_new(data.clone())
+                .unwrap()
+                .with_batch_size(2)
+                .build()
+                .unwrap()
+        };
+        assert_eq!(
+            matching_rows(reader(), "k8s").unwrap(),
+            [(0, vec![0]), (1, vec![0]), (2, vec![1])]
+        );
+        assert_eq!(
+            matching_rows(reader(), "pod").unwrap(),
+            [(0, vec![0]), (2, vec![0])]
+        );
+        assert!(matching_rows(reader(), "missing").unwrap().is_empty());
+        assert!(matching_rows(reader(), "").is_err());
+    }
+
+    #[test]
+    fn test_large_utf8() {
+        let logs = LargeStringArray::from(vec![Some("k8s pod"), None, Some("node k8s"), Some("")]);
+        let batch = RecordBatch::try_from_iter([("log", Arc::new(logs) as ArrayRef)]).unwrap();
+        let mut data = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut data, batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let reader = || {
+            ParquetRecordBatchReaderBuilder::try_new(Bytes::from(data.clone()))
+                .unwrap()
+                .build()
+                .unwrap()
+        };
+        assert_eq!(reader().schema().field(0).data_type(), &DataType::LargeUtf8);
+        assert_eq!(count_occurrences(reader(), "k8s").unwrap(), 2);
+        let matcher = crate::str::Substring::new(b"node");
+        assert_eq!(count_matches(reader(), &matcher).unwrap(), 1);
+        let mask = match_mask(&reader().next().unwrap().unwrap(), "pod").unwrap();
+        assert_eq!(mask.true_count(), 1);
+    }
+
+    #[test]
+    fn test_binary() {
+        let payloads: Vec<&[u8]> = vec![b"\xff\x00k8s", b"\xfe", b"k8s\xff"];
+        let large: Vec<&[u8]> = vec![b"k8s", b"", b"\x80"];
+        let batch = RecordBatch::try_from_iter([
+            ("payload", Arc::new(BinaryArray::from(payloads)) as ArrayRef),
+            ("large", Arc::new(LargeBinaryArray::from(large)) as ArrayRef),
+        ])
+        .unwrap();
+        let mut data = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut data, batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        let reader = || {
+            ParquetRecordBatchReaderBuilder::try_new(Bytes::from(data.clone()))
+                .unwrap()
+                .build()
+                .unwrap()
+        };
+        assert_eq!(count_occurrences(reader(), "k8s").unwrap(), 3);
+        let matcher = crate::str::Substring::new(b"\xff");
+        assert_eq!(count_matches(reader(), &matcher).unwrap(), 2);
+        let mask = match_mask(&reader().next().unwrap().unwrap(), "k8s").unwrap();
+        assert_eq!(mask.true_count(), 2);
+    }
+
+    #[test]
+    fn test_reader_builder() {
+        let generated = LogSpec {
+            rows: 5000,
+            label_columns: 1,
+            row_group_size: 1000,
+            needle_density: 0.1,
+            ..LogSpec::default()
+        }
+        .generate()
+        .unwrap();
+        let data = Arc::new(generated.data);
+        let builder = reader_builder(data.clone()).unwrap();
+        assert!(builder.metadata().page_indexes().is_some());
+        let num_rows = builder.metadata().file_metadata().num_rows() as u64;
+        let reader = builder
+            .with_row_selection(row_selection(&[3, 1500, 4999], num_rows))
+            .build()
+            .unwrap();
+        let rows: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+        assert_eq!(rows, 3);
+
+        let reader = reader_builder(Arc::new(parquet_bytes(&["k8s pod", "k8s"], 1)))
+            .unwrap()
+            .with_row_selection(row_selection(&[1], 2))
+            .build()
+            .unwrap();
+        assert_eq!(count_occurrences(reader, "k8s").unwrap(), 1);
+    }
+}
@@ -0,0 +1,194 @@
+//! Compute kernels matching arrays against a needle
+//!
+//! Like the kernels of [`arrow::compute`], these take an array and return a
+//! [`BooleanArray`] of the same length, null where the input is null, so
+//! that other engines and the [match UDF](crate::match_udf) can filter with
+//! the same code as the [arrow search](super): values without nulls are
+//! searched at once in their contiguous buffer, and large ones may be
+//! [offloaded](crate::kernel).
+
+use super::{match_column, match_finder, Needle};
+use crate::str::IgnoreAsciiCase;
+use arrow::buffer::MutableBuffer;
+use arrow_array::{
+    types::ByteArrayType, Array, BooleanArray, GenericBinaryArray, GenericByteArray,
+    GenericStringArray, OffsetSizeTrait,
+};
+use memchr::memmem::Finder;
+
+/// Returns which values of the text `array` contain the `needle`.  An empty
+/// needle is contained in every value.
+pub fn match_utf8<O: OffsetSizeTrait>(array: &GenericStringArray<O>, needle: &str) -> BooleanArray {
+    mask(array, &Needle::Exact(needle.as_bytes()))
+}
+
+/// Like [`match_utf8`], but with the needle of the `finder`, so that callers
+/// matching many arrays compile it once.
+pub fn match_utf8_with<O: OffsetSizeTrait>(
+    array: &GenericStringArray<O>,
+    finder: &Finder,
+) -> BooleanArray {
+    mask_with(array, finder)
+}
+
+/// Like [`match_utf8`], but ignores the case of ASCII letters.
+pub fn match_utf8_ignore_ascii_case<O: OffsetSizeTrait>(
+    array: &GenericStringArray<O>,
+    needle: &str,
+) -> BooleanArray {
+    mask(
+        array,
+        &Needle::IgnoreAsciiCase(IgnoreAsciiCase::new(needle.as_bytes())),
+    )
+}
+
+/// Like [`match_utf8`], but for binary values.
+pub fn match_binary<O: OffsetSizeTrait>(
+    array: &GenericBinaryArray<O>,
+    needle: &[u8],
+) -> BooleanArray {
+    mask(array, &Needle::Exact(needle))
+}
+
+/// Like [`match_utf8_with`], but for binary values.
+pub fn match_binary_with<O: OffsetSizeTrait>(
+    array: &GenericBinaryArray<O>,
+    finder: &Finder,
+) -> BooleanArray {
+    mask_with(array, finder)
+}
+
+/// Returns the `array` with the ASCII letters of its values lowercased, as a
+/// preprocessing step for repeated searches without case, e.g. by
+/// [`CachedBatches`](super::CachedBatches).  The values buffer is rewritten
+/// in one pass, eight bytes at a time, in a loop that compilers vectorize;
+/// the offsets and nulls are shared.
+pub fn ascii_lowercase<T: ByteArrayType>(array: &GenericByteArray<T>) -> GenericByteArray<T> {
+    let data = array.data();
+    let values = data.buffers()[1].as_slice();
+    let mut lowercase = MutableBuffer::new(values.len());
+    let words = values.chunks_exact(8);
+    let rest = words.remainder();
+    for word in words {
+        let word = u64::from_le_bytes(word.try_into().expect("8 bytes"));
+        lowercase.extend_from_slice(&lowercase_word(word).to_le_bytes());
+    }
+    lowercase.extend_from_slice(&rest.to_ascii_lowercase());
+    let data = data
+        .clone()
+        .into_builder()
+        .buffers(vec![data.buffers()[0].clone(), lowercase.into()]);
+    // SAFETY: only the bytes of ASCII letters change, and into other ASCII
+    // letters, so the values keep their lengths and stay valid UTF-8.
+    GenericByteArray::from(unsafe { data.build_unchecked() })
+}
+
+/// Lowercases the ASCII letters among the eight bytes of `word`.
+fn lowercase_word(word: u64) -> u64 {
+    const LOW_BITS: u64 = 0x7f7f_7f7f_7f7f_7f7f;
+    const HIGH_BITS: u64 = 0x8080_8080_8080_8080;
+    const fn splat(b: u8) -> u64 {
+        b as u64 * 0x0101_0101_0101_0101
+    }
+    // The low seven bits of each byte plus an amount that sets its high bit
+    // if it is at least `A`, respectively greater than `Z`, without carrying
+    // into the next byte.
+    let low = word & LOW_BITS;
+    let from_a = low + splat(0x80 - b'A');
+    let past_z = low + splat(0x80 - b'Z' - 1);
+    // Non-ASCII bytes have the high bit set themselves.
+    let upper = from_a & !past_z & !word & HIGH_BITS;
+    // The high bit moved to the case bit, 0x20.
+    word | (upper >> 2)
+}
+
+fn mask<T: ByteArrayType>(array: &GenericByteArray<T>, needle: &Needle) -> BooleanArray {
+    match needle {
+        Needle::Exact(needle) => mask_with(array, &Finder::new(needle)),
+        _ => {
+            let mut matched = vec![false; array.len()];
+            match_column(array, needle, &mut matched);
+            with_nulls(array, matched)
+        }
+    }
+}
+
+fn mask_with<T: ByteArrayType>(array: &GenericByteArray<T>, finder: &Finder) -> BooleanArray {
+    let mut matched = vec![false; array.len()];
+    match finder.needle() {
+        // The searches rely on needles not being empty.
+        [] => matched.fill(true),
+        _ => {
+            match_finder(array, finder, &mut matched);
+        }
+    }
+    with_nulls(array, matched)
+}
+
+fn with_nulls<T: ByteArrayType>(array: &GenericByteArray<T>, matched: Vec<bool>) -> BooleanArray {
+    matched
+        .into_iter()
+        .enumerate()
+        .map(|(row, m)| array.is_valid(row).then_some(m))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{BinaryArray, LargeStringArray, StringArray};
+
+    #[test]
+    fn test_ascii_lowercase() {
+        let all_bytes: Vec<u8> = (0..=255).collect();
+        let array = BinaryArray::from(vec![&all_bytes[..], b"", b"MiXeD CaSe @[`{", b"ABC"]);
+        let lowercase = ascii_lowercase(&array);
+        for (value, lowercase) in array.iter().zip(lowercase.iter()) {
+            assert_eq!(lowercase.unwrap(), value.unwrap().to_ascii_lowercase());
+        }
+
+        let array = StringArray::from(vec![Some("Été K8S"), None, Some("ÄÖÜ pod"), Some("NODE")]);
+        let sliced = array.slice(1, 3);
+        let sliced = sliced.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(
+            ascii_lowercase(sliced),
+            StringArray::from(vec![None, Some("ÄÖÜ pod"), Some("node")])
+        );
+        assert_eq!(ascii_lowercase(&array).value(0), "Été k8s");
+    }
+
+    #[test]
+    fn test_match_utf8() {
+        let array = StringArray::from(vec![Some("k8s pod"), None, Some("node"), Some("K8S")]);
+        assert_eq!(
+            match_utf8(&array, "k8s"),
+            BooleanArray::from(vec![Some(true), None, Some(false), Some(false)])
+        );
+        assert_eq!(
+            match_utf8_ignore_ascii_case(&array, "k8s"),
+            BooleanArray::from(vec![Some(true), None, Some(false), Some(true)])
+        );
+        assert_eq!(
+            match_utf8(&array, ""),
+            BooleanArray::from(vec![Some(true), None, Some(true), Some(true)])
+        );
+
+        let array = LargeStringArray::from(vec!["node", "k8s", "pod"]);
+        let sliced = array.slice(1, 2);
+        let sliced = sliced.as_any().downcast_ref::<LargeStringArray>().unwrap();
+        assert_eq!(
+            match_utf8(sliced, "k8s"),
+            BooleanArray::from(vec![true, false])
+        );
+
+        let array = BinaryArray::from(vec![Some(&b"\xffk8s"[..]), None, Some(b"node")]);
+        assert_eq!(
+            match_binary(&array, b"k8s"),
+            BooleanArray::from(vec![Some(true), None, Some(false)])
+        );
+        assert_eq!(
+            match_binary_with(&array, &Finder::new("k8s")),
+            match_binary(&array, b"k8s")
+        );
+    }
+}
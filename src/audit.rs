@@ -0,0 +1,214 @@
+//! Audit log of searches
+//!
+//! An [`AuditRecord`] tells who ran which query, over which files, how many
+//! bytes the scan read, and how many rows it returned.  An [`AuditLog`]
+//! appends them to an ND-JSON file, one line per record written in a single
+//! call, and never rewrites it; [`AuditLog::write_parquet`] converts the log
+//! with the [`writer`](crate::writer) for archiving and searching it like any
+//! other log.
+//!
+//! Once a log is installed with [`set_audit_log`], the Flight server records
+//! every search, taking the principal from the `x-zn-principal` request
+//! header; other callers record theirs with [`record`].
+
+use crate::{
+    writer::{write_ndjson, WriterOptions},
+    ZnError, ZnResult,
+};
+use serde_json::{json, Value};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A search, as recorded in an [`AuditLog`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// When the search started, in microseconds since the Unix epoch.
+    pub time: i64,
+    /// Who ran the search.
+    pub principal: String,
+    /// The query, e.g. a needle or a serialized search request.
+    pub query: String,
+    /// The files searched.
+    pub files: Vec<String>,
+    /// Size of the files searched in bytes.
+    pub bytes_scanned: u64,
+    pub rows_returned: u64,
+}
+
+impl AuditRecord {
+    /// Returns the record of a search starting now, which has scanned and
+    /// returned nothing yet.
+    pub fn new(principal: impl Into<String>, query: impl Into<String>) -> Self {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as i64);
+        Self {
+            time,
+            principal: principal.into(),
+            query: query.into(),
+            files: Vec::new(),
+            bytes_scanned: 0,
+            rows_returned: 0,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "_timestamp": self.time,
+            "principal": self.principal,
+            "query": self.query,
+            "files": self.files,
+            "bytes_scanned": self.bytes_scanned,
+            "rows_returned": self.rows_returned,
+        })
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        Some(Self {
+            time: value.get("_timestamp")?.as_i64()?,
+            principal: value.get("principal")?.as_str()?.to_owned(),
+            query: value.get("query")?.as_str()?.to_owned(),
+            files: value
+                .get("files")?
+                .as_array()?
+                .iter()
+                .map(|f| f.as_str().map(str::to_owned))
+                .collect::<Option<_>>()?,
+            bytes_scanned: value.get("bytes_scanned")?.as_u64()?,
+            rows_returned: value.get("rows_returned")?.as_u64()?,
+        })
+    }
+}
+
+/// An append-only ND-JSON file of [`AuditRecord`]s.
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    /// Opens the log at `path` for appending, creating it if needed.
+    pub fn open(path: impl Into<PathBuf>) -> ZnResult<Self> {
+        let path = path.into();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends the `record` and syncs it to disk.
+    pub fn append(&self, record: &AuditRecord) -> ZnResult<()> {
+        let mut line = record.to_json().to_string();
+        line.push('\n');
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    /// Reads the records of the log, oldest first.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::InvalidMetadata`] if a line is not an audit record.
+    pub fn records(&self) -> ZnResult<Vec<AuditRecord>> {
+        let mut records = Vec::new();
+        for (i, line) in BufReader::new(File::open(&self.path)?).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = serde_json::from_str(&line)
+                .ok()
+                .and_then(|value| AuditRecord::from_json(&value))
+                .ok_or_else(|| {
+                    ZnError::invalid_metadata(format!("line {} is not an audit record", i + 1))
+                })?;
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    /// Writes the records of the log to `output` as a parquet file sorted by
+    /// time, with the principals dictionary-encoded.  Returns the number of
+    /// records written.
+    pub fn write_parquet<W: Write + Send>(&self, output: W) -> ZnResult<usize> {
+        let options = WriterOptions {
+            label_columns: vec!["principal".to_owned()],
+            ..WriterOptions::default()
+        };
+        write_ndjson(BufReader::new(File::open(&self.path)?), output, &options)
+    }
+}
+
+static AUDIT_LOG: RwLock<Option<Arc<AuditLog>>> = RwLock::new(None);
+
+/// Installs a process-wide audit log, replacing the previously installed one.
+pub fn set_audit_log(log: Arc<AuditLog>) {
+    *AUDIT_LOG.write().unwrap_or_else(|e| e.into_inner()) = Some(log);
+}
+
+/// Removes the log installed with [`set_audit_log`].
+pub fn clear_audit_log() {
+    *AUDIT_LOG.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Returns the installed audit log, if any.
+pub fn audit_log() -> Option<Arc<AuditLog>> {
+    AUDIT_LOG.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Appends the `record` to the installed audit log, if any.
+pub fn record(record: &AuditRecord) -> ZnResult<()> {
+    match audit_log() {
+        Some(log) => log.append(record),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::file::{reader::FileReader, serialized_reader::SerializedFileReader};
+
+    #[test]
+    fn test_audit_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = AuditLog::open(dir.path().join("audit.ndjson")).unwrap();
+        let mut first = AuditRecord::new("alice", "error");
+        first.files = vec!["a.parquet".to_owned(), "b.parquet".to_owned()];
+        first.bytes_scanned = 4096;
+        first.rows_returned = 3;
+        let second = AuditRecord {
+            time: first.time + 1,
+            files: vec!["c.parquet".to_owned()],
+            ..AuditRecord::new("bob", "k8s")
+        };
+        log.append(&first).unwrap();
+        log.append(&second).unwrap();
+
+        // Reopening appends to the existing records.
+        let log = AuditLog::open(log.path()).unwrap();
+        log.append(&first).unwrap();
+        assert_eq!(log.records().unwrap(), [first.clone(), second, first]);
+
+        let mut parquet = Vec::new();
+        assert_eq!(log.write_parquet(&mut parquet).unwrap(), 3);
+        let reader = SerializedFileReader::new(bytes::Bytes::from(parquet)).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 3);
+
+        std::fs::write(log.path(), "{\"principal\": 1}\n").unwrap();
+        assert!(matches!(log.records(), Err(ZnError::InvalidMetadata(_))));
+    }
+}
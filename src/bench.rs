@@ -0,0 +1,373 @@
+//! Benchmark harness
+//!
+//! Compares the search paths on a parquet file of one's own, without
+//! criterion:
+//!
+//! ```no_run
+//! use zn_perf::bench::{BenchMatrix, BenchSuite};
+//!
+//! let results = BenchSuite::new("logs.parquet")?.run(&BenchMatrix::default())?;
+//! for result in results {
+//!     println!("{}: {:.1} MiB/s", result.label(), result.throughput() / (1 << 20) as f64);
+//! }
+//! # Ok::<(), zn_perf::ZnError>(())
+//! ```
+//!
+//! Files are loaded into memory once, so disk reads don't take part in the
+//! measurements, except for DataFusion, which reads the file itself.
+
+use crate::{file::byte_array_columns_uncompressed_size, ZnError, ZnResult};
+use bytes::Bytes;
+use datafusion::prelude::SessionContext;
+use futures::StreamExt;
+use parquet::{
+    arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder},
+    file::{reader::FileReader, serialized_reader::SerializedFileReader},
+};
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+/// How the DataFusion path matches the needle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SqlOp {
+    /// `column like '%needle%'`
+    Like,
+    /// `strpos(column, 'needle') > 0`
+    Strpos,
+    /// [`str_match(column, 'needle')`](crate::match_udf)
+    StrMatch,
+    /// [`match_all_columns('needle', column, ...)`](crate::match_udf), a
+    /// single call for all columns.
+    MatchAllColumns,
+}
+
+impl SqlOp {
+    /// Name of the operator or function.
+    pub fn name(self) -> &'static str {
+        match self {
+            SqlOp::Like => "like",
+            SqlOp::Strpos => "strpos",
+            SqlOp::StrMatch => "str_match",
+            SqlOp::MatchAllColumns => "match_all_columns",
+        }
+    }
+}
+
+/// A search path to measure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BenchPath {
+    /// [`crate::file::count_occurrences`]
+    File,
+    /// [`crate::arrow::count_occurrences`]
+    Arrow,
+    /// A SQL query selecting the rows matching in some text column.
+    DataFusion(SqlOp),
+}
+
+impl BenchPath {
+    /// Whether the batch size affects the path.
+    fn batched(self) -> bool {
+        !matches!(self, BenchPath::File)
+    }
+}
+
+/// The combinations of path and batch size to measure.
+#[derive(Debug, Clone)]
+pub struct BenchMatrix {
+    pub paths: Vec<BenchPath>,
+    /// Ignored by [`BenchPath::File`], which is measured once.
+    pub batch_sizes: Vec<usize>,
+    pub needle: String,
+    /// Runs per combination; the mean is reported.
+    pub iterations: usize,
+}
+
+impl Default for BenchMatrix {
+    fn default() -> Self {
+        Self {
+            paths: vec![
+                BenchPath::File,
+                BenchPath::Arrow,
+                BenchPath::DataFusion(SqlOp::Like),
+                BenchPath::DataFusion(SqlOp::Strpos),
+                BenchPath::DataFusion(SqlOp::StrMatch),
+            ],
+            batch_sizes: vec![1024, 4096, 8192],
+            needle: "k8s".to_owned(),
+            iterations: 3,
+        }
+    }
+}
+
+/// Measurement of one combination of a [`BenchMatrix`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchResult {
+    pub path: BenchPath,
+    /// `None` for [`BenchPath::File`].
+    pub batch_size: Option<usize>,
+    /// Bytes the path searches through per run, the basis of the throughput.
+    pub bytes: u64,
+    /// Matching cells, or rows for [`BenchPath::DataFusion`].
+    pub matches: usize,
+    pub mean: Duration,
+}
+
+impl BenchResult {
+    /// Searched bytes per second.
+    pub fn throughput(&self) -> f64 {
+        self.bytes as f64 / self.mean.as_secs_f64()
+    }
+
+    /// Names the combination, e.g.
+    /// `datafusion/4096/like`.
+    pub fn label(&self) -> String {
+        let batch_size = self.batch_size.map(|n| format!("/{n}")).unwrap_or_default();
+        match self.path {
+            BenchPath::File => "file".to_owned(),
+            BenchPath::Arrow => format!("arrow{batch_size}"),
+            BenchPath::DataFusion(op) => format!("datafusion{batch_size}/{}", op.name()),
+        }
+    }
+}
+
+/// A parquet file to benchmark the search paths on.
+pub struct BenchSuite {
+    path: PathBuf,
+    data: Bytes,
+}
+
+impl BenchSuite {
+    /// Loads the parquet file at `path` into memory.
+    pub fn new(path: impl Into<PathBuf>) -> ZnResult<Self> {
+        let path = path.into();
+        let data = std::fs::read(&path)?.into();
+        Ok(Self { path, data })
+    }
+
+    /// Measures every combination of the `matrix`, in order.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::InvalidArgument`] if the `matrix` has no
+    /// iterations or a zero batch size, and [`ZnError::EmptyNeedle`] if its
+    /// needle is empty.
+    pub fn run(&self, matrix: &BenchMatrix) -> ZnResult<Vec<BenchResult>> {
+        if matrix.iterations == 0 {
+            return Err(ZnError::invalid_argument("no iterations"));
+        }
+        if matrix.batch_sizes.contains(&0) {
+            return Err(ZnError::invalid_argument("batch size of 0"));
+        }
+        if matrix.needle.is_empty() {
+            return Err(ZnError::empty_needle());
+        }
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+        let mut results = Vec::new();
+        for &path in &matrix.paths {
+            let batch_sizes = if path.batched() {
+                matrix.batch_sizes.iter().copied().map(Some).collect()
+            } else {
+                vec![None]
+            };
+            for batch_size in batch_sizes {
+                let (bytes, matches, mean) = match path {
+                    BenchPath::File => self.bench_file(matrix)?,
+                    BenchPath::Arrow => self.bench_arrow(matrix, batch_size.unwrap_or(1024))?,
+                    BenchPath::DataFusion(op) => runtime.block_on(self.bench_datafusion(
+                        matrix,
+                        batch_size.unwrap_or(1024),
+                        op,
+                    ))?,
+                };
+                results.push(BenchResult {
+                    path,
+                    batch_size,
+                    bytes,
+                    matches,
+                    mean,
+                });
+            }
+        }
+        Ok(results)
+    }
+
+    fn bench_file(&self, matrix: &BenchMatrix) -> ZnResult<(u64, usize, Duration)> {
+        let reader = SerializedFileReader::new(self.data.clone())?;
+        let bytes = byte_array_columns_uncompressed_size(reader.metadata())?;
+        let (matches, mean) = measure(matrix.iterations, || {
+            crate::file::count_occurrences(&reader, matrix.needle.as_bytes())
+        })?;
+        Ok((bytes, matches, mean))
+    }
+
+    fn bench_arrow(
+        &self,
+        matrix: &BenchMatrix,
+        batch_size: usize,
+    ) -> ZnResult<(u64, usize, Duration)> {
+        let mut bytes = 0;
+        for batch in self.arrow_reader(batch_size)? {
+            bytes += batch?.get_array_memory_size() as u64;
+        }
+        let (matches, mean) = measure(matrix.iterations, || {
+            crate::arrow::count_occurrences(self.arrow_reader(batch_size)?, &matrix.needle)
+        })?;
+        Ok((bytes, matches, mean))
+    }
+
+    fn arrow_reader(&self, batch_size: usize) -> ZnResult<ParquetRecordBatchReader> {
+        Ok(ParquetRecordBatchReaderBuilder::try_new(self.data.clone())?
+            .with_batch_size(batch_size)
+            .build()?)
+    }
+
+    async fn bench_datafusion(
+        &self,
+        matrix: &BenchMatrix,
+        batch_size: usize,
+        op: SqlOp,
+    ) -> ZnResult<(u64, usize, Duration)> {
+        let mut bytes = 0;
+        let mut columns = Vec::new();
+        for (name, size) in crate::metadata::text_columns(&self.data)? {
+            // `SessionContext::sql()` takes "@timestamp" for a variable.
+            if name != "@timestamp" {
+                bytes += size;
+                columns.push(name);
+            }
+        }
+        let sql = search_sql("tbl", &columns, op, &matrix.needle);
+
+        let mut matches = 0;
+        let mut total = Duration::ZERO;
+        for _ in 0..matrix.iterations {
+            let start = Instant::now();
+            let ctx = self.session_context(batch_size).await?;
+            let mut stream = ctx.sql(&sql).await?.execute_stream().await?;
+            matches = 0;
+            while let Some(batch) = stream.next().await {
+                matches += batch?.num_rows();
+            }
+            total += start.elapsed();
+        }
+        Ok((bytes, matches, total / matrix.iterations as u32))
+    }
+
+    async fn session_context(&self, batch_size: usize) -> ZnResult<SessionContext> {
+        let ctx = crate::datafusion::new_session_context(batch_size, false);
+        let path = self.path.to_string_lossy();
+        ctx.register_parquet("tbl", &path, Default::default())
+            .await?;
+        Ok(ctx)
+    }
+}
+
+/// Returns a query selecting the rows of `table` containing `needle` in some
+/// of the `columns`.
+pub fn search_sql(table: &str, columns: &[String], op: SqlOp, needle: &str) -> String {
+    let needle = needle.replace('\'', "''");
+    if op == SqlOp::MatchAllColumns {
+        let columns: Vec<_> = columns
+            .iter()
+            .map(|column| format!(", \"{}\"", column.replace('"', "\"\"")))
+            .collect();
+        let where_clause = match columns.is_empty() {
+            true => "false".to_owned(),
+            false => format!("match_all_columns('{needle}'{})", columns.concat()),
+        };
+        return format!("select * from {table} where {where_clause}");
+    }
+    let predicates: Vec<_> = columns
+        .iter()
+        .map(|column| {
+            let column = column.replace('"', "\"\"");
+            match op {
+                SqlOp::Like => format!("\"{column}\" like '%{needle}%'"),
+                SqlOp::Strpos => format!("strpos(\"{column}\", '{needle}') > 0"),
+                SqlOp::StrMatch => format!("str_match(\"{column}\", '{needle}')"),
+                SqlOp::MatchAllColumns => unreachable!("a single predicate"),
+            }
+        })
+        .collect();
+    let where_clause = if predicates.is_empty() {
+        "false".to_owned()
+    } else {
+        predicates.join(" or ")
+    };
+    format!("select * from {table} where {where_clause}")
+}
+
+/// Runs `f` `iterations` times and returns its last result and the mean
+/// duration of a run.
+fn measure(
+    iterations: usize,
+    mut f: impl FnMut() -> ZnResult<usize>,
+) -> ZnResult<(usize, Duration)> {
+    let mut result = 0;
+    let start = Instant::now();
+    for _ in 0..iterations {
+        result = f()?;
+    }
+    Ok((result, start.elapsed() / iterations as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::parquet_bytes;
+
+    #[test]
+    fn test_bench_suite() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("logs.parquet");
+        std::fs::write(&path, parquet_bytes(&["k8s pod", "GET /", "k8s node"], 2)).unwrap();
+
+        let matrix = BenchMatrix {
+            batch_sizes: vec![1, 2],
+            iterations: 2,
+            ..BenchMatrix::default()
+        };
+        let results = BenchSuite::new(&path).unwrap().run(&matrix).unwrap();
+        let labels: Vec<_> = results.iter().map(|r| r.label()).collect();
+        assert_eq!(
+            labels,
+            [
+                "file",
+                "arrow/1",
+                "arrow/2",
+                "datafusion/1/like",
+                "datafusion/2/like",
+                "datafusion/1/strpos",
+                "datafusion/2/strpos",
+                "datafusion/1/str_match",
+                "datafusion/2/str_match",
+            ]
+        );
+        for result in &results {
+            assert_eq!(result.matches, 2, "{}", result.label());
+            assert!(result.bytes > 0);
+        }
+
+        assert_eq!(
+            search_sql("t", &["it's".to_owned()], SqlOp::Like, "o'k"),
+            r#"select * from t where "it's" like '%o''k%'"#
+        );
+        assert_eq!(
+            search_sql(
+                "t",
+                &["log".to_owned(), "host".to_owned()],
+                SqlOp::MatchAllColumns,
+                "k8s"
+            ),
+            r#"select * from t where match_all_columns('k8s', "log", "host")"#
+        );
+        let matrix = BenchMatrix {
+            iterations: 0,
+            ..BenchMatrix::default()
+        };
+        assert!(BenchSuite::new(&path).unwrap().run(&matrix).is_err());
+    }
+}
@@ -0,0 +1,321 @@
+//! N-gram bloom filters for file and row group skipping
+//!
+//! An [`NgramBloom`] holds one bloom filter over the byte n-grams of the text
+//! columns of a whole parquet file and one per row group.  A needle can only
+//! occur where all of its n-grams are present, so a negative answer from the
+//! file filter turns a search into a metadata-only operation, and the row
+//! group filters let [`count_occurrences_with_index`] skip row groups.
+//!
+//! Unlike a [`TrigramIndex`], the filters have a fixed size regardless of the
+//! variety of the text, at the cost of false positives.  They are meant to be
+//! computed once, when a file is written or compacted, and persisted next to
+//! it; see [`NgramBloom::sidecar_path`].
+//!
+//! [`count_occurrences_with_index`]: crate::file::count_occurrences_with_index
+//! [`TrigramIndex`]: crate::index::TrigramIndex
+
+use crate::{
+    codec::{read_usize, read_varint, write_varint},
+    file::{byte_array_columns, byte_array_value},
+    index::RowGroupPruner,
+    ZnError, ZnResult,
+};
+use parquet::file::reader::FileReader;
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+const MAGIC: &[u8; 4] = b"ZNBL";
+const VERSION: u8 = 1;
+
+/// A bloom filter over 64-bit hashes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    words: Vec<u64>,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    /// Creates an empty filter sized for `num_items` items at the given
+    /// false positive rate.
+    pub fn with_capacity(num_items: usize, false_positive_rate: f64) -> Self {
+        let num_items = num_items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-num_items * false_positive_rate.ln() / (ln2 * ln2)).ceil();
+        let num_words = ((num_bits / 64.0).ceil() as usize).max(1);
+        let num_hashes = ((num_words * 64) as f64 / num_items * ln2).round();
+        Self {
+            words: vec![0; num_words],
+            num_hashes: (num_hashes as u32).clamp(1, 16),
+        }
+    }
+
+    /// Bit positions of an item, computed with double hashing.
+    fn positions(&self, hash: u64) -> impl Iterator<Item = u64> {
+        let h2 = splitmix64(hash) | 1;
+        let num_bits = self.words.len() as u64 * 64;
+        (0..u64::from(self.num_hashes))
+            .map(move |i| hash.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+
+    pub fn insert_hash(&mut self, hash: u64) {
+        let positions = self.positions(hash);
+        for bit in positions {
+            self.words[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Returns `false` if the item with the `hash` was definitely not
+    /// inserted.
+    pub fn contains_hash(&self, hash: u64) -> bool {
+        self.positions(hash)
+            .all(|bit| self.words[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    fn write_to<W: Write>(&self, w: &mut W) -> ZnResult<()> {
+        write_varint(w, u64::from(self.num_hashes))?;
+        write_varint(w, self.words.len() as u64)?;
+        for word in &self.words {
+            w.write_all(&word.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn read_from<R: Read>(r: &mut R) -> ZnResult<Self> {
+        let num_hashes = read_varint(r)?;
+        if !(1..=16).contains(&num_hashes) {
+            return Err(ZnError::invalid_index(format!(
+                "invalid number of hashes: {num_hashes}"
+            )));
+        }
+        let num_words = read_usize(r)?;
+        if num_words == 0 {
+            return Err(ZnError::invalid_index("empty bloom filter"));
+        }
+        let mut words = Vec::with_capacity(num_words.min(1 << 20));
+        for _ in 0..num_words {
+            let mut word = [0; 8];
+            r.read_exact(&mut word)?;
+            words.push(u64::from_le_bytes(word));
+        }
+        Ok(Self {
+            words,
+            num_hashes: num_hashes as u32,
+        })
+    }
+}
+
+/// Bloom filters over the byte n-grams of a parquet file and of each of its
+/// row groups.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NgramBloom {
+    n: usize,
+    file: BloomFilter,
+    row_groups: Vec<BloomFilter>,
+}
+
+impl NgramBloom {
+    /// Length of the n-grams used by [`build_default`](Self::build_default).
+    pub const DEFAULT_N: usize = 3;
+
+    /// False positive rate used by [`build_default`](Self::build_default).
+    pub const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+    /// Builds the filters with [`DEFAULT_N`](Self::DEFAULT_N) and
+    /// [`DEFAULT_FALSE_POSITIVE_RATE`](Self::DEFAULT_FALSE_POSITIVE_RATE).
+    pub fn build_default<R: FileReader>(reader: &R) -> ZnResult<Self> {
+        Self::build(reader, Self::DEFAULT_N, Self::DEFAULT_FALSE_POSITIVE_RATE)
+    }
+
+    /// Builds the filters from the n-grams of length `n` of the [byte array]
+    /// columns.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::InvalidArgument`] if `n` is zero or the
+    /// `false_positive_rate` is not between 0 and 1.
+    ///
+    /// [byte array]: crate::file::byte_array_columns_uncompressed_size
+    pub fn build<R: FileReader>(reader: &R, n: usize, false_positive_rate: f64) -> ZnResult<Self> {
+        if n == 0 {
+            return Err(ZnError::invalid_argument("n-gram length must be positive"));
+        }
+        if !(false_positive_rate > 0.0 && false_positive_rate < 1.0) {
+            return Err(ZnError::invalid_argument(format!(
+                "false positive rate must be between 0 and 1, got {false_positive_rate}"
+            )));
+        }
+
+        let projection = byte_array_columns(reader.metadata())?;
+        let mut file_hashes = HashSet::new();
+        let mut row_groups = Vec::with_capacity(reader.num_row_groups());
+        for i in 0..reader.num_row_groups() {
+            let mut hashes = HashSet::new();
+            let row_group = reader.get_row_group(i)?;
+            for row in row_group.get_row_iter(Some(projection.clone()))? {
+                for (column_name, value) in row.get_column_iter() {
+                    if let Some(s) = byte_array_value(column_name, value)? {
+                        hashes.extend(s.windows(n).map(fnv1a));
+                    }
+                }
+            }
+            row_groups.push(filter_of(&hashes, false_positive_rate));
+            file_hashes.extend(hashes);
+        }
+        Ok(Self {
+            n,
+            file: filter_of(&file_hashes, false_positive_rate),
+            row_groups,
+        })
+    }
+
+    /// Returns `false` if the `needle` definitely does not occur in the file.
+    ///
+    /// Needles shorter than the n-gram length always may occur.
+    pub fn may_contain(&self, needle: &[u8]) -> bool {
+        needle
+            .windows(self.n)
+            .all(|w| self.file.contains_hash(fnv1a(w)))
+    }
+
+    /// Returns the conventional location of the filters of the parquet file at
+    /// `parquet_path`: the same path with `.bloom` appended.
+    pub fn sidecar_path(parquet_path: impl AsRef<Path>) -> PathBuf {
+        let mut path = parquet_path.as_ref().as_os_str().to_owned();
+        path.push(".bloom");
+        path.into()
+    }
+
+    /// Writes the filters to the file at `path`, replacing it if it exists.
+    pub fn save(&self, path: impl AsRef<Path>) -> ZnResult<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_to(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Reads filters previously written with [`save`](Self::save).
+    pub fn load(path: impl AsRef<Path>) -> ZnResult<Self> {
+        Self::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// Serializes the filters.
+    ///
+    /// The format is the magic `ZNBL`, a version byte, and then, as LEB128
+    /// varints, the n-gram length and the number of row groups, followed by
+    /// the file filter and the row group filters.  Every filter is written as
+    /// varints of its number of hash functions and 64-bit words, and then the
+    /// words in little endian.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> ZnResult<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&[VERSION])?;
+        write_varint(w, self.n as u64)?;
+        write_varint(w, self.row_groups.len() as u64)?;
+        self.file.write_to(w)?;
+        for filter in &self.row_groups {
+            filter.write_to(w)?;
+        }
+        Ok(())
+    }
+
+    /// Deserializes filters written with [`write_to`](Self::write_to).
+    pub fn read_from<R: Read>(r: &mut R) -> ZnResult<Self> {
+        let mut header = [0; 5];
+        r.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(ZnError::invalid_index("not an n-gram bloom filter"));
+        }
+        if header[4] != VERSION {
+            return Err(ZnError::invalid_index(format!(
+                "unsupported version {}",
+                header[4]
+            )));
+        }
+
+        let n = read_usize(r)?;
+        if n == 0 {
+            return Err(ZnError::invalid_index("n-gram length must be positive"));
+        }
+        let num_row_groups = read_usize(r)?;
+        let file = BloomFilter::read_from(r)?;
+        let row_groups = (0..num_row_groups)
+            .map(|_| BloomFilter::read_from(r))
+            .collect::<ZnResult<_>>()?;
+        Ok(Self {
+            n,
+            file,
+            row_groups,
+        })
+    }
+}
+
+impl RowGroupPruner for NgramBloom {
+    fn num_row_groups(&self) -> usize {
+        self.row_groups.len()
+    }
+
+    fn candidates(&self, needle: &[u8]) -> Vec<usize> {
+        if !self.may_contain(needle) {
+            return Vec::new();
+        }
+        let hashes: Vec<_> = needle.windows(self.n).map(fnv1a).collect();
+        self.row_groups
+            .iter()
+            .enumerate()
+            .filter(|(_, filter)| hashes.iter().all(|&h| filter.contains_hash(h)))
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+pub(crate) fn filter_of(hashes: &HashSet<u64>, false_positive_rate: f64) -> BloomFilter {
+    let mut filter = BloomFilter::with_capacity(hashes.len(), false_positive_rate);
+    for &h in hashes {
+        filter.insert_hash(h);
+    }
+    filter
+}
+
+/// 64-bit FNV-1a; unlike `std`'s hashers its output is stable, which the
+/// persisted filters depend on.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| {
+        (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::parquet_file;
+
+    #[test]
+    fn test_ngram_bloom() {
+        let file = parquet_file(&["GET /index.html", "k8s pod", "POST /api", "k8s node"], 1);
+        let bloom = NgramBloom::build_default(&file).unwrap();
+        assert_eq!(bloom.num_row_groups(), 4);
+        assert!(bloom.may_contain(b"k8s"));
+        assert!(!bloom.may_contain(b"us-west-2"));
+        // bloom filters have no false negatives
+        assert!(bloom.candidates(b"k8s").contains(&1));
+        assert!(bloom.candidates(b"k8s").contains(&3));
+        assert!(bloom.candidates(b"/api").contains(&2));
+        assert!(bloom.candidates(b"us-west-2").is_empty());
+
+        let mut buf = Vec::new();
+        bloom.write_to(&mut buf).unwrap();
+        assert_eq!(NgramBloom::read_from(&mut buf.as_slice()).unwrap(), bloom);
+
+        assert!(NgramBloom::build(&file, 0, 0.01).is_err());
+    }
+}
@@ -0,0 +1,409 @@
+//! Cache of decompressed column chunks
+//!
+//! Searching the same hot files over and over spends most of its time
+//! decompressing the same pages again.  A [`ChunkCache`] keeps the
+//! decompressed pages of column chunks, keyed by file, row group, and column,
+//! and evicts the least recently used chunks once its size bound is exceeded.
+//!
+//! [`CachedFileReader`] puts the cache in front of any [`FileReader`], so it
+//! serves both the [`file`](crate::file) scan and, through
+//! [`CachedParquetTable`](crate::datafusion::CachedParquetTable), DataFusion.
+
+use crate::metrics::{registry, Cache};
+use parquet::{
+    bloom_filter::Sbbf,
+    column::page::{Page, PageMetadata, PageReader},
+    errors::Result as ParquetResult,
+    file::{
+        metadata::{ParquetMetaData, RowGroupMetaData},
+        reader::{FileReader, RowGroupReader},
+    },
+    record::reader::RowIter,
+    schema::types::Type as SchemaType,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+/// Identifies a column chunk: file, row group index, and column index.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ChunkKey {
+    pub file: Arc<str>,
+    pub row_group: usize,
+    pub column: usize,
+}
+
+/// Counters of a [`ChunkCache`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// Number of cached column chunks.
+    pub entries: usize,
+    /// Total size of the cached pages in bytes.
+    pub bytes: usize,
+}
+
+struct Entry {
+    pages: Arc<Vec<Page>>,
+    size: usize,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<ChunkKey, Entry>,
+    /// `last_used` tick → key, oldest first.
+    lru: BTreeMap<u64, ChunkKey>,
+    tick: u64,
+    stats: CacheStats,
+}
+
+/// Size-bounded LRU cache of decompressed column chunk pages.
+///
+/// The cache is meant to be shared (`Arc<ChunkCache>`) between all readers of
+/// a process.
+pub struct ChunkCache {
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+impl ChunkCache {
+    /// Creates a cache holding at most `capacity` bytes of pages.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::default(),
+        }
+    }
+
+    /// Maximum total size of the cached pages in bytes.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.lock().stats
+    }
+
+    /// Returns the pages of the chunk, marking it as recently used.
+    pub fn get(&self, key: &ChunkKey) -> Option<Arc<Vec<Page>>> {
+        let mut inner = self.lock();
+        inner.tick += 1;
+        let tick = inner.tick;
+        let Inner {
+            entries,
+            lru,
+            stats,
+            ..
+        } = &mut *inner;
+        match entries.get_mut(key) {
+            Some(entry) => {
+                stats.hits += 1;
+                registry().cache_hits(Cache::Chunk).inc_by(1);
+                lru.remove(&entry.last_used);
+                lru.insert(tick, key.clone());
+                entry.last_used = tick;
+                Some(entry.pages.clone())
+            }
+            None => {
+                stats.misses += 1;
+                registry().cache_misses(Cache::Chunk).inc_by(1);
+                None
+            }
+        }
+    }
+
+    /// Adds the pages of a chunk, evicting least recently used chunks as
+    /// needed.  Chunks larger than the whole cache are not cached.
+    pub fn insert(&self, key: ChunkKey, pages: Arc<Vec<Page>>) {
+        let size = pages.iter().map(|page| page.buffer().len()).sum();
+        if size > self.capacity {
+            return;
+        }
+
+        let mut inner = self.lock();
+        inner.tick += 1;
+        let tick = inner.tick;
+        let Inner {
+            entries,
+            lru,
+            stats,
+            ..
+        } = &mut *inner;
+        if let Some(old) = entries.remove(&key) {
+            lru.remove(&old.last_used);
+            stats.bytes -= old.size;
+        }
+        while stats.bytes + size > self.capacity {
+            let Some((_, victim)) = lru.pop_first() else {
+                break;
+            };
+            if let Some(old) = entries.remove(&victim) {
+                stats.bytes -= old.size;
+                stats.evictions += 1;
+            }
+        }
+        lru.insert(tick, key.clone());
+        entries.insert(
+            key,
+            Entry {
+                pages,
+                size,
+                last_used: tick,
+            },
+        );
+        stats.bytes += size;
+        stats.entries = entries.len();
+    }
+
+    /// Drops all cached chunks of `file`.
+    pub fn invalidate_file(&self, file: &str) {
+        let mut inner = self.lock();
+        let Inner {
+            entries,
+            lru,
+            stats,
+            ..
+        } = &mut *inner;
+        entries.retain(|key, entry| {
+            let keep = &*key.file != file;
+            if !keep {
+                lru.remove(&entry.last_used);
+                stats.bytes -= entry.size;
+            }
+            keep
+        });
+        stats.entries = entries.len();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        // The cache holds no invariants a panicking thread could break
+        // half-way, so a poisoned lock is still usable.
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A [`FileReader`] that serves column chunk pages from a [`ChunkCache`],
+/// reading and decompressing them with the wrapped reader on a miss.
+pub struct CachedFileReader<R> {
+    inner: R,
+    file: Arc<str>,
+    cache: Arc<ChunkCache>,
+}
+
+impl<R: FileReader> CachedFileReader<R> {
+    /// Wraps `inner`; `file` names the file in cache keys and must be unique
+    /// among the files sharing the `cache` (e.g. its path).
+    pub fn new(inner: R, file: impl Into<Arc<str>>, cache: Arc<ChunkCache>) -> Self {
+        Self {
+            inner,
+            file: file.into(),
+            cache,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: FileReader> FileReader for CachedFileReader<R> {
+    fn metadata(&self) -> &ParquetMetaData {
+        self.inner.metadata()
+    }
+
+    fn num_row_groups(&self) -> usize {
+        self.inner.num_row_groups()
+    }
+
+    fn get_row_group(&self, i: usize) -> ParquetResult<Box<dyn RowGroupReader + '_>> {
+        Ok(Box::new(CachedRowGroupReader {
+            inner: self.inner.get_row_group(i)?,
+            file: &self.file,
+            row_group: i,
+            cache: &self.cache,
+        }))
+    }
+
+    fn get_row_iter(&self, projection: Option<SchemaType>) -> ParquetResult<RowIter<'_>> {
+        RowIter::from_file(projection, self)
+    }
+}
+
+struct CachedRowGroupReader<'a> {
+    inner: Box<dyn RowGroupReader + 'a>,
+    file: &'a Arc<str>,
+    row_group: usize,
+    cache: &'a ChunkCache,
+}
+
+impl RowGroupReader for CachedRowGroupReader<'_> {
+    fn metadata(&self) -> &RowGroupMetaData {
+        self.inner.metadata()
+    }
+
+    fn num_columns(&self) -> usize {
+        self.inner.num_columns()
+    }
+
+    fn get_column_page_reader(&self, i: usize) -> ParquetResult<Box<dyn PageReader>> {
+        let key = ChunkKey {
+            file: self.file.clone(),
+            row_group: self.row_group,
+            column: i,
+        };
+        let pages = match self.cache.get(&key) {
+            Some(pages) => pages,
+            None => {
+                #[cfg(feature = "tracing")]
+                let _span = tracing::debug_span!(
+                    "decompress_chunk",
+                    file = &**self.file,
+                    row_group = self.row_group,
+                    column = i
+                )
+                .entered();
+                let pages = Arc::new(
+                    self.inner
+                        .get_column_page_reader(i)?
+                        .collect::<ParquetResult<Vec<_>>>()?,
+                );
+                self.cache.insert(key, pages.clone());
+                pages
+            }
+        };
+        Ok(Box::new(CachedPageReader { pages, next: 0 }))
+    }
+
+    fn get_column_bloom_filter(&self, i: usize) -> Option<&Sbbf> {
+        self.inner.get_column_bloom_filter(i)
+    }
+
+    fn get_row_iter(&self, projection: Option<SchemaType>) -> ParquetResult<RowIter<'_>> {
+        RowIter::from_row_group(projection, self)
+    }
+}
+
+/// Replays the cached pages of a column chunk.
+struct CachedPageReader {
+    pages: Arc<Vec<Page>>,
+    next: usize,
+}
+
+impl Iterator for CachedPageReader {
+    type Item = ParquetResult<Page>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.get_next_page().transpose()
+    }
+}
+
+impl PageReader for CachedPageReader {
+    fn get_next_page(&mut self) -> ParquetResult<Option<Page>> {
+        let page = self.pages.get(self.next).cloned();
+        self.next += page.is_some() as usize;
+        Ok(page)
+    }
+
+    fn peek_next_page(&mut self) -> ParquetResult<Option<PageMetadata>> {
+        // Mirrors `PageMetadata::try_from(&PageHeader)`.
+        Ok(self.pages.get(self.next).map(|page| match page {
+            Page::DataPage { num_values, .. } => PageMetadata {
+                num_rows: *num_values as usize,
+                is_dict: false,
+            },
+            Page::DataPageV2 { num_rows, .. } => PageMetadata {
+                num_rows: *num_rows as usize,
+                is_dict: false,
+            },
+            Page::DictionaryPage { .. } => PageMetadata {
+                num_rows: 0,
+                is_dict: true,
+            },
+        }))
+    }
+
+    fn skip_next_page(&mut self) -> ParquetResult<()> {
+        self.next = (self.next + 1).min(self.pages.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{datafusion::CachedParquetTable, test_util::parquet_file};
+
+    #[test]
+    fn test_cached_file_reader() {
+        let logs = ["GET /index.html", "k8s pod", "POST /api", "k8s node"];
+        let cache = Arc::new(ChunkCache::new(1 << 20));
+        let file = CachedFileReader::new(parquet_file(&logs, 2), "test", cache.clone());
+
+        assert_eq!(crate::file::count_occurrences(&file, b"k8s").unwrap(), 2);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (0, 2, 2));
+
+        assert_eq!(crate::file::count_occurrences(&file, b"k8s").unwrap(), 2);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (2, 2, 2));
+
+        cache.invalidate_file("test");
+        assert_eq!(cache.stats().entries, 0);
+        assert_eq!(cache.stats().bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn test_cached_parquet_table() {
+        let logs = ["GET /index.html", "k8s pod", "POST /api", "k8s node"];
+        let cache = Arc::new(ChunkCache::new(1 << 20));
+        let file = CachedFileReader::new(parquet_file(&logs, 2), "test", cache.clone());
+        assert_eq!(crate::file::count_occurrences(&file, b"k8s").unwrap(), 2);
+
+        let table = CachedParquetTable::try_new(Arc::new(file), 1024).unwrap();
+        let ctx = datafusion::prelude::SessionContext::new();
+        ctx.register_table("t", Arc::new(table)).unwrap();
+        let batches = ctx
+            .sql("select id from t where log like '%k8s%'")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+        // the `log` chunks were decompressed by the file scan already
+        assert_eq!(cache.stats().hits, 2);
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let file = parquet_file(&["a"; 8], 1);
+        let row_group = file.get_row_group(0).unwrap();
+        let pages = Arc::new(
+            row_group
+                .get_column_page_reader(0)
+                .unwrap()
+                .collect::<ParquetResult<Vec<_>>>()
+                .unwrap(),
+        );
+        let size: usize = pages.iter().map(|page| page.buffer().len()).sum();
+        let key = |row_group| ChunkKey {
+            file: "f".into(),
+            row_group,
+            column: 0,
+        };
+
+        let cache = ChunkCache::new(2 * size);
+        cache.insert(key(0), pages.clone());
+        cache.insert(key(1), pages.clone());
+        assert!(cache.get(&key(0)).is_some()); // 1 is now the oldest
+        cache.insert(key(2), pages);
+        assert!(cache.get(&key(1)).is_none());
+        assert!(cache.get(&key(0)).is_some());
+        assert!(cache.get(&key(2)).is_some());
+        assert_eq!(cache.stats().evictions, 1);
+    }
+}
@@ -0,0 +1,83 @@
+//! Cancellation of searches
+//!
+//! A [`CancelToken`] aborts a search on any path: the cancellable
+//! [`file`](crate::file::count_occurrences_with_cancel),
+//! [`arrow`](crate::arrow::count_occurrences_with_cancel), and
+//! [multi-file](crate::file::count_occurrences_in_files_with_cancel) counts,
+//! the [`results`](crate::results) searches through their options, and the
+//! DataFusion [`collect`](crate::datafusion::collect_with_cancel) wrapper.
+//! They check the token before every row group or batch and return
+//! [`ZnError::Cancelled`] once it is cancelled, so a search stops within one
+//! row group or batch of [`CancelToken::cancel`].
+
+use crate::{ZnError, ZnResult};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// A flag shared by the clones of a token; cancelling any of them cancels
+/// the searches given any other.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Returns [`ZnError::Cancelled`] if the token is cancelled.
+    pub fn check(&self) -> ZnResult<()> {
+        if self.is_cancelled() {
+            return Err(ZnError::cancelled());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{parquet_bytes, parquet_file};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn test_cancel() {
+        let token = CancelToken::new();
+        let file = parquet_file(&["k8s pod", "node", "k8s"], 2);
+        assert_eq!(
+            crate::file::count_occurrences_with_cancel(&file, b"k8s", &token).unwrap(),
+            2
+        );
+
+        token.clone().cancel();
+        assert!(token.is_cancelled());
+        assert!(matches!(
+            crate::file::count_occurrences_with_cancel(&file, b"k8s", &token),
+            Err(ZnError::Cancelled)
+        ));
+        let reader = ParquetRecordBatchReaderBuilder::try_new(parquet_bytes(&["k8s"], 2))
+            .unwrap()
+            .build()
+            .unwrap();
+        assert!(matches!(
+            crate::arrow::count_occurrences_with_cancel(reader, "k8s", &token),
+            Err(ZnError::Cancelled)
+        ));
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("logs.parquet");
+        std::fs::write(&path, parquet_bytes(&["k8s"], 2)).unwrap();
+        assert!(matches!(
+            crate::file::count_occurrences_in_files_with_cancel(&[path], b"k8s", &token),
+            Err(ZnError::Cancelled)
+        ));
+    }
+}
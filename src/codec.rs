@@ -0,0 +1,40 @@
+//! Helpers for the binary formats of the sidecar files
+
+use crate::{ZnError, ZnResult};
+use std::io::{Read, Write};
+
+/// Writes `n` as an LEB128 varint.
+pub(crate) fn write_varint<W: Write>(w: &mut W, mut n: u64) -> ZnResult<()> {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            w.write_all(&[byte])?;
+            return Ok(());
+        }
+        w.write_all(&[byte | 0x80])?;
+    }
+}
+
+/// Reads an LEB128 varint written with [`write_varint`].
+pub(crate) fn read_varint<R: Read>(r: &mut R) -> ZnResult<u64> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        r.read_exact(&mut byte)?;
+        n |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(ZnError::invalid_index("varint is too long"))
+}
+
+/// Reads a varint that counts row groups or items of a sidecar.
+pub(crate) fn read_usize<R: Read>(r: &mut R) -> ZnResult<usize> {
+    let n = read_varint(r)?;
+    // Row groups are addressed with `u32`, which also bounds the counts.
+    u32::try_from(n)
+        .map(|n| n as usize)
+        .map_err(|_| ZnError::invalid_index(format!("count {n} is out of range")))
+}
@@ -0,0 +1,367 @@
+//! Cache of decoded text columns
+//!
+//! A [`ChunkCache`](crate::cache::ChunkCache) spares repeat searches the
+//! decompression, but they still decode the pages.  A [`ColumnCache`] keeps
+//! the decoded Arrow arrays of whole columns, keyed by file and column name,
+//! so a repeat search of a hot column is a pure in-memory scan.
+//!
+//! Decoded columns are large, so the cache is picky about what it keeps:
+//!
+//! * A column is only admitted once it has been requested
+//!   [`min_requests`](ColumnCache::with_min_requests) times, so one-off
+//!   searches don't displace the hot columns.
+//! * Columns unused for [`max_age`](ColumnCache::new) expire.
+//! * Beyond the size bound, the least recently used columns are evicted.
+
+use crate::{
+    metrics::{registry, Cache},
+    storage::{RangeChunkReader, RangeReader},
+    ZnError, ZnResult,
+};
+use arrow::array::{Array, ArrayRef};
+use arrow_array::cast::as_string_array;
+use arrow_schema::DataType;
+use memchr::memmem;
+use parquet::arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ProjectionMask};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// Number of columns not cached whose requests are counted at most.
+const MAX_TRACKED_REQUESTS: usize = 1 << 16;
+
+/// Identifies a cached column: file and column name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ColumnKey {
+    pub file: Arc<str>,
+    pub column: String,
+}
+
+/// Counters of a [`ColumnCache`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ColumnCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Columns evicted to make room.
+    pub evictions: u64,
+    /// Columns dropped for being unused for too long.
+    pub expirations: u64,
+    pub entries: usize,
+    /// Total memory size of the cached arrays in bytes.
+    pub bytes: usize,
+}
+
+struct Entry {
+    arrays: Arc<[ArrayRef]>,
+    size: usize,
+    last_used: u64,
+    used_at: Instant,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<ColumnKey, Entry>,
+    /// `last_used` tick → key, oldest first.
+    lru: BTreeMap<u64, ColumnKey>,
+    /// Requests of columns not cached (yet), for admission.
+    requests: HashMap<ColumnKey, usize>,
+    tick: u64,
+    stats: ColumnCacheStats,
+}
+
+/// Size- and age-bounded cache of decoded columns; see the
+/// [module docs](self).
+///
+/// The cache is meant to be shared (`Arc<ColumnCache>`) between all searches
+/// of a process.
+pub struct ColumnCache {
+    capacity: usize,
+    max_age: Duration,
+    min_requests: usize,
+    inner: Mutex<Inner>,
+}
+
+impl ColumnCache {
+    /// Creates a cache holding at most `capacity` bytes of arrays, each for
+    /// at most `max_age` since its last use.
+    pub fn new(capacity: usize, max_age: Duration) -> Self {
+        Self {
+            capacity,
+            max_age,
+            min_requests: 2,
+            inner: Mutex::default(),
+        }
+    }
+
+    /// Sets the number of requests of a column before it is cached; 1
+    /// caches every column requested.
+    pub fn with_min_requests(mut self, min_requests: usize) -> Self {
+        self.min_requests = min_requests.max(1);
+        self
+    }
+
+    pub fn stats(&self) -> ColumnCacheStats {
+        self.lock().stats
+    }
+
+    /// Returns the arrays of the `column` of the parquet `file` read by
+    /// `reader`, one per batch, from the cache or decoded (and cached, if
+    /// admitted); `file` must name the file uniquely among the files sharing
+    /// the cache.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::InvalidArgument`] if the file has no such column.
+    pub fn column<R: RangeReader + ?Sized + 'static>(
+        &self,
+        file: &str,
+        reader: Arc<R>,
+        column: &str,
+    ) -> ZnResult<Arc<[ArrayRef]>> {
+        let key = ColumnKey {
+            file: file.into(),
+            column: column.to_owned(),
+        };
+        if let Some(arrays) = self.get(&key) {
+            return Ok(arrays);
+        }
+
+        let builder = ParquetRecordBatchReaderBuilder::try_new(RangeChunkReader::try_new(reader)?)?;
+        let i = builder
+            .schema()
+            .index_of(column)
+            .map_err(|_| ZnError::invalid_argument(format!("no column {column:?}")))?;
+        let mask = ProjectionMask::roots(builder.metadata().file_metadata().schema_descr(), [i]);
+        let arrays = builder
+            .with_projection(mask)
+            .build()?
+            .map(|batch| Ok(batch?.column(0).clone()))
+            .collect::<ZnResult<Arc<[_]>>>()?;
+        self.admit(key, arrays.clone());
+        Ok(arrays)
+    }
+
+    /// Counts the cells of the Utf8 `columns` of `file` that contain the
+    /// `needle`, like [`crate::arrow::count_occurrences`] restricted to the
+    /// columns, through the cache.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::EmptyNeedle`] if the `needle` is empty, and
+    /// [`ZnError::UnsupportedType`] if a column is not a Utf8 column.
+    pub fn count_occurrences<R: RangeReader + ?Sized + 'static>(
+        &self,
+        file: &str,
+        reader: Arc<R>,
+        columns: &[&str],
+        needle: &str,
+    ) -> ZnResult<usize> {
+        if needle.is_empty() {
+            return Err(ZnError::empty_needle());
+        }
+        let finder = memmem::Finder::new(needle.as_bytes());
+        let mut count = 0;
+        for column in columns {
+            for array in self.column(file, reader.clone(), column)?.iter() {
+                if array.data_type() != &DataType::Utf8 {
+                    return Err(ZnError::unsupported_type(format!(
+                        "column {column:?} is not a Utf8 column"
+                    )));
+                }
+                count += as_string_array(array)
+                    .iter()
+                    .filter(|s| s.is_some_and(|s| finder.find(s.as_bytes()).is_some()))
+                    .count();
+            }
+        }
+        Ok(count)
+    }
+
+    /// Drops all cached columns of `file`.
+    pub fn invalidate_file(&self, file: &str) {
+        let mut inner = self.lock();
+        let Inner {
+            entries,
+            lru,
+            requests,
+            stats,
+            ..
+        } = &mut *inner;
+        entries.retain(|key, entry| {
+            let keep = &*key.file != file;
+            if !keep {
+                lru.remove(&entry.last_used);
+                stats.bytes -= entry.size;
+            }
+            keep
+        });
+        requests.retain(|key, _| &*key.file != file);
+        stats.entries = entries.len();
+    }
+
+    fn get(&self, key: &ColumnKey) -> Option<Arc<[ArrayRef]>> {
+        let mut inner = self.lock();
+        self.expire(&mut inner);
+        inner.tick += 1;
+        let tick = inner.tick;
+        let Inner {
+            entries,
+            lru,
+            stats,
+            ..
+        } = &mut *inner;
+        match entries.get_mut(key) {
+            Some(entry) => {
+                stats.hits += 1;
+                registry().cache_hits(Cache::Column).inc_by(1);
+                lru.remove(&entry.last_used);
+                lru.insert(tick, key.clone());
+                entry.last_used = tick;
+                entry.used_at = Instant::now();
+                Some(entry.arrays.clone())
+            }
+            None => {
+                stats.misses += 1;
+                registry().cache_misses(Cache::Column).inc_by(1);
+                None
+            }
+        }
+    }
+
+    /// Caches the decoded `arrays` if the column was requested often enough
+    /// and fits.
+    fn admit(&self, key: ColumnKey, arrays: Arc<[ArrayRef]>) {
+        let size = arrays.iter().map(|a| a.get_array_memory_size()).sum();
+        if size > self.capacity {
+            return;
+        }
+        let mut inner = self.lock();
+        if inner.requests.len() >= MAX_TRACKED_REQUESTS {
+            // Forgetting the counts only delays admissions.
+            inner.requests.clear();
+        }
+        let requested = inner.requests.entry(key.clone()).or_default();
+        *requested += 1;
+        if *requested < self.min_requests {
+            return;
+        }
+        inner.requests.remove(&key);
+
+        inner.tick += 1;
+        let tick = inner.tick;
+        let Inner {
+            entries,
+            lru,
+            stats,
+            ..
+        } = &mut *inner;
+        if let Some(old) = entries.remove(&key) {
+            lru.remove(&old.last_used);
+            stats.bytes -= old.size;
+        }
+        while stats.bytes + size > self.capacity {
+            let Some((_, victim)) = lru.pop_first() else {
+                break;
+            };
+            if let Some(old) = entries.remove(&victim) {
+                stats.bytes -= old.size;
+                stats.evictions += 1;
+            }
+        }
+        lru.insert(tick, key.clone());
+        entries.insert(
+            key,
+            Entry {
+                arrays,
+                size,
+                last_used: tick,
+                used_at: Instant::now(),
+            },
+        );
+        stats.bytes += size;
+        stats.entries = entries.len();
+    }
+
+    /// Drops the columns unused for longer than the maximum age.
+    fn expire(&self, inner: &mut Inner) {
+        let Inner {
+            entries,
+            lru,
+            stats,
+            ..
+        } = inner;
+        // The least recently used columns come first, and with them the
+        // longest unused.
+        while let Some((&tick, key)) = lru.first_key_value() {
+            let Some(entry) = entries.get(key) else {
+                lru.remove(&tick);
+                continue;
+            };
+            if entry.used_at.elapsed() <= self.max_age {
+                break;
+            }
+            let key = key.clone();
+            lru.remove(&tick);
+            if let Some(old) = entries.remove(&key) {
+                stats.bytes -= old.size;
+                stats.expirations += 1;
+            }
+        }
+        stats.entries = entries.len();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        // The cache holds no invariants a panicking thread could break
+        // half-way, so a poisoned lock is still usable.
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::parquet_bytes;
+
+    #[test]
+    fn test_column_cache() {
+        let a = Arc::new(parquet_bytes(&["k8s pod", "GET /", "k8s node"], 2));
+        let cache = ColumnCache::new(1 << 20, Duration::from_secs(60));
+
+        // Admitted on the second request, served from memory on the third.
+        for _ in 0..3 {
+            assert_eq!(
+                cache
+                    .count_occurrences("a", a.clone(), &["log"], "k8s")
+                    .unwrap(),
+                2
+            );
+        }
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 2, 1));
+        assert!(stats.bytes > 0);
+
+        assert!(matches!(
+            cache.count_occurrences("a", a.clone(), &["id"], "k8s"),
+            Err(ZnError::UnsupportedType(_))
+        ));
+        assert!(cache.column("a", a.clone(), "level").is_err());
+
+        // Too small for the columns of two files.
+        let small = ColumnCache::new(stats.bytes, Duration::from_secs(60)).with_min_requests(1);
+        small.column("a", a.clone(), "log").unwrap();
+        small.column("b", a.clone(), "log").unwrap();
+        assert_eq!(small.stats().evictions, 1);
+        assert_eq!(small.stats().entries, 1);
+        small.invalidate_file("b");
+        assert_eq!(small.stats().entries, 0);
+
+        let expiring = ColumnCache::new(1 << 20, Duration::ZERO).with_min_requests(1);
+        expiring.column("a", a.clone(), "log").unwrap();
+        std::thread::sleep(Duration::from_millis(1));
+        expiring.column("a", a, "log").unwrap();
+        assert_eq!(expiring.stats().expirations, 1);
+        assert_eq!(expiring.stats().hits, 0);
+    }
+}
@@ -0,0 +1,257 @@
+//! Merging of small parquet files
+//!
+//! Ingestion tends to produce many small files, and every file costs a
+//! footer read and a few requests per search.  [`merge`] rewrites them into
+//! files of about a target size, sorted by a column and encoded like the
+//! [`writer`](crate::writer) encodes new files.  Writing the files anew
+//! recomputes their statistics.
+
+use crate::{
+    storage::{RangeChunkReader, RangeReader},
+    writer::{properties, sort_by_column, DEFAULT_ROW_GROUP_SIZE},
+    ZnError, ZnResult,
+};
+use arrow::{
+    array::new_null_array,
+    compute::concat_batches,
+    datatypes::{Schema, SchemaRef},
+    record_batch::RecordBatch,
+};
+use bytes::Bytes;
+use parquet::{
+    arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter, ARROW_SCHEMA_META_KEY},
+    format::KeyValue,
+    schema::types::ColumnPath,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+/// Merges the parquet `files` into files of about `target_size` bytes and
+/// returns the merged files.
+///
+/// The files are packed, in order, into groups whose total size does not
+/// exceed `target_size` (unless a single file does), and each group is
+/// rewritten into one file.  A group is held in memory while it's rewritten.
+/// The merged file:
+///
+/// * has the union of the columns of the group, filled with nulls for the
+///   rows of files lacking some column,
+/// * is sorted by the column `sort_by`, if given,
+/// * dictionary-encodes the columns that were dictionary-encoded in some
+///   file of the group,
+/// * carries the key-value metadata of all files of the group, the first
+///   file winning for keys set by several files.
+///
+/// # Errors
+///
+/// Returns an error if the files of a group have columns of the same name
+/// but different types, or [`ZnError::InvalidArgument`] if a group lacks the
+/// `sort_by` column.
+pub fn merge<R: RangeReader + ?Sized + 'static>(
+    files: &[Arc<R>],
+    target_size: u64,
+    sort_by: Option<&str>,
+) -> ZnResult<Vec<Bytes>> {
+    let mut groups: Vec<Vec<Arc<R>>> = Vec::new();
+    let mut group_size = 0;
+    for file in files {
+        let size = file.size()?;
+        match groups.last_mut() {
+            Some(group) if group_size + size <= target_size => group.push(file.clone()),
+            _ => {
+                groups.push(vec![file.clone()]);
+                group_size = 0;
+            }
+        }
+        group_size += size;
+    }
+    groups
+        .into_iter()
+        .map(|group| merge_group(group, sort_by))
+        .collect()
+}
+
+fn merge_group<R: RangeReader + ?Sized + 'static>(
+    files: Vec<Arc<R>>,
+    sort_by: Option<&str>,
+) -> ZnResult<Bytes> {
+    let mut schemas = Vec::with_capacity(files.len());
+    let mut batches = Vec::new();
+    let mut key_value_metadata = BTreeMap::new();
+    let mut dictionary_columns = BTreeSet::new();
+    for file in files {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(RangeChunkReader::try_new(file)?)?;
+        let metadata = builder.metadata();
+        for kv in metadata
+            .file_metadata()
+            .key_value_metadata()
+            .into_iter()
+            .flatten()
+        {
+            // The writer derives the arrow schema of the merged file anew.
+            if kv.key != ARROW_SCHEMA_META_KEY {
+                key_value_metadata
+                    .entry(kv.key.clone())
+                    .or_insert_with(|| kv.value.clone());
+            }
+        }
+        for row_group in metadata.row_groups() {
+            for column in row_group.columns() {
+                if column.dictionary_page_offset().is_some() {
+                    dictionary_columns.insert(column.column_path().parts().to_vec());
+                }
+            }
+        }
+        // Schema-level metadata would make merging fail on conflicting values.
+        schemas.push(Schema::new(builder.schema().fields().clone()));
+        for batch in builder.build()? {
+            batches.push(batch?);
+        }
+    }
+
+    let schema = merged_schema(&schemas)?;
+    let batches = batches
+        .iter()
+        .map(|batch| adapt(batch, &schema))
+        .collect::<ZnResult<Vec<_>>>()?;
+    let mut batch = concat_batches(&schema, &batches)?;
+    let mut sort_column = None;
+    if let Some(name) = sort_by {
+        let i = schema.index_of(name).map_err(|_| {
+            ZnError::invalid_argument(format!("no sort column {name:?} in the files"))
+        })?;
+        batch = sort_by_column(&batch, i)?;
+        sort_column = Some(i);
+    }
+
+    let key_value_metadata = key_value_metadata
+        .into_iter()
+        .map(|(key, value)| KeyValue { key, value })
+        .collect::<Vec<_>>();
+    let dictionary_columns = dictionary_columns.into_iter().map(ColumnPath::new);
+    let props = properties(DEFAULT_ROW_GROUP_SIZE, sort_column, dictionary_columns)
+        .set_key_value_metadata((!key_value_metadata.is_empty()).then_some(key_value_metadata))
+        .build();
+    let mut buf = Vec::new();
+    let mut writer = ArrowWriter::try_new(&mut buf, schema, Some(props))?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(buf.into())
+}
+
+/// Merges the schemas, making the columns some of them lack nullable.
+pub(crate) fn merged_schema(schemas: &[Schema]) -> ZnResult<SchemaRef> {
+    let merged = Schema::try_merge(schemas.iter().cloned())?;
+    let fields = merged
+        .fields()
+        .iter()
+        .map(|field| {
+            let everywhere = schemas
+                .iter()
+                .all(|schema| schema.field_with_name(field.name()).is_ok());
+            field
+                .clone()
+                .with_nullable(field.is_nullable() || !everywhere)
+        })
+        .collect();
+    Ok(Arc::new(Schema::new(fields)))
+}
+
+/// Reorders the columns of the `batch` like the `schema`, adding columns of
+/// nulls for the missing ones.
+pub(crate) fn adapt(batch: &RecordBatch, schema: &SchemaRef) -> ZnResult<RecordBatch> {
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|field| match batch.schema().index_of(field.name()) {
+            Ok(i) => batch.column(i).clone(),
+            Err(_) => new_null_array(field.data_type(), batch.num_rows()),
+        })
+        .collect();
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::parquet_bytes;
+    use arrow::{
+        array::StringArray,
+        datatypes::{DataType, Field},
+    };
+    use parquet::{
+        file::{
+            properties::WriterProperties, reader::FileReader,
+            serialized_reader::SerializedFileReader,
+        },
+        record::RowAccessor,
+    };
+
+    /// A file with only a `log` and a `host` column, and key-value metadata.
+    fn file_with_host(log: &str, host: &str) -> Bytes {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("log", DataType::Utf8, false),
+            Field::new("host", DataType::Utf8, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(StringArray::from(vec![log])),
+                Arc::new(StringArray::from(vec![host])),
+            ],
+        )
+        .unwrap();
+        let props = WriterProperties::builder()
+            .set_key_value_metadata(Some(vec![KeyValue::new(
+                "origin".to_owned(),
+                host.to_owned(),
+            )]))
+            .build();
+        let mut buf = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut buf, schema, Some(props)).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        buf.into()
+    }
+
+    #[test]
+    fn test_merge() {
+        let files: Vec<Arc<Bytes>> = vec![
+            Arc::new(parquet_bytes(&["d", "b"], 1)),
+            Arc::new(file_with_host("a", "host-1")),
+            Arc::new(file_with_host("c", "host-2")),
+            Arc::new(parquet_bytes(&["e"], 1)),
+        ];
+        let target_size = files[..3].iter().map(|f| f.len() as u64).sum();
+        let merged = merge(&files, target_size, Some("log")).unwrap();
+        assert_eq!(merged.len(), 2);
+
+        let file = SerializedFileReader::new(merged[0].clone()).unwrap();
+        let metadata = file.metadata().file_metadata();
+        assert_eq!(metadata.num_rows(), 4);
+        let kv = metadata.key_value_metadata().unwrap();
+        let origin = kv.iter().find(|kv| kv.key == "origin").unwrap();
+        assert_eq!(origin.value.as_deref(), Some("host-1"));
+        let names: Vec<_> = metadata
+            .schema_descr()
+            .columns()
+            .iter()
+            .map(|c| c.name().to_owned())
+            .collect();
+        assert_eq!(names, ["log", "id", "host"]);
+
+        let logs: Vec<_> = file
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.get_string(0).unwrap().clone())
+            .collect();
+        assert_eq!(logs, ["a", "b", "c", "d"]);
+        let stats = file.metadata().row_group(0).column(0).statistics().unwrap();
+        assert_eq!(stats.min_bytes(), b"a");
+        assert_eq!(stats.max_bytes(), b"d");
+
+        assert!(merge(&files, target_size, Some("level")).is_err());
+    }
+}
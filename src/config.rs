@@ -0,0 +1,424 @@
+//! Configuration from the environment or a TOML file
+//!
+//! A [`ZnConfig`] gathers the settings of the subsystems: the [tuned](crate::tune)
+//! batch size, parallelism, and prefetch depth, the cache sizes, the
+//! DataFusion pushdown toggle, the scan [limits](crate::limits), and the
+//! object store credentials.  [`ZnConfig::load`] starts from the defaults,
+//! applies a TOML file, then the `ZN_<SECTION>_<KEY>` environment variables,
+//! e.g. `ZN_TUNING_BATCH_SIZE=4096`.  [`ZnConfig::install`] hands the
+//! settings to the subsystems, and [`ZnConfig::chunk_cache`],
+//! [`ZnConfig::column_cache`], the disk cache options with the `native`
+//! feature, and DataFusion sessions with the `datafusion` feature are built
+//! from them.
+//!
+//! ```toml
+//! [tuning]
+//! batch_size = 8192
+//! parallelism = 8
+//!
+//! [cache]
+//! chunk_bytes = 268_435_456
+//!
+//! [datafusion]
+//! pushdown = true
+//!
+//! [limits]
+//! max_concurrent_scans = 16
+//! queue_timeout_ms = 5000
+//!
+//! [object_store]
+//! url = "https://logs.example.com/"
+//! ```
+//!
+//! The crate depends on no TOML parser: files may only hold `[section]`
+//! headers, `#` comments, and `key = value` lines whose values are basic
+//! strings, integers, or booleans.
+
+use crate::{limits, limits::Limits, tune, tune::Tuning, ZnError, ZnResult};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+/// Prefix of the environment variables read by [`ZnConfig::load`].
+const ENV_PREFIX: &str = "ZN_";
+
+/// Environment variable naming the TOML file read by [`ZnConfig::load`].
+pub const CONFIG_FILE_ENV: &str = "ZN_CONFIG";
+
+/// Sizes of the in-memory and disk caches, in bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Capacity of a [`ChunkCache`](crate::cache::ChunkCache).
+    pub chunk_bytes: usize,
+    /// Capacity of a [`ColumnCache`](crate::column_cache::ColumnCache).
+    pub column_bytes: usize,
+    /// Age after which cached columns are decoded again.
+    pub column_max_age: Duration,
+    pub disk_bytes: u64,
+    pub disk_block_size: usize,
+    /// Age after which disk-cached blocks are fetched again.
+    pub disk_max_age: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            chunk_bytes: 256 << 20,
+            column_bytes: 256 << 20,
+            column_max_age: Duration::from_secs(5 * 60),
+            disk_bytes: 10 << 30,
+            disk_block_size: 1 << 20,
+            disk_max_age: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+/// Where and how to reach the object store holding the files.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct ObjectStoreConfig {
+    pub url: Option<String>,
+    pub region: Option<String>,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+}
+
+impl fmt::Debug for ObjectStoreConfig {
+    /// Hides the secret key, so configurations can be logged.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ObjectStoreConfig")
+            .field("url", &self.url)
+            .field("region", &self.region)
+            .field("access_key_id", &self.access_key_id)
+            .field(
+                "secret_access_key",
+                &self.secret_access_key.as_ref().map(|_| "***"),
+            )
+            .finish()
+    }
+}
+
+/// Settings of the subsystems; see the [module docs](self).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ZnConfig {
+    /// `[tuning]`: batch size, parallelism (the size of the scan thread
+    /// pools), and prefetch depth.
+    pub tuning: Tuning,
+    /// `[cache]`
+    pub cache: CacheConfig,
+    /// `[datafusion] pushdown`: whether sessions push filters into the
+    /// parquet scans and use page indexes.
+    pub pushdown: bool,
+    /// `[limits]`, with `queue_timeout_ms` in milliseconds.
+    pub limits: Limits,
+    /// `[object_store]`
+    pub object_store: ObjectStoreConfig,
+}
+
+impl ZnConfig {
+    /// Returns the defaults, overridden by the TOML file at `path`, or named
+    /// by the `ZN_CONFIG` environment variable if `None`, then by the
+    /// `ZN_<SECTION>_<KEY>` environment variables.
+    pub fn load(path: Option<&Path>) -> ZnResult<Self> {
+        let mut config = Self::default();
+        let path = path
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::var_os(CONFIG_FILE_ENV).map(PathBuf::from));
+        if let Some(path) = path {
+            config.apply_toml(&std::fs::read_to_string(path)?)?;
+        }
+        config.apply_env(std::env::vars())?;
+        Ok(config)
+    }
+
+    /// Returns the defaults overridden by the TOML `input`.
+    pub fn from_toml(input: &str) -> ZnResult<Self> {
+        let mut config = Self::default();
+        config.apply_toml(input)?;
+        Ok(config)
+    }
+
+    /// Applies the settings of the TOML `input`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::InvalidArgument`] for syntax outside of the
+    /// supported subset, unknown keys, and values of the wrong type.
+    pub fn apply_toml(&mut self, input: &str) -> ZnResult<()> {
+        let mut section = String::new();
+        for (i, line) in input.lines().enumerate() {
+            let error = |e| with_context(e, format_args!("line {}", i + 1));
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[') {
+                let name = name.strip_suffix(']').ok_or_else(|| {
+                    error(ZnError::invalid_argument("unterminated section header"))
+                })?;
+                section = name.trim().to_owned();
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error(ZnError::invalid_argument("expected `key = value`")))?;
+            let value = parse_toml_value(value.trim()).map_err(error)?;
+            self.set(&section, key.trim(), &value).map_err(error)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the `ZN_<SECTION>_<KEY>` variables of `vars`, ignoring the
+    /// others; e.g. `ZN_LIMITS_MAX_CONCURRENT_SCANS` sets
+    /// `max_concurrent_scans` of `[limits]`.
+    pub fn apply_env(&mut self, vars: impl IntoIterator<Item = (String, String)>) -> ZnResult<()> {
+        for (name, value) in vars {
+            let Some(name) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let name = name.to_ascii_lowercase();
+            let Some((section, key)) = SECTIONS.iter().find_map(|section| {
+                let key = name.strip_prefix(section)?.strip_prefix('_')?;
+                Some((*section, key))
+            }) else {
+                continue;
+            };
+            self.set(section, key, &value).map_err(|e| {
+                with_context(e, format_args!("{ENV_PREFIX}{}", name.to_uppercase()))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Sets the `key` of the `section` from its textual `value`.
+    pub fn set(&mut self, section: &str, key: &str, value: &str) -> ZnResult<()> {
+        let tuning = &mut self.tuning;
+        let cache = &mut self.cache;
+        let limits = &mut self.limits;
+        let store = &mut self.object_store;
+        match (section, key) {
+            ("tuning", "batch_size") => tuning.batch_size = parse(value)?,
+            ("tuning", "parallelism") => tuning.parallelism = parse(value)?,
+            ("tuning", "prefetch_depth") => tuning.prefetch_depth = parse(value)?,
+            ("cache", "chunk_bytes") => cache.chunk_bytes = parse(value)?,
+            ("cache", "column_bytes") => cache.column_bytes = parse(value)?,
+            ("cache", "column_max_age_secs") => {
+                cache.column_max_age = Duration::from_secs(parse(value)?)
+            }
+            ("cache", "disk_bytes") => cache.disk_bytes = parse(value)?,
+            ("cache", "disk_block_size") => cache.disk_block_size = parse(value)?,
+            ("cache", "disk_max_age_secs") => {
+                cache.disk_max_age = Duration::from_secs(parse(value)?)
+            }
+            ("datafusion", "pushdown") => self.pushdown = parse(value)?,
+            ("limits", "bytes_per_second") => limits.bytes_per_second = Some(parse(value)?),
+            ("limits", "burst_bytes") => limits.burst_bytes = parse(value)?,
+            ("limits", "max_concurrent_scans") => limits.max_concurrent_scans = Some(parse(value)?),
+            ("limits", "queue_timeout_ms") => {
+                limits.queue_timeout = Some(Duration::from_millis(parse(value)?))
+            }
+            ("object_store", "url") => store.url = Some(value.to_owned()),
+            ("object_store", "region") => store.region = Some(value.to_owned()),
+            ("object_store", "access_key_id") => store.access_key_id = Some(value.to_owned()),
+            ("object_store", "secret_access_key") => {
+                store.secret_access_key = Some(value.to_owned())
+            }
+            _ => {
+                return Err(ZnError::invalid_argument(format!(
+                    "unknown setting {key:?} of [{section}]"
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    /// Installs the [tuning](tune::set_tuning), the [limiter](limits::set_limiter)
+    /// if any limit is set, and the configuration itself as the one
+    /// [`config`] returns.
+    pub fn install(&self) {
+        tune::set_tuning(self.tuning);
+        if self.limits == Limits::default() {
+            limits::clear_limiter();
+        } else {
+            limits::set_limiter(limits::Limiter::new(self.limits.clone()));
+        }
+        *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(self.clone()));
+    }
+
+    pub fn chunk_cache(&self) -> crate::cache::ChunkCache {
+        crate::cache::ChunkCache::new(self.cache.chunk_bytes)
+    }
+
+    pub fn column_cache(&self) -> crate::column_cache::ColumnCache {
+        crate::column_cache::ColumnCache::new(self.cache.column_bytes, self.cache.column_max_age)
+    }
+
+    #[cfg(feature = "native")]
+    pub fn disk_cache_options(&self) -> crate::disk_cache::DiskCacheOptions {
+        crate::disk_cache::DiskCacheOptions {
+            block_size: self.cache.disk_block_size,
+            max_bytes: self.cache.disk_bytes,
+            max_age: self.cache.disk_max_age,
+        }
+    }
+
+    /// Returns a DataFusion session with the configured batch size and
+    /// pushdown.
+    #[cfg(feature = "datafusion")]
+    pub fn session_context(&self) -> datafusion::prelude::SessionContext {
+        crate::datafusion::new_session_context(self.tuning.batch_size, self.pushdown)
+    }
+}
+
+/// The sections of a configuration, for splitting variable names.
+const SECTIONS: [&str; 5] = ["tuning", "cache", "datafusion", "limits", "object_store"];
+
+static CONFIG: RwLock<Option<Arc<ZnConfig>>> = RwLock::new(None);
+
+/// Returns the [installed](ZnConfig::install) configuration, or the defaults.
+pub fn config() -> Arc<ZnConfig> {
+    CONFIG
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .unwrap_or_default()
+}
+
+/// Prefixes the message of an [`ZnError::InvalidArgument`] with `context`.
+fn with_context(e: ZnError, context: fmt::Arguments<'_>) -> ZnError {
+    match e {
+        ZnError::InvalidArgument(message) => {
+            ZnError::invalid_argument(format!("{context}: {message}"))
+        }
+        e => e,
+    }
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> ZnResult<T>
+where
+    T::Err: fmt::Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|e| ZnError::invalid_argument(format!("invalid value {value:?}: {e}")))
+}
+
+/// Removes a `#` comment, unless within a string, from a TOML line.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+/// Parses a TOML basic string, integer, or boolean into the text that
+/// [`ZnConfig::set`] parses.
+fn parse_toml_value(value: &str) -> ZnResult<String> {
+    if let Some(s) = value.strip_prefix('"') {
+        let s = s
+            .strip_suffix('"')
+            .ok_or_else(|| ZnError::invalid_argument("unterminated string"))?;
+        let mut unescaped = String::with_capacity(s.len());
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                unescaped.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('"') => unescaped.push('"'),
+                Some('\\') => unescaped.push('\\'),
+                Some('n') => unescaped.push('\n'),
+                Some('t') => unescaped.push('\t'),
+                other => {
+                    return Err(ZnError::invalid_argument(format!(
+                        "unsupported escape \\{}",
+                        other.map(String::from).unwrap_or_default()
+                    )))
+                }
+            }
+        }
+        return Ok(unescaped);
+    }
+    if value == "true" || value == "false" {
+        return Ok(value.to_owned());
+    }
+    let digits = value.replace('_', "");
+    if digits.parse::<i64>().is_ok() {
+        return Ok(digits);
+    }
+    Err(ZnError::invalid_argument(format!(
+        "unsupported value {value:?}"
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let config = ZnConfig::from_toml(
+            r#"
+            # Overrides of the defaults
+            [tuning]
+            batch_size = 4_096  # rows
+            [datafusion]
+            pushdown = true
+            [limits]
+            queue_timeout_ms = 250
+            [object_store]
+            url = "https://logs.example.com/#a"
+            secret_access_key = "s3cr\"t"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.tuning.batch_size, 4096);
+        assert!(config.pushdown);
+        assert_eq!(
+            config.limits.queue_timeout,
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(
+            config.object_store.url.as_deref(),
+            Some("https://logs.example.com/#a")
+        );
+        assert_eq!(
+            config.object_store.secret_access_key.as_deref(),
+            Some("s3cr\"t")
+        );
+        assert!(!format!("{config:?}").contains("s3cr"));
+
+        let mut config = config;
+        let vars = [
+            ("ZN_TUNING_BATCH_SIZE", "1024"),
+            ("ZN_LIMITS_MAX_CONCURRENT_SCANS", "3"),
+            ("ZN_OBJECT_STORE_REGION", "us-west-2"),
+            ("HOME", "/root"),
+        ];
+        config
+            .apply_env(vars.map(|(k, v)| (k.to_owned(), v.to_owned())))
+            .unwrap();
+        assert_eq!(config.tuning.batch_size, 1024);
+        assert_eq!(config.limits.max_concurrent_scans, Some(3));
+        assert_eq!(config.object_store.region.as_deref(), Some("us-west-2"));
+
+        for invalid in ["[tuning]\nbatch_size = -1", "[tuning]\nfoo = 1", "x = [1]"] {
+            assert!(matches!(
+                ZnConfig::from_toml(invalid),
+                Err(ZnError::InvalidArgument(_))
+            ));
+        }
+    }
+}
@@ -1,7 +1,36 @@
-use datafusion::execution::context::{SessionConfig, SessionContext};
+use crate::{
+    cancel::CancelToken,
+    limits,
+    metrics::{registry, SearchPath},
+    tune, ZnResult,
+};
+use async_trait::async_trait;
+use datafusion::{
+    arrow::{datatypes::SchemaRef, record_batch::RecordBatch},
+    dataframe::DataFrame,
+    datasource::TableProvider,
+    error::DataFusionError,
+    execution::context::{SessionConfig, SessionContext, SessionState},
+    logical_expr::TableType,
+    physical_plan::{memory::MemoryExec, ExecutionPlan},
+    prelude::Expr,
+};
+use futures::StreamExt;
+#[allow(deprecated)]
+use parquet::{
+    arrow::{ArrowReader, ParquetFileArrowReader, ProjectionMask},
+    file::reader::FileReader,
+};
+use std::{any::Any, sync::Arc};
 
+/// Returns a session decoding `batch_size` rows at a time and running as
+/// many partitions as the [tuned](crate::tune) parallelism, with the
+/// [`match_udf`](crate::match_udf) functions
+/// [registered](crate::match_udf::register_all).
 pub fn new_session_context(batch_size: usize, optimized_p: bool) -> SessionContext {
-    let cfg = SessionConfig::default().with_batch_size(batch_size);
+    let cfg = SessionConfig::default()
+        .with_batch_size(batch_size)
+        .with_target_partitions(tune::tuning().parallelism);
     let cfg = if !optimized_p {
         cfg
     } else {
@@ -22,5 +51,125 @@ pub fn new_session_context(batch_size: usize, optimized_p: bool) -> SessionConte
             // will be reordered heuristically to minimize the cost of evaluation
             .set_bool("datafusion.execution.parquet.reorder_filters", true)
     };
-    SessionContext::with_config(cfg)
+    let ctx = SessionContext::with_config(cfg);
+    crate::match_udf::register_all(&ctx);
+    ctx
+}
+
+/// Runs the DataFrame `df` and returns its batches, checking the `cancel`
+/// token before every batch.
+///
+/// # Errors
+///
+/// Returns [`ZnError::Cancelled`] if the token is cancelled.
+///
+/// [`ZnError::Cancelled`]: crate::ZnError::Cancelled
+pub async fn collect_with_cancel(
+    df: DataFrame,
+    cancel: &CancelToken,
+) -> ZnResult<Vec<RecordBatch>> {
+    let mut stream = df.execute_stream().await?;
+    let mut batches = Vec::new();
+    while let Some(batch) = stream.next().await {
+        cancel.check()?;
+        batches.push(batch?);
+    }
+    Ok(batches)
+}
+
+/// A DataFusion table over a parquet [`FileReader`], typically a
+/// [`CachedFileReader`](crate::cache::CachedFileReader), so that queries share
+/// decompressed column chunks with the [`file`](crate::file) scan.
+///
+/// The projected columns are decoded when the query is planned, on the
+/// calling thread, within the [limits](crate::limits) of the installed
+/// limiter.
+pub struct CachedParquetTable {
+    reader: Arc<dyn FileReader>,
+    schema: SchemaRef,
+    batch_size: usize,
+}
+
+impl CachedParquetTable {
+    // `ParquetRecordBatchReaderBuilder`, which replaces `ParquetFileArrowReader`,
+    // cannot read through a `FileReader`.
+    #[allow(deprecated)]
+    pub fn try_new(reader: Arc<dyn FileReader>, batch_size: usize) -> ZnResult<Self> {
+        let schema = ParquetFileArrowReader::new(reader.clone()).get_schema()?;
+        Ok(Self {
+            reader,
+            schema: Arc::new(schema),
+            batch_size,
+        })
+    }
+}
+
+#[async_trait]
+impl TableProvider for CachedParquetTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    #[allow(deprecated)] // see `CachedParquetTable::try_new`
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(projection = ?projection))
+    )]
+    async fn scan(
+        &self,
+        _state: &SessionState,
+        projection: Option<&Vec<usize>>,
+        _filters: &[Expr],
+        _limit: Option<usize>,
+    ) -> datafusion::error::Result<Arc<dyn ExecutionPlan>> {
+        let permit = limits::admit().map_err(|e| DataFusionError::External(Box::new(e)))?;
+        let _timer = registry()
+            .query_latency(SearchPath::DataFusion)
+            .start_timer();
+        // Decode at least one column so that batches know their row counts.
+        let columns = match projection {
+            Some(columns) if columns.is_empty() => vec![0],
+            Some(columns) => columns.clone(),
+            None => (0..self.schema.fields().len()).collect(),
+        };
+        let mask = ProjectionMask::roots(
+            self.reader.metadata().file_metadata().schema_descr(),
+            columns.clone(),
+        );
+
+        if let Some(permit) = &permit {
+            let metadata = self.reader.metadata();
+            permit.throttle(
+                metadata
+                    .row_groups()
+                    .iter()
+                    .flat_map(|row_group| row_group.columns().iter().enumerate())
+                    .filter(|(i, _)| mask.leaf_included(*i))
+                    .map(|(_, column)| column.compressed_size().max(0) as u64)
+                    .sum(),
+            );
+        }
+
+        let mut arrow_reader = ParquetFileArrowReader::new(self.reader.clone());
+        let schema = Arc::new(arrow_reader.get_schema_by_columns(mask.clone())?);
+        let batches = arrow_reader
+            .get_record_reader_by_columns(mask, self.batch_size)?
+            .collect::<Result<Vec<_>, _>>()?;
+        let exec_projection = projection
+            .filter(|columns| columns.is_empty())
+            .map(|_| Vec::new());
+        Ok(Arc::new(MemoryExec::try_new(
+            &[batches],
+            schema,
+            exec_projection,
+        )?))
+    }
 }
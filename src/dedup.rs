@@ -0,0 +1,293 @@
+//! Duplicate rows across files
+//!
+//! At-least-once ingestion delivers some log rows twice, which then are
+//! counted twice.  [`find_duplicates`] identifies rows by the values of key
+//! columns (e.g. a request id and the timestamp) and reports every row whose
+//! key occurred before, in file order and row order within a file;
+//! [`filter_duplicates`] rewrites the files without them.
+//!
+//! Only the key columns are read, and rows are remembered by a 128-bit hash
+//! of their key.  Memory stays bounded by [`DedupOptions::max_keys`]: if the
+//! files have more rows, they are read in several passes, each remembering
+//! the keys of one partition of the hash space.
+
+use crate::{
+    storage::{RangeChunkReader, RangeReader},
+    writer::{properties, DEFAULT_ROW_GROUP_SIZE},
+    ZnError, ZnResult,
+};
+use arrow::{
+    datatypes::DataType,
+    row::{RowConverter, SortField},
+};
+use bytes::Bytes;
+use parquet::arrow::{
+    arrow_reader::{ParquetRecordBatchReaderBuilder, RowSelection, RowSelector},
+    ArrowWriter, ProjectionMask,
+};
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+#[derive(Debug, Clone)]
+pub struct DedupOptions {
+    /// Columns whose values identify a row.
+    pub key_columns: Vec<String>,
+    /// Maximum number of keys remembered at a time.
+    pub max_keys: usize,
+    /// Rows per decoded batch.
+    pub batch_size: usize,
+}
+
+impl DedupOptions {
+    pub fn new(key_columns: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            key_columns: key_columns.into_iter().map(Into::into).collect(),
+            max_keys: 10_000_000,
+            batch_size: 8192,
+        }
+    }
+}
+
+/// Duplicates found by [`find_duplicates`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DedupReport {
+    /// Rows of all files.
+    pub rows: u64,
+    /// Sorted numbers of the duplicate rows of each file.
+    pub duplicates: Vec<Vec<u64>>,
+    /// Passes over the files it took.
+    pub passes: usize,
+}
+
+impl DedupReport {
+    /// Total number of duplicate rows.
+    pub fn num_duplicates(&self) -> u64 {
+        self.duplicates.iter().map(|rows| rows.len() as u64).sum()
+    }
+}
+
+/// Finds the rows of the parquet `files` whose key occurred in an earlier
+/// row.
+///
+/// # Errors
+///
+/// Returns [`ZnError::InvalidArgument`] if there are no key columns or a
+/// file lacks one, or if a key column has different types in different
+/// files.
+pub fn find_duplicates<R: RangeReader + ?Sized + 'static>(
+    files: &[Arc<R>],
+    options: &DedupOptions,
+) -> ZnResult<DedupReport> {
+    if options.key_columns.is_empty() {
+        return Err(ZnError::invalid_argument("no key columns"));
+    }
+    let mut rows = 0;
+    let mut key_types: Option<Vec<DataType>> = None;
+    for file in files {
+        let builder =
+            ParquetRecordBatchReaderBuilder::try_new(RangeChunkReader::try_new(file.clone())?)?;
+        rows += builder.metadata().file_metadata().num_rows() as u64;
+        let schema = builder.schema();
+        let types = options
+            .key_columns
+            .iter()
+            .map(|name| {
+                schema
+                    .field_with_name(name)
+                    .map(|field| field.data_type().clone())
+                    .map_err(|_| ZnError::invalid_argument(format!("no key column {name:?}")))
+            })
+            .collect::<ZnResult<Vec<_>>>()?;
+        match &key_types {
+            Some(key_types) if *key_types != types => {
+                return Err(ZnError::invalid_argument(
+                    "key columns have different types in different files",
+                ))
+            }
+            Some(_) => (),
+            None => key_types = Some(types),
+        }
+    }
+    let Some(key_types) = key_types else {
+        return Ok(DedupReport::default());
+    };
+
+    let passes = (rows as usize).div_ceil(options.max_keys.max(1)).max(1);
+    let mut duplicates = vec![Vec::new(); files.len()];
+    for pass in 0..passes {
+        let mut seen = HashSet::new();
+        let mut converter =
+            RowConverter::new(key_types.iter().cloned().map(SortField::new).collect())?;
+        for (file, file_duplicates) in files.iter().zip(&mut duplicates) {
+            let builder =
+                ParquetRecordBatchReaderBuilder::try_new(RangeChunkReader::try_new(file.clone())?)?;
+            let projection = key_projection(&builder, &options.key_columns)?;
+            let reader = builder
+                .with_projection(projection)
+                .with_batch_size(options.batch_size.max(1))
+                .build()?;
+            let mut row = 0;
+            for batch in reader {
+                let batch = batch?;
+                // The projection keeps the file order of the columns.
+                let columns = options
+                    .key_columns
+                    .iter()
+                    .map(|name| Ok(batch.column(batch.schema().index_of(name)?).clone()))
+                    .collect::<ZnResult<Vec<_>>>()?;
+                for key in converter.convert_columns(&columns)?.iter() {
+                    let hash = key_hash(key.as_ref());
+                    if hash % passes as u128 == pass as u128 && !seen.insert(hash) {
+                        file_duplicates.push(row);
+                    }
+                    row += 1;
+                }
+            }
+        }
+    }
+    for file_duplicates in &mut duplicates {
+        file_duplicates.sort_unstable();
+    }
+    Ok(DedupReport {
+        rows,
+        duplicates,
+        passes,
+    })
+}
+
+/// Rewrites the `files` without the duplicates of the `report` that
+/// [`find_duplicates`] made for them.  Files without duplicates are rewritten
+/// as well, so the result has one file per input file.
+pub fn filter_duplicates<R: RangeReader + ?Sized + 'static>(
+    files: &[Arc<R>],
+    report: &DedupReport,
+) -> ZnResult<Vec<Bytes>> {
+    if files.len() != report.duplicates.len() {
+        return Err(ZnError::invalid_argument(format!(
+            "report covers {} file(s), got {}",
+            report.duplicates.len(),
+            files.len()
+        )));
+    }
+    files
+        .iter()
+        .zip(&report.duplicates)
+        .map(|(file, duplicates)| {
+            let builder =
+                ParquetRecordBatchReaderBuilder::try_new(RangeChunkReader::try_new(file.clone())?)?;
+            let num_rows = builder.metadata().file_metadata().num_rows() as u64;
+            let schema = builder.schema().clone();
+            let reader = builder
+                .with_row_selection(skip_rows(duplicates, num_rows))
+                .build()?;
+            let props = properties(DEFAULT_ROW_GROUP_SIZE, None, std::iter::empty()).build();
+            let mut buf = Vec::new();
+            let mut writer = ArrowWriter::try_new(&mut buf, schema, Some(props))?;
+            for batch in reader {
+                writer.write(&batch?)?;
+            }
+            writer.close()?;
+            Ok(buf.into())
+        })
+        .collect()
+}
+
+fn key_projection<T>(
+    builder: &parquet::arrow::arrow_reader::ArrowReaderBuilder<T>,
+    key_columns: &[String],
+) -> ZnResult<ProjectionMask> {
+    let schema = builder.schema();
+    let roots = key_columns
+        .iter()
+        .map(|name| Ok(schema.index_of(name)?))
+        .collect::<ZnResult<Vec<_>>>()?;
+    Ok(ProjectionMask::roots(
+        builder.metadata().file_metadata().schema_descr(),
+        roots,
+    ))
+}
+
+/// Selects all rows but the sorted `rows`.
+fn skip_rows(rows: &[u64], num_rows: u64) -> RowSelection {
+    let mut selectors = Vec::new();
+    let mut next = 0; // first row not covered by `selectors`
+    for &row in rows {
+        if row > next {
+            selectors.push(RowSelector::select((row - next) as usize));
+        }
+        match selectors.last_mut() {
+            Some(last) if last.skip && row == next => last.row_count += 1,
+            _ => selectors.push(RowSelector::skip(1)),
+        }
+        next = row + 1;
+    }
+    if num_rows > next {
+        selectors.push(RowSelector::select((num_rows - next) as usize));
+    }
+    selectors.into()
+}
+
+fn key_hash(key: &[u8]) -> u128 {
+    let half = |seed: u8| {
+        let mut hasher = DefaultHasher::new();
+        seed.hash(&mut hasher);
+        key.hash(&mut hasher);
+        hasher.finish() as u128
+    };
+    (half(0) << 64) | half(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::parquet_bytes;
+    use parquet::file::{reader::FileReader, serialized_reader::SerializedFileReader};
+
+    #[test]
+    fn test_dedup() {
+        let files: Vec<Arc<Bytes>> = vec![
+            Arc::new(parquet_bytes(&["a", "b", "a", "c"], 2)),
+            Arc::new(parquet_bytes(&["c", "d", "d"], 2)),
+        ];
+        let options = DedupOptions::new(["log"]);
+        let report = find_duplicates(&files, &options).unwrap();
+        assert_eq!(report.rows, 7);
+        assert_eq!(report.duplicates, [vec![2], vec![0, 2]]);
+        assert_eq!(report.passes, 1);
+
+        // Bounded to 2 keys at a time.
+        let bounded = find_duplicates(
+            &files,
+            &DedupOptions {
+                max_keys: 2,
+                batch_size: 1,
+                ..options.clone()
+            },
+        )
+        .unwrap();
+        assert_eq!(bounded.duplicates, report.duplicates);
+        assert_eq!(bounded.passes, 4);
+
+        // `id` numbers the rows per file, so only (log, id) pairs repeat.
+        let by_pair = find_duplicates(&files, &DedupOptions::new(["log", "id"])).unwrap();
+        assert_eq!(by_pair.num_duplicates(), 0);
+
+        let filtered = filter_duplicates(&files, &report).unwrap();
+        let rows: Vec<_> = filtered
+            .into_iter()
+            .map(|file| {
+                SerializedFileReader::new(file)
+                    .unwrap()
+                    .metadata()
+                    .file_metadata()
+                    .num_rows()
+            })
+            .collect();
+        assert_eq!(rows, [3, 1]);
+
+        assert!(find_duplicates(&files, &DedupOptions::new(["level"])).is_err());
+    }
+}
@@ -0,0 +1,517 @@
+//! Read-through local disk cache for object stores
+//!
+//! [`DiskCachedStore`] wraps an [`ObjectStore`] (typically S3) and keeps the
+//! byte ranges read through it on local disk, in blocks of
+//! [`DiskCacheOptions::block_size`] bytes.  Repeated searches of the same
+//! remote files then read from local NVMe instead of the network.  Blocks are
+//! evicted least recently used first once the cache exceeds
+//! [`DiskCacheOptions::max_bytes`], and are refetched once they are older than
+//! [`DiskCacheOptions::max_age`].
+//!
+//! Register the wrapped store with DataFusion to use it for queries:
+//!
+//! ```ignore
+//! let store = DiskCachedStore::try_new(s3, "/mnt/nvme/zn-cache", DiskCacheOptions::default())?;
+//! ctx.runtime_env()
+//!     .register_object_store("s3", "bucket", Arc::new(store));
+//! ```
+
+use crate::{
+    metrics::{registry, Cache},
+    runtime,
+};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use futures::stream::BoxStream;
+use object_store::{
+    path::Path, GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore,
+    Result as ObjectStoreResult,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    ops::Range,
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+use tokio::io::AsyncWrite;
+
+const STORE_NAME: &str = "DiskCache";
+
+/// Settings of a [`DiskCachedStore`].
+#[derive(Debug, Clone)]
+pub struct DiskCacheOptions {
+    /// Size of the cached blocks; reads are rounded out to whole blocks.
+    pub block_size: usize,
+    /// Maximum total size of the cached blocks.
+    pub max_bytes: u64,
+    /// Blocks older than this are fetched again.
+    pub max_age: Duration,
+}
+
+impl Default for DiskCacheOptions {
+    fn default() -> Self {
+        Self {
+            block_size: 1 << 20,
+            max_bytes: 10 << 30,
+            max_age: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+/// Counters of a [`DiskCachedStore`], in blocks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiskCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// Total size of the cached blocks in bytes.
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct BlockKey {
+    location: Path,
+    block: usize,
+}
+
+#[derive(Debug)]
+struct Block {
+    file: PathBuf,
+    size: u64,
+    created: Instant,
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    blocks: HashMap<BlockKey, Block>,
+    /// `last_used` tick → key, oldest first.
+    lru: BTreeMap<u64, BlockKey>,
+    /// Object sizes, needed to clamp the last block of an object.
+    sizes: HashMap<Path, usize>,
+    tick: u64,
+    next_file: u64,
+    stats: DiskCacheStats,
+}
+
+impl State {
+    fn remove(&mut self, key: &BlockKey) -> Option<Block> {
+        let block = self.blocks.remove(key)?;
+        self.lru.remove(&block.last_used);
+        self.stats.bytes -= block.size;
+        Some(block)
+    }
+}
+
+/// An [`ObjectStore`] that caches ranges read from another store on local
+/// disk.
+///
+/// Writes, deletes, and listings go straight to the wrapped store; writes and
+/// deletes also drop the cached blocks of the objects involved.
+pub struct DiskCachedStore {
+    inner: Arc<dyn ObjectStore>,
+    dir: PathBuf,
+    options: DiskCacheOptions,
+    state: Mutex<State>,
+}
+
+impl DiskCachedStore {
+    /// Wraps `inner`, keeping cached blocks in `dir`.
+    ///
+    /// The cache owns `dir`: it is created if needed and everything in it is
+    /// deleted, because blocks left behind by a previous process can't be
+    /// trusted to be current.
+    pub fn try_new(
+        inner: Arc<dyn ObjectStore>,
+        dir: impl Into<PathBuf>,
+        options: DiskCacheOptions,
+    ) -> std::io::Result<Self> {
+        let dir = dir.into();
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)?;
+        }
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            inner,
+            dir,
+            options: DiskCacheOptions {
+                block_size: options.block_size.max(1),
+                ..options
+            },
+            state: Mutex::default(),
+        })
+    }
+
+    pub fn stats(&self) -> DiskCacheStats {
+        self.lock().stats
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // Every critical section leaves the state consistent, so a poisoned
+        // lock is still usable.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    async fn object_size(&self, location: &Path) -> ObjectStoreResult<usize> {
+        if let Some(&size) = self.lock().sizes.get(location) {
+            return Ok(size);
+        }
+        let size = self.inner.head(location).await?.size;
+        self.lock().sizes.insert(location.clone(), size);
+        Ok(size)
+    }
+
+    /// Returns the file of a fresh cached block, marking it as recently used.
+    fn lookup(&self, key: &BlockKey) -> Option<PathBuf> {
+        let mut state = self.lock();
+        let expired = match state.blocks.get(key) {
+            None => {
+                state.stats.misses += 1;
+                registry().cache_misses(Cache::Disk).inc_by(1);
+                return None;
+            }
+            Some(block) => block.created.elapsed() > self.options.max_age,
+        };
+        if expired {
+            let block = state.remove(key)?;
+            state.stats.misses += 1;
+            registry().cache_misses(Cache::Disk).inc_by(1);
+            drop(state);
+            let _ = std::fs::remove_file(block.file);
+            return None;
+        }
+
+        state.tick += 1;
+        let tick = state.tick;
+        state.stats.hits += 1;
+        registry().cache_hits(Cache::Disk).inc_by(1);
+        let block = state.blocks.get_mut(key)?;
+        let old_tick = std::mem::replace(&mut block.last_used, tick);
+        let file = block.file.clone();
+        state.lru.remove(&old_tick);
+        state.lru.insert(tick, key.clone());
+        Some(file)
+    }
+
+    /// Writes a fetched block to disk and registers it, evicting old blocks.
+    async fn store(&self, key: BlockKey, data: Bytes) -> ObjectStoreResult<()> {
+        let size = data.len() as u64;
+        if size > self.options.max_bytes {
+            return Ok(());
+        }
+        let file = {
+            let mut state = self.lock();
+            state.next_file += 1;
+            self.dir.join(format!("{:016x}.block", state.next_file))
+        };
+        let path = file.clone();
+        blocking(move || std::fs::write(path, data)).await?;
+
+        let mut victims = Vec::new();
+        {
+            let mut state = self.lock();
+            if let Some(old) = state.remove(&key) {
+                victims.push(old.file);
+            }
+            while state.stats.bytes + size > self.options.max_bytes {
+                let Some((_, victim)) = state.lru.pop_first() else {
+                    break;
+                };
+                if let Some(old) = state.blocks.remove(&victim) {
+                    state.stats.bytes -= old.size;
+                    state.stats.evictions += 1;
+                    victims.push(old.file);
+                }
+            }
+            state.tick += 1;
+            let tick = state.tick;
+            state.lru.insert(tick, key.clone());
+            state.blocks.insert(
+                key,
+                Block {
+                    file,
+                    size,
+                    created: Instant::now(),
+                    last_used: tick,
+                },
+            );
+            state.stats.bytes += size;
+        }
+        if !victims.is_empty() {
+            blocking(move || {
+                for file in victims {
+                    let _ = std::fs::remove_file(file);
+                }
+                Ok(())
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Drops the cached blocks and size of the object at `location`.
+    fn invalidate(&self, location: &Path) {
+        let files: Vec<_> = {
+            let mut state = self.lock();
+            state.sizes.remove(location);
+            let keys: Vec<_> = state
+                .blocks
+                .keys()
+                .filter(|key| &key.location == location)
+                .cloned()
+                .collect();
+            keys.iter()
+                .filter_map(|key| state.remove(key))
+                .map(|block| block.file)
+                .collect()
+        };
+        for file in files {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+
+    async fn read_range(&self, location: &Path, range: Range<usize>) -> ObjectStoreResult<Bytes> {
+        if range.is_empty() {
+            return Ok(Bytes::new());
+        }
+        let size = self.object_size(location).await?;
+        let bs = self.options.block_size;
+        let end = range.end.min(size);
+        if range.start >= end {
+            // Let the wrapped store report the out-of-bounds range.
+            return self.inner.get_range(location, range).await;
+        }
+
+        let blocks: Vec<_> = (range.start / bs..=(end - 1) / bs).collect();
+        let mut data: Vec<Option<Bytes>> = Vec::with_capacity(blocks.len());
+        let mut missing = Vec::new();
+        for &block in &blocks {
+            let key = BlockKey {
+                location: location.clone(),
+                block,
+            };
+            let cached = match self.lookup(&key) {
+                // A block file that vanished is simply refetched.
+                Some(file) => blocking(move || Ok(std::fs::read(file).ok()))
+                    .await?
+                    .map(Bytes::from),
+                None => None,
+            };
+            if cached.is_none() {
+                missing.push(block);
+            }
+            data.push(cached);
+        }
+
+        if !missing.is_empty() {
+            let ranges: Vec<_> = missing
+                .iter()
+                .map(|&block| block * bs..((block + 1) * bs).min(size))
+                .collect();
+            let fetched = self.inner.get_ranges(location, &ranges).await?;
+            for (block, bytes) in missing.into_iter().zip(fetched) {
+                data[block - blocks[0]] = Some(bytes.clone());
+                let key = BlockKey {
+                    location: location.clone(),
+                    block,
+                };
+                self.store(key, bytes).await?;
+            }
+        }
+
+        let mut out = BytesMut::with_capacity(end - range.start);
+        for (block, bytes) in blocks.into_iter().zip(data) {
+            let bytes = bytes.unwrap_or_default();
+            let block_start = block * bs;
+            let from = range.start.saturating_sub(block_start).min(bytes.len());
+            let to = (end - block_start).min(bytes.len());
+            out.extend_from_slice(&bytes[from..to]);
+        }
+        Ok(out.freeze())
+    }
+}
+
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> std::io::Result<T> + Send + 'static,
+) -> ObjectStoreResult<T> {
+    match runtime::spawn_blocking(&*runtime::current(), f).await {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(e)) => Err(object_store::Error::Generic {
+            store: STORE_NAME,
+            source: Box::new(e),
+        }),
+        Err(e) => Err(object_store::Error::Generic {
+            store: STORE_NAME,
+            source: Box::new(e),
+        }),
+    }
+}
+
+impl fmt::Display for DiskCachedStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DiskCachedStore({}, {})", self.inner, self.dir.display())
+    }
+}
+
+impl fmt::Debug for DiskCachedStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DiskCachedStore")
+            .field("inner", &self.inner)
+            .field("dir", &self.dir)
+            .field("options", &self.options)
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl ObjectStore for DiskCachedStore {
+    async fn put(&self, location: &Path, bytes: Bytes) -> ObjectStoreResult<()> {
+        self.invalidate(location);
+        self.inner.put(location, bytes).await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> ObjectStoreResult<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.invalidate(location);
+        self.inner.put_multipart(location).await
+    }
+
+    async fn abort_multipart(
+        &self,
+        location: &Path,
+        multipart_id: &MultipartId,
+    ) -> ObjectStoreResult<()> {
+        self.inner.abort_multipart(location, multipart_id).await
+    }
+
+    async fn get(&self, location: &Path) -> ObjectStoreResult<GetResult> {
+        // Whole-object reads stream from the wrapped store uncached.
+        self.inner.get(location).await
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> ObjectStoreResult<Bytes> {
+        self.read_range(location, range).await
+    }
+
+    async fn get_ranges(
+        &self,
+        location: &Path,
+        ranges: &[Range<usize>],
+    ) -> ObjectStoreResult<Vec<Bytes>> {
+        let mut out = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            out.push(self.read_range(location, range.clone()).await?);
+        }
+        Ok(out)
+    }
+
+    async fn head(&self, location: &Path) -> ObjectStoreResult<ObjectMeta> {
+        self.inner.head(location).await
+    }
+
+    async fn delete(&self, location: &Path) -> ObjectStoreResult<()> {
+        self.invalidate(location);
+        self.inner.delete(location).await
+    }
+
+    // `async_trait` names the lifetime of `&self` behind the scenes.
+    #[allow(mismatched_lifetime_syntaxes)]
+    async fn list(
+        &self,
+        prefix: Option<&Path>,
+    ) -> ObjectStoreResult<BoxStream<'_, ObjectStoreResult<ObjectMeta>>> {
+        self.inner.list(prefix).await
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> ObjectStoreResult<ListResult> {
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        self.invalidate(to);
+        self.inner.copy(from, to).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        self.invalidate(from);
+        self.invalidate(to);
+        self.inner.rename(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        self.invalidate(to);
+        self.inner.copy_if_not_exists(from, to).await
+    }
+
+    async fn rename_if_not_exists(&self, from: &Path, to: &Path) -> ObjectStoreResult<()> {
+        self.invalidate(from);
+        self.invalidate(to);
+        self.inner.rename_if_not_exists(from, to).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    #[tokio::test]
+    async fn test_disk_cache() {
+        let inner = Arc::new(InMemory::new());
+        let location = Path::from("logs/1.parquet");
+        let data: Bytes = (0..100u8).collect::<Vec<_>>().into();
+        inner.put(&location, data.clone()).await.unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let options = DiskCacheOptions {
+            block_size: 16,
+            max_bytes: 64,
+            ..Default::default()
+        };
+        let store = DiskCachedStore::try_new(inner, dir.path().join("cache"), options).unwrap();
+
+        assert_eq!(
+            store.get_range(&location, 10..40).await.unwrap(),
+            data[10..40]
+        );
+        let stats = store.stats();
+        assert_eq!((stats.hits, stats.misses, stats.bytes), (0, 3, 48));
+
+        assert_eq!(
+            store.get_range(&location, 20..30).await.unwrap(),
+            data[20..30]
+        );
+        assert_eq!(store.stats().hits, 1);
+
+        // the last block is short, and the size bound evicts the oldest
+        assert_eq!(
+            store.get_range(&location, 90..100).await.unwrap(),
+            data[90..100]
+        );
+        let stats = store.stats();
+        assert_eq!((stats.bytes, stats.evictions), (52, 1));
+        assert_eq!(
+            store.get_range(&location, 50..60).await.unwrap(),
+            data[50..60]
+        );
+        assert_eq!(store.stats().evictions, 2);
+        assert_eq!(
+            store.get_range(&location, 20..30).await.unwrap(),
+            data[20..30]
+        );
+        assert_eq!(store.stats().hits, 2);
+
+        // writes invalidate
+        let data: Bytes = vec![7; 100].into();
+        store.put(&location, data.clone()).await.unwrap();
+        assert_eq!(
+            store.get_range(&location, 10..40).await.unwrap(),
+            data[10..40]
+        );
+        assert_eq!(store.stats().bytes, 48);
+    }
+}
@@ -0,0 +1,397 @@
+//! Scale-out search over Flight workers
+//!
+//! A [`Coordinator`] spreads a search over [`SearchService`] workers serving
+//! the same [`PartitionLayout`] from shared storage.  It lists the files of
+//! the time range of the [`SearchRequest`], deals them out to the workers,
+//! sends each worker the request with its shard of the files, and merges the
+//! streamed results: counts and histogram buckets are summed, and the
+//! matching rows are gathered up to a global limit, optionally ordered by
+//! time or kept in the order of their files.
+//!
+//! Only available with the `flight` feature.
+//!
+//! [`SearchService`]: crate::server::SearchService
+
+use crate::{
+    compact::{adapt, merged_schema},
+    partition::PartitionLayout,
+    server::{relative_path, SearchRequest, SearchTicket},
+    ZnError, ZnResult,
+};
+use arrow::{
+    array::{as_primitive_array, Int64Array, UInt64Array},
+    compute::{concat_batches, sort_to_indices, take, SortOptions},
+    datatypes::{DataType, Field, Int64Type, Schema, UInt64Type},
+    record_batch::RecordBatch,
+};
+use arrow_flight::{error::FlightError, FlightClient, Ticket};
+use futures::{future::try_join_all, stream, StreamExt};
+use std::{collections::BTreeMap, sync::Arc};
+use tonic::{
+    transport::{Channel, Endpoint},
+    Code, Status,
+};
+
+/// Order of the rows of a [`SearchRequest::Filter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    OldestFirst,
+    NewestFirst,
+}
+
+/// How a [`Coordinator`] merges the rows of a [`SearchRequest::Filter`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeOptions {
+    /// Maximum number of rows over all workers.
+    pub limit: Option<usize>,
+    /// Order of the rows by time; in the order they arrive if `None`.
+    pub order: Option<Order>,
+    /// Without an `order`, keeps the rows in the order of the files of the
+    /// time range and of the rows within a file, instead of the order they
+    /// arrive, so that repeated searches return the same rows in the same
+    /// order.  The rows of a worker are buffered until those of the workers
+    /// with earlier files are merged.
+    pub in_file_order: bool,
+}
+
+/// Spreads searches over Flight workers; see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct Coordinator {
+    layout: PartitionLayout,
+    timestamp_column: String,
+    workers: Vec<Channel>,
+}
+
+impl Coordinator {
+    /// Searches the files of `layout`, whose rows hold their time in the
+    /// `timestamp_column`, with the `workers`.
+    pub fn new(
+        layout: PartitionLayout,
+        timestamp_column: impl Into<String>,
+        workers: Vec<Channel>,
+    ) -> Self {
+        Self {
+            layout,
+            timestamp_column: timestamp_column.into(),
+            workers,
+        }
+    }
+
+    /// Connects to the workers at the `endpoints`, e.g. `http://10.0.0.1:50051`.
+    pub async fn connect(
+        layout: PartitionLayout,
+        timestamp_column: impl Into<String>,
+        endpoints: &[&str],
+    ) -> ZnResult<Self> {
+        let mut workers = Vec::with_capacity(endpoints.len());
+        for endpoint in endpoints {
+            workers.push(
+                Endpoint::from_shared(endpoint.to_string())?
+                    .connect()
+                    .await?,
+            );
+        }
+        Ok(Self::new(layout, timestamp_column, workers))
+    }
+
+    /// Runs the search on the workers and returns the merged results, in
+    /// the shape a single [`SearchService`](crate::server::SearchService)
+    /// returns them.
+    ///
+    /// Without an [`Order`], the workers' scans stop as soon as the limit
+    /// is reached; with one, all matching rows are gathered first.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::InvalidArgument`] if there are no workers, and
+    /// [`ZnError::Remote`] if the search fails on a worker.
+    pub async fn search(
+        &self,
+        request: &SearchRequest,
+        options: &MergeOptions,
+    ) -> ZnResult<Vec<RecordBatch>> {
+        if self.workers.is_empty() {
+            return Err(ZnError::invalid_argument("no workers"));
+        }
+        let calls = self
+            .shards(request, options)?
+            .into_iter()
+            .zip(&self.workers)
+            .enumerate()
+            // The first worker answers even if there is nothing to search,
+            // so that the results have their usual shape.
+            .filter(|(i, (files, _))| *i == 0 || !files.is_empty())
+            .map(|(_, (files, worker))| {
+                let ticket = SearchTicket {
+                    request: request.clone(),
+                    files: Some(files),
+                };
+                let mut client = FlightClient::new(worker.clone());
+                async move {
+                    let ticket = serde_json::to_vec(&ticket)
+                        .map_err(|e| ZnError::invalid_argument(e.to_string()))?;
+                    client
+                        .do_get(Ticket {
+                            ticket: ticket.into(),
+                        })
+                        .await
+                        .map_err(worker_error)
+                }
+            });
+        let streams = try_join_all(calls).await?;
+        // Each worker returns the rows of its files in order, and the shards
+        // are in file order when it is kept, so their streams are chained.
+        let mut batches = if options.in_file_order && options.order.is_none() {
+            stream::iter(streams).flatten().boxed()
+        } else {
+            stream::select_all(streams).boxed()
+        };
+
+        match request {
+            SearchRequest::Count { .. } => {
+                let mut count = 0;
+                while let Some(batch) = batches.next().await {
+                    let batch = batch.map_err(worker_error)?;
+                    let counts: &UInt64Array = as_primitive_array::<UInt64Type>(batch.column(0));
+                    count += counts.values().iter().sum::<u64>();
+                }
+                let schema = Schema::new(vec![Field::new("count", DataType::UInt64, false)]);
+                let counts = UInt64Array::from(vec![count]);
+                Ok(vec![RecordBatch::try_new(
+                    Arc::new(schema),
+                    vec![Arc::new(counts)],
+                )?])
+            }
+            SearchRequest::Histogram { .. } => {
+                let mut buckets = BTreeMap::new();
+                while let Some(batch) = batches.next().await {
+                    let batch = batch.map_err(worker_error)?;
+                    let starts: &Int64Array = as_primitive_array::<Int64Type>(batch.column(0));
+                    let counts: &UInt64Array = as_primitive_array::<UInt64Type>(batch.column(1));
+                    for (&start, &count) in starts.values().iter().zip(counts.values()) {
+                        *buckets.entry(start).or_insert(0) += count;
+                    }
+                }
+                let schema = Schema::new(vec![
+                    Field::new("bucket", DataType::Int64, false),
+                    Field::new("count", DataType::UInt64, false),
+                ]);
+                let starts: Int64Array = buckets.keys().copied().collect();
+                let counts: UInt64Array = buckets.values().copied().collect();
+                Ok(vec![RecordBatch::try_new(
+                    Arc::new(schema),
+                    vec![Arc::new(starts), Arc::new(counts)],
+                )?])
+            }
+            SearchRequest::Filter { .. } => {
+                let mut gathered = Vec::new();
+                let mut rows = 0;
+                while let Some(batch) = batches.next().await {
+                    let batch = batch.map_err(worker_error)?;
+                    rows += batch.num_rows();
+                    gathered.push(batch);
+                    if options.order.is_none() && options.limit.is_some_and(|l| rows >= l) {
+                        break;
+                    }
+                }
+                // Dropping the streams stops the workers' scans.
+                drop(batches);
+                self.merge_rows(gathered, options)
+                    .map(|b| b.into_iter().collect())
+            }
+        }
+    }
+
+    /// Deals the files of the request's range out to the workers,
+    /// round-robin, or in consecutive runs to keep them
+    /// [in file order](MergeOptions::in_file_order).
+    fn shards(
+        &self,
+        request: &SearchRequest,
+        options: &MergeOptions,
+    ) -> ZnResult<Vec<Vec<String>>> {
+        let files = self.layout.files(request.range())?;
+        let run = files.len().div_ceil(self.workers.len()).max(1);
+        let mut shards = vec![Vec::new(); self.workers.len()];
+        for (i, path) in files.iter().enumerate() {
+            let shard = match options.in_file_order {
+                true => i / run,
+                false => i % self.workers.len(),
+            };
+            if let Some(path) = relative_path(self.layout.root(), path) {
+                shards[shard].push(path);
+            }
+        }
+        Ok(shards)
+    }
+
+    /// Concatenates the rows, orders them, and applies the limit.
+    fn merge_rows(
+        &self,
+        batches: Vec<RecordBatch>,
+        options: &MergeOptions,
+    ) -> ZnResult<Option<RecordBatch>> {
+        if batches.is_empty() {
+            return Ok(None);
+        }
+        let schemas: Vec<_> = batches
+            .iter()
+            .map(|b| Schema::new(b.schema().fields().clone()))
+            .collect();
+        let schema = merged_schema(&schemas)?;
+        let batches = batches
+            .iter()
+            .map(|b| adapt(b, &schema))
+            .collect::<ZnResult<Vec<_>>>()?;
+        let mut batch = concat_batches(&schema, &batches)?;
+        if let Some(order) = options.order {
+            let column = schema.index_of(&self.timestamp_column).map_err(|_| {
+                ZnError::invalid_argument(format!(
+                    "no timestamp column {:?}",
+                    self.timestamp_column
+                ))
+            })?;
+            let sort = SortOptions {
+                descending: order == Order::NewestFirst,
+                nulls_first: false,
+            };
+            let indices = sort_to_indices(batch.column(column), Some(sort), options.limit)?;
+            let columns = batch
+                .columns()
+                .iter()
+                .map(|c| take(c, &indices, None))
+                .collect::<Result<_, _>>()?;
+            batch = RecordBatch::try_new(schema, columns)?;
+        }
+        if let Some(limit) = options.limit {
+            batch = batch.slice(0, limit.min(batch.num_rows()));
+        }
+        Ok(Some(batch))
+    }
+}
+
+fn worker_error(e: FlightError) -> ZnError {
+    match e {
+        FlightError::Arrow(e) => e.into(),
+        FlightError::Tonic(status) if status.code() == Code::ResourceExhausted => {
+            ZnError::overloaded(status.message())
+        }
+        FlightError::Tonic(status) => ZnError::remote(status),
+        e => ZnError::remote(Status::internal(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        partition::Granularity,
+        server::{serve, SearchService},
+        writer::{write_ndjson, WriterOptions},
+    };
+    use std::{fs::File, net::TcpListener, time::Duration};
+
+    async fn worker(service: SearchService) -> Channel {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        tokio::spawn(serve(service, addr));
+        for _ in 0..100 {
+            if let Ok(channel) = Endpoint::from_shared(format!("http://{addr}"))
+                .unwrap()
+                .connect()
+                .await
+            {
+                return channel;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("worker at {addr} did not start");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_coordinator() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = PartitionLayout::new(dir.path(), Granularity::Day);
+        let partition = layout.partition_dir(0).unwrap();
+        std::fs::create_dir_all(&partition).unwrap();
+        for (i, times) in [[10, 40], [20, 50], [30, 60]].iter().enumerate() {
+            let lines: Vec<_> = times
+                .iter()
+                .map(|t| format!(r#"{{"_timestamp": {t}, "log": "error {t}"}}"#))
+                .collect();
+            let file = File::create(partition.join(format!("{i}.parquet"))).unwrap();
+            write_ndjson(lines.join("\n").as_bytes(), file, &WriterOptions::default()).unwrap();
+        }
+        let service = SearchService::new(layout.clone(), "_timestamp");
+        let workers = vec![worker(service.clone()).await, worker(service).await];
+        let coordinator = Coordinator::new(layout, "_timestamp", workers);
+
+        let needle = "error".to_owned();
+        let request = SearchRequest::Count {
+            needle: needle.clone(),
+            start: 0,
+            end: 100,
+        };
+        let batches = coordinator
+            .search(&request, &MergeOptions::default())
+            .await
+            .unwrap();
+        let counts: &UInt64Array = as_primitive_array::<UInt64Type>(batches[0].column(0));
+        assert_eq!(counts.value(0), 6);
+
+        let request = SearchRequest::Histogram {
+            needle: needle.clone(),
+            start: 0,
+            end: 60,
+            interval: 30,
+        };
+        let batches = coordinator
+            .search(&request, &MergeOptions::default())
+            .await
+            .unwrap();
+        let counts: &UInt64Array = as_primitive_array::<UInt64Type>(batches[0].column(1));
+        assert_eq!(counts.values(), &[2, 3]);
+
+        let request = SearchRequest::Filter {
+            needle,
+            start: 0,
+            end: 100,
+        };
+        let options = MergeOptions {
+            limit: Some(2),
+            order: Some(Order::NewestFirst),
+            ..MergeOptions::default()
+        };
+        let batches = coordinator.search(&request, &options).await.unwrap();
+        let column = batches[0].schema().index_of("_timestamp").unwrap();
+        let times: &Int64Array = as_primitive_array::<Int64Type>(batches[0].column(column));
+        assert_eq!(times.values(), &[60, 50]);
+
+        let options = MergeOptions {
+            in_file_order: true,
+            ..MergeOptions::default()
+        };
+        for _ in 0..3 {
+            let batches = coordinator.search(&request, &options).await.unwrap();
+            let column = batches[0].schema().index_of("_timestamp").unwrap();
+            let times: &Int64Array = as_primitive_array::<Int64Type>(batches[0].column(column));
+            assert_eq!(times.values(), &[10, 40, 20, 50, 30, 60]);
+        }
+
+        let options = MergeOptions {
+            limit: Some(1),
+            ..MergeOptions::default()
+        };
+        let batches = coordinator.search(&request, &options).await.unwrap();
+        assert_eq!(batches[0].num_rows(), 1);
+
+        let idle = Coordinator::new(
+            PartitionLayout::new(dir.path(), Granularity::Day),
+            "_timestamp",
+            Vec::new(),
+        );
+        assert!(idle.search(&request, &options).await.is_err());
+    }
+}
@@ -0,0 +1,341 @@
+//! Downsampled match counts for histograms
+//!
+//! Dashboards chart the number of rows matching a needle over long time
+//! ranges, e.g. errors per hour over a month, and every refresh scans all
+//! rows of the range.  A [`SeriesSpec`] names the needles worth charting and
+//! a base interval; [`merge_with_series`] computes the [`Series`] of the
+//! files that compaction writes, the match counts of each needle per base
+//! bucket, to be stored next to them (see [`Series::sidecar_path`]).
+//!
+//! [`histogram`] then answers a count-over-time query from the series of
+//! the files that have one for the needle, as long as the query's buckets
+//! are made of whole base buckets, and scans only the other files, typically
+//! the recent ones that compaction has not reached yet.
+
+use crate::{
+    arrow::match_mask,
+    compact::merge,
+    storage::{RangeChunkReader, RangeReader},
+    ZnError, ZnResult,
+};
+use arrow::{
+    array::{Array, Int64Array},
+    compute::cast,
+    datatypes::{DataType, TimeUnit},
+};
+use bytes::Bytes;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Version of the JSON format of a [`Series`].
+const FORMAT: u64 = 1;
+
+/// Upper bound of the number of buckets of a [`histogram`].
+const MAX_BUCKETS: i64 = 1 << 20;
+
+/// The needles and base interval of [`Series`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeriesSpec {
+    /// Int64 or microsecond timestamp column holding the time of a row, in
+    /// microseconds since the Unix epoch.
+    pub timestamp_column: String,
+    /// Length of a base bucket, in microseconds.
+    pub interval: i64,
+    pub needles: Vec<String>,
+}
+
+impl SeriesSpec {
+    pub fn new(interval: i64, needles: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            timestamp_column: "_timestamp".to_owned(),
+            interval,
+            needles: needles.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Counts the rows of the parquet `file` that match each needle, like
+    /// the Flight server's histograms, per base bucket.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::InvalidArgument`] if the interval is not positive,
+    /// a needle is empty, or the file lacks the timestamp column.
+    pub fn compute<R: RangeReader + ?Sized + 'static>(&self, file: Arc<R>) -> ZnResult<Series> {
+        if self.interval <= 0 {
+            return Err(ZnError::invalid_argument("interval must be positive"));
+        }
+        if self.needles.iter().any(String::is_empty) {
+            return Err(ZnError::empty_needle());
+        }
+        let mut series = Series {
+            interval: self.interval,
+            counts: self
+                .needles
+                .iter()
+                .map(|needle| (needle.clone(), BTreeMap::new()))
+                .collect(),
+        };
+        let reader = ParquetRecordBatchReaderBuilder::try_new(RangeChunkReader::try_new(file)?)?;
+        for batch in reader.build()? {
+            let batch = batch?;
+            let times = timestamps(&batch, &self.timestamp_column)?;
+            for (needle, counts) in &mut series.counts {
+                let mask = match_mask(&batch, needle)?;
+                for (t, matched) in times.iter().zip(mask.iter()) {
+                    if let (Some(t), Some(true)) = (t, matched) {
+                        let bucket = t.div_euclid(self.interval) * self.interval;
+                        *counts.entry(bucket).or_default() += 1;
+                    }
+                }
+            }
+        }
+        Ok(series)
+    }
+}
+
+/// Match counts of some needles per bucket of a file; see the
+/// [module docs](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Series {
+    /// Length of a bucket, in microseconds.
+    pub interval: i64,
+    /// Number of matching rows per bucket start, per needle.
+    pub counts: BTreeMap<String, BTreeMap<i64, u64>>,
+}
+
+impl Series {
+    /// Returns the conventional location of the series of the parquet file
+    /// at `parquet_path`: the same path with `.series` appended.
+    pub fn sidecar_path(parquet_path: impl AsRef<Path>) -> PathBuf {
+        let mut path = parquet_path.as_ref().as_os_str().to_owned();
+        path.push(".series");
+        path.into()
+    }
+
+    /// Returns `true` if the series counts the `needle` in whole buckets of
+    /// `range` and `interval`.
+    pub fn covers(&self, needle: &str, range: &Range<i64>, interval: i64) -> bool {
+        self.counts.contains_key(needle)
+            && interval % self.interval == 0
+            && range.start.rem_euclid(self.interval) == 0
+            && range.end.rem_euclid(self.interval) == 0
+    }
+
+    /// Serializes the series as JSON.
+    pub fn to_bytes(&self) -> Bytes {
+        let counts: serde_json::Map<_, _> = self
+            .counts
+            .iter()
+            .map(|(needle, counts)| {
+                let counts: Vec<_> = counts.iter().map(|(b, c)| json!([b, c])).collect();
+                (needle.clone(), Value::Array(counts))
+            })
+            .collect();
+        let value = json!({"format": FORMAT, "interval": self.interval, "counts": counts});
+        value.to_string().into()
+    }
+
+    /// Deserializes a series written with [`to_bytes`](Self::to_bytes).
+    pub fn from_bytes(data: &[u8]) -> ZnResult<Self> {
+        let invalid = || ZnError::invalid_index("not a downsampled series");
+        let value: Value = serde_json::from_slice(data).map_err(|_| invalid())?;
+        if value.get("format").and_then(Value::as_u64) != Some(FORMAT) {
+            return Err(invalid());
+        }
+        let interval = value
+            .get("interval")
+            .and_then(Value::as_i64)
+            .filter(|&i| i > 0)
+            .ok_or_else(invalid)?;
+        let mut counts = BTreeMap::new();
+        for (needle, buckets) in value
+            .get("counts")
+            .and_then(Value::as_object)
+            .ok_or_else(invalid)?
+        {
+            let buckets: Vec<(i64, u64)> =
+                serde_json::from_value(buckets.clone()).map_err(|_| invalid())?;
+            counts.insert(needle.clone(), buckets.into_iter().collect());
+        }
+        Ok(Self { interval, counts })
+    }
+
+    /// Writes the series to the file at `path`, replacing it if it exists.
+    pub fn save(&self, path: impl AsRef<Path>) -> ZnResult<()> {
+        Ok(std::fs::write(path, self.to_bytes())?)
+    }
+
+    /// Reads a series previously written with [`save`](Self::save).
+    pub fn load(path: impl AsRef<Path>) -> ZnResult<Self> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+/// A file written by [`merge_with_series`] with its series.
+#[derive(Debug, Clone)]
+pub struct Downsampled {
+    pub file: Bytes,
+    pub series: Series,
+}
+
+/// Like [`merge`], but also computes the series of every merged file.
+///
+/// The series of a file replaces those of the files merged into it.
+pub fn merge_with_series<R: RangeReader + ?Sized + 'static>(
+    files: &[Arc<R>],
+    target_size: u64,
+    sort_by: Option<&str>,
+    spec: &SeriesSpec,
+) -> ZnResult<Vec<Downsampled>> {
+    merge(files, target_size, sort_by)?
+        .into_iter()
+        .map(|file| {
+            let series = spec.compute(Arc::new(file.clone()))?;
+            Ok(Downsampled { file, series })
+        })
+        .collect()
+}
+
+/// Number of matching rows per bucket of a [`histogram`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    /// Start of the first bucket.
+    pub start: i64,
+    pub interval: i64,
+    pub counts: Vec<u64>,
+    /// Files answered from their series.
+    pub files_downsampled: usize,
+    /// Files whose rows were scanned.
+    pub files_scanned: usize,
+}
+
+/// Counts the rows of the `files` that match the `needle` per `interval`
+/// microseconds of the time `range`, answering from the series of a file if
+/// it [covers](Series::covers) the query, and scanning it otherwise.
+///
+/// # Errors
+///
+/// Returns [`ZnError::EmptyNeedle`] if the `needle` is empty, and
+/// [`ZnError::InvalidArgument`] if the interval is not positive, the
+/// histogram has too many buckets, or a scanned file lacks the
+/// `timestamp_column`.
+pub fn histogram<R: RangeReader + ?Sized + 'static>(
+    files: &[(Arc<R>, Option<&Series>)],
+    timestamp_column: &str,
+    needle: &str,
+    range: Range<i64>,
+    interval: i64,
+) -> ZnResult<Histogram> {
+    if needle.is_empty() {
+        return Err(ZnError::empty_needle());
+    }
+    if interval <= 0 {
+        return Err(ZnError::invalid_argument("interval must be positive"));
+    }
+    let num_buckets = (range.end.saturating_sub(range.start).max(0) + interval - 1) / interval;
+    if num_buckets > MAX_BUCKETS {
+        return Err(ZnError::invalid_argument(format!(
+            "histogram of {num_buckets} buckets, at most {MAX_BUCKETS} are allowed"
+        )));
+    }
+    let mut histogram = Histogram {
+        start: range.start,
+        interval,
+        counts: vec![0; num_buckets as usize],
+        files_downsampled: 0,
+        files_scanned: 0,
+    };
+    let bucket = |t: i64| ((t - range.start) / interval) as usize;
+    for (file, series) in files {
+        if let Some(series) = series.filter(|s| s.covers(needle, &range, interval)) {
+            for (&start, &count) in series.counts[needle].range(range.clone()) {
+                histogram.counts[bucket(start)] += count;
+            }
+            histogram.files_downsampled += 1;
+            continue;
+        }
+        let reader =
+            ParquetRecordBatchReaderBuilder::try_new(RangeChunkReader::try_new(file.clone())?)?;
+        for batch in reader.build()? {
+            let batch = batch?;
+            let times = timestamps(&batch, timestamp_column)?;
+            let mask = match_mask(&batch, needle)?;
+            for (t, matched) in times.iter().zip(mask.iter()) {
+                if let (Some(t), Some(true)) = (t, matched) {
+                    if range.contains(&t) {
+                        histogram.counts[bucket(t)] += 1;
+                    }
+                }
+            }
+        }
+        histogram.files_scanned += 1;
+    }
+    Ok(histogram)
+}
+
+/// Returns the `column` of the `batch` in microseconds.
+fn timestamps(batch: &arrow::record_batch::RecordBatch, column: &str) -> ZnResult<Int64Array> {
+    let i = batch
+        .schema()
+        .index_of(column)
+        .map_err(|_| ZnError::invalid_argument(format!("no timestamp column {column:?}")))?;
+    let array = batch.column(i);
+    match array.data_type() {
+        DataType::Int64 | DataType::Timestamp(TimeUnit::Microsecond, _) => (),
+        t => {
+            return Err(ZnError::unsupported_type(format!(
+                "timestamp column {column:?} is of type {t}"
+            )))
+        }
+    }
+    let array = cast(array, &DataType::Int64)?;
+    Ok(array
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .expect("cast to Int64")
+        .clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::parquet_bytes;
+
+    #[test]
+    fn test_downsampled_histogram() {
+        let old = Arc::new(parquet_bytes(&["error a", "ok", "error b", "error c"], 2));
+        let recent = Arc::new(parquet_bytes(&["error d", "ok"], 1));
+        let spec = SeriesSpec {
+            timestamp_column: "id".to_owned(),
+            ..SeriesSpec::new(2, ["error"])
+        };
+        let compacted = merge_with_series(&[old], u64::MAX, None, &spec).unwrap();
+        let series = &compacted[0].series;
+        assert_eq!(series.counts["error"], BTreeMap::from([(0, 1), (2, 2)]));
+        assert_eq!(&Series::from_bytes(&series.to_bytes()).unwrap(), series);
+
+        let files = [
+            (Arc::new(compacted[0].file.clone()), Some(series)),
+            (recent, None),
+        ];
+        let h = histogram(&files, "id", "error", 0..4, 4).unwrap();
+        assert_eq!(h.counts, [4]);
+        assert_eq!((h.files_downsampled, h.files_scanned), (1, 1));
+
+        // Buckets splitting base buckets, or another needle, need a scan.
+        let h = histogram(&files, "id", "error", 0..4, 1).unwrap();
+        assert_eq!(h.counts, [2, 0, 1, 1]);
+        assert_eq!(h.files_scanned, 2);
+        let h = histogram(&files, "id", "ok", 0..4, 2).unwrap();
+        assert_eq!((h.counts, h.files_scanned), (vec![2, 0], 2));
+
+        assert!(histogram(&files, "id", "", 0..4, 1).is_err());
+        assert!(Series::from_bytes(b"{}").is_err());
+    }
+}
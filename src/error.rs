@@ -1,3 +1,4 @@
+use std::sync::{Arc, RwLock};
 use thiserror::Error;
 
 pub type ZnResult<T, E = ZnError> = Result<T, E>;
@@ -5,11 +6,219 @@ pub type ZnResult<T, E = ZnError> = Result<T, E>;
 #[derive(Debug, Error)]
 pub enum ZnError {
     #[error(transparent)]
-    Io(#[from] std::io::Error),
+    Io(std::io::Error),
 
     #[error(transparent)]
-    Parquet(#[from] parquet::errors::ParquetError),
+    Parquet(parquet::errors::ParquetError),
 
     #[error(transparent)]
-    Arrow(#[from] arrow_schema::ArrowError),
+    Arrow(arrow_schema::ArrowError),
+
+    #[cfg(feature = "datafusion")]
+    #[error(transparent)]
+    DataFusion(datafusion::error::DataFusionError),
+
+    #[cfg(feature = "native")]
+    #[error(transparent)]
+    ObjectStore(object_store::Error),
+
+    #[cfg(feature = "tantivy")]
+    #[error(transparent)]
+    Tantivy(tantivy::TantivyError),
+
+    #[cfg(feature = "flight")]
+    #[error(transparent)]
+    Transport(tonic::transport::Error),
+
+    /// A search failed on a worker of a
+    /// [`Coordinator`](crate::distributed::Coordinator).
+    #[cfg(feature = "flight")]
+    #[error("worker failed: {0}")]
+    Remote(Box<tonic::Status>),
+
+    #[error("needle must not be empty")]
+    EmptyNeedle,
+
+    #[error("invalid parquet metadata: {0}")]
+    InvalidMetadata(String),
+
+    #[error("unsupported column type: {0}")]
+    UnsupportedType(String),
+
+    #[error("invalid index: {0}")]
+    InvalidIndex(String),
+
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+
+    /// A search was rejected by the [limiter](crate::limits).
+    #[error("overloaded: {0}")]
+    Overloaded(String),
+
+    /// A search was aborted by its [`CancelToken`](crate::cancel::CancelToken).
+    #[error("search was cancelled")]
+    Cancelled,
+}
+
+impl ZnError {
+    /// Returns a stable, machine-readable name of the error class, suitable
+    /// for use as a metrics label.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ZnError::Io(_) => "io",
+            ZnError::Parquet(_) => "parquet",
+            ZnError::Arrow(_) => "arrow",
+            #[cfg(feature = "datafusion")]
+            ZnError::DataFusion(_) => "datafusion",
+            #[cfg(feature = "native")]
+            ZnError::ObjectStore(_) => "object_store",
+            #[cfg(feature = "tantivy")]
+            ZnError::Tantivy(_) => "tantivy",
+            #[cfg(feature = "flight")]
+            ZnError::Transport(_) => "transport",
+            #[cfg(feature = "flight")]
+            ZnError::Remote(_) => "remote",
+            ZnError::EmptyNeedle => "empty_needle",
+            ZnError::InvalidMetadata(_) => "invalid_metadata",
+            ZnError::UnsupportedType(_) => "unsupported_type",
+            ZnError::InvalidIndex(_) => "invalid_index",
+            ZnError::InvalidArgument(_) => "invalid_argument",
+            ZnError::Overloaded(_) => "overloaded",
+            ZnError::Cancelled => "cancelled",
+        }
+    }
+
+    pub(crate) fn empty_needle() -> Self {
+        observed(ZnError::EmptyNeedle)
+    }
+
+    pub(crate) fn invalid_metadata(msg: impl Into<String>) -> Self {
+        observed(ZnError::InvalidMetadata(msg.into()))
+    }
+
+    pub(crate) fn unsupported_type(msg: impl Into<String>) -> Self {
+        observed(ZnError::UnsupportedType(msg.into()))
+    }
+
+    pub(crate) fn invalid_index(msg: impl Into<String>) -> Self {
+        observed(ZnError::InvalidIndex(msg.into()))
+    }
+
+    pub(crate) fn invalid_argument(msg: impl Into<String>) -> Self {
+        observed(ZnError::InvalidArgument(msg.into()))
+    }
+
+    pub(crate) fn overloaded(msg: impl Into<String>) -> Self {
+        observed(ZnError::Overloaded(msg.into()))
+    }
+
+    pub(crate) fn cancelled() -> Self {
+        observed(ZnError::Cancelled)
+    }
+
+    #[cfg(feature = "flight")]
+    pub(crate) fn remote(status: tonic::Status) -> Self {
+        observed(ZnError::Remote(Box::new(status)))
+    }
+}
+
+impl From<std::io::Error> for ZnError {
+    fn from(e: std::io::Error) -> Self {
+        observed(ZnError::Io(e))
+    }
+}
+
+impl From<parquet::errors::ParquetError> for ZnError {
+    fn from(e: parquet::errors::ParquetError) -> Self {
+        observed(ZnError::Parquet(e))
+    }
+}
+
+impl From<arrow_schema::ArrowError> for ZnError {
+    fn from(e: arrow_schema::ArrowError) -> Self {
+        observed(ZnError::Arrow(e))
+    }
+}
+
+#[cfg(feature = "datafusion")]
+impl From<datafusion::error::DataFusionError> for ZnError {
+    fn from(e: datafusion::error::DataFusionError) -> Self {
+        observed(ZnError::DataFusion(e))
+    }
+}
+
+#[cfg(feature = "native")]
+impl From<object_store::Error> for ZnError {
+    fn from(e: object_store::Error) -> Self {
+        observed(ZnError::ObjectStore(e))
+    }
+}
+
+#[cfg(feature = "tantivy")]
+impl From<tantivy::TantivyError> for ZnError {
+    fn from(e: tantivy::TantivyError) -> Self {
+        observed(ZnError::Tantivy(e))
+    }
+}
+
+#[cfg(feature = "flight")]
+impl From<tonic::transport::Error> for ZnError {
+    fn from(e: tonic::transport::Error) -> Self {
+        observed(ZnError::Transport(e))
+    }
+}
+
+type ErrorHook = Arc<dyn Fn(&ZnError) + Send + Sync>;
+
+static ERROR_HOOK: RwLock<Option<ErrorHook>> = RwLock::new(None);
+
+/// Installs a process-wide callback that is invoked every time the crate
+/// constructs a [`ZnError`], replacing the previously installed one.
+///
+/// The callback receives the error itself; [`ZnError::code`] gives the error
+/// class and its `Display` implementation the context.  This lets services
+/// count error classes without wrapping every call site.  The callback must be
+/// cheap, as it runs on the thread that produced the error.
+pub fn set_error_hook(hook: impl Fn(&ZnError) + Send + Sync + 'static) {
+    *ERROR_HOOK.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(hook));
+}
+
+/// Removes the callback installed with [`set_error_hook`].
+pub fn clear_error_hook() {
+    *ERROR_HOOK.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+fn observed(e: ZnError) -> ZnError {
+    let hook = ERROR_HOOK.read().unwrap_or_else(|e| e.into_inner()).clone();
+    if let Some(hook) = hook {
+        hook(&e);
+    }
+    e
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_error_hook() {
+        // The hook is process-wide and sees the errors of tests running
+        // meanwhile, so only those carrying this marker are looked at.
+        const MARKER: &str = "error hook test";
+        let codes = Arc::new(Mutex::new(Vec::new()));
+        let sink = codes.clone();
+        set_error_hook(move |e| {
+            if e.to_string().contains(MARKER) {
+                sink.lock().unwrap().push(e.code());
+            }
+        });
+
+        let _ = ZnError::invalid_argument(MARKER);
+        let _ = ZnError::from(std::io::Error::new(std::io::ErrorKind::NotFound, MARKER));
+        clear_error_hook();
+        let _ = ZnError::invalid_metadata(MARKER);
+
+        assert_eq!(*codes.lock().unwrap(), ["invalid_argument", "io"]);
+    }
 }
@@ -0,0 +1,307 @@
+//! Approximate occurrence counts
+//!
+//! An exact [`count_occurrences`](crate::file::count_occurrences) scans
+//! every row group, which is too slow for rendering a result-count preview
+//! while the user types.  [`estimate_occurrences`] scans a few evenly spaced
+//! row groups instead and extrapolates their selectivity (matching cells per
+//! cell) to the others, returning the estimate with a confidence interval.
+//!
+//! Two more sources narrow the interval:
+//!
+//! * Dictionary pages: a dictionary-encoded column chunk none of whose
+//!   dictionary entries contains the needle has no match at all, provided
+//!   its page encoding statistics show that no data page fell back to plain
+//!   encoding.
+//! * A [`SelectivityHistory`] of earlier counts of the same needle, which
+//!   acts as a prior worth up to [`EstimateOptions::prior_cells`] cells.
+
+use crate::{
+    file::{byte_array_columns, count_in_rows},
+    str::Substring,
+    ZnError, ZnResult,
+};
+use memchr::memmem;
+use parquet::{
+    basic::{Encoding, PageType, Type as BasicType},
+    column::page::Page,
+    file::{metadata::ColumnChunkMetaData, reader::FileReader},
+};
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+};
+
+/// An approximate count with the bounds of its confidence interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Estimate {
+    pub count: u64,
+    pub low: u64,
+    pub high: u64,
+    /// Whether every cell was accounted for exactly, so `low == count ==
+    /// high`.
+    pub exact: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct EstimateOptions {
+    /// Number of row groups to scan.
+    pub sample_row_groups: usize,
+    /// Standard score of the confidence interval, e.g. 1.96 for 95%.
+    pub z: f64,
+    /// Maximum weight of the [`SelectivityHistory`], in cells.
+    pub prior_cells: u64,
+}
+
+impl Default for EstimateOptions {
+    fn default() -> Self {
+        Self {
+            sample_row_groups: 2,
+            z: 1.96,
+            prior_cells: 10_000,
+        }
+    }
+}
+
+/// Matching and total cells observed per needle.
+///
+/// Meant to be shared by all estimates of a process and fed with the
+/// results of exact scans through [`record`](SelectivityHistory::record);
+/// [`estimate_occurrences`] records its samples itself.
+#[derive(Debug, Default)]
+pub struct SelectivityHistory {
+    needles: Mutex<HashMap<Vec<u8>, (u64, u64)>>,
+}
+
+impl SelectivityHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an observation of `matches` matching cells out of `cells`.
+    pub fn record(&self, needle: &[u8], matches: u64, cells: u64) {
+        let mut needles = self.lock();
+        let (m, c) = needles.entry(needle.to_vec()).or_default();
+        *m += matches;
+        *c += cells;
+    }
+
+    /// Returns the matching and total cells observed for `needle`.
+    pub fn get(&self, needle: &[u8]) -> Option<(u64, u64)> {
+        self.lock().get(needle).copied()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<Vec<u8>, (u64, u64)>> {
+        // Each update is a single statement, so a poisoned lock is still
+        // consistent.
+        self.needles.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Estimates [`count_occurrences`](crate::file::count_occurrences) of the
+/// `needle` in `file`.
+///
+/// # Errors
+///
+/// Returns [`ZnError::EmptyNeedle`] if `needle` is empty, and the errors of
+/// reading the sampled row groups and dictionary pages.
+pub fn estimate_occurrences<R: FileReader>(
+    file: &R,
+    needle: &[u8],
+    options: &EstimateOptions,
+    history: Option<&SelectivityHistory>,
+) -> ZnResult<Estimate> {
+    if needle.is_empty() {
+        return Err(ZnError::empty_needle());
+    }
+
+    let metadata = file.metadata();
+    let projection = byte_array_columns(metadata)?;
+    let num_row_groups = metadata.num_row_groups();
+    let samples = options.sample_row_groups.min(num_row_groups);
+    let sampled: Vec<_> = (0..samples).map(|i| i * num_row_groups / samples).collect();
+    let matcher = Substring::new(needle);
+
+    // Exactly counted matches and cells, and cells left to extrapolate to.
+    let (mut matches, mut cells, mut remaining) = (0, 0, 0);
+    for i in 0..num_row_groups {
+        let row_group = file.get_row_group(i)?;
+        let columns = searched_columns(row_group.metadata().columns());
+        if sampled.contains(&i) {
+            let rows = row_group.get_row_iter(Some(projection.clone()))?;
+            matches += count_in_rows(rows, &matcher)? as u64;
+            cells += columns.map(|(_, c)| num_values(c)).sum::<ZnResult<u64>>()?;
+            continue;
+        }
+        for (j, column) in columns {
+            if !dictionary_rules_out(column, || row_group.get_column_page_reader(j), needle)? {
+                remaining += num_values(column)?;
+            }
+        }
+    }
+    if let Some(history) = history {
+        if cells > 0 {
+            history.record(needle, matches, cells);
+        }
+    }
+    if remaining == 0 {
+        return Ok(Estimate {
+            count: matches,
+            low: matches,
+            high: matches,
+            exact: true,
+        });
+    }
+
+    // The history, scaled down to at most `prior_cells`, counts as more
+    // sampled cells.
+    let (mut m, mut n) = (matches as f64, cells as f64);
+    if let Some((prior_matches, prior_cells)) = history.and_then(|h| h.get(needle)) {
+        if prior_cells > cells {
+            // Not counting the sample just recorded twice.
+            let (pm, pc) = (prior_matches - matches, prior_cells - cells);
+            let weight = pc.min(options.prior_cells) as f64;
+            m += pm as f64 * weight / pc as f64;
+            n += weight;
+        }
+    }
+    let (p, low, high) = if n > 0.0 {
+        let p = m / n;
+        let (low, high) = wilson_interval(p, n, options.z);
+        (p, low, high)
+    } else {
+        (0.5, 0.0, 1.0)
+    };
+    let remaining = remaining as f64;
+    Ok(Estimate {
+        count: matches + (p * remaining).round() as u64,
+        low: matches + (low * remaining).floor() as u64,
+        high: matches + (high * remaining).ceil() as u64,
+        exact: false,
+    })
+}
+
+/// Selects the chunks of the columns [`count_in_rows`] searches, with their
+/// indexes.
+fn searched_columns(
+    columns: &[ColumnChunkMetaData],
+) -> impl Iterator<Item = (usize, &ColumnChunkMetaData)> {
+    columns.iter().enumerate().filter(|(_, c)| {
+        crate::file::is_byte_array(c.column_type()) && c.column_path().parts().len() == 1
+    })
+}
+
+fn num_values(column: &ColumnChunkMetaData) -> ZnResult<u64> {
+    column.num_values().try_into().map_err(|_| {
+        ZnError::invalid_metadata(format!(
+            "negative number of values in column {}",
+            column.column_path()
+        ))
+    })
+}
+
+/// Returns whether the dictionary of the `column` chunk proves that no cell
+/// contains the `needle`.
+fn dictionary_rules_out<P: Iterator<Item = parquet::errors::Result<Page>>>(
+    column: &ColumnChunkMetaData,
+    pages: impl FnOnce() -> parquet::errors::Result<P>,
+    needle: &[u8],
+) -> ZnResult<bool> {
+    if column.column_type() != BasicType::BYTE_ARRAY || column.dictionary_page_offset().is_none() {
+        return Ok(false);
+    }
+    // Without page encoding statistics, a writer may have fallen back to
+    // plain encoding for some data pages.
+    let Some(stats) = column.page_encoding_stats() else {
+        return Ok(false);
+    };
+    let all_dictionary_encoded = stats.iter().all(|s| {
+        s.page_type == PageType::DICTIONARY_PAGE
+            || matches!(
+                s.encoding,
+                Encoding::PLAIN_DICTIONARY | Encoding::RLE_DICTIONARY
+            )
+    });
+    if !all_dictionary_encoded {
+        return Ok(false);
+    }
+    let Some(Page::DictionaryPage {
+        buf, num_values, ..
+    }) = pages()?.next().transpose()?
+    else {
+        return Ok(false);
+    };
+
+    // Plain-encoded byte arrays: 4-byte little-endian length, then the bytes.
+    let mut data = buf.data();
+    let finder = memmem::Finder::new(needle);
+    for _ in 0..num_values {
+        let Some((len, rest)) = data.split_first_chunk::<4>() else {
+            return Err(ZnError::invalid_metadata("truncated dictionary page"));
+        };
+        let len = u32::from_le_bytes(*len) as usize;
+        if rest.len() < len {
+            return Err(ZnError::invalid_metadata("truncated dictionary page"));
+        }
+        if finder.find(&rest[..len]).is_some() {
+            return Ok(false);
+        }
+        data = &rest[len..];
+    }
+    Ok(true)
+}
+
+/// Wilson score interval of a proportion `p` observed in `n` trials.
+fn wilson_interval(p: f64, n: f64, z: f64) -> (f64, f64) {
+    let z2 = z * z;
+    let denominator = 1.0 + z2 / n;
+    let center = (p + z2 / (2.0 * n)) / denominator;
+    let half_width = z * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt() / denominator;
+    (
+        (center - half_width).max(0.0),
+        (center + half_width).min(1.0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::parquet_file;
+
+    #[test]
+    fn test_estimate_occurrences() {
+        // 10 row groups of 10 rows each, a quarter of the rows matching.
+        let logs: Vec<_> = (0..100)
+            .map(|i| if i % 4 == 0 { "k8s pod" } else { "GET /" })
+            .collect();
+        let file = parquet_file(&logs, 10);
+        let exact = crate::file::count_occurrences(&file, b"k8s").unwrap() as u64;
+
+        let history = SelectivityHistory::new();
+        let options = EstimateOptions::default();
+        let estimate = estimate_occurrences(&file, b"k8s", &options, Some(&history)).unwrap();
+        assert!(!estimate.exact);
+        assert!(
+            estimate.low <= exact && exact <= estimate.high,
+            "{estimate:?}"
+        );
+        assert!(estimate.low <= estimate.count && estimate.count <= estimate.high);
+        assert_eq!(history.get(b"k8s").map(|(_, cells)| cells), Some(20));
+
+        // The recorded sample narrows the next interval.
+        let again = estimate_occurrences(&file, b"k8s", &options, Some(&history)).unwrap();
+        assert!(again.high - again.low < estimate.high - estimate.low);
+
+        let all = EstimateOptions {
+            sample_row_groups: 10,
+            ..options.clone()
+        };
+        let estimate = estimate_occurrences(&file, b"k8s", &all, None).unwrap();
+        assert_eq!((estimate.count, estimate.exact), (exact, true));
+
+        assert!(matches!(
+            estimate_occurrences(&file, b"", &options, None),
+            Err(ZnError::EmptyNeedle)
+        ));
+        assert_eq!(wilson_interval(0.0, 100.0, 1.96).0, 0.0);
+    }
+}
@@ -0,0 +1,307 @@
+//! C interface
+//!
+//! `extern "C"` functions for embedding the search and metadata APIs into
+//! services written in languages that can't link Rust crates, such as Go or
+//! C++.  Parquet files are opaque [`ZnFile`] handles.  Every function returns
+//! one of the `ZN_*` status codes and writes its result through an out
+//! pointer; after a failure [`zn_last_error`] describes the error.  Record
+//! batches are exported through the [Arrow C stream interface].
+//!
+//! Only available with the `ffi` feature.
+//!
+//! [Arrow C stream interface]: https://arrow.apache.org/docs/format/CStreamInterface.html
+
+use crate::{file::byte_array_columns_uncompressed_size, storage::RangeChunkReader, ZnError};
+use arrow::{error::ArrowError, ffi_stream::FFI_ArrowArrayStream, record_batch::RecordBatchReader};
+use parquet::{
+    arrow::arrow_reader::ParquetRecordBatchReaderBuilder,
+    errors::ParquetError,
+    file::{reader::FileReader, serialized_reader::SerializedFileReader},
+};
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    fs::File,
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+    sync::Arc,
+};
+
+pub const ZN_OK: i32 = 0;
+/// A pointer argument was null.
+pub const ZN_ERR_NULL_POINTER: i32 = 1;
+/// A string argument was not valid UTF-8.
+pub const ZN_ERR_INVALID_UTF8: i32 = 2;
+pub const ZN_ERR_IO: i32 = 3;
+pub const ZN_ERR_PARQUET: i32 = 4;
+pub const ZN_ERR_ARROW: i32 = 5;
+pub const ZN_ERR_EMPTY_NEEDLE: i32 = 6;
+pub const ZN_ERR_INVALID_ARGUMENT: i32 = 7;
+/// Any other error; see [`zn_last_error`].
+pub const ZN_ERR_OTHER: i32 = 8;
+/// The library panicked, which is a bug.
+pub const ZN_ERR_PANIC: i32 = 9;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// An open parquet file.
+pub struct ZnFile {
+    path: String,
+    reader: SerializedFileReader<RangeChunkReader<File>>,
+}
+
+/// Returns the message of the last error of a function called on this
+/// thread, or null if there was none.  The string stays valid until the next
+/// call of a `zn_` function on this thread.
+#[no_mangle]
+pub extern "C" fn zn_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// Opens the parquet file at the NUL-terminated `path` and stores its handle
+/// in `out`, to be released with [`zn_file_close`].
+///
+/// # Safety
+///
+/// `path` must be a valid C string and `out` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn zn_file_open(path: *const c_char, out: *mut *mut ZnFile) -> i32 {
+    run(|| {
+        let path = str_arg(path)?;
+        let out = not_null(out)?;
+        let reader = crate::file::open(Arc::new(File::open(path)?))?;
+        *out = Box::into_raw(Box::new(ZnFile {
+            path: path.to_owned(),
+            reader,
+        }));
+        Ok(())
+    })
+}
+
+/// Releases a handle returned by [`zn_file_open`]; null is ignored.
+///
+/// # Safety
+///
+/// `file` must be null or a handle that has not been released yet.
+#[no_mangle]
+pub unsafe extern "C" fn zn_file_close(file: *mut ZnFile) {
+    if !file.is_null() {
+        drop(Box::from_raw(file));
+    }
+}
+
+/// Stores the number of rows of the file in `out`.
+///
+/// # Safety
+///
+/// `file` must be a live handle and `out` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn zn_file_num_rows(file: *const ZnFile, out: *mut u64) -> i32 {
+    run(|| {
+        let file = &not_null(file.cast_mut())?.reader;
+        let num_rows = file.metadata().file_metadata().num_rows();
+        *not_null(out)? = u64::try_from(num_rows).map_err(|_| {
+            ZnError::invalid_metadata(format!("negative number of rows: {num_rows}"))
+        })?;
+        Ok(())
+    })
+}
+
+/// Stores the uncompressed size of the byte array columns of the file in
+/// `out`; see [`byte_array_columns_uncompressed_size`].
+///
+/// # Safety
+///
+/// `file` must be a live handle and `out` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn zn_file_text_size(file: *const ZnFile, out: *mut u64) -> i32 {
+    run(|| {
+        let file = &not_null(file.cast_mut())?.reader;
+        *not_null(out)? = byte_array_columns_uncompressed_size(file.metadata())?;
+        Ok(())
+    })
+}
+
+/// Counts the cells of the byte array columns of the file that contain the
+/// `needle` of `needle_len` bytes and stores the count in `out`; see
+/// [`crate::file::count_occurrences`].
+///
+/// # Safety
+///
+/// `file` must be a live handle, `needle` valid for reads of `needle_len`
+/// bytes, and `out` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn zn_file_count_occurrences(
+    file: *const ZnFile,
+    needle: *const u8,
+    needle_len: usize,
+    out: *mut u64,
+) -> i32 {
+    run(|| {
+        let file = &not_null(file.cast_mut())?.reader;
+        let needle = std::slice::from_raw_parts(not_null(needle.cast_mut())?, needle_len);
+        *not_null(out)? = crate::file::count_occurrences(file, needle)? as u64;
+        Ok(())
+    })
+}
+
+/// Exports the rows of the file, `batch_size` rows per batch, as an Arrow C
+/// stream into `out`.  The consumer releases the stream; it doesn't borrow
+/// the handle, which may be closed meanwhile.
+///
+/// # Safety
+///
+/// `file` must be a live handle and `out` valid for writes of an
+/// `ArrowArrayStream`.
+#[no_mangle]
+pub unsafe extern "C" fn zn_file_arrow_stream(
+    file: *const ZnFile,
+    batch_size: usize,
+    out: *mut FFI_ArrowArrayStream,
+) -> i32 {
+    run(|| {
+        let file = not_null(file.cast_mut())?;
+        let out = not_null(out)?;
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&file.path)?)?
+            .with_batch_size(batch_size.max(1))
+            .build()?;
+        let reader: Box<dyn RecordBatchReader> = Box::new(reader);
+        ptr::write(out, FFI_ArrowArrayStream::new(reader));
+        Ok(())
+    })
+}
+
+/// Failure of a `zn_` function.
+enum Error {
+    NullPointer,
+    InvalidUtf8,
+    Zn(ZnError),
+}
+
+impl Error {
+    fn code(&self) -> i32 {
+        match self {
+            Error::NullPointer => ZN_ERR_NULL_POINTER,
+            Error::InvalidUtf8 => ZN_ERR_INVALID_UTF8,
+            Error::Zn(ZnError::Io(_)) => ZN_ERR_IO,
+            Error::Zn(ZnError::Parquet(_)) => ZN_ERR_PARQUET,
+            Error::Zn(ZnError::Arrow(_)) => ZN_ERR_ARROW,
+            Error::Zn(ZnError::EmptyNeedle) => ZN_ERR_EMPTY_NEEDLE,
+            Error::Zn(ZnError::InvalidArgument(_)) => ZN_ERR_INVALID_ARGUMENT,
+            Error::Zn(_) => ZN_ERR_OTHER,
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            Error::NullPointer => "null pointer argument".to_owned(),
+            Error::InvalidUtf8 => "string argument is not valid UTF-8".to_owned(),
+            Error::Zn(e) => e.to_string(),
+        }
+    }
+}
+
+impl From<ZnError> for Error {
+    fn from(e: ZnError) -> Self {
+        Error::Zn(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Zn(e.into())
+    }
+}
+
+impl From<ParquetError> for Error {
+    fn from(e: ParquetError) -> Self {
+        Error::Zn(e.into())
+    }
+}
+
+impl From<ArrowError> for Error {
+    fn from(e: ArrowError) -> Self {
+        Error::Zn(e.into())
+    }
+}
+
+fn not_null<'a, T>(p: *mut T) -> Result<&'a mut T, Error> {
+    // SAFETY: the callers promise that non-null pointers are valid.
+    unsafe { p.as_mut() }.ok_or(Error::NullPointer)
+}
+
+fn str_arg<'a>(s: *const c_char) -> Result<&'a str, Error> {
+    if s.is_null() {
+        return Err(Error::NullPointer);
+    }
+    // SAFETY: the callers promise that non-null strings are valid C strings.
+    unsafe { CStr::from_ptr(s) }
+        .to_str()
+        .map_err(|_| Error::InvalidUtf8)
+}
+
+/// Runs `f`, recording its error for [`zn_last_error`] and turning it into
+/// a status code.
+fn run(f: impl FnOnce() -> Result<(), Error>) -> i32 {
+    let (code, message) = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => (ZN_OK, None),
+        Ok(Err(e)) => (e.code(), Some(e.message())),
+        Err(_) => (ZN_ERR_PANIC, Some("panic in zn_perf".to_owned())),
+    };
+    LAST_ERROR.with(|last| {
+        *last.borrow_mut() =
+            message.map(|m| CString::new(m.replace('\0', " ")).expect("NULs are replaced"));
+    });
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::parquet_bytes;
+    use arrow::ffi_stream::ArrowArrayStreamReader;
+    use std::mem::MaybeUninit;
+
+    #[test]
+    fn test_ffi() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("logs.parquet");
+        std::fs::write(&path, parquet_bytes(&["k8s pod", "GET /", "k8s node"], 2)).unwrap();
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+
+        unsafe {
+            let mut file = ptr::null_mut();
+            assert_eq!(zn_file_open(c_path.as_ptr(), &mut file), ZN_OK);
+            assert!(zn_last_error().is_null());
+
+            let mut n = 0;
+            assert_eq!(zn_file_num_rows(file, &mut n), ZN_OK);
+            assert_eq!(n, 3);
+            assert_eq!(zn_file_text_size(file, &mut n), ZN_OK);
+            assert!(n > 0);
+            assert_eq!(
+                zn_file_count_occurrences(file, b"k8s".as_ptr(), 3, &mut n),
+                ZN_OK
+            );
+            assert_eq!(n, 2);
+            assert_eq!(
+                zn_file_count_occurrences(file, b"".as_ptr(), 0, &mut n),
+                ZN_ERR_EMPTY_NEEDLE
+            );
+            assert!(!zn_last_error().is_null());
+            assert_eq!(zn_file_num_rows(file, ptr::null_mut()), ZN_ERR_NULL_POINTER);
+
+            let mut stream = MaybeUninit::<FFI_ArrowArrayStream>::uninit();
+            assert_eq!(zn_file_arrow_stream(file, 2, stream.as_mut_ptr()), ZN_OK);
+            zn_file_close(file);
+            let reader = ArrowArrayStreamReader::from_raw(stream.as_mut_ptr()).unwrap();
+            let rows: Vec<_> = reader.map(|batch| batch.unwrap().num_rows()).collect();
+            assert_eq!(rows, [2, 1]);
+
+            let missing = CString::new(dir.path().join("missing").to_str().unwrap()).unwrap();
+            assert_eq!(zn_file_open(missing.as_ptr(), &mut file), ZN_ERR_IO);
+        }
+    }
+}
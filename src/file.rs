@@ -2,95 +2,1076 @@
 //!
 //! [`parquet::file`]: https://docs.rs/parquet/latest/parquet/file/index.html
 
-use crate::ZnResult;
+use crate::{
+    cancel::CancelToken,
+    index::RowGroupPruner,
+    limits,
+    metrics::{registry, SearchPath},
+    storage::{RangeChunkReader, RangeReader},
+    str::{Matcher, Substring},
+    ZnError, ZnResult,
+};
+use bytes::Bytes;
 use memchr::memmem;
 use parquet::{
-    basic::Type as BasicType,
-    file::{metadata::ParquetMetaData, reader::FileReader},
-    record::Field,
-    schema::types::Type as SchemaType,
+    basic::{ConvertedType, Encoding, LogicalType, PageType, Type as BasicType},
+    column::{
+        page::{Page, PageReader},
+        reader::{ColumnReader, ColumnReaderImpl},
+    },
+    data_type::{AsBytes, DataType},
+    file::{
+        metadata::{ColumnChunkMetaData, ParquetMetaData},
+        page_index::index::Index,
+        reader::{FileReader, RowGroupReader},
+        serialized_reader::{ReadOptionsBuilder, SerializedFileReader},
+    },
+    record::{reader::RowIter, Field},
+    schema::types::{ColumnDescriptor, Type as SchemaType, TypePtr},
 };
+use std::{fs::File, path::Path, sync::Arc};
+
+/// Opens the parquet file read by `reader`, on any [storage] backend, with
+/// its page index if it has one.
+///
+/// [storage]: crate::storage
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+pub fn open<R: RangeReader + ?Sized + 'static>(
+    reader: Arc<R>,
+) -> ZnResult<SerializedFileReader<RangeChunkReader<R>>> {
+    let file = SerializedFileReader::new(RangeChunkReader::try_new(reader.clone())?)?;
+    if !has_offset_index(file.metadata()) {
+        return Ok(file);
+    }
+    Ok(SerializedFileReader::new_with_options(
+        RangeChunkReader::try_new(reader)?,
+        ReadOptionsBuilder::new().with_page_index().build(),
+    )?)
+}
+
+/// Opens the parquet file held in memory in `data`, e.g. an object fetched
+/// whole from S3, with its page index if it has one.  The column chunks are
+/// read as slices of `data`, which is never copied.
+pub fn open_bytes(data: Bytes) -> ZnResult<SerializedFileReader<Bytes>> {
+    let file = SerializedFileReader::new(data.clone())?;
+    if !has_offset_index(file.metadata()) {
+        return Ok(file);
+    }
+    Ok(SerializedFileReader::new_with_options(
+        data,
+        ReadOptionsBuilder::new().with_page_index().build(),
+    )?)
+}
+
+/// Returns whether every column chunk has an offset index, without which the
+/// page index cannot be loaded.
+pub(crate) fn has_offset_index(metadata: &ParquetMetaData) -> bool {
+    metadata.num_row_groups() > 0
+        && metadata.row_groups().iter().all(|rg| {
+            rg.columns()
+                .iter()
+                .all(|column| column.offset_index_offset().is_some())
+        })
+}
 
-fn is_byte_array(t: BasicType) -> bool {
+pub(crate) fn is_byte_array(t: BasicType) -> bool {
     matches!(t, BasicType::BYTE_ARRAY | BasicType::FIXED_LEN_BYTE_ARRAY)
 }
 
 /// Returns the projection of [byte array] columns.
 ///
 /// [byte_array]: is_byte_array()
-fn byte_array_columns(metadata: &ParquetMetaData) -> SchemaType {
+pub(crate) fn byte_array_columns(metadata: &ParquetMetaData) -> ZnResult<SchemaType> {
     match metadata.file_metadata().schema().clone() {
-        SchemaType::PrimitiveType { .. } => unimplemented!(),
+        SchemaType::PrimitiveType { .. } => Err(ZnError::invalid_metadata(
+            "root of the schema is not a group",
+        )),
         SchemaType::GroupType {
             basic_info,
             mut fields,
         } => {
-            fields.retain(|t| is_byte_array(t.get_physical_type()));
-            SchemaType::GroupType { basic_info, fields }
+            fields.retain(|t| t.is_primitive() && is_byte_array(t.get_physical_type()));
+            Ok(SchemaType::GroupType { basic_info, fields })
         }
     }
 }
 
+/// Returns the projection of the [byte array] columns called `names`.
+///
+/// # Errors
+///
+/// Returns [`ZnError::InvalidArgument`] if one of the `names` is not a byte
+/// array column.
+///
+/// [byte_array]: is_byte_array()
+#[cfg_attr(not(feature = "tantivy"), allow(dead_code))]
+pub(crate) fn byte_array_columns_named(
+    metadata: &ParquetMetaData,
+    names: &[&str],
+) -> ZnResult<SchemaType> {
+    let projection = byte_array_columns(metadata)?;
+    let fields: Vec<_> = projection
+        .get_fields()
+        .iter()
+        .filter(|t| names.contains(&t.name()))
+        .cloned()
+        .collect();
+    if let Some(missing) = names
+        .iter()
+        .find(|name| !fields.iter().any(|t| t.name() == **name))
+    {
+        return Err(ZnError::invalid_argument(format!(
+            "no byte array column {missing:?}"
+        )));
+    }
+    Ok(SchemaType::GroupType {
+        basic_info: projection.get_basic_info().clone(),
+        fields,
+    })
+}
+
 /// Returns total byte size of uncompressed data of all [byte array] columns.
 ///
+/// # Errors
+///
+/// Returns an error if the metadata reports a negative column chunk size.
+///
 /// [byte_array]: is_byte_array()
-pub fn byte_array_columns_uncompressed_size(metadata: &ParquetMetaData) -> u64 {
+pub fn byte_array_columns_uncompressed_size(metadata: &ParquetMetaData) -> ZnResult<u64> {
     let mut size = 0;
     for row_group in metadata.row_groups() {
         size += row_group
             .columns()
             .iter()
-            .filter_map(|col| is_byte_array(col.column_type()).then(|| col.uncompressed_size()))
+            .filter(|col| is_byte_array(col.column_type()))
+            .map(|col| col.uncompressed_size())
             .sum::<i64>();
     }
-    size.try_into().expect("BUG")
+    size.try_into()
+        .map_err(|_| ZnError::invalid_metadata(format!("negative uncompressed size: {size}")))
+}
+
+/// Returns the bytes of a cell of a [byte array] column, or `None` if the cell
+/// holds nothing searchable.
+///
+/// [byte array]: is_byte_array()
+pub(crate) fn byte_array_value<'a>(
+    column_name: &str,
+    value: &'a Field,
+) -> ZnResult<Option<&'a [u8]>> {
+    match value {
+        // Decimals may be stored as fixed length byte arrays, but they
+        // are not text.
+        Field::Null | Field::Decimal(_) => Ok(None),
+        Field::Str(s) => Ok(Some(s.as_bytes())),
+        Field::Bytes(b) => Ok(Some(b.data())),
+        Field::Bool(_)
+        | Field::Byte(_)
+        | Field::Short(_)
+        | Field::Int(_)
+        | Field::Long(_)
+        | Field::UByte(_)
+        | Field::UShort(_)
+        | Field::UInt(_)
+        | Field::ULong(_)
+        | Field::Float(_)
+        | Field::Double(_)
+        | Field::Date(_)
+        | Field::TimestampMillis(_)
+        | Field::TimestampMicros(_)
+        | Field::Group(_)
+        | Field::ListInternal(_)
+        | Field::MapInternal(_) => Err(ZnError::unsupported_type(format!(
+            "column {column_name:?} is not a byte array"
+        ))),
+    }
+}
+
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(bytes_scanned = tracing::field::Empty, rows_matched = tracing::field::Empty)
+    )
+)]
+pub(crate) fn count_in_rows(row_iter: RowIter<'_>, matcher: &dyn Matcher) -> ZnResult<usize> {
+    count_in_rows_by(row_iter, CountMode::Cells, |s| {
+        usize::from(matcher.is_match(s))
+    })
+}
+
+/// Counts what the `mode` says in the rows, given the number of occurrences
+/// in a value.
+fn count_in_rows_by(
+    row_iter: RowIter<'_>,
+    mode: CountMode,
+    occurrences: impl Fn(&[u8]) -> usize,
+) -> ZnResult<usize> {
+    let mut count = 0;
+    let mut rows_matched = 0;
+    let mut bytes_scanned = 0;
+    for row in row_iter {
+        let mut row_cells = 0;
+        for (column_name, value) in row.get_column_iter() {
+            if let Some(s) = byte_array_value(column_name, value)? {
+                bytes_scanned += s.len() as u64;
+                let n = occurrences(s);
+                row_cells += usize::from(n > 0);
+                if mode == CountMode::Occurrences {
+                    count += n;
+                }
+            }
+        }
+        count += match mode {
+            CountMode::Cells => row_cells,
+            CountMode::Rows => usize::from(row_cells > 0),
+            CountMode::Occurrences => 0,
+        };
+        rows_matched += u64::from(row_cells > 0);
+    }
+    #[cfg(feature = "tracing")]
+    tracing::Span::current()
+        .record("bytes_scanned", bytes_scanned)
+        .record("rows_matched", rows_matched);
+    registry().bytes_scanned().inc_by(bytes_scanned);
+    registry().rows_matched().inc_by(rows_matched);
+    Ok(count)
 }
 
 /// Counts the number of cells (intersections of column and row) that contain
 /// the `needle`, taking only [byte array] columns into account.
 ///
-/// # Panics
+/// If the file was [opened](open) with its page index, the pages whose min
+/// and max values prove that they cannot contain the `needle`, i.e. pages of
+/// nulls or of a single repeated value, are skipped without being read.
+///
+/// # Errors
 ///
-/// Panics if `needle` is empty.
+/// Returns [`ZnError::EmptyNeedle`] if `needle` is empty and
+/// [`ZnError::UnsupportedType`] if a byte array column decodes into a value
+/// that cannot be searched.
 ///
 /// [byte array]: is_byte_array()
 pub fn count_occurrences<R: FileReader>(haystack: &R, needle: &[u8]) -> ZnResult<usize> {
-    assert!(!needle.is_empty());
+    if needle.is_empty() {
+        return Err(ZnError::empty_needle());
+    }
+    count_with_page_index(haystack, &Substring::new(needle), &|min, max| {
+        min == max && memmem::find(min, needle).is_none()
+    })
+}
+
+/// Like [`count_occurrences`], but counts the cells that start with the
+/// `prefix`.  Pages whose values all sort before or after the values starting
+/// with the `prefix` are skipped, which on a sorted column leaves only the
+/// pages around the prefix to read.
+///
+/// # Errors
+///
+/// Returns the errors of [`count_occurrences`].
+pub fn count_prefix_matches<R: FileReader>(haystack: &R, prefix: &[u8]) -> ZnResult<usize> {
+    if prefix.is_empty() {
+        return Err(ZnError::empty_needle());
+    }
+    count_with_page_index(haystack, &Prefix(prefix), &|min, max| {
+        max < prefix || (min > prefix && !min.starts_with(prefix))
+    })
+}
+
+/// Matches the values starting with the bytes it holds.
+struct Prefix<'a>(&'a [u8]);
+
+impl Matcher for Prefix<'_> {
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        haystack.starts_with(self.0)
+    }
+}
+
+/// Counts the cells that the `matcher` matches, skipping the pages whose min
+/// and max values, in the page index of the file if it was loaded,
+/// `rules_out` a match, and the pages of nulls.
+fn count_with_page_index<R: FileReader>(
+    haystack: &R,
+    matcher: &dyn Matcher,
+    rules_out: &dyn Fn(&[u8], &[u8]) -> bool,
+) -> ZnResult<usize> {
+    let _timer = registry().query_latency(SearchPath::File).start_timer();
 
-    let projection = byte_array_columns(haystack.metadata());
-    let row_iter = haystack.get_row_iter(Some(projection))?;
+    let metadata = haystack.metadata();
+    let projection = byte_array_columns(metadata)?;
+    let columns: Vec<_> = leaf_columns(metadata, &projection)?
+        .into_iter()
+        .map(|(_, column)| column)
+        .collect();
     let mut count = 0;
-    for row in row_iter {
-        for (_column_name, value) in row.get_column_iter() {
-            match value {
-                Field::Null => (),
-                // Field::Str(s) => count += memmem::find_iter(s.as_bytes(), needle).count(),
-                Field::Str(s) => {
-                    if memmem::find(s.as_bytes(), needle).is_some() {
-                        count += 1;
-                    }
+    for i in 0..haystack.num_row_groups() {
+        let row_group = haystack.get_row_group(i)?;
+        let pages: Vec<_> = columns
+            .iter()
+            .map(|&column| pruned_pages(metadata, i, column, rules_out))
+            .collect();
+        if pages.iter().all(Option::is_none) {
+            count += count_in_rows(row_group.get_row_iter(Some(projection.clone()))?, matcher)?;
+        } else if pages.iter().all(|pages| {
+            pages
+                .as_ref()
+                .is_some_and(|p| p.iter().all(|&(_, skip)| skip))
+        }) {
+            registry().row_groups_pruned().inc_by(1);
+        } else {
+            count += count_in_pages(&*row_group, &columns, &pages, matcher)?;
+        }
+    }
+    Ok(count)
+}
+
+/// Returns the number of rows of each page of the `column` of row group `i`
+/// and whether the page is skipped, or `None` if the page index is not
+/// loaded or no page is skipped.
+fn pruned_pages(
+    metadata: &ParquetMetaData,
+    i: usize,
+    column: usize,
+    rules_out: &dyn Fn(&[u8], &[u8]) -> bool,
+) -> Option<Vec<(usize, bool)>> {
+    if is_decimal(metadata.row_group(i).column(column).column_descr()) {
+        return None;
+    }
+    let locations = metadata.offset_indexes()?.get(i)?.get(column)?;
+    let (Index::BYTE_ARRAY(index) | Index::FIXED_LEN_BYTE_ARRAY(index)) =
+        metadata.page_indexes()?.get(i)?.get(column)?
+    else {
+        return None;
+    };
+    if index.indexes.len() != locations.len() {
+        return None;
+    }
+    let num_rows = metadata.row_group(i).num_rows();
+    let pages: Vec<_> = locations
+        .iter()
+        .zip(&index.indexes)
+        .enumerate()
+        .map(|(j, (location, page))| {
+            let end = locations
+                .get(j + 1)
+                .map_or(num_rows, |next| next.first_row_index);
+            // Only pages of nulls have no min and max.
+            let skip = match (&page.min, &page.max) {
+                (Some(min), Some(max)) => rules_out(min, max),
+                _ => true,
+            };
+            ((end - location.first_row_index).max(0) as usize, skip)
+        })
+        .collect();
+    pages.iter().any(|&(_, skip)| skip).then_some(pages)
+}
+
+/// Decimals may be stored as fixed length byte arrays, but they are not
+/// text; see [`byte_array_value`].
+fn is_decimal(column: &ColumnDescriptor) -> bool {
+    column.converted_type() == ConvertedType::DECIMAL
+        || matches!(column.logical_type(), Some(LogicalType::Decimal { .. }))
+}
+
+/// Counts the cells of the `columns` of the `row_group` that the `matcher`
+/// matches, reading the columns one by one to skip their `pages` ruled out,
+/// if any.
+fn count_in_pages(
+    row_group: &dyn RowGroupReader,
+    columns: &[usize],
+    pages: &[Option<Vec<(usize, bool)>>],
+    matcher: &dyn Matcher,
+) -> ZnResult<usize> {
+    let num_rows = row_group.metadata().num_rows().max(0) as usize;
+    let mut matched = vec![false; num_rows];
+    let mut bytes_scanned = 0;
+    let mut count = 0;
+    for (&column, pages) in columns.iter().zip(pages) {
+        let descr = row_group.metadata().column(column).column_descr();
+        let whole = [(num_rows, false)];
+        let pages = pages.as_deref().unwrap_or(&whole);
+        if is_decimal(descr) || pages.iter().all(|&(_, skip)| skip) {
+            continue;
+        }
+        let max_def_level = descr.max_def_level();
+        count += match row_group.get_column_reader(column)? {
+            ColumnReader::ByteArrayColumnReader(reader) => scan_pages(
+                reader,
+                pages,
+                max_def_level,
+                matcher,
+                &mut matched,
+                &mut bytes_scanned,
+            )?,
+            ColumnReader::FixedLenByteArrayColumnReader(reader) => scan_pages(
+                reader,
+                pages,
+                max_def_level,
+                matcher,
+                &mut matched,
+                &mut bytes_scanned,
+            )?,
+            _ => {
+                return Err(ZnError::invalid_metadata(format!(
+                    "column {column} is not a byte array column"
+                )))
+            }
+        };
+    }
+    let rows_matched = matched.iter().filter(|&&m| m).count() as u64;
+    #[cfg(feature = "tracing")]
+    tracing::Span::current()
+        .record("bytes_scanned", bytes_scanned)
+        .record("rows_matched", rows_matched);
+    registry().bytes_scanned().inc_by(bytes_scanned);
+    registry().rows_matched().inc_by(rows_matched);
+    Ok(count)
+}
+
+/// Matches the values of the `pages` read by `reader` that are not skipped,
+/// marking their rows as `matched`, and returns the number of matches.
+fn scan_pages<T: DataType>(
+    mut reader: ColumnReaderImpl<T>,
+    pages: &[(usize, bool)],
+    max_def_level: i16,
+    matcher: &dyn Matcher,
+    matched: &mut [bool],
+    bytes_scanned: &mut u64,
+) -> ZnResult<usize> {
+    const BATCH_SIZE: usize = 1024;
+    // The levels stay 0 for required columns, which have none.
+    let mut levels = vec![0; BATCH_SIZE];
+    let mut values = vec![T::T::default(); BATCH_SIZE];
+    let mut count = 0;
+    let mut row = 0;
+    for &(num_rows, skip) in pages {
+        if skip {
+            row += reader.skip_records(num_rows)?;
+            continue;
+        }
+        let end = row + num_rows;
+        while row < end {
+            let (num_values, num_levels) = reader.read_batch(
+                (end - row).min(BATCH_SIZE),
+                Some(&mut levels[..]),
+                None,
+                &mut values,
+            )?;
+            if num_levels == 0 {
+                return Err(ZnError::invalid_metadata(
+                    "page index covers more rows than the column chunk",
+                ));
+            }
+            let mut batch = values[..num_values].iter();
+            for (r, &level) in (row..).zip(&levels[..num_levels]) {
+                let Some(matched) = matched.get_mut(r) else {
+                    return Err(ZnError::invalid_metadata(
+                        "column chunk has more rows than its row group",
+                    ));
+                };
+                if level < max_def_level {
+                    continue;
+                }
+                let Some(value) = batch.next() else {
+                    return Err(ZnError::invalid_metadata(
+                        "column chunk has fewer values than defined levels",
+                    ));
+                };
+                let value = value.as_bytes();
+                *bytes_scanned += value.len() as u64;
+                if matcher.is_match(value) {
+                    *matched = true;
+                    count += 1;
                 }
-                Field::Bool(_) => todo!(),
-                Field::Byte(_) => todo!(),
-                Field::Short(_) => todo!(),
-                Field::Int(_) => todo!(),
-                Field::Long(_) => todo!(),
-                Field::UByte(_) => todo!(),
-                Field::UShort(_) => todo!(),
-                Field::UInt(_) => todo!(),
-                Field::ULong(_) => todo!(),
-                Field::Float(_) => todo!(),
-                Field::Double(_) => todo!(),
-                Field::Decimal(_) => todo!(),
-                Field::Bytes(_) => todo!(),
-                Field::Date(_) => todo!(),
-                Field::TimestampMillis(_) => todo!(),
-                Field::TimestampMicros(_) => todo!(),
-                Field::Group(_) => todo!(),
-                Field::ListInternal(_) => todo!(),
-                Field::MapInternal(_) => todo!(),
             }
+            row += num_levels;
         }
     }
     Ok(count)
 }
+
+/// What a search counts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CountMode {
+    /// Cells (intersections of column and row) containing the needle.
+    #[default]
+    Cells,
+    /// Rows with at least one cell containing the needle.
+    Rows,
+    /// Non-overlapping occurrences of the needle, including repeats within a
+    /// cell.
+    Occurrences,
+}
+
+/// Like [`count_occurrences`], but counts what the `mode` says, e.g. the
+/// matching rows instead of the cells.
+///
+/// # Errors
+///
+/// Returns the errors of [`count_occurrences`].
+pub fn count_occurrences_with_mode<R: FileReader>(
+    haystack: &R,
+    needle: &[u8],
+    mode: CountMode,
+) -> ZnResult<usize> {
+    if needle.is_empty() {
+        return Err(ZnError::empty_needle());
+    }
+    let _timer = registry().query_latency(SearchPath::File).start_timer();
+
+    let finder = memmem::Finder::new(needle);
+    let projection = byte_array_columns(haystack.metadata())?;
+    let mut count = 0;
+    for i in 0..haystack.num_row_groups() {
+        let row_group = haystack.get_row_group(i)?;
+        count += count_in_rows_by(
+            row_group.get_row_iter(Some(projection.clone()))?,
+            mode,
+            |s| match mode {
+                CountMode::Occurrences => finder.find_iter(s).count(),
+                CountMode::Cells | CountMode::Rows => usize::from(finder.find(s).is_some()),
+            },
+        )?;
+    }
+    Ok(count)
+}
+
+/// How [`count_occurrences_parallel`] splits a file into tasks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScanUnit {
+    /// A row group.
+    #[default]
+    RowGroup,
+    /// A [byte array] column chunk of a row group, which keeps more threads
+    /// busy with files of few row groups, but counts a row for the
+    /// `rows_matched` metric once per matching column.
+    ///
+    /// [byte array]: is_byte_array()
+    ColumnChunk,
+}
+
+/// Like [`count_occurrences`], but decompresses and scans the parts of the
+/// file that `unit` says on `threads` threads at a time, and sums their
+/// counts.  With 0 threads, they are scanned on the [scan pool](crate::pool).
+///
+/// # Errors
+///
+/// Returns [`ZnError::InvalidArgument`] if the threads cannot be spawned,
+/// and the errors of [`count_occurrences`].
+#[cfg(feature = "native")]
+pub fn count_occurrences_parallel<R: FileReader>(
+    haystack: &R,
+    needle: &[u8],
+    threads: usize,
+    unit: ScanUnit,
+) -> ZnResult<usize> {
+    use rayon::prelude::*;
+
+    if needle.is_empty() {
+        return Err(ZnError::empty_needle());
+    }
+    let _timer = registry().query_latency(SearchPath::File).start_timer();
+
+    let projection = byte_array_columns(haystack.metadata())?;
+    let projections = match unit {
+        ScanUnit::RowGroup => vec![projection],
+        ScanUnit::ColumnChunk => projection
+            .get_fields()
+            .iter()
+            .map(|field| SchemaType::GroupType {
+                basic_info: projection.get_basic_info().clone(),
+                fields: vec![field.clone()],
+            })
+            .collect(),
+    };
+    let tasks: Vec<_> = (0..haystack.num_row_groups())
+        .flat_map(|i| projections.iter().map(move |projection| (i, projection)))
+        .collect();
+    let matcher = Substring::new(needle);
+    let scan = || {
+        tasks
+            .par_iter()
+            .map(|&(i, projection)| {
+                let row_group = haystack.get_row_group(i)?;
+                count_in_rows(row_group.get_row_iter(Some(projection.clone()))?, &matcher)
+            })
+            .sum::<ZnResult<usize>>()
+    };
+    match threads {
+        0 => crate::pool::install(scan)?,
+        threads => crate::pool::PoolOptions {
+            threads,
+            ..crate::pool::PoolOptions::default()
+        }
+        .build()?
+        .install(scan),
+    }
+}
+
+/// Like [`count_occurrences`], but first matches the dictionary of each
+/// column chunk that is fully dictionary encoded, e.g. of a low-cardinality
+/// label column, and leaves the chunk out of the scan if no entry matches,
+/// without decoding its data pages.  Whether the data pages all use the
+/// dictionary is read from the page encoding statistics of the chunk if the
+/// writer recorded them, or else from the headers of the data pages, which
+/// still have to be decompressed, so this pays off for files with
+/// statistics or with columns that mostly do not match.
+///
+/// # Errors
+///
+/// Returns [`ZnError::InvalidMetadata`] if a dictionary page is malformed,
+/// and the errors of [`count_occurrences`].
+pub fn count_occurrences_with_dictionary_pruning<R: FileReader>(
+    haystack: &R,
+    needle: &[u8],
+) -> ZnResult<usize> {
+    if needle.is_empty() {
+        return Err(ZnError::empty_needle());
+    }
+    let _timer = registry().query_latency(SearchPath::File).start_timer();
+
+    let projection = byte_array_columns(haystack.metadata())?;
+    let columns = leaf_columns(haystack.metadata(), &projection)?;
+    let matcher = Substring::new(needle);
+    let mut count = 0;
+    for i in 0..haystack.num_row_groups() {
+        let row_group = haystack.get_row_group(i)?;
+        let mut fields = Vec::with_capacity(columns.len());
+        for &(field, column) in &columns {
+            let mut pages = row_group.get_column_page_reader(column)?;
+            if !dictionary_rules_out(row_group.metadata().column(column), &mut *pages, &matcher)? {
+                fields.push(field.clone());
+            }
+        }
+        if fields.is_empty() {
+            registry().row_groups_pruned().inc_by(1);
+            continue;
+        }
+        let projection = SchemaType::GroupType {
+            basic_info: projection.get_basic_info().clone(),
+            fields,
+        };
+        count += count_in_rows(row_group.get_row_iter(Some(projection))?, &matcher)?;
+    }
+    Ok(count)
+}
+
+/// Returns the fields of the `projection` of [byte array columns] with the
+/// indices of their leaf columns.
+///
+/// [byte array columns]: byte_array_columns()
+///
+/// # Errors
+///
+/// Returns [`ZnError::InvalidMetadata`] if a field has no leaf column.
+fn leaf_columns<'a>(
+    metadata: &ParquetMetaData,
+    projection: &'a SchemaType,
+) -> ZnResult<Vec<(&'a TypePtr, usize)>> {
+    let schema = metadata.file_metadata().schema_descr();
+    // The byte array columns are top-level primitive ones.
+    projection
+        .get_fields()
+        .iter()
+        .map(|field| {
+            let column = (0..schema.num_columns())
+                .find(|&i| schema.column(i).path().parts() == [field.name()])
+                .ok_or_else(|| {
+                    ZnError::invalid_metadata(format!("no leaf column for {}", field.name()))
+                })?;
+            Ok((field, column))
+        })
+        .collect()
+}
+
+/// Returns whether the `matcher` matches no value of the column chunk read
+/// by `pages` because the chunk is fully dictionary encoded and the matcher
+/// matches no entry of its dictionary.
+fn dictionary_rules_out(
+    column: &ColumnChunkMetaData,
+    pages: &mut dyn PageReader,
+    matcher: &dyn Matcher,
+) -> ZnResult<bool> {
+    let is_dictionary =
+        |e: Encoding| matches!(e, Encoding::PLAIN_DICTIONARY | Encoding::RLE_DICTIONARY);
+
+    let Some(Page::DictionaryPage {
+        buf, num_values, ..
+    }) = pages.get_next_page()?
+    else {
+        return Ok(false);
+    };
+    if dictionary_entries(column.column_descr(), buf.data(), num_values)?
+        .into_iter()
+        .any(|entry| matcher.is_match(entry))
+    {
+        return Ok(false);
+    }
+    if let Some(stats) = column.page_encoding_stats() {
+        return Ok(stats
+            .iter()
+            .filter(|s| matches!(s.page_type, PageType::DATA_PAGE | PageType::DATA_PAGE_V2))
+            .all(|s| is_dictionary(s.encoding)));
+    }
+    while let Some(page) = pages.get_next_page()? {
+        match page {
+            Page::DataPage { encoding, .. } | Page::DataPageV2 { encoding, .. }
+                if !is_dictionary(encoding) =>
+            {
+                return Ok(false)
+            }
+            _ => {}
+        }
+    }
+    Ok(true)
+}
+
+/// Splits a plain-encoded dictionary page of `num_values` byte arrays, each
+/// prefixed with its length as a little-endian `u32` unless the column is of
+/// fixed length.
+fn dictionary_entries<'a>(
+    column: &ColumnDescriptor,
+    mut data: &'a [u8],
+    num_values: u32,
+) -> ZnResult<Vec<&'a [u8]>> {
+    let malformed = || {
+        ZnError::invalid_metadata(format!(
+            "malformed dictionary page of column {:?}",
+            column.name()
+        ))
+    };
+    let mut entries = Vec::with_capacity(num_values as usize);
+    for _ in 0..num_values {
+        let len = match column.physical_type() {
+            BasicType::FIXED_LEN_BYTE_ARRAY => {
+                usize::try_from(column.type_length()).map_err(|_| malformed())?
+            }
+            _ => {
+                let (len, rest) = data.split_first_chunk::<4>().ok_or_else(malformed)?;
+                data = rest;
+                u32::from_le_bytes(*len) as usize
+            }
+        };
+        if len > data.len() {
+            return Err(malformed());
+        }
+        let (entry, rest) = data.split_at(len);
+        entries.push(entry);
+        data = rest;
+    }
+    Ok(entries)
+}
+
+/// Like [`count_occurrences`], but checks the `cancel` token before every
+/// row group.
+///
+/// # Errors
+///
+/// Returns [`ZnError::Cancelled`] if the token is cancelled, and the errors
+/// of [`count_occurrences`].
+pub fn count_occurrences_with_cancel<R: FileReader>(
+    haystack: &R,
+    needle: &[u8],
+    cancel: &CancelToken,
+) -> ZnResult<usize> {
+    if needle.is_empty() {
+        return Err(ZnError::empty_needle());
+    }
+    count_matches_with_cancel(haystack, &Substring::new(needle), cancel)
+}
+
+/// Counts the number of cells of [byte array] columns that the `matcher`,
+/// e.g. a [registered](crate::plugins) one, matches.
+///
+/// # Errors
+///
+/// Returns [`ZnError::UnsupportedType`] if a byte array column decodes into
+/// a value that cannot be searched.
+///
+/// [byte array]: is_byte_array()
+pub fn count_matches<R: FileReader>(haystack: &R, matcher: &dyn Matcher) -> ZnResult<usize> {
+    count_matches_with_cancel(haystack, matcher, &CancelToken::new())
+}
+
+/// Like [`count_matches`], but checks the `cancel` token before every row
+/// group.
+///
+/// # Errors
+///
+/// Returns [`ZnError::Cancelled`] if the token is cancelled, and the errors
+/// of [`count_matches`].
+pub fn count_matches_with_cancel<R: FileReader>(
+    haystack: &R,
+    matcher: &dyn Matcher,
+    cancel: &CancelToken,
+) -> ZnResult<usize> {
+    let _timer = registry().query_latency(SearchPath::File).start_timer();
+
+    let projection = byte_array_columns(haystack.metadata())?;
+    let mut count = 0;
+    for i in 0..haystack.num_row_groups() {
+        cancel.check()?;
+        let row_group = haystack.get_row_group(i)?;
+        count += count_in_rows(row_group.get_row_iter(Some(projection.clone()))?, matcher)?;
+    }
+    Ok(count)
+}
+
+/// Like [`count_occurrences`], but only scans the row groups that the `index`
+/// (e.g. a [`TrigramIndex`]) reports as [candidates] for the `needle`.
+///
+/// [`TrigramIndex`]: crate::index::TrigramIndex
+/// [candidates]: RowGroupPruner::candidates
+pub fn count_occurrences_with_index<R: FileReader, P: RowGroupPruner>(
+    haystack: &R,
+    index: &P,
+    needle: &[u8],
+) -> ZnResult<usize> {
+    if needle.is_empty() {
+        return Err(ZnError::empty_needle());
+    }
+    if index.num_row_groups() != haystack.num_row_groups() {
+        return Err(ZnError::invalid_index(format!(
+            "index covers {} row group(s), file has {}",
+            index.num_row_groups(),
+            haystack.num_row_groups()
+        )));
+    }
+    let _timer = registry().query_latency(SearchPath::File).start_timer();
+
+    let projection = byte_array_columns(haystack.metadata())?;
+    let matcher = Substring::new(needle);
+    let mut count = 0;
+    let mut scanned = 0;
+    for i in index.candidates(needle) {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("scan_row_group", row_group = i).entered();
+        let row_group = haystack.get_row_group(i)?;
+        count += count_in_rows(row_group.get_row_iter(Some(projection.clone()))?, &matcher)?;
+        scanned += 1;
+    }
+    registry()
+        .row_groups_pruned()
+        .inc_by((haystack.num_row_groups() - scanned) as u64);
+    Ok(count)
+}
+
+/// Sums [`count_occurrences`] over the local parquet `files`, e.g. the files
+/// of a [time range](crate::partition::PartitionLayout::files), scanning the
+/// files in parallel on the [scan pool](crate::pool) (without the `native`
+/// feature, as many at a time as the [tuned](crate::tune) parallelism),
+/// within the [limits](crate::limits) of the installed limiter.
+///
+/// # Errors
+///
+/// Returns [`ZnError::Overloaded`] if the limiter rejects the scan.
+pub fn count_occurrences_in_files<P: AsRef<Path> + Sync>(
+    files: &[P],
+    needle: &[u8],
+) -> ZnResult<usize> {
+    count_occurrences_in_files_with_cancel(files, needle, &CancelToken::new())
+}
+
+/// Like [`count_occurrences_in_files`], but checks the `cancel` token before
+/// every row group of every file.
+///
+/// # Errors
+///
+/// Returns [`ZnError::Cancelled`] if the token is cancelled, and the errors
+/// of [`count_occurrences_in_files`].
+pub fn count_occurrences_in_files_with_cancel<P: AsRef<Path> + Sync>(
+    files: &[P],
+    needle: &[u8],
+    cancel: &CancelToken,
+) -> ZnResult<usize> {
+    if needle.is_empty() {
+        return Err(ZnError::empty_needle());
+    }
+    let permit = limits::admit()?;
+    let count_file = |path: &P| {
+        let file = open(Arc::new(File::open(path)?))?;
+        if let Some(permit) = &permit {
+            permit.throttle(byte_array_columns_compressed_size(file.metadata()));
+        }
+        count_occurrences_with_cancel(&file, needle, cancel)
+    };
+
+    #[cfg(feature = "native")]
+    {
+        use rayon::prelude::*;
+        crate::pool::install(|| files.par_iter().map(count_file).sum())?
+    }
+    #[cfg(not(feature = "native"))]
+    {
+        let parallelism = crate::tune::tuning().parallelism.max(1);
+        if parallelism == 1 || files.len() < 2 {
+            return files.iter().map(count_file).sum();
+        }
+        let chunk_size = files.len().div_ceil(parallelism);
+        std::thread::scope(|scope| {
+            let scans: Vec<_> = files
+                .chunks(chunk_size)
+                .map(|chunk| scope.spawn(|| chunk.iter().map(count_file).sum::<ZnResult<usize>>()))
+                .collect();
+            scans
+                .into_iter()
+                .map(|scan| scan.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+                .sum()
+        })
+    }
+}
+
+/// Returns the bytes of the [byte array] column chunks as stored.
+///
+/// [byte array]: is_byte_array()
+fn byte_array_columns_compressed_size(metadata: &ParquetMetaData) -> u64 {
+    metadata
+        .row_groups()
+        .iter()
+        .flat_map(|row_group| row_group.columns())
+        .filter(|col| is_byte_array(col.column_type()))
+        .map(|col| col.compressed_size().max(0) as u64)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::parquet_bytes;
+    use arrow::{
+        array::{ArrayRef, StringArray},
+        record_batch::RecordBatch,
+    };
+    use parquet::{arrow::ArrowWriter, file::properties::WriterProperties};
+
+    #[test]
+    fn test_count_occurrences_with_dictionary_pruning() {
+        let logs = ["level=info", "level=warn", "level=info", "level=error k8s"];
+        let dictionary = open_bytes(parquet_bytes(&logs, 2)).unwrap();
+        let plain = {
+            let batch = RecordBatch::try_from_iter([(
+                "log",
+                Arc::new(StringArray::from(logs.to_vec())) as ArrayRef,
+            )])
+            .unwrap();
+            let props = WriterProperties::builder()
+                .set_dictionary_enabled(false)
+                .set_max_row_group_size(2)
+                .build();
+            let mut data = Vec::new();
+            let mut writer = ArrowWriter::try_new(&mut data, batch.schema(), Some(props)).unwrap();
+            writer.write(&batch).unwrap();
+            writer.close().unwrap();
+            open_bytes(data.into()).unwrap()
+        };
+
+        for file in [&dictionary, &plain] {
+            for needle in [&b"level"[..], b"info", b"k8s", b"debug"] {
+                assert_eq!(
+                    count_occurrences_with_dictionary_pruning(file, needle).unwrap(),
+                    count_occurrences(file, needle).unwrap(),
+                    "{}",
+                    String::from_utf8_lossy(needle)
+                );
+            }
+        }
+        assert!(count_occurrences_with_dictionary_pruning(&dictionary, b"").is_err());
+
+        let rules_out = |file: &SerializedFileReader<Bytes>, row_group: usize, needle: &[u8]| {
+            let row_group = file.get_row_group(row_group).unwrap();
+            let mut pages = row_group.get_column_page_reader(0).unwrap();
+            dictionary_rules_out(
+                row_group.metadata().column(0),
+                &mut *pages,
+                &Substring::new(needle),
+            )
+            .unwrap()
+        };
+        assert!(rules_out(&dictionary, 0, b"k8s"));
+        assert!(!rules_out(&dictionary, 1, b"k8s"));
+        assert!(!rules_out(&dictionary, 0, b"info"));
+        assert!(!rules_out(&plain, 0, b"k8s"));
+    }
+
+    #[test]
+    fn test_count_with_page_index() {
+        let logs = [
+            Some("apple"),
+            Some("apricot"),
+            Some("banana"),
+            Some("berry"),
+            Some("cherry"),
+            Some("cherry"),
+            None,
+            None,
+        ];
+        let batch = RecordBatch::try_from_iter([(
+            "log",
+            Arc::new(StringArray::from(logs.to_vec())) as ArrayRef,
+        )])
+        .unwrap();
+        // Pages of two rows each.
+        let props = WriterProperties::builder()
+            .set_data_page_row_count_limit(2)
+            .set_write_batch_size(2)
+            .build();
+        let mut data = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut data, batch.schema(), Some(props)).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        let data = Bytes::from(data);
+        let file = open_bytes(data.clone()).unwrap();
+        let no_index = SerializedFileReader::new(data).unwrap();
+        assert!(file.metadata().page_indexes().is_some());
+        assert!(no_index.metadata().page_indexes().is_none());
+
+        for needle in [&b"an"[..], b"err", b"cherry", b"a", b"z"] {
+            assert_eq!(
+                count_occurrences(&file, needle).unwrap(),
+                count_matches(&no_index, &Substring::new(needle)).unwrap(),
+                "{}",
+                String::from_utf8_lossy(needle)
+            );
+        }
+        for (prefix, count) in [(&b"ap"[..], 2), (b"b", 2), (b"cherry", 2), (b"z", 0)] {
+            assert_eq!(count_prefix_matches(&file, prefix).unwrap(), count);
+            assert_eq!(count_prefix_matches(&no_index, prefix).unwrap(), count);
+        }
+        assert!(count_prefix_matches(&file, b"").is_err());
+
+        let skipped = |rules_out: &dyn Fn(&[u8], &[u8]) -> bool| {
+            pruned_pages(file.metadata(), 0, 0, rules_out)
+                .map(|pages| pages.into_iter().map(|(_, skip)| skip).collect::<Vec<_>>())
+        };
+        assert_eq!(
+            skipped(&|min, max| max < &b"b"[..] || (min > &b"b"[..] && !min.starts_with(b"b"))),
+            Some(vec![true, false, true, true])
+        );
+        assert_eq!(
+            skipped(&|min, max| min == max && memmem::find(min, b"an").is_none()),
+            Some(vec![false, false, true, true])
+        );
+
+        // A page index inconsistent with the column chunk is an error, not a
+        // panic: pages covering more rows than the chunk has, or a chunk with
+        // more rows than its row group.
+        let matcher = Substring::new(b"cherry");
+        let row_group = file.get_row_group(0).unwrap();
+        for pages in [vec![(2, true), (8, false)], vec![(9, false)]] {
+            assert!(matches!(
+                count_in_pages(&*row_group, &[0], &[Some(pages)], &matcher),
+                Err(ZnError::InvalidMetadata(_))
+            ));
+        }
+        let ColumnReader::ByteArrayColumnReader(reader) = row_group.get_column_reader(0).unwrap()
+        else {
+            panic!("byte array column");
+        };
+        let mut matched = vec![false; 4];
+        assert!(matches!(
+            scan_pages(reader, &[(8, false)], 1, &matcher, &mut matched, &mut 0),
+            Err(ZnError::InvalidMetadata(_))
+        ));
+    }
+}
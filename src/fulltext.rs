@@ -0,0 +1,210 @@
+//! Full-text index of parquet rows built with [tantivy]
+//!
+//! A [`FullTextIndex`] stores one tantivy document per row of a parquet file,
+//! made of the selected text columns and the row number.  [`hybrid_sql`] uses
+//! it to resolve the text predicate of a query to row numbers, reads only
+//! those rows from the parquet file and runs the rest of the query on them
+//! with DataFusion.
+//!
+//! Only available with the `tantivy` feature.
+//!
+//! [tantivy]: https://docs.rs/tantivy
+
+use crate::{
+    arrow::row_selection,
+    datafusion::new_session_context,
+    file::{byte_array_columns_named, byte_array_value},
+    redact, ZnError, ZnResult,
+};
+use datafusion::{arrow::record_batch::RecordBatch, datasource::MemTable};
+use parquet::{arrow::arrow_reader::ParquetRecordBatchReaderBuilder, file::reader::FileReader};
+use std::{fs::File, path::Path, sync::Arc};
+use tantivy::{
+    collector::DocSetCollector,
+    query::QueryParser,
+    schema::{Field, FieldType, Schema, FAST, TEXT},
+    Document, Index, IndexReader,
+};
+
+/// Name of the tantivy field holding the parquet row number.
+const ROW_FIELD: &str = "__row";
+
+/// Memory budget of the index writer.
+const WRITER_MEMORY_BYTES: usize = 50_000_000;
+
+/// Tantivy index of some text columns of a parquet file.
+pub struct FullTextIndex {
+    index: Index,
+    reader: IndexReader,
+    row: Field,
+    columns: Vec<Field>,
+}
+
+impl FullTextIndex {
+    /// Builds an index of the `columns` of the file, held in memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::InvalidArgument`] if one of the `columns` is not a
+    /// byte array column.
+    pub fn build_in_ram<R: FileReader>(reader: &R, columns: &[&str]) -> ZnResult<Self> {
+        let (schema, row, fields) = schema(columns);
+        Self::build(Index::create_in_ram(schema), reader, columns, row, fields)
+    }
+
+    /// Builds an index of the `columns` of the file and stores it in the
+    /// directory `dir`, which must exist and be empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::InvalidArgument`] if one of the `columns` is not a
+    /// byte array column.
+    pub fn build_in_dir<R: FileReader>(
+        reader: &R,
+        columns: &[&str],
+        dir: impl AsRef<Path>,
+    ) -> ZnResult<Self> {
+        let (schema, row, fields) = schema(columns);
+        let index = Index::create_in_dir(dir, schema)?;
+        Self::build(index, reader, columns, row, fields)
+    }
+
+    /// Opens an index previously stored with [`build_in_dir`](Self::build_in_dir).
+    pub fn open(dir: impl AsRef<Path>) -> ZnResult<Self> {
+        let index = Index::open_in_dir(dir)?;
+        let schema = index.schema();
+        let row = schema
+            .get_field(ROW_FIELD)
+            .ok_or_else(|| ZnError::invalid_index(format!("no {ROW_FIELD:?} field")))?;
+        let columns = schema
+            .fields()
+            .filter(|(_, entry)| matches!(entry.field_type(), FieldType::Str(_)))
+            .map(|(field, _)| field)
+            .collect();
+        Ok(Self {
+            reader: index.reader()?,
+            index,
+            row,
+            columns,
+        })
+    }
+
+    fn build<R: FileReader>(
+        index: Index,
+        reader: &R,
+        columns: &[&str],
+        row: Field,
+        fields: Vec<Field>,
+    ) -> ZnResult<Self> {
+        let projection = byte_array_columns_named(reader.metadata(), columns)?;
+        let mut writer = index.writer_with_num_threads(1, WRITER_MEMORY_BYTES)?;
+        for (row_number, record) in reader.get_row_iter(Some(projection))?.enumerate() {
+            let mut doc = Document::default();
+            doc.add_u64(row, row_number as u64);
+            for (column_name, value) in record.get_column_iter() {
+                let Some(s) = byte_array_value(column_name, value)? else {
+                    continue;
+                };
+                if let Some(i) = columns.iter().position(|name| name == column_name) {
+                    doc.add_text(fields[i], String::from_utf8_lossy(s));
+                }
+            }
+            writer.add_document(doc)?;
+        }
+        writer.commit()?;
+
+        Ok(Self {
+            reader: index.reader()?,
+            index,
+            row,
+            columns: fields,
+        })
+    }
+
+    /// Returns the sorted numbers of rows matching the tantivy `query`, e.g.
+    /// `error AND (timeout OR refused)`.  Terms without a field name are
+    /// looked up in all indexed columns.
+    pub fn search(&self, query: &str) -> ZnResult<Vec<u64>> {
+        // New segments only become visible after a reload.
+        self.reader.reload()?;
+        let query = QueryParser::for_index(&self.index, self.columns.clone())
+            .parse_query(query)
+            .map_err(|e| ZnError::invalid_argument(e.to_string()))?;
+        let searcher = self.reader.searcher();
+        let mut rows = Vec::new();
+        for address in searcher.search(&query, &DocSetCollector)? {
+            let row_numbers = searcher
+                .segment_reader(address.segment_ord)
+                .fast_fields()
+                .u64(self.row)?;
+            rows.push(row_numbers.get_val(address.doc_id));
+        }
+        rows.sort_unstable();
+        Ok(rows)
+    }
+}
+
+fn schema(columns: &[&str]) -> (Schema, Field, Vec<Field>) {
+    let mut builder = Schema::builder();
+    let row = builder.add_u64_field(ROW_FIELD, FAST);
+    let fields = columns
+        .iter()
+        .map(|name| builder.add_text_field(name, TEXT))
+        .collect();
+    (builder.build(), row, fields)
+}
+
+/// Runs `sql` over the rows of the parquet file at `path` that match the
+/// full-text `text_query`.
+///
+/// The `index` resolves the text query to row numbers, only those rows are
+/// decoded, and they are registered as the table `table` for DataFusion to
+/// evaluate `sql` on.  The result is [redacted](crate::redact).
+pub async fn hybrid_sql(
+    index: &FullTextIndex,
+    path: impl AsRef<Path>,
+    text_query: &str,
+    table: &str,
+    sql: &str,
+    batch_size: usize,
+) -> ZnResult<Vec<RecordBatch>> {
+    let rows = index.search(text_query)?;
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+    let num_rows = builder.metadata().file_metadata().num_rows();
+    let num_rows = u64::try_from(num_rows)
+        .map_err(|_| ZnError::invalid_metadata(format!("negative number of rows: {num_rows}")))?;
+    let schema = builder.schema().clone();
+    let batches = builder
+        .with_batch_size(batch_size)
+        .with_row_selection(row_selection(&rows, num_rows))
+        .build()?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let ctx = new_session_context(batch_size, false);
+    ctx.register_table(table, Arc::new(MemTable::try_new(schema, vec![batches])?))?;
+    redact::collect(ctx.sql(sql).await?).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::parquet_file;
+
+    #[test]
+    fn test_fulltext_search() {
+        let file = parquet_file(
+            &[
+                "connection timeout",
+                "connection refused",
+                "all good",
+                "error: timeout",
+            ],
+            2,
+        );
+        let index = FullTextIndex::build_in_ram(&file, &["log"]).unwrap();
+        assert_eq!(index.search("timeout").unwrap(), [0, 3]);
+        assert_eq!(index.search("+connection -refused").unwrap(), [0]);
+        assert_eq!(index.search("log:good").unwrap(), [2]);
+        assert!(FullTextIndex::build_in_ram(&file, &["id"]).is_err());
+    }
+}
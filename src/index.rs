@@ -0,0 +1,283 @@
+//! Trigram index of row groups
+//!
+//! A [`TrigramIndex`] maps every trigram (three consecutive bytes) found in
+//! the text columns of a parquet file to the list of row groups containing it.
+//! A needle can only occur in a row group that contains all of the needle's
+//! trigrams, so searches can skip the remaining row groups without
+//! decompressing them.
+//!
+//! The index is persisted next to the parquet file as a sidecar; see
+//! [`TrigramIndex::sidecar_path`].
+
+use crate::{
+    codec::{read_usize, read_varint, write_varint},
+    file::{byte_array_columns, byte_array_value},
+    ZnError, ZnResult,
+};
+use parquet::file::reader::FileReader;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+type Trigram = [u8; 3];
+
+/// A structure that can tell which row groups of a parquet file may contain a
+/// needle, allowing searches to skip the others.
+pub trait RowGroupPruner {
+    /// Number of row groups of the file the structure was built for.
+    fn num_row_groups(&self) -> usize;
+
+    /// Returns indices of the row groups that may contain the `needle`, in
+    /// ascending order.
+    fn candidates(&self, needle: &[u8]) -> Vec<usize>;
+}
+
+const MAGIC: &[u8; 4] = b"ZNTG";
+const VERSION: u8 = 1;
+/// The most row groups preallocated for a posting list, whose length is read
+/// from the index and may be corrupt.
+const MAX_PREALLOCATED: usize = 1024;
+
+/// Sorted trigram → row-group posting lists of a parquet file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrigramIndex {
+    num_row_groups: usize,
+    /// Sorted by trigram; every posting list is sorted and free of duplicates.
+    postings: Vec<(Trigram, Vec<u32>)>,
+}
+
+impl TrigramIndex {
+    /// Builds the index from the [byte array] columns of every row group.
+    ///
+    /// [byte array]: crate::file::byte_array_columns_uncompressed_size
+    pub fn build<R: FileReader>(reader: &R) -> ZnResult<Self> {
+        let projection = byte_array_columns(reader.metadata())?;
+        let num_row_groups = reader.num_row_groups();
+        let mut postings: HashMap<Trigram, Vec<u32>> = HashMap::new();
+        for i in 0..num_row_groups {
+            let rg = u32::try_from(i)
+                .map_err(|_| ZnError::invalid_metadata(format!("too many row groups: {i}")))?;
+            let row_group = reader.get_row_group(i)?;
+            for row in row_group.get_row_iter(Some(projection.clone()))? {
+                for (column_name, value) in row.get_column_iter() {
+                    let Some(s) = byte_array_value(column_name, value)? else {
+                        continue;
+                    };
+                    for w in s.windows(3) {
+                        let list = postings.entry([w[0], w[1], w[2]]).or_default();
+                        if list.last() != Some(&rg) {
+                            list.push(rg);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut postings: Vec<_> = postings.into_iter().collect();
+        postings.sort_unstable_by_key(|(trigram, _)| *trigram);
+        Ok(Self {
+            num_row_groups,
+            postings,
+        })
+    }
+
+    /// Number of distinct trigrams in the index.
+    pub fn num_trigrams(&self) -> usize {
+        self.postings.len()
+    }
+
+    fn postings(&self, trigram: &Trigram) -> Option<&[u32]> {
+        self.postings
+            .binary_search_by_key(trigram, |(t, _)| *t)
+            .ok()
+            .map(|i| self.postings[i].1.as_slice())
+    }
+
+    /// Returns the conventional location of the index of the parquet file at
+    /// `parquet_path`: the same path with `.tri` appended.
+    pub fn sidecar_path(parquet_path: impl AsRef<Path>) -> PathBuf {
+        let mut path = parquet_path.as_ref().as_os_str().to_owned();
+        path.push(".tri");
+        path.into()
+    }
+
+    /// Writes the index to the file at `path`, replacing it if it exists.
+    pub fn save(&self, path: impl AsRef<Path>) -> ZnResult<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_to(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Reads an index previously written with [`save`](Self::save).
+    pub fn load(path: impl AsRef<Path>) -> ZnResult<Self> {
+        Self::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// Serializes the index.
+    ///
+    /// The format is the magic `ZNTG`, a version byte, and then, as LEB128
+    /// varints, the number of row groups, the number of trigrams, and for
+    /// every trigram its three bytes followed by the length of its posting
+    /// list and the delta-encoded row group indices.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> ZnResult<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&[VERSION])?;
+        write_varint(w, self.num_row_groups as u64)?;
+        write_varint(w, self.postings.len() as u64)?;
+        for (trigram, list) in &self.postings {
+            w.write_all(trigram)?;
+            write_varint(w, list.len() as u64)?;
+            let mut prev = 0;
+            for &rg in list {
+                write_varint(w, u64::from(rg - prev))?;
+                prev = rg;
+            }
+        }
+        Ok(())
+    }
+
+    /// Deserializes an index written with [`write_to`](Self::write_to).
+    pub fn read_from<R: Read>(r: &mut R) -> ZnResult<Self> {
+        let mut header = [0; 5];
+        r.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(ZnError::invalid_index("not a trigram index"));
+        }
+        if header[4] != VERSION {
+            return Err(ZnError::invalid_index(format!(
+                "unsupported version {}",
+                header[4]
+            )));
+        }
+
+        let num_row_groups = read_usize(r)?;
+        let num_trigrams = read_usize(r)?;
+        let mut postings = Vec::new();
+        for _ in 0..num_trigrams {
+            let mut trigram = [0; 3];
+            r.read_exact(&mut trigram)?;
+            if postings.last().is_some_and(|(t, _)| *t >= trigram) {
+                return Err(ZnError::invalid_index("trigrams are not sorted"));
+            }
+            let len = read_usize(r)?;
+            if len > num_row_groups {
+                return Err(ZnError::invalid_index("posting list is too long"));
+            }
+            let mut list = Vec::with_capacity(len.min(MAX_PREALLOCATED));
+            let mut rg = 0u64;
+            for i in 0..len {
+                let delta = read_varint(r)?;
+                if i > 0 && delta == 0 {
+                    return Err(ZnError::invalid_index(
+                        "duplicate row group in posting list",
+                    ));
+                }
+                rg = rg.saturating_add(delta);
+                if rg >= num_row_groups as u64 {
+                    return Err(ZnError::invalid_index(format!(
+                        "row group {rg} is out of range"
+                    )));
+                }
+                list.push(rg as u32);
+            }
+            postings.push((trigram, list));
+        }
+        Ok(Self {
+            num_row_groups,
+            postings,
+        })
+    }
+}
+
+impl RowGroupPruner for TrigramIndex {
+    fn num_row_groups(&self) -> usize {
+        self.num_row_groups
+    }
+
+    /// Needles shorter than three bytes have no trigrams, so every row group
+    /// is a candidate for them.
+    fn candidates(&self, needle: &[u8]) -> Vec<usize> {
+        if needle.len() < 3 {
+            return (0..self.num_row_groups).collect();
+        }
+
+        let mut result: Option<Vec<u32>> = None;
+        for w in needle.windows(3) {
+            let Some(list) = self.postings(&[w[0], w[1], w[2]]) else {
+                return Vec::new();
+            };
+            result = Some(match result {
+                None => list.to_vec(),
+                Some(acc) => intersect(&acc, list),
+            });
+            if result.as_ref().is_some_and(Vec::is_empty) {
+                return Vec::new();
+            }
+        }
+        result
+            .unwrap_or_default()
+            .into_iter()
+            .map(|rg| rg as usize)
+            .collect()
+    }
+}
+
+/// Intersects two sorted lists.
+fn intersect(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (mut i, mut j) = (0, 0);
+    let mut out = Vec::new();
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                out.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::parquet_file;
+
+    #[test]
+    fn test_trigram_index() {
+        let file = parquet_file(&["GET /index.html", "k8s pod", "POST /api", "k8s node"], 1);
+        let index = TrigramIndex::build(&file).unwrap();
+        assert_eq!(index.num_row_groups(), 4);
+        assert_eq!(index.candidates(b"k8s"), [1, 3]);
+        assert_eq!(index.candidates(b"k8s pod"), [1]);
+        assert_eq!(index.candidates(b"/api"), [2]);
+        assert!(index.candidates(b"missing").is_empty());
+        assert_eq!(index.candidates(b"/"), [0, 1, 2, 3]);
+
+        assert_eq!(
+            crate::file::count_occurrences_with_index(&file, &index, b"k8s").unwrap(),
+            crate::file::count_occurrences(&file, b"k8s").unwrap()
+        );
+
+        let mut buf = Vec::new();
+        index.write_to(&mut buf).unwrap();
+        assert_eq!(TrigramIndex::read_from(&mut buf.as_slice()).unwrap(), index);
+        assert!(TrigramIndex::read_from(&mut &buf[..buf.len() - 1]).is_err());
+
+        // A corrupt header claiming a huge posting list, with nothing behind.
+        let mut corrupt = Vec::new();
+        corrupt.extend_from_slice(MAGIC);
+        corrupt.push(VERSION);
+        write_varint(&mut corrupt, u64::from(u32::MAX)).unwrap();
+        write_varint(&mut corrupt, 1).unwrap();
+        corrupt.extend_from_slice(b"k8s");
+        write_varint(&mut corrupt, u64::from(u32::MAX)).unwrap();
+        assert!(TrigramIndex::read_from(&mut corrupt.as_slice()).is_err());
+    }
+}
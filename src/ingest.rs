@@ -0,0 +1,363 @@
+//! Streaming ingestion
+//!
+//! An [`Ingester`] accepts rows as Arrow batches or JSON objects and buffers
+//! them in memory until the buffer reaches [`IngestOptions::max_rows`] or
+//! [`IngestOptions::max_bytes`], or its oldest row
+//! [`IngestOptions::max_age`].  Then it flushes the rows, sorted by
+//! timestamp, to parquet files in the partitions of a [`PartitionLayout`],
+//! where the searches of cold files find them.  Until then,
+//! [`buffered`](Ingester::buffered) returns the buffer as an in-memory
+//! parquet file, searchable with the same APIs.
+//!
+//! Every accepted batch is appended to a write-ahead log first, so a
+//! restarted [`Ingester::open`] recovers the rows that were not flushed.
+//! The log is a sequence of frames, each an 8-byte little-endian length and
+//! an Arrow IPC stream of one batch; a frame torn by a crash is dropped.
+
+use crate::{
+    compact::{adapt, merged_schema},
+    partition::PartitionLayout,
+    writer::{decode_records, properties, sort_by_column, DEFAULT_ROW_GROUP_SIZE},
+    ZnError, ZnResult,
+};
+use arrow::{
+    array::{Array, Int64Array},
+    compute::{cast, concat_batches},
+    datatypes::{DataType, Schema, TimeUnit},
+    ipc::{reader::StreamReader, writer::StreamWriter},
+    record_batch::RecordBatch,
+};
+use bytes::Bytes;
+use parquet::{arrow::ArrowWriter, schema::types::ColumnPath};
+use serde_json::Value;
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone)]
+pub struct IngestOptions {
+    /// File of the write-ahead log, created if missing.
+    pub wal_path: PathBuf,
+    /// Int64 or microsecond timestamp column holding the time of a row, in
+    /// microseconds since the Unix epoch.  Every row must have it.
+    pub timestamp_column: String,
+    /// Columns to dictionary-encode; see
+    /// [`WriterOptions::label_columns`](crate::writer::WriterOptions::label_columns).
+    pub label_columns: Vec<String>,
+    pub max_rows: usize,
+    /// Maximum size of the buffered batches in memory.
+    pub max_bytes: usize,
+    pub max_age: Duration,
+    /// Whether to wait for every append to the log to reach the disk.
+    pub sync_wal: bool,
+}
+
+impl IngestOptions {
+    pub fn new(wal_path: impl Into<PathBuf>) -> Self {
+        Self {
+            wal_path: wal_path.into(),
+            timestamp_column: "_timestamp".to_owned(),
+            label_columns: Vec::new(),
+            max_rows: DEFAULT_ROW_GROUP_SIZE,
+            max_bytes: 64 << 20,
+            max_age: Duration::from_secs(60),
+            sync_wal: true,
+        }
+    }
+}
+
+/// Buffers rows and flushes them to parquet; see the [module docs](self).
+#[derive(Debug)]
+pub struct Ingester {
+    layout: PartitionLayout,
+    options: IngestOptions,
+    wal: File,
+    batches: Vec<RecordBatch>,
+    rows: usize,
+    bytes: usize,
+    /// When the oldest buffered row was accepted or recovered.
+    oldest: Option<Instant>,
+}
+
+impl Ingester {
+    /// Opens the write-ahead log of `options` and recovers its rows into the
+    /// buffer.
+    pub fn open(layout: PartitionLayout, options: IngestOptions) -> ZnResult<Self> {
+        let mut wal = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&options.wal_path)?;
+        let mut log = Vec::new();
+        wal.read_to_end(&mut log)?;
+
+        let mut ingester = Self {
+            layout,
+            options,
+            wal,
+            batches: Vec::new(),
+            rows: 0,
+            bytes: 0,
+            oldest: None,
+        };
+        let mut rest = &log[..];
+        while let Some((len, frame)) = rest.split_first_chunk::<8>() {
+            let len = u64::from_le_bytes(*len) as usize;
+            if frame.len() < len {
+                break;
+            }
+            for batch in StreamReader::try_new(&frame[..len], None)? {
+                ingester.buffer(batch?);
+            }
+            rest = &frame[len..];
+        }
+        if !rest.is_empty() {
+            // Drop the torn frame, so appends follow the last whole one.
+            ingester.wal.set_len((log.len() - rest.len()) as u64)?;
+        }
+        Ok(ingester)
+    }
+
+    /// Number of buffered rows.
+    pub fn num_rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Accepts the rows of `batch` and returns the files flushed, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::InvalidArgument`] if a row lacks a timestamp, and
+    /// [`ZnError::UnsupportedType`] if the timestamp column has another type
+    /// than Int64 or microsecond timestamps.
+    pub fn push(&mut self, batch: RecordBatch) -> ZnResult<Vec<PathBuf>> {
+        self.timestamps(&batch)?;
+        if batch.num_rows() == 0 {
+            return Ok(Vec::new());
+        }
+        let mut frame = Vec::new();
+        let mut writer = StreamWriter::try_new(&mut frame, &batch.schema())?;
+        writer.write(&batch)?;
+        writer.finish()?;
+        drop(writer);
+        let mut record = Vec::with_capacity(8 + frame.len());
+        record.extend_from_slice(&(frame.len() as u64).to_le_bytes());
+        record.extend_from_slice(&frame);
+        self.wal.write_all(&record)?;
+        if self.options.sync_wal {
+            self.wal.sync_data()?;
+        }
+
+        self.buffer(batch);
+        self.flush_if_due()
+    }
+
+    /// Accepts JSON object `records`, with the schema inferred from them; see
+    /// [`push`](Self::push).
+    pub fn push_json(&mut self, records: Vec<Value>) -> ZnResult<Vec<PathBuf>> {
+        if let Some(i) = records.iter().position(|r| !r.is_object()) {
+            return Err(ZnError::invalid_argument(format!(
+                "record {i} is not a JSON object"
+            )));
+        }
+        if records.is_empty() {
+            return self.flush_if_due();
+        }
+        self.push(decode_records(records, None)?)
+    }
+
+    /// Flushes the buffer if it reached a threshold; meant to be called
+    /// periodically, so the age threshold holds without new rows.
+    pub fn flush_if_due(&mut self) -> ZnResult<Vec<PathBuf>> {
+        let due = self.rows >= self.options.max_rows
+            || self.bytes >= self.options.max_bytes
+            || self
+                .oldest
+                .is_some_and(|t| t.elapsed() >= self.options.max_age);
+        if due {
+            self.flush()
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Writes the buffered rows to one new file per partition they fall in,
+    /// empties the buffer and the log, and returns the files.
+    pub fn flush(&mut self) -> ZnResult<Vec<PathBuf>> {
+        let Some(batch) = self.sorted_buffer()? else {
+            return Ok(Vec::new());
+        };
+        let timestamps = self.timestamps(&batch)?;
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros();
+
+        let mut files = Vec::new();
+        let mut start = 0;
+        while start < batch.num_rows() {
+            let dir = self.layout.partition_dir(timestamps.value(start))?;
+            let mut end = start + 1;
+            while end < batch.num_rows() && self.layout.partition_dir(timestamps.value(end))? == dir
+            {
+                end += 1;
+            }
+            std::fs::create_dir_all(&dir)?;
+            let path = dir.join(format!("ingest-{stamp}-{}.parquet", files.len()));
+            // Written under another name first, so searches never see a
+            // partial file.
+            let partial = path.with_extension("parquet.partial");
+            let mut file = File::create(&partial)?;
+            file.write_all(&self.write_parquet(&batch.slice(start, end - start))?)?;
+            file.sync_data()?;
+            std::fs::rename(&partial, &path)?;
+            files.push(path);
+            start = end;
+        }
+
+        self.wal.set_len(0)?;
+        self.batches.clear();
+        self.rows = 0;
+        self.bytes = 0;
+        self.oldest = None;
+        Ok(files)
+    }
+
+    /// Returns the buffered rows as a parquet file, sorted like flushed ones,
+    /// or `None` if the buffer is empty.
+    pub fn buffered(&self) -> ZnResult<Option<Bytes>> {
+        match self.sorted_buffer()? {
+            Some(batch) => Ok(Some(self.write_parquet(&batch)?.into())),
+            None => Ok(None),
+        }
+    }
+
+    fn buffer(&mut self, batch: RecordBatch) {
+        self.rows += batch.num_rows();
+        self.bytes += batch.get_array_memory_size();
+        self.oldest.get_or_insert_with(Instant::now);
+        self.batches.push(batch);
+    }
+
+    fn sorted_buffer(&self) -> ZnResult<Option<RecordBatch>> {
+        if self.batches.is_empty() {
+            return Ok(None);
+        }
+        let schemas: Vec<_> = self
+            .batches
+            .iter()
+            .map(|batch| Schema::new(batch.schema().fields().clone()))
+            .collect();
+        let schema = merged_schema(&schemas)?;
+        let batches = self
+            .batches
+            .iter()
+            .map(|batch| adapt(batch, &schema))
+            .collect::<ZnResult<Vec<_>>>()?;
+        let batch = concat_batches(&schema, &batches)?;
+        let column = schema.index_of(&self.options.timestamp_column)?;
+        Ok(Some(sort_by_column(&batch, column)?))
+    }
+
+    fn write_parquet(&self, batch: &RecordBatch) -> ZnResult<Vec<u8>> {
+        let sort_column = batch.schema().index_of(&self.options.timestamp_column)?;
+        let dictionary_columns = self
+            .options
+            .label_columns
+            .iter()
+            .map(|label| ColumnPath::from(label.as_str()));
+        let props = properties(
+            DEFAULT_ROW_GROUP_SIZE,
+            Some(sort_column),
+            dictionary_columns,
+        );
+        let mut buf = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut buf, batch.schema(), Some(props.build()))?;
+        writer.write(batch)?;
+        writer.close()?;
+        Ok(buf)
+    }
+
+    /// Returns the timestamps of the rows of `batch`, all non-null.
+    fn timestamps(&self, batch: &RecordBatch) -> ZnResult<Int64Array> {
+        let name = &self.options.timestamp_column;
+        let i = batch.schema().index_of(name).map_err(|_| {
+            ZnError::invalid_argument(format!("no timestamp column {name:?} in the rows"))
+        })?;
+        let column = batch.column(i);
+        match column.data_type() {
+            DataType::Int64 | DataType::Timestamp(TimeUnit::Microsecond, _) => (),
+            t => {
+                return Err(ZnError::unsupported_type(format!(
+                    "timestamp column {name:?} is of type {t}"
+                )))
+            }
+        }
+        if column.null_count() > 0 {
+            return Err(ZnError::invalid_argument(format!(
+                "{} row(s) without timestamp",
+                column.null_count()
+            )));
+        }
+        let column = cast(column, &DataType::Int64)?;
+        Ok(column
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .expect("cast to Int64")
+            .clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::partition::Granularity;
+    use serde_json::json;
+
+    #[test]
+    fn test_ingester() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = PartitionLayout::new(dir.path().join("logs"), Granularity::Hour);
+        let hour = 3_600_000_000;
+        let options = IngestOptions {
+            max_rows: 3,
+            ..IngestOptions::new(dir.path().join("wal"))
+        };
+
+        let mut ingester = Ingester::open(layout.clone(), options.clone()).unwrap();
+        let flushed = ingester
+            .push_json(vec![
+                json!({"_timestamp": hour + 2, "log": "b"}),
+                json!({"_timestamp": 1, "log": "a"}),
+            ])
+            .unwrap();
+        assert!(flushed.is_empty());
+        assert!(ingester.push_json(vec![json!({"log": "c"})]).is_err());
+        let buffered = ingester.buffered().unwrap().unwrap();
+        assert_eq!(crate::metadata::text_columns(&buffered).unwrap().len(), 1);
+
+        // Recovered after a crash, torn last frame included.
+        drop(ingester);
+        let mut wal = OpenOptions::new()
+            .append(true)
+            .open(&options.wal_path)
+            .unwrap();
+        wal.write_all(&[42, 0, 0]).unwrap();
+        let mut ingester = Ingester::open(layout.clone(), options.clone()).unwrap();
+        assert_eq!(ingester.num_rows(), 2);
+
+        let flushed = ingester
+            .push_json(vec![json!({"_timestamp": 3, "log": "c", "host": "h"})])
+            .unwrap();
+        assert_eq!(flushed.len(), 2);
+        assert_eq!(layout.files(0..hour).unwrap(), &flushed[..1]);
+        assert_eq!(layout.count_occurrences(0..2 * hour, b"c").unwrap(), 1);
+        assert_eq!(layout.count_occurrences(0..2 * hour, b"b").unwrap(), 1);
+        assert_eq!(ingester.num_rows(), 0);
+        assert!(ingester.buffered().unwrap().is_none());
+        assert_eq!(std::fs::metadata(&options.wal_path).unwrap().len(), 0);
+    }
+}
@@ -0,0 +1,316 @@
+//! JSON path extraction without parsing documents
+//!
+//! [`JsonPath::get`] scans a document for the value at a path, skipping the
+//! values on the way without allocating, so JSON-encoded log lines are
+//! filtered without building a tree per line, e.g. by
+//! [`json_get`](crate::match_udf::JSON_GET_UDF).  The scanner only checks
+//! as much of the syntax as it needs to find the value.
+
+use crate::{ZnError, ZnResult};
+use std::borrow::Cow;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    Key(String),
+    Index(usize),
+}
+
+/// A path of object keys and array indices from the root of a document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPath {
+    steps: Vec<Step>,
+}
+
+impl JsonPath {
+    /// Parses a path from the root `$`, e.g. `$.kubernetes.pod_name` or
+    /// `$.containers[0].image`.  Keys with dots or brackets are quoted, e.g.
+    /// `$.labels['app.kubernetes.io/name']`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::InvalidArgument`] if the path is malformed.
+    pub fn parse(path: &str) -> ZnResult<Self> {
+        let invalid = || ZnError::invalid_argument(format!("invalid JSON path: {path}"));
+        let mut rest = path.trim().strip_prefix('$').ok_or_else(invalid)?;
+        let mut steps = Vec::new();
+        while !rest.is_empty() {
+            if let Some(tail) = rest.strip_prefix('.') {
+                let end = tail.find(['.', '[']).unwrap_or(tail.len());
+                if end == 0 {
+                    return Err(invalid());
+                }
+                steps.push(Step::Key(tail[..end].to_owned()));
+                rest = &tail[end..];
+            } else if let Some(tail) = rest.strip_prefix('[') {
+                let (step, tail) = match tail.chars().next() {
+                    Some(quote @ ('\'' | '"')) => {
+                        let (key, tail) = tail[1..].split_once(quote).ok_or_else(invalid)?;
+                        let tail = tail.strip_prefix(']').ok_or_else(invalid)?;
+                        (Step::Key(key.to_owned()), tail)
+                    }
+                    _ => {
+                        let (index, tail) = tail.split_once(']').ok_or_else(invalid)?;
+                        let index = index.trim().parse().map_err(|_| invalid())?;
+                        (Step::Index(index), tail)
+                    }
+                };
+                steps.push(step);
+                rest = tail;
+            } else {
+                return Err(invalid());
+            }
+        }
+        Ok(Self { steps })
+    }
+
+    /// Returns the JSON text of the value at the path in `doc`, or `None` if
+    /// there is none or the document is malformed on the way to it.  The
+    /// first of duplicate keys is taken.
+    pub fn get<'a>(&self, doc: &'a [u8]) -> Option<&'a [u8]> {
+        let mut scanner = Scanner { doc, pos: 0 };
+        for step in &self.steps {
+            match step {
+                Step::Key(key) => scanner.enter_member(key.as_bytes())?,
+                Step::Index(index) => scanner.enter_element(*index)?,
+            }
+        }
+        scanner.value()
+    }
+
+    /// Like [`get`](Self::get), but returns strings unescaped and other
+    /// values as their JSON text, and `None` for nulls.
+    pub fn get_text<'a>(&self, doc: &'a [u8]) -> Option<Cow<'a, str>> {
+        match self.get(doc)? {
+            b"null" => None,
+            value @ [b'"', .., b'"'] => unescape(&value[1..value.len() - 1]),
+            value => std::str::from_utf8(value).ok().map(Cow::Borrowed),
+        }
+    }
+}
+
+struct Scanner<'a> {
+    doc: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn skip_whitespace(&mut self) {
+        while self
+            .doc
+            .get(self.pos)
+            .is_some_and(|b| matches!(b, b' ' | b'\t' | b'\n' | b'\r'))
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.doc.get(self.pos).copied()
+    }
+
+    /// Consumes `byte`, after whitespace.
+    fn eat(&mut self, byte: u8) -> Option<()> {
+        (self.peek()? == byte).then(|| self.pos += 1)
+    }
+
+    /// Consumes the value at the position and returns its text.
+    fn value(&mut self) -> Option<&'a [u8]> {
+        let start = match self.peek()? {
+            b'"' => {
+                let start = self.pos;
+                self.skip_string()?;
+                start
+            }
+            b'{' | b'[' => {
+                let start = self.pos;
+                self.skip_container()?;
+                start
+            }
+            _ => {
+                let start = self.pos;
+                let len = self.doc[start..]
+                    .iter()
+                    .position(|b| matches!(b, b',' | b'}' | b']' | b' ' | b'\t' | b'\n' | b'\r'))
+                    .unwrap_or(self.doc.len() - start);
+                if len == 0 {
+                    return None;
+                }
+                self.pos += len;
+                start
+            }
+        };
+        Some(&self.doc[start..self.pos])
+    }
+
+    /// Consumes the string starting at the position.
+    fn skip_string(&mut self) -> Option<()> {
+        self.pos += 1;
+        loop {
+            let i = memchr::memchr2(b'"', b'\\', &self.doc[self.pos..])?;
+            self.pos += i + 1;
+            if self.doc[self.pos - 1] == b'"' {
+                return Some(());
+            }
+            // Skips the escaped byte.
+            self.pos += 1;
+            if self.pos > self.doc.len() {
+                return None;
+            }
+        }
+    }
+
+    /// Consumes the object or array starting at the position, only pairing
+    /// its brackets.
+    fn skip_container(&mut self) -> Option<()> {
+        let mut depth = 0;
+        loop {
+            match *self.doc.get(self.pos)? {
+                b'"' => {
+                    self.skip_string()?;
+                    continue;
+                }
+                b'{' | b'[' => depth += 1,
+                b'}' | b']' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.pos += 1;
+                        return Some(());
+                    }
+                }
+                _ => (),
+            }
+            self.pos += 1;
+        }
+    }
+
+    /// Moves to the value of the member named `key` of the object at the
+    /// position.
+    fn enter_member(&mut self, key: &[u8]) -> Option<()> {
+        self.eat(b'{')?;
+        if self.peek()? == b'}' {
+            return None;
+        }
+        loop {
+            if self.peek()? != b'"' {
+                return None;
+            }
+            let start = self.pos + 1;
+            self.skip_string()?;
+            let name = &self.doc[start..self.pos - 1];
+            self.eat(b':')?;
+            if name == key || name.contains(&b'\\') && unescape(name)?.as_bytes() == key {
+                return Some(());
+            }
+            self.value()?;
+            // The object ends without the key if there is no comma.
+            self.eat(b',')?;
+        }
+    }
+
+    /// Moves to the element at `index` of the array at the position.
+    fn enter_element(&mut self, index: usize) -> Option<()> {
+        self.eat(b'[')?;
+        if self.peek()? == b']' {
+            return None;
+        }
+        for _ in 0..index {
+            self.value()?;
+            self.eat(b',')?;
+        }
+        Some(())
+    }
+}
+
+/// Unescapes the contents of a JSON string, only copying them if they have
+/// escapes.
+fn unescape(s: &[u8]) -> Option<Cow<'_, str>> {
+    if memchr::memchr(b'\\', s).is_none() {
+        return std::str::from_utf8(s).ok().map(Cow::Borrowed);
+    }
+    let mut unescaped = Vec::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = memchr::memchr(b'\\', rest) {
+        unescaped.extend_from_slice(&rest[..i]);
+        let (&escape, tail) = rest[i + 1..].split_first()?;
+        rest = tail;
+        let c = match escape {
+            b'"' => '"',
+            b'\\' => '\\',
+            b'/' => '/',
+            b'b' => '\u{8}',
+            b'f' => '\u{c}',
+            b'n' => '\n',
+            b'r' => '\r',
+            b't' => '\t',
+            b'u' => {
+                let unit = hex4(rest)?;
+                rest = &rest[4..];
+                if (0xd800..0xdc00).contains(&unit) {
+                    // A high surrogate, which a low one must follow.
+                    let low = rest
+                        .strip_prefix(b"\\u")
+                        .and_then(hex4)
+                        .filter(|low| (0xdc00..0xe000).contains(low))?;
+                    rest = &rest[6..];
+                    char::from_u32(0x10000 + ((unit - 0xd800) << 10) + (low - 0xdc00))?
+                } else {
+                    char::from_u32(unit)?
+                }
+            }
+            _ => return None,
+        };
+        unescaped.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+    }
+    unescaped.extend_from_slice(rest);
+    String::from_utf8(unescaped).ok().map(Cow::Owned)
+}
+
+fn hex4(s: &[u8]) -> Option<u32> {
+    let digits = s.get(..4)?;
+    if !digits.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    u32::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_path() {
+        let doc = br#"{"log": "GET /api", "n": 1.5e3, "kubernetes": {
+            "labels": {"app.kubernetes.io/name": "web", "tier": null},
+            "containers": [{"image": "nginx"}, {"image": "envoy\u00e9\ud83d\ude00", "args": ["-v"]}],
+            "pod_name": "web-\"1\"", "pod_name": "dup"}}"#;
+        let get = |path: &str| JsonPath::parse(path).unwrap().get_text(doc);
+
+        assert_eq!(get("$.log").as_deref(), Some("GET /api"));
+        assert_eq!(get("$.n").as_deref(), Some("1.5e3"));
+        assert_eq!(get("$.kubernetes.pod_name").as_deref(), Some(r#"web-"1""#));
+        assert_eq!(
+            get("$.kubernetes.labels['app.kubernetes.io/name']").as_deref(),
+            Some("web")
+        );
+        assert_eq!(get("$.kubernetes.labels.tier"), None);
+        assert_eq!(
+            get("$.kubernetes.containers[1].image").as_deref(),
+            Some("envoy\u{e9}\u{1f600}")
+        );
+        assert_eq!(
+            get(r#"$.kubernetes.containers[1]["args"]"#).as_deref(),
+            Some(r#"["-v"]"#)
+        );
+        assert_eq!(get("$.kubernetes.containers[2]"), None);
+        assert_eq!(get("$.log.missing"), None);
+        assert_eq!(get("$.missing"), None);
+        assert!(get("$").unwrap().starts_with('{'));
+
+        assert_eq!(JsonPath::parse("$.a.b").unwrap().get(br#"{"a": {"b"#), None);
+        assert_eq!(JsonPath::parse("$.b").unwrap().get(br#"{"a": "x"#), None);
+        for path in ["", "a", "$.", "$[", "$[x]", "$['a'", "$..a"] {
+            assert!(JsonPath::parse(path).is_err(), "{path}");
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! Pluggable substring-match kernels
+//!
+//! The [`arrow`](crate::arrow) search matches every value of a text column,
+//! whose values are one contiguous buffer.  A [`MatchKernel`] matches such a
+//! buffer at once, so it can be offloaded to an accelerator: install one with
+//! [`set_offload`], and [`count_occurrences`](crate::arrow::count_occurrences)
+//! hands it the columns of at least `min_bytes`, falling back to the
+//! [`CpuKernel`] if it fails.  [`crossover`] times a kernel against the CPU
+//! over a range of buffer sizes, to find the `min_bytes` worth offloading.
+//!
+//! The crate ships no GPU kernel, as it depends on no GPU API; a `wgpu` or
+//! CUDA kernel implements [`MatchKernel`] outside of it.
+
+use crate::{ZnError, ZnResult};
+use memchr::memmem;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+/// Matches a needle against the values of a contiguous buffer.
+pub trait MatchKernel: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Sets `matched[i]` if the value `values[offsets[i]..offsets[i + 1]]`
+    /// contains the non-empty `needle`, leaving the other flags as they are;
+    /// `offsets` has one more element than `matched`, in ascending order
+    /// and relative to the start of `values`.
+    fn match_values(
+        &self,
+        values: &[u8],
+        offsets: &[usize],
+        needle: &[u8],
+        matched: &mut [bool],
+    ) -> ZnResult<()>;
+}
+
+/// Searches the whole buffer with one [`memmem::Finder`], skipping to the
+/// next value after each match.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpuKernel;
+
+impl MatchKernel for CpuKernel {
+    fn name(&self) -> &str {
+        "cpu"
+    }
+
+    fn match_values(
+        &self,
+        values: &[u8],
+        offsets: &[usize],
+        needle: &[u8],
+        matched: &mut [bool],
+    ) -> ZnResult<()> {
+        let finder = memmem::Finder::new(needle);
+        let end = offsets.last().copied().unwrap_or(0).min(values.len());
+        let mut pos = offsets.first().copied().unwrap_or(0);
+        while pos < end {
+            let Some(found) = finder.find(&values[pos..end]) else {
+                break;
+            };
+            let start = pos + found;
+            // The value the match starts in, the last one starting at or
+            // before it.
+            let i = offsets.partition_point(|&o| o <= start) - 1;
+            if start + needle.len() <= offsets[i + 1] {
+                matched[i] = true;
+                pos = offsets[i + 1];
+            } else {
+                // The match spans a value boundary; look further.
+                pos = start + 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Offload {
+    kernel: Arc<dyn MatchKernel>,
+    min_bytes: usize,
+}
+
+static OFFLOAD: RwLock<Option<Offload>> = RwLock::new(None);
+/// Whether a kernel is installed, so that columns are matched without taking
+/// the lock when none is.
+static OFFLOADING: AtomicBool = AtomicBool::new(false);
+
+/// Offloads the matching of buffers of at least `min_bytes` to the `kernel`,
+/// replacing the previously installed one.
+pub fn set_offload(kernel: Arc<dyn MatchKernel>, min_bytes: usize) {
+    let mut offload = OFFLOAD.write().unwrap_or_else(|e| e.into_inner());
+    *offload = Some(Offload { kernel, min_bytes });
+    OFFLOADING.store(true, Ordering::Release);
+}
+
+/// Removes the kernel installed with [`set_offload`].
+pub fn clear_offload() {
+    let mut offload = OFFLOAD.write().unwrap_or_else(|e| e.into_inner());
+    *offload = None;
+    OFFLOADING.store(false, Ordering::Release);
+}
+
+/// Returns the installed kernel if a buffer of `bytes` is worth offloading.
+pub(crate) fn offload_for(bytes: usize) -> Option<Arc<dyn MatchKernel>> {
+    if !OFFLOADING.load(Ordering::Acquire) {
+        return None;
+    }
+    let offload = OFFLOAD.read().unwrap_or_else(|e| e.into_inner());
+    offload
+        .as_ref()
+        .filter(|o| bytes >= o.min_bytes)
+        .map(|o| o.kernel.clone())
+}
+
+/// Matches with the `kernel`, or with the [`CpuKernel`] if it fails.
+pub(crate) fn match_values_or_cpu(
+    kernel: &dyn MatchKernel,
+    values: &[u8],
+    offsets: &[usize],
+    needle: &[u8],
+    matched: &mut [bool],
+) {
+    let mut flags = matched.to_vec();
+    match kernel.match_values(values, offsets, needle, &mut flags) {
+        Ok(()) => matched.copy_from_slice(&flags),
+        Err(_) => {
+            // The CPU kernel does not fail.
+            let _ = CpuKernel.match_values(values, offsets, needle, matched);
+        }
+    }
+}
+
+/// Times of one buffer size in a [`crossover`] measurement.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Crossover {
+    pub bytes: usize,
+    pub cpu: Duration,
+    pub kernel: Duration,
+}
+
+impl Crossover {
+    pub fn kernel_is_faster(&self) -> bool {
+        self.kernel < self.cpu
+    }
+}
+
+/// Times the [`CpuKernel`] and the `kernel` matching the `needle` against
+/// synthetic buffers of each of the `sizes` in bytes, made of 64-byte log
+/// lines without the needle, so every byte is searched.  Each time is the
+/// fastest of `rounds` runs.
+///
+/// # Errors
+///
+/// Returns [`ZnError::EmptyNeedle`] if the `needle` is empty, and the
+/// errors of the `kernel`.
+pub fn crossover(
+    kernel: &dyn MatchKernel,
+    sizes: &[usize],
+    needle: &[u8],
+    rounds: usize,
+) -> ZnResult<Vec<Crossover>> {
+    if needle.is_empty() {
+        return Err(ZnError::empty_needle());
+    }
+    const LINE: &[u8] = b"2023-01-01T00:00:00Z INFO GET /api/v1/items 200 12ms host=web-1\n";
+    let mut results = Vec::with_capacity(sizes.len());
+    for &bytes in sizes {
+        let values: Vec<u8> = LINE.iter().copied().cycle().take(bytes).collect();
+        let mut offsets: Vec<usize> = (0..bytes).step_by(LINE.len()).collect();
+        offsets.push(bytes);
+        let mut matched = vec![false; offsets.len() - 1];
+        let mut time = |kernel: &dyn MatchKernel| -> ZnResult<Duration> {
+            let mut best = Duration::MAX;
+            for _ in 0..rounds.max(1) {
+                let start = Instant::now();
+                kernel.match_values(&values, &offsets, needle, &mut matched)?;
+                best = best.min(start.elapsed());
+            }
+            Ok(best)
+        };
+        results.push(Crossover {
+            bytes,
+            cpu: time(&CpuKernel)?,
+            kernel: time(kernel)?,
+        });
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Failing;
+
+    impl MatchKernel for Failing {
+        fn name(&self) -> &str {
+            "failing"
+        }
+
+        fn match_values(&self, _: &[u8], _: &[usize], _: &[u8], _: &mut [bool]) -> ZnResult<()> {
+            Err(ZnError::invalid_argument("no device"))
+        }
+    }
+
+    #[test]
+    fn test_cpu_kernel() {
+        // "k8" is in the first two values, "8k8" only spans their boundary.
+        let values = b"a k8k8s pod";
+        let offsets = [0, 4, 6, 7, 11];
+        let mut matched = [false; 4];
+        CpuKernel
+            .match_values(values, &offsets, b"k8", &mut matched)
+            .unwrap();
+        assert_eq!(matched, [true, true, false, false]);
+        let mut matched = [false; 4];
+        CpuKernel
+            .match_values(values, &offsets, b"8k8", &mut matched)
+            .unwrap();
+        assert_eq!(matched, [false; 4]);
+
+        let mut matched = [false; 4];
+        match_values_or_cpu(&Failing, values, &offsets, b"pod", &mut matched);
+        assert_eq!(matched, [false, false, false, true]);
+
+        let results = crossover(&CpuKernel, &[1 << 10, 1 << 16], b"k8s", 2).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].bytes, 1 << 16);
+        assert!(crossover(&Failing, &[1 << 10], b"k8s", 1).is_err());
+    }
+}
@@ -1,8 +1,68 @@
 pub mod arrow;
+pub mod audit;
+#[cfg(feature = "datafusion")]
+pub mod bench;
+pub mod bloom;
+pub mod cache;
+pub mod cancel;
+mod codec;
+pub mod column_cache;
+pub mod compact;
+pub mod config;
+#[cfg(feature = "datafusion")]
 pub mod datafusion;
+pub mod dedup;
+#[cfg(feature = "native")]
+pub mod disk_cache;
+#[cfg(feature = "flight")]
+pub mod distributed;
+pub mod downsample;
 mod error;
+pub mod estimate;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 pub mod file;
+#[cfg(feature = "tantivy")]
+pub mod fulltext;
+pub mod index;
+#[cfg(feature = "native")]
+pub mod ingest;
+pub mod json;
+pub mod kernel;
+pub mod limits;
+pub mod manifest;
+#[cfg(feature = "datafusion")]
 pub mod match_udf;
 pub mod metadata;
+pub mod metrics;
+#[cfg(feature = "native")]
+pub mod partition;
+pub mod plugins;
+#[cfg(feature = "native")]
+pub mod pool;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "datafusion")]
+pub mod query;
+pub mod query_string;
+pub mod redact;
+#[cfg(feature = "datafusion")]
+pub mod results;
+#[cfg(feature = "datafusion")]
+pub mod rollup;
+pub mod runtime;
+pub mod schema_registry;
+#[cfg(feature = "flight")]
+pub mod server;
+pub mod storage;
+pub mod str;
+pub mod terms;
+#[cfg(test)]
+mod test_util;
+pub mod testdata;
+pub mod tune;
+#[cfg(feature = "datafusion")]
+pub mod verify;
+pub mod writer;
 
-pub use error::{ZnError, ZnResult};
+pub use error::{clear_error_hook, set_error_hook, ZnError, ZnResult};
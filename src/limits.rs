@@ -0,0 +1,223 @@
+//! Admission control for searches
+//!
+//! Searches share their storage backends, so one heavy search can starve the
+//! others or overload the backend.  A [`Limiter`] caps the number of
+//! concurrent scans and, with a token bucket, the bytes read per second.
+//! Once installed with [`set_limiter`], it is enforced by
+//! [`count_occurrences_in_files`](crate::file::count_occurrences_in_files)
+//! and by the scans of a [`CachedParquetTable`](crate::datafusion::CachedParquetTable):
+//! each scan waits for a [`ScanPermit`], then for the tokens of every file or
+//! column chunk before reading it.
+//!
+//! Waiting blocks the calling thread, which for DataFusion is the one
+//! planning the query.
+
+use crate::{ZnError, ZnResult};
+use std::{
+    sync::{Arc, Condvar, Mutex, MutexGuard, RwLock},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    /// Rate at which bytes may be read, or `None` for no limit.
+    pub bytes_per_second: Option<u64>,
+    /// Bytes that may be read at once after an idle period.
+    pub burst_bytes: u64,
+    /// Maximum number of scans running at the same time, or `None` for no
+    /// limit.
+    pub max_concurrent_scans: Option<usize>,
+    /// How long a scan waits for a permit before it is rejected, or `None` to
+    /// wait indefinitely.
+    pub queue_timeout: Option<Duration>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Negative while readers are in debt.
+    tokens: f64,
+    refilled: Instant,
+}
+
+/// Enforces [`Limits`]; see the [module docs](self).
+#[derive(Debug)]
+pub struct Limiter {
+    limits: Limits,
+    bucket: Mutex<Bucket>,
+    scans: Mutex<usize>,
+    released: Condvar,
+}
+
+impl Limiter {
+    pub fn new(limits: Limits) -> Arc<Self> {
+        Arc::new(Self {
+            bucket: Mutex::new(Bucket {
+                tokens: limits.burst_bytes as f64,
+                refilled: Instant::now(),
+            }),
+            limits,
+            scans: Mutex::new(0),
+            released: Condvar::new(),
+        })
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Waits until fewer than [`Limits::max_concurrent_scans`] scans run and
+    /// returns the permit of a new one.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::Overloaded`] if no permit became available within
+    /// the [`Limits::queue_timeout`].
+    pub fn admit(self: &Arc<Self>) -> ZnResult<ScanPermit> {
+        let Some(max) = self.limits.max_concurrent_scans else {
+            *lock(&self.scans) += 1;
+            return Ok(ScanPermit {
+                limiter: self.clone(),
+            });
+        };
+        let deadline = self.limits.queue_timeout.map(|t| Instant::now() + t);
+        let mut scans = lock(&self.scans);
+        while *scans >= max {
+            scans = match deadline {
+                None => self.released.wait(scans).unwrap_or_else(|e| e.into_inner()),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(ZnError::overloaded(format!(
+                            "{max} scan(s) running, waited {:?} for a permit",
+                            self.limits.queue_timeout.unwrap_or_default()
+                        )));
+                    }
+                    self.released
+                        .wait_timeout(scans, deadline - now)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+            };
+        }
+        *scans += 1;
+        Ok(ScanPermit {
+            limiter: self.clone(),
+        })
+    }
+
+    /// Number of scans holding a permit.
+    pub fn running_scans(&self) -> usize {
+        *lock(&self.scans)
+    }
+
+    /// Takes the tokens for reading `bytes`, first waiting until the bucket
+    /// is out of debt.  Reads larger than the burst are allowed, but make
+    /// the following reads wait longer.
+    pub fn throttle(&self, bytes: u64) {
+        let Some(rate) = self.limits.bytes_per_second.filter(|&r| r > 0) else {
+            return;
+        };
+        let rate = rate as f64;
+        let wait = {
+            let mut bucket = lock(&self.bucket);
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.refilled).as_secs_f64() * rate;
+            bucket.tokens = (bucket.tokens + refill).min(self.limits.burst_bytes as f64);
+            bucket.refilled = now;
+            let wait = (-bucket.tokens).max(0.0) / rate;
+            bucket.tokens -= bytes as f64;
+            Duration::from_secs_f64(wait)
+        };
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+/// A running scan of a [`Limiter`], ending when dropped.
+#[derive(Debug)]
+pub struct ScanPermit {
+    limiter: Arc<Limiter>,
+}
+
+impl ScanPermit {
+    /// See [`Limiter::throttle`].
+    pub fn throttle(&self, bytes: u64) {
+        self.limiter.throttle(bytes)
+    }
+}
+
+impl Drop for ScanPermit {
+    fn drop(&mut self) {
+        *lock(&self.limiter.scans) -= 1;
+        self.limiter.released.notify_one();
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // Every update is a single statement, so a poisoned lock is still
+    // consistent.
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+static LIMITER: RwLock<Option<Arc<Limiter>>> = RwLock::new(None);
+
+/// Installs a process-wide limiter enforced by the searches, replacing the
+/// previously installed one.  Scans holding permits of the previous limiter
+/// keep them.
+pub fn set_limiter(limiter: Arc<Limiter>) {
+    *LIMITER.write().unwrap_or_else(|e| e.into_inner()) = Some(limiter);
+}
+
+/// Removes the limiter installed with [`set_limiter`].
+pub fn clear_limiter() {
+    *LIMITER.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Admits a scan with the installed limiter, if any.
+pub(crate) fn admit() -> ZnResult<Option<ScanPermit>> {
+    let limiter = LIMITER.read().unwrap_or_else(|e| e.into_inner()).clone();
+    limiter.map(|l| l.admit()).transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limiter() {
+        let limiter = Limiter::new(Limits {
+            bytes_per_second: Some(1000),
+            burst_bytes: 100,
+            max_concurrent_scans: Some(1),
+            queue_timeout: Some(Duration::from_millis(10)),
+        });
+        let permit = limiter.admit().unwrap();
+        assert_eq!(limiter.running_scans(), 1);
+        assert!(matches!(limiter.admit(), Err(ZnError::Overloaded(_))));
+
+        // The burst passes at once, the 50 bytes of debt take 50 ms.
+        let start = Instant::now();
+        permit.throttle(150);
+        permit.throttle(1);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        drop(permit);
+        assert_eq!(limiter.running_scans(), 0);
+
+        // Without a timeout, a waiting scan is admitted when the running one
+        // ends.
+        let limiter = Limiter::new(Limits {
+            max_concurrent_scans: Some(1),
+            ..Limits::default()
+        });
+        let permit = limiter.admit().unwrap();
+        let waiting = {
+            let limiter = limiter.clone();
+            std::thread::spawn(move || limiter.admit().map(drop))
+        };
+        std::thread::sleep(Duration::from_millis(10));
+        drop(permit);
+        assert!(waiting.join().unwrap().is_ok());
+        assert_eq!(limiter.running_scans(), 0);
+    }
+}
@@ -0,0 +1,365 @@
+//! Versioned snapshots of a dataset
+//!
+//! A [`Manifest`] lists the parquet files of a dataset under a root
+//! directory, each with its size, row count, Arrow schema, time range, and
+//! index sidecars.  Searches, catalogs, and the compactor that take the files
+//! from the manifest rather than from a directory listing agree on the state
+//! of the dataset, and never see a file that is still being written or one
+//! that compaction has already replaced.
+//!
+//! Every version is an immutable JSON file in the `_manifest` directory of
+//! the root, named by its zero-padded version number; the highest is the
+//! current state.  [`Manifest::commit`] writes the next version to a
+//! temporary file and hard-links it into place, which fails if another
+//! writer committed that version first; the update is then retried on the
+//! new state.
+
+use crate::{
+    bloom::NgramBloom, downsample::Series, index::TrigramIndex, metadata, ZnError, ZnResult,
+};
+use arrow::datatypes::{Schema, SchemaRef};
+use parquet::file::statistics::Statistics;
+use serde_json::{json, Value};
+use std::{
+    fs::File,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Directory of the manifest versions, under the root of the dataset.
+pub const MANIFEST_DIR: &str = "_manifest";
+
+/// Version of the JSON format of the manifest files.
+const FORMAT: u64 = 1;
+
+/// Number of times [`Manifest::commit`] retries after a conflict.
+const MAX_COMMIT_ATTEMPTS: usize = 16;
+
+/// A parquet file of a [`Manifest`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileEntry {
+    /// Path of the file relative to the root of the dataset, with `/`
+    /// separators.
+    pub path: String,
+    pub size: u64,
+    pub num_rows: u64,
+    pub schema: SchemaRef,
+    /// Smallest and largest timestamp of the rows, both inclusive, if known.
+    pub time_range: Option<(i64, i64)>,
+    /// Paths of the index sidecars of the file, relative to the root.
+    pub sidecars: Vec<String>,
+}
+
+impl FileEntry {
+    /// Describes the parquet file at `path` under the `root`, taking its time
+    /// range from the statistics of the integer `timestamp_column`, and its
+    /// sidecars from the [trigram index](TrigramIndex::sidecar_path),
+    /// [Bloom filter](NgramBloom::sidecar_path), and
+    /// [series](Series::sidecar_path) files next to it.
+    pub fn describe(
+        root: impl AsRef<Path>,
+        path: &str,
+        timestamp_column: Option<&str>,
+    ) -> ZnResult<Self> {
+        let root = root.as_ref();
+        let file = File::open(root.join(path))?;
+        let metadata = metadata::read_metadata(&file)?;
+        let file_metadata = metadata.file_metadata();
+        let schema = metadata::arrow_schema(&file)?;
+
+        let time_range = timestamp_column.and_then(|name| {
+            let column = file_metadata
+                .schema_descr()
+                .columns()
+                .iter()
+                .position(|c| c.path().string() == name)?;
+            let mut range: Option<(i64, i64)> = None;
+            for row_group in metadata.row_groups() {
+                let (min, max) = match row_group.column(column).statistics()? {
+                    Statistics::Int64(s) if s.has_min_max_set() => (*s.min(), *s.max()),
+                    _ => return None,
+                };
+                range = Some(range.map_or((min, max), |(lo, hi)| (lo.min(min), hi.max(max))));
+            }
+            range
+        });
+
+        let sidecars = [
+            TrigramIndex::sidecar_path(path),
+            NgramBloom::sidecar_path(path),
+            Series::sidecar_path(path),
+        ]
+        .into_iter()
+        .filter(|sidecar| root.join(sidecar).is_file())
+        .filter_map(|sidecar| sidecar.to_str().map(str::to_owned))
+        .collect();
+
+        Ok(Self {
+            path: path.to_owned(),
+            size: file.metadata()?.len(),
+            num_rows: file_metadata.num_rows().max(0) as u64,
+            schema: Arc::new(schema),
+            time_range,
+            sidecars,
+        })
+    }
+
+    /// Returns `true` if some rows of the file may fall into the time
+    /// `range`, which files of unknown time range always may.
+    pub fn overlaps(&self, range: &Range<i64>) -> bool {
+        self.time_range
+            .is_none_or(|(min, max)| min < range.end && max >= range.start)
+    }
+
+    fn to_json(&self) -> ZnResult<Value> {
+        Ok(json!({
+            "path": self.path,
+            "size": self.size,
+            "num_rows": self.num_rows,
+            "schema": serde_json::to_value(&*self.schema).map_err(invalid_manifest)?,
+            "time_range": self.time_range.map(|(min, max)| [min, max]),
+            "sidecars": self.sidecars,
+        }))
+    }
+
+    fn from_json(value: &Value) -> ZnResult<Self> {
+        let field = |name: &str| {
+            value
+                .get(name)
+                .ok_or_else(|| ZnError::invalid_metadata(format!("manifest entry lacks {name:?}")))
+        };
+        let u64_field = |name: &str| {
+            field(name)?.as_u64().ok_or_else(|| {
+                ZnError::invalid_metadata(format!("manifest entry {name:?} is not a number"))
+            })
+        };
+        let schema: Schema =
+            serde_json::from_value(field("schema")?.clone()).map_err(invalid_manifest)?;
+        let time_range = match field("time_range")? {
+            Value::Null => None,
+            range => {
+                let [min, max]: [i64; 2] =
+                    serde_json::from_value(range.clone()).map_err(invalid_manifest)?;
+                Some((min, max))
+            }
+        };
+        Ok(Self {
+            path: serde_json::from_value(field("path")?.clone()).map_err(invalid_manifest)?,
+            size: u64_field("size")?,
+            num_rows: u64_field("num_rows")?,
+            schema: Arc::new(schema),
+            time_range,
+            sidecars: serde_json::from_value(field("sidecars")?.clone())
+                .map_err(invalid_manifest)?,
+        })
+    }
+}
+
+/// A version of the files of a dataset; see the [module docs](self).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Manifest {
+    /// 0 for the empty dataset before the first commit.
+    pub version: u64,
+    pub files: Vec<FileEntry>,
+}
+
+impl Manifest {
+    /// Loads the current version of the manifest of the dataset at `root`,
+    /// or the empty version 0 if there is none yet.
+    pub fn load(root: impl AsRef<Path>) -> ZnResult<Self> {
+        let root = root.as_ref();
+        match Self::versions(root)?.last() {
+            Some(&version) => Self::load_version(root, version),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Loads the given `version` of the manifest of the dataset at `root`.
+    pub fn load_version(root: impl AsRef<Path>, version: u64) -> ZnResult<Self> {
+        let data = std::fs::read(version_path(root.as_ref(), version))?;
+        let value: Value = serde_json::from_slice(&data).map_err(invalid_manifest)?;
+        if value.get("format").and_then(Value::as_u64) != Some(FORMAT) {
+            return Err(ZnError::invalid_metadata(format!(
+                "manifest version {version} is not of format {FORMAT}"
+            )));
+        }
+        let files = value
+            .get("files")
+            .and_then(Value::as_array)
+            .ok_or_else(|| ZnError::invalid_metadata("manifest lacks \"files\""))?
+            .iter()
+            .map(FileEntry::from_json)
+            .collect::<ZnResult<_>>()?;
+        Ok(Self { version, files })
+    }
+
+    /// Returns the committed versions of the manifest of the dataset at
+    /// `root`, in ascending order.
+    pub fn versions(root: impl AsRef<Path>) -> ZnResult<Vec<u64>> {
+        let entries = match std::fs::read_dir(root.as_ref().join(MANIFEST_DIR)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut versions = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name();
+            if let Some(version) = name
+                .to_str()
+                .and_then(|n| n.strip_suffix(".json"))
+                .and_then(|n| n.parse().ok())
+            {
+                versions.push(version);
+            }
+        }
+        versions.sort_unstable();
+        Ok(versions)
+    }
+
+    /// Returns the files that may hold rows of the time `range`.
+    pub fn files(&self, range: Range<i64>) -> impl Iterator<Item = &FileEntry> {
+        self.files.iter().filter(move |f| f.overlaps(&range))
+    }
+
+    /// Returns the paths under `root` of the files that may hold rows of the
+    /// time `range`, e.g. for
+    /// [`count_occurrences_in_files`](crate::file::count_occurrences_in_files).
+    pub fn paths(&self, root: impl AsRef<Path>, range: Range<i64>) -> Vec<PathBuf> {
+        let root = root.as_ref();
+        self.files(range).map(|f| root.join(&f.path)).collect()
+    }
+
+    /// Applies `update` to the files of the current version of the manifest
+    /// of the dataset at `root` and commits the result as the next version,
+    /// which it returns.  If another writer commits first, `update` runs
+    /// again on its version.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of `update`, and [`ZnError::InvalidMetadata`] if
+    /// the commit kept conflicting with other writers.  An I/O error syncing
+    /// the manifest directory leaves the version committed but maybe not
+    /// durable.
+    pub fn commit(
+        root: impl AsRef<Path>,
+        mut update: impl FnMut(&mut Vec<FileEntry>) -> ZnResult<()>,
+    ) -> ZnResult<Self> {
+        let root = root.as_ref();
+        let dir = root.join(MANIFEST_DIR);
+        std::fs::create_dir_all(&dir)?;
+        for _ in 0..MAX_COMMIT_ATTEMPTS {
+            let mut manifest = Self::load(root)?;
+            update(&mut manifest.files)?;
+            manifest.version += 1;
+
+            let files = manifest
+                .files
+                .iter()
+                .map(FileEntry::to_json)
+                .collect::<ZnResult<Vec<_>>>()?;
+            let value = json!({"format": FORMAT, "version": manifest.version, "files": files});
+            let temp = dir.join(format!(".{}.{}.tmp", manifest.version, std::process::id()));
+            std::fs::write(&temp, serde_json::to_vec(&value).map_err(invalid_manifest)?)?;
+            File::open(&temp)?.sync_all()?;
+            let linked = std::fs::hard_link(&temp, version_path(root, manifest.version));
+            // A temporary file left behind does not undo the commit, so errors
+            // removing it are ignored.
+            let _ = std::fs::remove_file(&temp);
+            match linked {
+                Ok(()) => {
+                    sync_dir(&dir)?;
+                    return Ok(manifest);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(ZnError::invalid_metadata(format!(
+            "manifest commit conflicted {MAX_COMMIT_ATTEMPTS} times"
+        )))
+    }
+
+    /// Commits the replacement of the `removed` files by the `added` ones,
+    /// as compaction does.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::InvalidArgument`] if some of the `removed` files
+    /// are not in the current version, e.g. because another compaction
+    /// replaced them first.
+    pub fn replace(
+        root: impl AsRef<Path>,
+        removed: &[&str],
+        added: Vec<FileEntry>,
+    ) -> ZnResult<Self> {
+        Self::commit(root, |files| {
+            for path in removed {
+                let i = files.iter().position(|f| f.path == *path).ok_or_else(|| {
+                    ZnError::invalid_argument(format!("{path:?} is not in the manifest"))
+                })?;
+                files.remove(i);
+            }
+            files.extend(added.iter().cloned());
+            Ok(())
+        })
+    }
+}
+
+fn version_path(root: &Path, version: u64) -> PathBuf {
+    root.join(MANIFEST_DIR).join(format!("{version:020}.json"))
+}
+
+fn invalid_manifest(e: serde_json::Error) -> ZnError {
+    ZnError::invalid_metadata(format!("invalid manifest: {e}"))
+}
+
+/// Persists the entries of `dir`, e.g. a new version linked into it.
+fn sync_dir(dir: &Path) -> ZnResult<()> {
+    // Directories cannot be opened as files on Windows, whose file system
+    // persists directory entries on its own.
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::parquet_bytes;
+
+    #[test]
+    fn test_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::write(root.join("a.parquet"), parquet_bytes(&["x", "y", "z"], 2)).unwrap();
+        std::fs::write(root.join("b.parquet"), parquet_bytes(&["k8s"], 1)).unwrap();
+        std::fs::write(root.join("b.parquet.bloom"), b"").unwrap();
+        assert_eq!(Manifest::load(root).unwrap(), Manifest::default());
+
+        let a = FileEntry::describe(root, "a.parquet", Some("id")).unwrap();
+        assert_eq!((a.num_rows, a.time_range), (3, Some((0, 2))));
+        assert!(a.sidecars.is_empty());
+        let b = FileEntry::describe(root, "b.parquet", None).unwrap();
+        assert_eq!(b.time_range, None);
+        assert_eq!(b.sidecars, ["b.parquet.bloom"]);
+
+        let manifest = Manifest::commit(root, |files| {
+            files.push(a.clone());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(manifest.version, 1);
+        let manifest = Manifest::replace(root, &["a.parquet"], vec![b.clone()]).unwrap();
+        assert_eq!(manifest.version, 2);
+        assert!(Manifest::replace(root, &["a.parquet"], Vec::new()).is_err());
+
+        assert!(a.overlaps(&(2..3)) && !a.overlaps(&(3..10)));
+        assert_eq!(Manifest::versions(root).unwrap(), [1, 2]);
+        assert_eq!(Manifest::load(root).unwrap(), manifest);
+        assert_eq!(Manifest::load_version(root, 1).unwrap().files, [a]);
+        assert_eq!(manifest.paths(root, 0..10), [root.join("b.parquet")]);
+    }
+}
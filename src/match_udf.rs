@@ -1,51 +1,1673 @@
+use crate::{
+    arrow::kernels,
+    json::JsonPath,
+    query_string::QueryString,
+    str::{
+        is_token_separator, Fuzzy, Glob, IgnoreAsciiCase, LogfmtKey, Matcher, Near, Phrase, Term,
+    },
+};
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder, MatchKind};
+use arrow_array::downcast_dictionary_array;
 use datafusion::{
     arrow::{
-        array::{ArrayRef, BooleanArray, StringArray},
-        datatypes::DataType,
+        array::{
+            Array, ArrayRef, BooleanArray, DictionaryArray, Float64Array, GenericBinaryArray,
+            Int32Array, Int64Array, Int64Builder, LargeStringArray, ListBuilder, OffsetSizeTrait,
+            StringArray, StructBuilder,
+        },
+        compute::cast,
+        datatypes::{DataType, Field, Int32Type},
     },
+    common::{Column, DFSchema},
     error::DataFusionError,
-    logical_expr::{ScalarFunctionImplementation, ScalarUDF, Volatility},
+    logical_expr::{
+        Accumulator, AccumulatorFunctionImplementation, AggregateUDF, ColumnarValue,
+        ReturnTypeFunction, ScalarFunctionImplementation, ScalarUDF, Signature, StateTypeFunction,
+        TypeSignature, Volatility,
+    },
     physical_plan::functions::make_scalar_function,
-    prelude::create_udf,
+    prelude::{create_udf, lit, Expr, SessionContext},
+    scalar::ScalarValue,
     sql::sqlparser::parser::ParserError,
 };
 use once_cell::sync::Lazy;
-use std::sync::Arc;
+use regex::Regex;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
 
 /// The name of the match UDF given to DataFusion.
 pub const MATCH_UDF_NAME: &str = "str_match";
 
-/// The name of the match_no_case UDF given to DataFusion.
-pub const MATCH_UDF_NO_CASE_NAME: &str = "str_match_no_case";
+/// The name of the match_no_case UDF given to DataFusion.
+pub const MATCH_UDF_NO_CASE_NAME: &str = "str_match_no_case";
+
+/// The name of the match_ignore_case UDF given to DataFusion.
+pub const MATCH_IGNORE_CASE_UDF_NAME: &str = "str_match_ignore_case";
+
+/// The name of the re_match UDF given to DataFusion.
+pub const RE_MATCH_UDF_NAME: &str = "re_match";
+
+/// The name of the regexp_extract_group UDF given to DataFusion.
+pub const REGEXP_EXTRACT_GROUP_UDF_NAME: &str = "regexp_extract_group";
+
+/// The name of the match_any UDF given to DataFusion.
+pub const MATCH_ANY_UDF_NAME: &str = "str_match_any";
+
+/// The name of the not_match UDF given to DataFusion.
+pub const NOT_MATCH_UDF_NAME: &str = "str_not_match";
+
+/// The name of the fuzzy_match UDF given to DataFusion.
+pub const FUZZY_MATCH_UDF_NAME: &str = "fuzzy_match";
+
+/// The name of the starts_with_match UDF given to DataFusion.
+pub const STARTS_WITH_MATCH_UDF_NAME: &str = "starts_with_match";
+
+/// The name of the ends_with_match UDF given to DataFusion.
+pub const ENDS_WITH_MATCH_UDF_NAME: &str = "ends_with_match";
+
+/// The name of the match_all_columns UDF given to DataFusion.
+pub const MATCH_ALL_COLUMNS_UDF_NAME: &str = "match_all_columns";
+
+/// The name of the find UDF given to DataFusion.
+pub const FIND_UDF_NAME: &str = "str_find";
+
+/// The name of the match_spans UDF given to DataFusion.
+pub const MATCH_SPANS_UDF_NAME: &str = "str_match_spans";
+
+/// The name of the token_match UDF given to DataFusion.
+pub const TOKEN_MATCH_UDF_NAME: &str = "token_match";
+
+/// The name of the ip_in_cidr UDF given to DataFusion.
+pub const IP_IN_CIDR_UDF_NAME: &str = "ip_in_cidr";
+
+/// The name of the kv_extract UDF given to DataFusion.
+pub const KV_EXTRACT_UDF_NAME: &str = "kv_extract";
+
+/// The name of the json_get UDF given to DataFusion.
+pub const JSON_GET_UDF_NAME: &str = "json_get";
+
+/// The name of the phrase_match UDF given to DataFusion.
+pub const PHRASE_MATCH_UDF_NAME: &str = "phrase_match";
+
+/// The name of the near_match UDF given to DataFusion.
+pub const NEAR_MATCH_UDF_NAME: &str = "near_match";
+
+/// The name of the glob_match UDF given to DataFusion.
+pub const GLOB_MATCH_UDF_NAME: &str = "glob_match";
+
+/// The name of the match_query UDF given to DataFusion.
+pub const MATCH_QUERY_UDF_NAME: &str = "match_query";
+
+/// The name of the log_level UDF given to DataFusion.
+pub const LOG_LEVEL_UDF_NAME: &str = "log_level";
+
+/// The name of the parse_duration UDF given to DataFusion.
+pub const PARSE_DURATION_UDF_NAME: &str = "parse_duration";
+
+/// The name of the count_matches UDAF given to DataFusion.
+pub const COUNT_MATCHES_UDAF_NAME: &str = "count_matches";
+
+/// The name of the count_occurrences UDAF given to DataFusion.
+pub const COUNT_OCCURRENCES_UDAF_NAME: &str = "count_occurrences";
+
+/// Implementation of match_range, returning null for a null value or
+/// needle.
+pub static MATCH_UDF: Lazy<ScalarUDF> = Lazy::new(|| match_udf(false, Nulls::Propagate));
+
+/// Implementation of match_no_case, returning null for a null value or
+/// needle.
+pub static MATCH_NO_CASE_UDF: Lazy<ScalarUDF> = Lazy::new(|| match_udf(true, Nulls::Propagate));
+
+/// Returns the `str_match(col, needle)` UDF, or `str_match_no_case` if
+/// `case_insensitive`, handling nulls as `nulls` says.  With
+/// [`Nulls::AsNoMatch`] the result is never null, as filters pushed down
+/// like `LIKE` expect, so `NOT` or `OR` of it never turn a row null either.
+pub fn match_udf(case_insensitive: bool, nulls: Nulls) -> ScalarUDF {
+    let name = match case_insensitive {
+        true => MATCH_UDF_NO_CASE_NAME,
+        false => MATCH_UDF_NAME,
+    };
+    let return_type: ReturnTypeFunction = Arc::new(|_| Ok(Arc::new(DataType::Boolean)));
+    ScalarUDF::new(
+        name,
+        &match_signature(),
+        &return_type,
+        &match_expr_impl_with_nulls(case_insensitive, nulls),
+    )
+}
+
+/// Expects a string, large string, raw bytes or dictionary of strings, which
+/// are not cast, and a string; arrow 31 has no string view type to accept.
+fn match_signature() -> Signature {
+    let keys = [
+        DataType::Int8,
+        DataType::Int16,
+        DataType::Int32,
+        DataType::Int64,
+        DataType::UInt8,
+        DataType::UInt16,
+        DataType::UInt32,
+        DataType::UInt64,
+    ];
+    let haystacks = [
+        DataType::Utf8,
+        DataType::LargeUtf8,
+        DataType::Binary,
+        DataType::LargeBinary,
+    ]
+    .into_iter()
+    .chain(
+        keys.into_iter()
+            .map(|key| DataType::Dictionary(Box::new(key), Box::new(DataType::Utf8))),
+    );
+    Signature::one_of(
+        haystacks
+            .map(|haystack| TypeSignature::Exact(vec![haystack, DataType::Utf8]))
+            .collect(),
+        Volatility::Stable,
+    )
+}
+
+/// Implementation of match_ignore_case, folding the case of ASCII letters.
+pub static MATCH_IGNORE_CASE_UDF: Lazy<ScalarUDF> =
+    Lazy::new(|| match_ignore_case_udf(CaseFolding::Ascii));
+
+/// How [`match_ignore_case_udf`] folds case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseFolding {
+    /// Only ASCII letters match regardless of case, comparing the bytes of
+    /// the values in place.
+    Ascii,
+    /// All letters match regardless of case.  ASCII values are compared in
+    /// place; others are lowercased first, like [`MATCH_NO_CASE_UDF`] does.
+    Unicode,
+}
+
+/// Returns a `str_match_ignore_case` UDF, which matches like `str_match`
+/// but ignoring case as the `folding` does.  Unlike `lower(col) like
+/// '%needle%'`, it neither copies nor lowercases the values it compares in
+/// place.
+pub fn match_ignore_case_udf(folding: CaseFolding) -> ScalarUDF {
+    create_udf(
+        MATCH_IGNORE_CASE_UDF_NAME,
+        // expects two string
+        vec![DataType::Utf8, DataType::Utf8],
+        // returns boolean
+        Arc::new(DataType::Boolean),
+        Volatility::Stable,
+        match_ignore_case_impl(folding),
+    )
+}
+
+/// match_ignore_case function for datafusion
+pub fn match_ignore_case_impl(folding: CaseFolding) -> ScalarFunctionImplementation {
+    let func = move |args: &[ArrayRef]| -> datafusion::error::Result<ArrayRef> {
+        if args.len() != 2 {
+            return Err(DataFusionError::SQL(ParserError::ParserError(
+                "match UDF expects two string".to_string(),
+            )));
+        }
+        let haystack = as_string_arg(&args[0])?;
+        let needle = as_string_arg(&args[1])?;
+
+        // The needle is usually the same for all rows, so its matcher is
+        // only rebuilt when it changes.
+        let mut matcher: Option<IgnoreAsciiCase> = None;
+        let array = haystack
+            .iter()
+            .zip(needle.iter())
+            .map(|(haystack, needle)| {
+                let (haystack, needle) = (haystack?, needle?);
+                if folding == CaseFolding::Unicode && !(haystack.is_ascii() && needle.is_ascii()) {
+                    return Some(
+                        memchr::memmem::find(
+                            haystack.to_lowercase().as_bytes(),
+                            needle.to_lowercase().as_bytes(),
+                        )
+                        .is_some(),
+                    );
+                }
+                let matcher = match &mut matcher {
+                    Some(m) if m.needle().eq_ignore_ascii_case(needle.as_bytes()) => m,
+                    m => m.insert(IgnoreAsciiCase::new(needle.as_bytes())),
+                };
+                Some(matcher.find(haystack.as_bytes()).is_some())
+            })
+            .collect::<BooleanArray>();
+        Ok(Arc::new(array) as ArrayRef)
+    };
+
+    make_scalar_function(func)
+}
+
+/// Implementation of re_match, sharing its compiled patterns process-wide.
+pub static RE_MATCH_UDF: Lazy<ScalarUDF> = Lazy::new(re_match_udf);
+
+/// Number of compiled patterns a re_match or regexp_extract_group UDF keeps;
+/// the cache is emptied when it is full.
+const MAX_CACHED_PATTERNS: usize = 64;
+
+/// Returns a `re_match(col, pattern)` UDF, which returns whether the value
+/// contains a match of the [`regex`] `pattern`.
+///
+/// Each UDF caches the patterns it has compiled, so a pattern is compiled
+/// once rather than for every batch; registering a new UDF, e.g. per query,
+/// scopes the cache to it.
+pub fn re_match_udf() -> ScalarUDF {
+    create_udf(
+        RE_MATCH_UDF_NAME,
+        // expects two string
+        vec![DataType::Utf8, DataType::Utf8],
+        // returns boolean
+        Arc::new(DataType::Boolean),
+        Volatility::Stable,
+        re_match_impl(),
+    )
+}
+
+/// Returns a function compiling patterns for the UDF called `name`, with
+/// the last [`MAX_CACHED_PATTERNS`] compiled ones cached.
+fn regex_cache(
+    name: &'static str,
+) -> impl Fn(&str) -> datafusion::error::Result<Arc<Regex>> + Send + Sync {
+    let cache: Mutex<HashMap<String, Arc<Regex>>> = Mutex::new(HashMap::new());
+    move |pattern: &str| {
+        let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(regex) = cache.get(pattern) {
+            return Ok(regex.clone());
+        }
+        let regex = Arc::new(Regex::new(pattern).map_err(|e| {
+            DataFusionError::Execution(format!("invalid {name} pattern {pattern:?}: {e}"))
+        })?);
+        if cache.len() >= MAX_CACHED_PATTERNS {
+            cache.clear();
+        }
+        cache.insert(pattern.to_owned(), regex.clone());
+        Ok(regex)
+    }
+}
+
+/// re_match function for datafusion
+pub fn re_match_impl() -> ScalarFunctionImplementation {
+    let compile = regex_cache(RE_MATCH_UDF_NAME);
+    let func = move |args: &[ArrayRef]| -> datafusion::error::Result<ArrayRef> {
+        if args.len() != 2 {
+            return Err(DataFusionError::SQL(ParserError::ParserError(
+                "re_match UDF expects two string".to_string(),
+            )));
+        }
+        let haystack = as_string_arg(&args[0])?;
+        let pattern = as_string_arg(&args[1])?;
+
+        let mut regex: Option<(&str, Arc<Regex>)> = None;
+        let array = haystack
+            .iter()
+            .zip(pattern.iter())
+            .map(|(haystack, pattern)| {
+                let (Some(haystack), Some(pattern)) = (haystack, pattern) else {
+                    return Ok(None);
+                };
+                let regex = match &regex {
+                    Some((last, regex)) if *last == pattern => regex,
+                    _ => &regex.insert((pattern, compile(pattern)?)).1,
+                };
+                Ok(Some(regex.is_match(haystack)))
+            })
+            .collect::<datafusion::error::Result<BooleanArray>>()?;
+        Ok(Arc::new(array) as ArrayRef)
+    };
+
+    make_scalar_function(func)
+}
+
+/// Implementation of regexp_extract_group, sharing its compiled patterns
+/// process-wide.
+pub static REGEXP_EXTRACT_GROUP_UDF: Lazy<ScalarUDF> = Lazy::new(regexp_extract_group_udf);
+
+/// Returns a `regexp_extract_group(col, pattern, group_idx)` UDF, which
+/// returns the text of capture group `group_idx` of the first match of the
+/// [`regex`] `pattern` in the value, the whole match for 0, or null if there
+/// is no match or the group did not take part in it, so fields can be
+/// derived from log lines in the query, e.g.
+/// `regexp_extract_group(log, 'status=(\d+)', 1)`.
+///
+/// Patterns are cached per UDF like [`re_match_udf`]'s.
+pub fn regexp_extract_group_udf() -> ScalarUDF {
+    create_udf(
+        REGEXP_EXTRACT_GROUP_UDF_NAME,
+        // expects two string and the group index
+        vec![DataType::Utf8, DataType::Utf8, DataType::Int64],
+        // returns the group
+        Arc::new(DataType::Utf8),
+        Volatility::Stable,
+        regexp_extract_group_impl(),
+    )
+}
+
+/// regexp_extract_group function for datafusion
+pub fn regexp_extract_group_impl() -> ScalarFunctionImplementation {
+    let compile = regex_cache(REGEXP_EXTRACT_GROUP_UDF_NAME);
+    let func = move |args: &[ArrayRef]| -> datafusion::error::Result<ArrayRef> {
+        if args.len() != 3 {
+            return Err(DataFusionError::SQL(ParserError::ParserError(
+                "regexp_extract_group UDF expects two string and a group index".to_string(),
+            )));
+        }
+        let haystack = as_string_arg(&args[0])?;
+        let pattern = as_string_arg(&args[1])?;
+        let group = args[2]
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .ok_or_else(|| {
+                DataFusionError::Execution(format!(
+                    "regexp_extract_group UDF expects an Int64 group index, got {}",
+                    args[2].data_type()
+                ))
+            })?;
+
+        let mut regex: Option<(&str, Arc<Regex>)> = None;
+        let array = haystack
+            .iter()
+            .zip(pattern.iter())
+            .zip(group.iter())
+            .map(|((haystack, pattern), group)| {
+                let (Some(haystack), Some(pattern), Some(group)) = (haystack, pattern, group)
+                else {
+                    return Ok(None);
+                };
+                let regex = match &regex {
+                    Some((last, regex)) if *last == pattern => regex,
+                    _ => &regex.insert((pattern, compile(pattern)?)).1,
+                };
+                let index = usize::try_from(group)
+                    .ok()
+                    .filter(|&index| index < regex.captures_len())
+                    .ok_or_else(|| {
+                        DataFusionError::Execution(format!(
+                            "regexp_extract_group pattern {pattern:?} has no group {group}"
+                        ))
+                    })?;
+                let found = match index {
+                    0 => regex.find(haystack),
+                    _ => regex
+                        .captures(haystack)
+                        .and_then(|groups| groups.get(index)),
+                };
+                Ok(found.map(|found| found.as_str()))
+            })
+            .collect::<datafusion::error::Result<StringArray>>()?;
+        Ok(Arc::new(array) as ArrayRef)
+    };
+
+    make_scalar_function(func)
+}
+
+/// Implementation of match_any: `str_match_any(col, needle, ...)` returns
+/// whether the value contains any of the needles, null ones aside.
+///
+/// The needles are searched for at once with an Aho-Corasick automaton,
+/// built once for as long as the needles stay the same, so the values are
+/// scanned once however many needles there are, unlike with `str_match`
+/// calls joined with `OR`.
+pub static MATCH_ANY_UDF: Lazy<ScalarUDF> = Lazy::new(|| {
+    let return_type: ReturnTypeFunction = Arc::new(|_| Ok(Arc::new(DataType::Boolean)));
+    ScalarUDF::new(
+        MATCH_ANY_UDF_NAME,
+        // expects strings: the value, then the needles
+        &Signature::variadic(vec![DataType::Utf8], Volatility::Stable),
+        &return_type,
+        &match_any_impl(),
+    )
+});
+
+/// match_any function for datafusion
+pub fn match_any_impl() -> ScalarFunctionImplementation {
+    let last: Mutex<Option<(Vec<String>, Arc<AhoCorasick>)>> = Mutex::new(None);
+    let func = move |args: &[ArrayRef]| -> datafusion::error::Result<ArrayRef> {
+        if args.len() < 2 {
+            return Err(DataFusionError::SQL(ParserError::ParserError(
+                "match_any UDF expects a string and at least one needle".to_string(),
+            )));
+        }
+        let haystack = as_string_arg(&args[0])?;
+        let needles = args[1..]
+            .iter()
+            .map(as_string_arg)
+            .collect::<datafusion::error::Result<Vec<_>>>()?;
+
+        // The lock is only held to take and put back the automaton, so that
+        // batches of other partitions are scanned meanwhile.
+        let mut current = last.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let array = haystack
+            .iter()
+            .enumerate()
+            .map(|(row, haystack)| {
+                let haystack = haystack?;
+                let row_needles = || {
+                    needles
+                        .iter()
+                        .filter(|needles| needles.is_valid(row))
+                        .map(move |needles| needles.value(row))
+                };
+                let automaton = match &current {
+                    Some((key, automaton)) if row_needles().eq(key.iter().map(String::as_str)) => {
+                        automaton
+                    }
+                    _ => {
+                        let key: Vec<_> = row_needles().map(str::to_owned).collect();
+                        let automaton = Arc::new(AhoCorasick::new(&key));
+                        &current.insert((key, automaton)).1
+                    }
+                };
+                Some(automaton.is_match(haystack))
+            })
+            .collect::<BooleanArray>();
+        if current.is_some() {
+            *last.lock().unwrap_or_else(|e| e.into_inner()) = current;
+        }
+        Ok(Arc::new(array) as ArrayRef)
+    };
+
+    make_scalar_function(func)
+}
+
+/// Implementation of not_match, returning null for a null value or needle.
+pub static NOT_MATCH_UDF: Lazy<ScalarUDF> = Lazy::new(|| not_match_udf(Nulls::Propagate));
+
+/// What a match UDF returns for a null value or needle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Nulls {
+    /// Null, like SQL comparisons do, so that `NOT` of the result is null
+    /// too.
+    Propagate,
+    /// What it returns for a value not containing the needle.
+    AsNoMatch,
+}
+
+/// Returns a `str_not_match(col, needle)` UDF, which returns whether the
+/// value does not contain the needle, in a single pass instead of `NOT
+/// str_match(col, needle)`, and handles nulls as `nulls` says.
+pub fn not_match_udf(nulls: Nulls) -> ScalarUDF {
+    create_udf(
+        NOT_MATCH_UDF_NAME,
+        // expects two string
+        vec![DataType::Utf8, DataType::Utf8],
+        // returns boolean
+        Arc::new(DataType::Boolean),
+        Volatility::Stable,
+        not_match_impl(nulls),
+    )
+}
+
+/// not_match function for datafusion
+pub fn not_match_impl(nulls: Nulls) -> ScalarFunctionImplementation {
+    let func = move |args: &[ArrayRef]| -> datafusion::error::Result<ArrayRef> {
+        if args.len() != 2 {
+            return Err(DataFusionError::SQL(ParserError::ParserError(
+                "not_match UDF expects two string".to_string(),
+            )));
+        }
+        let haystack = as_string_arg(&args[0])?;
+        let needle = as_string_arg(&args[1])?;
+
+        let mut finder: Option<memchr::memmem::Finder> = None;
+        let array = haystack
+            .iter()
+            .zip(needle.iter())
+            .map(|(haystack, needle)| match (haystack, needle) {
+                (Some(haystack), Some(needle)) => {
+                    let finder = match &finder {
+                        Some(f) if f.needle() == needle.as_bytes() => f,
+                        _ => finder.insert(memchr::memmem::Finder::new(needle)),
+                    };
+                    Some(finder.find(haystack.as_bytes()).is_none())
+                }
+                _ => match nulls {
+                    Nulls::Propagate => None,
+                    Nulls::AsNoMatch => Some(true),
+                },
+            })
+            .collect::<BooleanArray>();
+        Ok(Arc::new(array) as ArrayRef)
+    };
+
+    make_scalar_function(func)
+}
+
+/// Implementation of fuzzy_match: `fuzzy_match(col, needle, max_dist)`
+/// returns whether the value contains a substring at most `max_dist` byte
+/// insertions, deletions, or substitutions away from the needle, so that
+/// searches tolerate typos, e.g. in service and host names.
+///
+/// Needles of up to 64 bytes are searched for with a bit-parallel algorithm
+/// in a single pass over the value; longer ones cost a pass per needle byte.
+pub static FUZZY_MATCH_UDF: Lazy<ScalarUDF> = Lazy::new(|| {
+    create_udf(
+        FUZZY_MATCH_UDF_NAME,
+        // expects two string and the maximum distance
+        vec![DataType::Utf8, DataType::Utf8, DataType::Int64],
+        // returns boolean
+        Arc::new(DataType::Boolean),
+        Volatility::Stable,
+        fuzzy_match_impl(),
+    )
+});
+
+/// fuzzy_match function for datafusion
+pub fn fuzzy_match_impl() -> ScalarFunctionImplementation {
+    let func = move |args: &[ArrayRef]| -> datafusion::error::Result<ArrayRef> {
+        if args.len() != 3 {
+            return Err(DataFusionError::SQL(ParserError::ParserError(
+                "fuzzy_match UDF expects two string and a distance".to_string(),
+            )));
+        }
+        let haystack = as_string_arg(&args[0])?;
+        let needle = as_string_arg(&args[1])?;
+        let max_dist = args[2]
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .ok_or_else(|| {
+                DataFusionError::Execution(format!(
+                    "fuzzy_match UDF expects an Int64 distance, got {}",
+                    args[2].data_type()
+                ))
+            })?;
+
+        let mut matcher: Option<(&str, i64, Fuzzy)> = None;
+        let array = haystack
+            .iter()
+            .zip(needle.iter())
+            .zip(max_dist.iter())
+            .map(|((haystack, needle), max_dist)| {
+                let (Some(haystack), Some(needle), Some(max_dist)) = (haystack, needle, max_dist)
+                else {
+                    return Ok(None);
+                };
+                let matcher = match &matcher {
+                    Some((n, d, matcher)) if *n == needle && *d == max_dist => matcher,
+                    _ => {
+                        let dist = usize::try_from(max_dist).map_err(|_| {
+                            DataFusionError::Execution(format!(
+                                "fuzzy_match distance must not be negative, got {max_dist}"
+                            ))
+                        })?;
+                        let fuzzy = Fuzzy::new(needle.as_bytes(), dist);
+                        &matcher.insert((needle, max_dist, fuzzy)).2
+                    }
+                };
+                Ok(Some(matcher.is_match(haystack.as_bytes())))
+            })
+            .collect::<datafusion::error::Result<BooleanArray>>()?;
+        Ok(Arc::new(array) as ArrayRef)
+    };
+
+    make_scalar_function(func)
+}
+
+/// Implementation of starts_with_match, comparing only the leading bytes of
+/// the values, unlike `LIKE 'needle%'` after DataFusion rewrites it.
+pub static STARTS_WITH_MATCH_UDF: Lazy<ScalarUDF> = Lazy::new(|| {
+    create_udf(
+        STARTS_WITH_MATCH_UDF_NAME,
+        // expects two string
+        vec![DataType::Utf8, DataType::Utf8],
+        // returns boolean
+        Arc::new(DataType::Boolean),
+        Volatility::Stable,
+        affix_match_impl(false),
+    )
+});
+
+/// Implementation of ends_with_match, comparing only the trailing bytes of
+/// the values.
+pub static ENDS_WITH_MATCH_UDF: Lazy<ScalarUDF> = Lazy::new(|| {
+    create_udf(
+        ENDS_WITH_MATCH_UDF_NAME,
+        // expects two string
+        vec![DataType::Utf8, DataType::Utf8],
+        // returns boolean
+        Arc::new(DataType::Boolean),
+        Volatility::Stable,
+        affix_match_impl(true),
+    )
+});
+
+/// starts_with_match, or ends_with_match if `suffix`, function for
+/// datafusion
+pub fn affix_match_impl(suffix: bool) -> ScalarFunctionImplementation {
+    let func = move |args: &[ArrayRef]| -> datafusion::error::Result<ArrayRef> {
+        if args.len() != 2 {
+            return Err(DataFusionError::SQL(ParserError::ParserError(
+                "affix match UDF expects two string".to_string(),
+            )));
+        }
+        let haystack = as_string_arg(&args[0])?;
+        let needle = as_string_arg(&args[1])?;
+
+        let array = haystack
+            .iter()
+            .zip(needle.iter())
+            .map(|(haystack, needle)| {
+                let (haystack, needle) = (haystack?.as_bytes(), needle?.as_bytes());
+                Some(match suffix {
+                    true => haystack.ends_with(needle),
+                    false => haystack.starts_with(needle),
+                })
+            })
+            .collect::<BooleanArray>();
+        Ok(Arc::new(array) as ArrayRef)
+    };
+
+    make_scalar_function(func)
+}
+
+/// Implementation of match_all_columns: `match_all_columns(needle, col,
+/// ...)` returns whether any of the columns contains the needle, checking
+/// the columns of a row in turn up to the first match.
+///
+/// [`match_all_columns`] calls it with all text columns of a table, instead
+/// of a `str_match` call per column joined with `OR`.
+pub static MATCH_ALL_COLUMNS_UDF: Lazy<ScalarUDF> = Lazy::new(|| {
+    let return_type: ReturnTypeFunction = Arc::new(|_| Ok(Arc::new(DataType::Boolean)));
+    ScalarUDF::new(
+        MATCH_ALL_COLUMNS_UDF_NAME,
+        // expects strings: the needle, then the columns
+        &Signature::variadic(vec![DataType::Utf8], Volatility::Stable),
+        &return_type,
+        &match_all_columns_impl(),
+    )
+});
+
+/// Returns a predicate on the rows of `schema` containing the `needle` in
+/// some [`DataType::Utf8`] column, calling [`MATCH_ALL_COLUMNS_UDF`], or
+/// `false` if there is no such column.
+pub fn match_all_columns(schema: &DFSchema, needle: &str) -> Expr {
+    let columns: Vec<_> = schema
+        .fields()
+        .iter()
+        .filter(|field| field.data_type() == &DataType::Utf8)
+        .map(|field| Expr::Column(Column::from_name(field.name())))
+        .collect();
+    if columns.is_empty() {
+        return lit(false);
+    }
+    MATCH_ALL_COLUMNS_UDF.call(std::iter::once(lit(needle)).chain(columns).collect())
+}
+
+/// match_all_columns function for datafusion
+pub fn match_all_columns_impl() -> ScalarFunctionImplementation {
+    let func = move |args: &[ArrayRef]| -> datafusion::error::Result<ArrayRef> {
+        if args.len() < 2 {
+            return Err(DataFusionError::SQL(ParserError::ParserError(
+                "match_all_columns UDF expects a needle and at least one column".to_string(),
+            )));
+        }
+        let needle = as_string_arg(&args[0])?;
+        let columns = args[1..]
+            .iter()
+            .map(as_string_arg)
+            .collect::<datafusion::error::Result<Vec<_>>>()?;
+
+        let mut finder: Option<memchr::memmem::Finder> = None;
+        let array = needle
+            .iter()
+            .enumerate()
+            .map(|(row, needle)| {
+                let needle = needle?;
+                let finder = match &finder {
+                    Some(f) if f.needle() == needle.as_bytes() => f,
+                    _ => finder.insert(memchr::memmem::Finder::new(needle)),
+                };
+                Some(columns.iter().any(|column| {
+                    column.is_valid(row) && finder.find(column.value(row).as_bytes()).is_some()
+                }))
+            })
+            .collect::<BooleanArray>();
+        Ok(Arc::new(array) as ArrayRef)
+    };
+
+    make_scalar_function(func)
+}
+
+/// Implementation of find: `str_find(col, needle)` returns the byte offset,
+/// counted from 0, of the first match of the needle in the value, or null if
+/// there is none, e.g. to cut snippets or highlight matches in SQL.
+pub static FIND_UDF: Lazy<ScalarUDF> = Lazy::new(|| {
+    create_udf(
+        FIND_UDF_NAME,
+        // expects two string
+        vec![DataType::Utf8, DataType::Utf8],
+        // returns the offset
+        Arc::new(DataType::Int64),
+        Volatility::Stable,
+        find_impl(),
+    )
+});
+
+/// find function for datafusion
+pub fn find_impl() -> ScalarFunctionImplementation {
+    let func = move |args: &[ArrayRef]| -> datafusion::error::Result<ArrayRef> {
+        if args.len() != 2 {
+            return Err(DataFusionError::SQL(ParserError::ParserError(
+                "find UDF expects two string".to_string(),
+            )));
+        }
+        let haystack = as_string_arg(&args[0])?;
+        let needle = as_string_arg(&args[1])?;
+
+        let mut finder: Option<memchr::memmem::Finder> = None;
+        let array = haystack
+            .iter()
+            .zip(needle.iter())
+            .map(|(haystack, needle)| {
+                let (haystack, needle) = (haystack?, needle?);
+                let finder = match &finder {
+                    Some(f) if f.needle() == needle.as_bytes() => f,
+                    _ => finder.insert(memchr::memmem::Finder::new(needle)),
+                };
+                finder.find(haystack.as_bytes()).map(|offset| offset as i64)
+            })
+            .collect::<Int64Array>();
+        Ok(Arc::new(array) as ArrayRef)
+    };
+
+    make_scalar_function(func)
+}
+
+/// Implementation of match_spans: `str_match_spans(col, needle)` returns the
+/// non-overlapping matches of the needle in the value, as a list of
+/// `{start, end}` structs of byte offsets, so that user interfaces highlight
+/// them straight from the query results.
+pub static MATCH_SPANS_UDF: Lazy<ScalarUDF> = Lazy::new(|| {
+    create_udf(
+        MATCH_SPANS_UDF_NAME,
+        // expects two string
+        vec![DataType::Utf8, DataType::Utf8],
+        // returns the spans
+        Arc::new(span_list_type()),
+        Volatility::Stable,
+        match_spans_impl(),
+    )
+});
+
+fn span_fields() -> Vec<Field> {
+    vec![
+        Field::new("start", DataType::Int64, false),
+        Field::new("end", DataType::Int64, false),
+    ]
+}
+
+fn span_list_type() -> DataType {
+    DataType::List(Box::new(Field::new(
+        "item",
+        DataType::Struct(span_fields()),
+        true,
+    )))
+}
+
+/// match_spans function for datafusion
+pub fn match_spans_impl() -> ScalarFunctionImplementation {
+    let func = move |args: &[ArrayRef]| -> datafusion::error::Result<ArrayRef> {
+        if args.len() != 2 {
+            return Err(DataFusionError::SQL(ParserError::ParserError(
+                "match_spans UDF expects two string".to_string(),
+            )));
+        }
+        let haystack = as_string_arg(&args[0])?;
+        let needle = as_string_arg(&args[1])?;
+
+        let spans = StructBuilder::new(
+            span_fields(),
+            vec![Box::new(Int64Builder::new()), Box::new(Int64Builder::new())],
+        );
+        let mut builder = ListBuilder::new(spans);
+        let mut finder: Option<memchr::memmem::Finder> = None;
+        for (haystack, needle) in haystack.iter().zip(needle.iter()) {
+            let (Some(haystack), Some(needle)) = (haystack, needle) else {
+                builder.append(false);
+                continue;
+            };
+            let finder = match &finder {
+                Some(f) if f.needle() == needle.as_bytes() => f,
+                _ => finder.insert(memchr::memmem::Finder::new(needle)),
+            };
+            let spans = builder.values();
+            // An empty needle matches once, at the start.
+            let mut next = 0;
+            while let Some(start) = finder.find(&haystack.as_bytes()[next..]) {
+                let start = next + start;
+                let end = start + needle.len();
+                let span = [start as i64, end as i64];
+                for (i, offset) in span.into_iter().enumerate() {
+                    spans
+                        .field_builder::<Int64Builder>(i)
+                        .expect("Int64 field")
+                        .append_value(offset);
+                }
+                spans.append(true);
+                if needle.is_empty() {
+                    break;
+                }
+                next = end;
+            }
+            builder.append(true);
+        }
+        Ok(Arc::new(builder.finish()) as ArrayRef)
+    };
+
+    make_scalar_function(func)
+}
+
+/// Implementation of token_match: `token_match(col, term)` returns whether
+/// the value contains the term as whole [tokens](crate::str::tokens), so
+/// that `err` does not match `error_count`.
+pub static TOKEN_MATCH_UDF: Lazy<ScalarUDF> = Lazy::new(|| {
+    create_udf(
+        TOKEN_MATCH_UDF_NAME,
+        // expects two string
+        vec![DataType::Utf8, DataType::Utf8],
+        // returns boolean
+        Arc::new(DataType::Boolean),
+        Volatility::Stable,
+        token_match_impl(),
+    )
+});
+
+/// token_match function for datafusion
+pub fn token_match_impl() -> ScalarFunctionImplementation {
+    let func = move |args: &[ArrayRef]| -> datafusion::error::Result<ArrayRef> {
+        if args.len() != 2 {
+            return Err(DataFusionError::SQL(ParserError::ParserError(
+                "token_match UDF expects two string".to_string(),
+            )));
+        }
+        let haystack = as_string_arg(&args[0])?;
+        let term = as_string_arg(&args[1])?;
+
+        let mut matcher: Option<Term> = None;
+        let array = haystack
+            .iter()
+            .zip(term.iter())
+            .map(|(haystack, term)| {
+                let (haystack, term) = (haystack?, term?);
+                let matcher = match &matcher {
+                    Some(m) if m.term() == term.as_bytes() => m,
+                    _ => matcher.insert(Term::new(term.as_bytes())),
+                };
+                Some(matcher.is_match(haystack.as_bytes()))
+            })
+            .collect::<BooleanArray>();
+        Ok(Arc::new(array) as ArrayRef)
+    };
+
+    make_scalar_function(func)
+}
+
+/// Implementation of ip_in_cidr: `ip_in_cidr(col, '10.0.0.0/8')` tests if
+/// the IPv4 or IPv6 address in the value is in the subnet, e.g. to filter
+/// the pod and node addresses of kubernetes logs.  IPv4-mapped IPv6
+/// addresses are in the IPv4 subnets; values that are not addresses are in
+/// none.
+pub static IP_IN_CIDR_UDF: Lazy<ScalarUDF> = Lazy::new(|| {
+    create_udf(
+        IP_IN_CIDR_UDF_NAME,
+        // expects two string
+        vec![DataType::Utf8, DataType::Utf8],
+        // returns boolean
+        Arc::new(DataType::Boolean),
+        Volatility::Stable,
+        ip_in_cidr_impl(),
+    )
+});
+
+/// ip_in_cidr function for datafusion
+pub fn ip_in_cidr_impl() -> ScalarFunctionImplementation {
+    let func = move |args: &[ArrayRef]| -> datafusion::error::Result<ArrayRef> {
+        if args.len() != 2 {
+            return Err(DataFusionError::SQL(ParserError::ParserError(
+                "ip_in_cidr UDF expects two string".to_string(),
+            )));
+        }
+        let haystack = as_string_arg(&args[0])?;
+        let needle = as_string_arg(&args[1])?;
+
+        let mut cidr: Option<(&str, Cidr)> = None;
+        let array = haystack
+            .iter()
+            .zip(needle.iter())
+            .map(|(haystack, needle)| {
+                let (Some(haystack), Some(needle)) = (haystack, needle) else {
+                    return Ok(None);
+                };
+                let subnet = match cidr {
+                    Some((text, subnet)) if text == needle => subnet,
+                    _ => cidr.insert((needle, Cidr::parse(needle)?)).1,
+                };
+                let ip = haystack.trim().parse::<IpAddr>();
+                Ok(Some(ip.is_ok_and(|ip| subnet.contains(ip))))
+            })
+            .collect::<datafusion::error::Result<BooleanArray>>()?;
+        Ok(Arc::new(array) as ArrayRef)
+    };
+
+    make_scalar_function(func)
+}
+
+/// A subnet in CIDR notation; a bare address is a subnet of one.
+#[derive(Debug, Clone, Copy)]
+struct Cidr {
+    network: IpAddr,
+    prefix: u32,
+}
+
+impl Cidr {
+    fn parse(s: &str) -> datafusion::error::Result<Self> {
+        let invalid = || DataFusionError::Execution(format!("invalid CIDR: {s}"));
+        let (network, prefix) = s
+            .trim()
+            .split_once('/')
+            .map_or((s.trim(), None), |(n, p)| (n, Some(p)));
+        let network: IpAddr = network.parse().map_err(|_| invalid())?;
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|&prefix| prefix <= bits)
+                .ok_or_else(invalid)?,
+            None => bits,
+        };
+        Ok(Self { network, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        let (network, ip, bits) = match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                (u32::from(network).into(), u32::from(ip).into(), 32)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => (u128::from(network), u128::from(ip), 128),
+            (IpAddr::V4(_), IpAddr::V6(ip)) => {
+                return ip
+                    .to_ipv4_mapped()
+                    .is_some_and(|ip| self.contains(IpAddr::V4(ip)))
+            }
+            (IpAddr::V6(_), IpAddr::V4(_)) => return false,
+        };
+        // Shifting out all bits of a /0 subnet leaves nothing to compare.
+        (network ^ ip).checked_shr(bits - self.prefix).unwrap_or(0) == 0
+    }
+}
+
+/// Implementation of kv_extract: `kv_extract(log, 'key')` returns the value
+/// of the first logfmt-style `key=value` pair with the key in the value, or
+/// null if there is none, so structured filters can be written over
+/// unstructured log lines, e.g. `kv_extract(log, 'status') = '500'`.
+/// Double-quoted values are unquoted.
+pub static KV_EXTRACT_UDF: Lazy<ScalarUDF> = Lazy::new(|| {
+    create_udf(
+        KV_EXTRACT_UDF_NAME,
+        // expects two string
+        vec![DataType::Utf8, DataType::Utf8],
+        // returns the value
+        Arc::new(DataType::Utf8),
+        Volatility::Stable,
+        kv_extract_impl(),
+    )
+});
+
+/// kv_extract function for datafusion
+pub fn kv_extract_impl() -> ScalarFunctionImplementation {
+    let func = move |args: &[ArrayRef]| -> datafusion::error::Result<ArrayRef> {
+        if args.len() != 2 {
+            return Err(DataFusionError::SQL(ParserError::ParserError(
+                "kv_extract UDF expects two string".to_string(),
+            )));
+        }
+        let haystack = as_string_arg(&args[0])?;
+        let needle = as_string_arg(&args[1])?;
+
+        let mut key: Option<LogfmtKey> = None;
+        let array = haystack
+            .iter()
+            .zip(needle.iter())
+            .map(|(haystack, needle)| {
+                let (haystack, needle) = (haystack?, needle?);
+                let key = match &key {
+                    Some(k) if k.key() == needle.as_bytes() => k,
+                    _ => key.insert(LogfmtKey::new(needle.as_bytes())),
+                };
+                // Values are cut at ASCII bytes, so they stay valid UTF-8.
+                let value = key.value(haystack.as_bytes())?;
+                Some(String::from_utf8_lossy(&value).into_owned())
+            })
+            .collect::<StringArray>();
+        Ok(Arc::new(array) as ArrayRef)
+    };
+
+    make_scalar_function(func)
+}
+
+/// Implementation of json_get: `json_get(col, '$.kubernetes.pod_name')`
+/// returns the value at the [path](JsonPath::parse) in the JSON document in
+/// the value as text, or null if there is none, so JSON-encoded log lines can
+/// be filtered.  The documents are [scanned](crate::json), not parsed.
+pub static JSON_GET_UDF: Lazy<ScalarUDF> = Lazy::new(|| {
+    create_udf(
+        JSON_GET_UDF_NAME,
+        // expects two string
+        vec![DataType::Utf8, DataType::Utf8],
+        // returns the value
+        Arc::new(DataType::Utf8),
+        Volatility::Stable,
+        json_get_impl(),
+    )
+});
+
+/// json_get function for datafusion
+pub fn json_get_impl() -> ScalarFunctionImplementation {
+    let func = move |args: &[ArrayRef]| -> datafusion::error::Result<ArrayRef> {
+        if args.len() != 2 {
+            return Err(DataFusionError::SQL(ParserError::ParserError(
+                "json_get UDF expects two string".to_string(),
+            )));
+        }
+        let haystack = as_string_arg(&args[0])?;
+        let needle = as_string_arg(&args[1])?;
+
+        let mut path: Option<(&str, JsonPath)> = None;
+        let array = haystack
+            .iter()
+            .zip(needle.iter())
+            .map(|(haystack, needle)| {
+                let (Some(haystack), Some(needle)) = (haystack, needle) else {
+                    return Ok(None);
+                };
+                let path = match &path {
+                    Some((text, path)) if *text == needle => path,
+                    _ => {
+                        let parsed = JsonPath::parse(needle)
+                            .map_err(|e| DataFusionError::External(Box::new(e)))?;
+                        &path.insert((needle, parsed)).1
+                    }
+                };
+                Ok(path.get_text(haystack.as_bytes()))
+            })
+            .collect::<datafusion::error::Result<StringArray>>()?;
+        Ok(Arc::new(array) as ArrayRef)
+    };
+
+    make_scalar_function(func)
+}
+
+/// Implementation of phrase_match: `phrase_match(col, 'connection refused')`
+/// returns whether the value contains the whitespace-separated terms of the
+/// phrase as whole tokens, in order, which is more selective than matching
+/// each term and less brittle than one long needle.
+/// `phrase_match(col, phrase, max_gap)` also bounds the number of bytes
+/// between consecutive terms; see [`Phrase`].
+pub static PHRASE_MATCH_UDF: Lazy<ScalarUDF> = Lazy::new(|| {
+    let return_type: ReturnTypeFunction = Arc::new(|_| Ok(Arc::new(DataType::Boolean)));
+    ScalarUDF::new(
+        PHRASE_MATCH_UDF_NAME,
+        &Signature::one_of(
+            vec![
+                // expects two string
+                TypeSignature::Exact(vec![DataType::Utf8, DataType::Utf8]),
+                // and optionally the maximum gap
+                TypeSignature::Exact(vec![DataType::Utf8, DataType::Utf8, DataType::Int64]),
+            ],
+            Volatility::Stable,
+        ),
+        &return_type,
+        &phrase_match_impl(),
+    )
+});
+
+/// phrase_match function for datafusion
+pub fn phrase_match_impl() -> ScalarFunctionImplementation {
+    let func = move |args: &[ArrayRef]| -> datafusion::error::Result<ArrayRef> {
+        if args.len() != 2 && args.len() != 3 {
+            return Err(DataFusionError::SQL(ParserError::ParserError(
+                "phrase_match UDF expects two string and an optional gap".to_string(),
+            )));
+        }
+        let haystack = as_string_arg(&args[0])?;
+        let needle = as_string_arg(&args[1])?;
+        let max_gap = match args.get(2) {
+            Some(arg) => Some(arg.as_any().downcast_ref::<Int64Array>().ok_or_else(|| {
+                DataFusionError::Execution(format!(
+                    "phrase_match UDF expects an Int64 gap, got {}",
+                    arg.data_type()
+                ))
+            })?),
+            None => None,
+        };
+
+        let mut matcher: Option<(&str, Option<i64>, Phrase)> = None;
+        let array = haystack
+            .iter()
+            .zip(needle.iter())
+            .enumerate()
+            .map(|(row, (haystack, needle))| {
+                let gap = match max_gap {
+                    Some(gaps) if gaps.is_null(row) => return Ok(None),
+                    Some(gaps) => Some(gaps.value(row)),
+                    None => None,
+                };
+                let (Some(haystack), Some(needle)) = (haystack, needle) else {
+                    return Ok(None);
+                };
+                let matcher = match &matcher {
+                    Some((n, g, matcher)) if *n == needle && *g == gap => matcher,
+                    _ => {
+                        let max_gap = gap
+                            .map(|gap| {
+                                usize::try_from(gap).map_err(|_| {
+                                    DataFusionError::Execution(format!(
+                                        "phrase_match gap must not be negative, got {gap}"
+                                    ))
+                                })
+                            })
+                            .transpose()?;
+                        let phrase = Phrase::new(needle.as_bytes(), max_gap);
+                        &matcher.insert((needle, gap, phrase)).2
+                    }
+                };
+                Ok(Some(matcher.is_match(haystack.as_bytes())))
+            })
+            .collect::<datafusion::error::Result<BooleanArray>>()?;
+        Ok(Arc::new(array) as ArrayRef)
+    };
+
+    make_scalar_function(func)
+}
+
+/// Implementation of near_match: `near_match(col, term_a, term_b,
+/// max_distance)` returns whether the value contains both terms as whole
+/// tokens, in either order, at most `max_distance` bytes apart, e.g. to find
+/// an error next to the context it correlates with; see [`Near`].
+pub static NEAR_MATCH_UDF: Lazy<ScalarUDF> = Lazy::new(|| {
+    create_udf(
+        NEAR_MATCH_UDF_NAME,
+        // expects three string and the maximum distance
+        vec![
+            DataType::Utf8,
+            DataType::Utf8,
+            DataType::Utf8,
+            DataType::Int64,
+        ],
+        // returns boolean
+        Arc::new(DataType::Boolean),
+        Volatility::Stable,
+        near_match_impl(),
+    )
+});
+
+/// near_match function for datafusion
+pub fn near_match_impl() -> ScalarFunctionImplementation {
+    let func = move |args: &[ArrayRef]| -> datafusion::error::Result<ArrayRef> {
+        if args.len() != 4 {
+            return Err(DataFusionError::SQL(ParserError::ParserError(
+                "near_match UDF expects three string and a distance".to_string(),
+            )));
+        }
+        let haystack = as_string_arg(&args[0])?;
+        let first = as_string_arg(&args[1])?;
+        let second = as_string_arg(&args[2])?;
+        let max_dist = args[3]
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .ok_or_else(|| {
+                DataFusionError::Execution(format!(
+                    "near_match UDF expects an Int64 distance, got {}",
+                    args[3].data_type()
+                ))
+            })?;
+
+        let mut matcher: Option<(&str, &str, i64, Near)> = None;
+        let array = haystack
+            .iter()
+            .zip(first.iter())
+            .zip(second.iter())
+            .zip(max_dist.iter())
+            .map(|(((haystack, first), second), max_dist)| {
+                let (Some(haystack), Some(first), Some(second), Some(max_dist)) =
+                    (haystack, first, second, max_dist)
+                else {
+                    return Ok(None);
+                };
+                let matcher = match &matcher {
+                    Some((a, b, d, matcher)) if *a == first && *b == second && *d == max_dist => {
+                        matcher
+                    }
+                    _ => {
+                        let dist = usize::try_from(max_dist).map_err(|_| {
+                            DataFusionError::Execution(format!(
+                                "near_match distance must not be negative, got {max_dist}"
+                            ))
+                        })?;
+                        let near = Near::new(first.as_bytes(), second.as_bytes(), dist);
+                        &matcher.insert((first, second, max_dist, near)).3
+                    }
+                };
+                Ok(Some(matcher.is_match(haystack.as_bytes())))
+            })
+            .collect::<datafusion::error::Result<BooleanArray>>()?;
+        Ok(Arc::new(array) as ArrayRef)
+    };
+
+    make_scalar_function(func)
+}
+
+/// Implementation of glob_match: `glob_match(col, 'app-*-prod')` returns
+/// whether the whole value matches the shell-style pattern, with `*`, `?`,
+/// and `\` escapes; see [`Glob`].
+pub static GLOB_MATCH_UDF: Lazy<ScalarUDF> = Lazy::new(|| {
+    create_udf(
+        GLOB_MATCH_UDF_NAME,
+        // expects two string
+        vec![DataType::Utf8, DataType::Utf8],
+        // returns boolean
+        Arc::new(DataType::Boolean),
+        Volatility::Stable,
+        glob_match_impl(),
+    )
+});
+
+/// glob_match function for datafusion
+pub fn glob_match_impl() -> ScalarFunctionImplementation {
+    let func = move |args: &[ArrayRef]| -> datafusion::error::Result<ArrayRef> {
+        if args.len() != 2 {
+            return Err(DataFusionError::SQL(ParserError::ParserError(
+                "glob_match UDF expects two string".to_string(),
+            )));
+        }
+        let haystack = as_string_arg(&args[0])?;
+        let needle = as_string_arg(&args[1])?;
+
+        let mut matcher: Option<(&str, Glob)> = None;
+        let array = haystack
+            .iter()
+            .zip(needle.iter())
+            .map(|(haystack, needle)| {
+                let (haystack, needle) = (haystack?, needle?);
+                let matcher = match &matcher {
+                    Some((pattern, glob)) if *pattern == needle => glob,
+                    _ => &matcher.insert((needle, Glob::new(needle))).1,
+                };
+                Some(matcher.is_match(haystack.as_bytes()))
+            })
+            .collect::<BooleanArray>();
+        Ok(Arc::new(array) as ArrayRef)
+    };
+
+    make_scalar_function(func)
+}
 
-/// Implementation of match_range
-pub static MATCH_UDF: Lazy<ScalarUDF> = Lazy::new(|| {
+/// Implementation of match_query: `match_query(col, 'error AND (timeout OR
+/// refused)')` returns whether the value matches the boolean text
+/// [query](QueryString::parse), evaluated in one pass over the column, so
+/// clients don't have to rewrite such queries into SQL.
+pub static MATCH_QUERY_UDF: Lazy<ScalarUDF> = Lazy::new(|| {
     create_udf(
-        MATCH_UDF_NAME,
+        MATCH_QUERY_UDF_NAME,
         // expects two string
         vec![DataType::Utf8, DataType::Utf8],
         // returns boolean
         Arc::new(DataType::Boolean),
         Volatility::Stable,
-        match_expr_impl(false),
+        match_query_impl(),
     )
 });
 
-/// Implementation of match_no_case
-pub static MATCH_NO_CASE_UDF: Lazy<ScalarUDF> = Lazy::new(|| {
+/// match_query function for datafusion
+pub fn match_query_impl() -> ScalarFunctionImplementation {
+    let func = move |args: &[ArrayRef]| -> datafusion::error::Result<ArrayRef> {
+        if args.len() != 2 {
+            return Err(DataFusionError::SQL(ParserError::ParserError(
+                "match_query UDF expects two string".to_string(),
+            )));
+        }
+        let haystack = as_string_arg(&args[0])?;
+        let needle = as_string_arg(&args[1])?;
+
+        let mut query: Option<(&str, QueryString)> = None;
+        let array = haystack
+            .iter()
+            .zip(needle.iter())
+            .map(|(haystack, needle)| {
+                let (Some(haystack), Some(needle)) = (haystack, needle) else {
+                    return Ok(None);
+                };
+                let query = match &query {
+                    Some((text, query)) if *text == needle => query,
+                    _ => {
+                        let parsed = QueryString::parse(needle)
+                            .map_err(|e| DataFusionError::External(Box::new(e)))?;
+                        &query.insert((needle, parsed)).1
+                    }
+                };
+                Ok(Some(query.is_match(haystack.as_bytes())))
+            })
+            .collect::<datafusion::error::Result<BooleanArray>>()?;
+        Ok(Arc::new(array) as ArrayRef)
+    };
+
+    make_scalar_function(func)
+}
+
+/// The levels [`LOG_LEVEL_UDF`] returns, the values of its dictionaries.
+const LOG_LEVELS: [&str; 4] = ["DEBUG", "INFO", "WARN", "ERROR"];
+
+/// The level tokens [`LOG_LEVEL_UDF`] looks for and the indices of their
+/// levels in [`LOG_LEVELS`].
+const LOG_LEVEL_TOKENS: [(&str, i32); 5] = [
+    ("DEBUG", 0),
+    ("INFO", 1),
+    ("WARN", 2),
+    ("WARNING", 2),
+    ("ERROR", 3),
+];
+
+static LOG_LEVEL_AUTOMATON: Lazy<AhoCorasick> = Lazy::new(|| {
+    AhoCorasickBuilder::new()
+        .ascii_case_insensitive(true)
+        .match_kind(MatchKind::LeftmostLongest)
+        .build(LOG_LEVEL_TOKENS.map(|(token, _)| token))
+});
+
+/// Implementation of log_level: `log_level(col)` returns the severity of the
+/// value, the first of `DEBUG`, `INFO`, `WARN` or `WARNING`, and `ERROR` in
+/// it as a whole token in any case, or null if there is none, as a
+/// dictionary of the [levels](LOG_LEVELS), so severity facets over raw logs
+/// group a handful of keys.
+pub static LOG_LEVEL_UDF: Lazy<ScalarUDF> = Lazy::new(|| {
     create_udf(
-        MATCH_UDF_NO_CASE_NAME,
-        // expects two string
-        vec![DataType::Utf8, DataType::Utf8],
-        // returns boolean
-        Arc::new(DataType::Boolean),
+        LOG_LEVEL_UDF_NAME,
+        // expects a string
+        vec![DataType::Utf8],
+        // returns the level
+        Arc::new(DataType::Dictionary(
+            Box::new(DataType::Int32),
+            Box::new(DataType::Utf8),
+        )),
+        Volatility::Stable,
+        log_level_impl(),
+    )
+});
+
+/// log_level function for datafusion
+pub fn log_level_impl() -> ScalarFunctionImplementation {
+    let func = move |args: &[ArrayRef]| -> datafusion::error::Result<ArrayRef> {
+        if args.len() != 1 {
+            return Err(DataFusionError::SQL(ParserError::ParserError(
+                "log_level UDF expects one string".to_string(),
+            )));
+        }
+        let haystack = as_string_arg(&args[0])?;
+
+        let keys = haystack
+            .iter()
+            .map(|haystack| log_level(haystack?.as_bytes()))
+            .collect::<Int32Array>();
+        let levels = StringArray::from(LOG_LEVELS.to_vec());
+        let array = DictionaryArray::<Int32Type>::try_new(&keys, &levels)?;
+        Ok(Arc::new(array) as ArrayRef)
+    };
+
+    make_scalar_function(func)
+}
+
+/// Returns the index in [`LOG_LEVELS`] of the first level token in
+/// `haystack`.
+fn log_level(haystack: &[u8]) -> Option<i32> {
+    LOG_LEVEL_AUTOMATON.find_iter(haystack).find_map(|found| {
+        let starts_token = haystack[..found.start()]
+            .last()
+            .is_none_or(|&b| is_token_separator(b));
+        let ends_token = haystack
+            .get(found.end())
+            .is_none_or(|&b| is_token_separator(b));
+        (starts_token && ends_token).then_some(LOG_LEVEL_TOKENS[found.pattern()].1)
+    })
+}
+
+/// Implementation of parse_duration: `parse_duration(col)` returns the
+/// duration in the value, e.g. `153ms`, `2.5s`, or `1m30s`, in milliseconds,
+/// or null if the value is not one, so latency histograms can be computed
+/// from text logs.  The units are `ns`, `us` or `µs`, `ms`, `s`, `m`, `h`,
+/// and `d`.
+pub static PARSE_DURATION_UDF: Lazy<ScalarUDF> = Lazy::new(|| {
+    create_udf(
+        PARSE_DURATION_UDF_NAME,
+        // expects a string
+        vec![DataType::Utf8],
+        // returns milliseconds
+        Arc::new(DataType::Float64),
         Volatility::Stable,
-        match_expr_impl(true),
+        parse_duration_impl(),
     )
 });
 
+/// parse_duration function for datafusion
+pub fn parse_duration_impl() -> ScalarFunctionImplementation {
+    let func = move |args: &[ArrayRef]| -> datafusion::error::Result<ArrayRef> {
+        if args.len() != 1 {
+            return Err(DataFusionError::SQL(ParserError::ParserError(
+                "parse_duration UDF expects one string".to_string(),
+            )));
+        }
+        let haystack = as_string_arg(&args[0])?;
+
+        let array = haystack
+            .iter()
+            .map(|haystack| duration_millis(haystack?))
+            .collect::<Float64Array>();
+        Ok(Arc::new(array) as ArrayRef)
+    };
+
+    make_scalar_function(func)
+}
+
+/// Parses a duration of one or more numbers with units, surrounded by
+/// whitespace or not, into milliseconds.
+fn duration_millis(s: &str) -> Option<f64> {
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    // Summing nanoseconds keeps whole durations like `12µs` exact until the
+    // one division at the end.
+    let mut nanos = 0.0;
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let unit_len = rest[number_len..]
+            .find(|c: char| !c.is_alphabetic())
+            .unwrap_or(rest.len() - number_len);
+        let number: f64 = rest[..number_len].parse().ok()?;
+        let unit = match &rest[number_len..number_len + unit_len] {
+            "ns" => 1.0,
+            "us" | "µs" => 1e3,
+            "ms" => 1e6,
+            "s" => 1e9,
+            "m" => 60e9,
+            "h" => 3600e9,
+            "d" => 86400e9,
+            _ => return None,
+        };
+        nanos += number * unit;
+        rest = &rest[number_len + unit_len..];
+    }
+    Some(nanos / 1e6)
+}
+
+/// What a [`count_matches_udaf`] counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Counting {
+    /// Rows whose value contains the needle, like
+    /// `count(*) ... where str_match(col, needle)`.
+    Rows,
+    /// Non-overlapping matches of the needle in all values.
+    Occurrences,
+}
+
+/// Implementation of count_matches: `count_matches(col, needle)` counts the
+/// rows whose value contains the needle in the aggregation itself, instead
+/// of filtering a stream of batches for `count(*)` to count.
+pub static COUNT_MATCHES_UDAF: Lazy<AggregateUDF> =
+    Lazy::new(|| count_matches_udaf(Counting::Rows));
+
+/// Implementation of count_occurrences: `count_occurrences(col, needle)`
+/// counts the matches of the needle in all values.
+pub static COUNT_OCCURRENCES_UDAF: Lazy<AggregateUDF> =
+    Lazy::new(|| count_matches_udaf(Counting::Occurrences));
+
+/// Returns the count_matches UDAF counting as given, named
+/// [`COUNT_MATCHES_UDAF_NAME`] if it counts rows and
+/// [`COUNT_OCCURRENCES_UDAF_NAME`] if it counts occurrences.  Null values and
+/// needles are not counted.
+pub fn count_matches_udaf(counting: Counting) -> AggregateUDF {
+    let name = match counting {
+        Counting::Rows => COUNT_MATCHES_UDAF_NAME,
+        Counting::Occurrences => COUNT_OCCURRENCES_UDAF_NAME,
+    };
+    let return_type: ReturnTypeFunction = Arc::new(|_| Ok(Arc::new(DataType::Int64)));
+    let accumulator: AccumulatorFunctionImplementation =
+        Arc::new(move |_| Ok(Box::new(CountMatches { counting, count: 0 })));
+    let state_type: StateTypeFunction = Arc::new(|_| Ok(Arc::new(vec![DataType::Int64])));
+    AggregateUDF::new(
+        name,
+        &match_signature(),
+        &return_type,
+        &accumulator,
+        &state_type,
+    )
+}
+
+#[derive(Debug)]
+struct CountMatches {
+    counting: Counting,
+    count: i64,
+}
+
+impl Accumulator for CountMatches {
+    fn state(&self) -> datafusion::error::Result<Vec<ScalarValue>> {
+        Ok(vec![ScalarValue::Int64(Some(self.count))])
+    }
+
+    fn update_batch(&mut self, values: &[ArrayRef]) -> datafusion::error::Result<()> {
+        if values.len() != 2 {
+            return Err(DataFusionError::SQL(ParserError::ParserError(
+                "count_matches UDAF expects two string".to_string(),
+            )));
+        }
+        // A dictionary is counted through its values like any other text.
+        let haystack = match values[0].data_type() {
+            DataType::Dictionary(_, _) => cast(&values[0], &DataType::Utf8)?,
+            _ => values[0].clone(),
+        };
+        let needle = as_string_arg(&values[1])?;
+
+        let mut finder: Option<memchr::memmem::Finder> = None;
+        for (haystack, needle) in haystack_arg(&haystack)?.zip(needle.iter()) {
+            let (Some(haystack), Some(needle)) = (haystack, needle) else {
+                continue;
+            };
+            let finder = match &finder {
+                Some(f) if f.needle() == needle.as_bytes() => f,
+                _ => finder.insert(memchr::memmem::Finder::new(needle)),
+            };
+            self.count += match self.counting {
+                Counting::Rows => finder.find(haystack).is_some() as i64,
+                Counting::Occurrences => finder.find_iter(haystack).count() as i64,
+            };
+        }
+        Ok(())
+    }
+
+    fn merge_batch(&mut self, states: &[ArrayRef]) -> datafusion::error::Result<()> {
+        let counts = states[0]
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .ok_or_else(|| {
+                DataFusionError::Execution(format!(
+                    "count_matches UDAF expects Int64 states, got {}",
+                    states[0].data_type()
+                ))
+            })?;
+        self.count += counts.iter().flatten().sum::<i64>();
+        Ok(())
+    }
+
+    fn evaluate(&self) -> datafusion::error::Result<ScalarValue> {
+        Ok(ScalarValue::Int64(Some(self.count)))
+    }
+
+    fn size(&self) -> usize {
+        std::mem::size_of_val(self)
+    }
+}
+
+/// Registers every UDF and UDAF of this module with the `ctx`.
+///
+/// [`new_session_context`](crate::datafusion::new_session_context) calls it,
+/// so only sessions built otherwise need to.
+pub fn register_all(ctx: &SessionContext) {
+    for udf in [
+        &MATCH_UDF,
+        &MATCH_NO_CASE_UDF,
+        &MATCH_IGNORE_CASE_UDF,
+        &RE_MATCH_UDF,
+        &REGEXP_EXTRACT_GROUP_UDF,
+        &MATCH_ANY_UDF,
+        &NOT_MATCH_UDF,
+        &FUZZY_MATCH_UDF,
+        &STARTS_WITH_MATCH_UDF,
+        &ENDS_WITH_MATCH_UDF,
+        &MATCH_ALL_COLUMNS_UDF,
+        &FIND_UDF,
+        &MATCH_SPANS_UDF,
+        &TOKEN_MATCH_UDF,
+        &IP_IN_CIDR_UDF,
+        &KV_EXTRACT_UDF,
+        &JSON_GET_UDF,
+        &PHRASE_MATCH_UDF,
+        &NEAR_MATCH_UDF,
+        &GLOB_MATCH_UDF,
+        &MATCH_QUERY_UDF,
+        &LOG_LEVEL_UDF,
+        &PARSE_DURATION_UDF,
+    ] {
+        ctx.register_udf(ScalarUDF::clone(udf));
+    }
+    ctx.register_udaf(COUNT_MATCHES_UDAF.clone());
+    ctx.register_udaf(COUNT_OCCURRENCES_UDAF.clone());
+}
+
 /// match function for datafusion
+///
+/// A literal needle, e.g. in `str_match(log, 'k8s')`, is compiled into a
+/// [`memmem::Finder`](memchr::memmem::Finder) once, and the finder reused for
+/// every batch of the query, and of later ones with the same needle.  Text
+/// and binary columns are searched with it by the
+/// [kernels](crate::arrow::kernels) of the arrow search, which search the
+/// values of a batch at once instead of row by row.  Needles of other
+/// expressions, matching without case, and dictionaries take the per-row
+/// path.
 pub fn match_expr_impl(case_insensitive: bool) -> ScalarFunctionImplementation {
+    match_expr_impl_with_nulls(case_insensitive, Nulls::Propagate)
+}
+
+/// Like [`match_expr_impl`], but handles nulls as `nulls` says.
+pub fn match_expr_impl_with_nulls(
+    case_insensitive: bool,
+    nulls: Nulls,
+) -> ScalarFunctionImplementation {
     let func = move |args: &[ArrayRef]| -> datafusion::error::Result<ArrayRef> {
         if args.len() != 2 {
             return Err(DataFusionError::SQL(ParserError::ParserError(
@@ -53,60 +1675,220 @@ pub fn match_expr_impl(case_insensitive: bool) -> ScalarFunctionImplementation {
             )));
         }
 
-        // 1. cast both arguments to string. These casts MUST be aligned with the signature or this function panics!
-        let haystack = &args[0]
-            .as_any()
-            .downcast_ref::<StringArray>()
-            .expect("cast failed");
-        let needle = &args[1]
-            .as_any()
-            .downcast_ref::<StringArray>()
-            .expect("cast failed");
+        let matches = move |haystack: &[u8], needle: &str| match case_insensitive {
+            // Raw bytes that are not text only have their ASCII letters
+            // lowercased.
+            true => match std::str::from_utf8(haystack) {
+                Ok(haystack) => memchr::memmem::find(
+                    haystack.to_lowercase().as_bytes(),
+                    needle.to_lowercase().as_bytes(),
+                )
+                .is_some(),
+                Err(_) => memchr::memmem::find(
+                    &haystack.to_ascii_lowercase(),
+                    needle.to_lowercase().as_bytes(),
+                )
+                .is_some(),
+            },
+            false => memchr::memmem::find(haystack, needle.as_bytes()).is_some(),
+        };
+
+        // 1. cast both arguments to string. These casts MUST be aligned with the signature.
+        let needle = as_string_arg(&args[1])?;
+        if let DataType::Dictionary(_, _) = args[0].data_type() {
+            let array = match_dictionary(&args[0], needle, matches)?;
+            return Ok(Arc::new(handle_nulls(array, nulls)) as ArrayRef);
+        }
+        let haystack = haystack_arg(&args[0])?;
 
         // 2. perform the computation
         let array = haystack
-            .iter()
             .zip(needle.iter())
             .map(|(haystack, needle)| {
                 match (haystack, needle) {
                     // in arrow, any value can be null.
                     // Here we decide to make our UDF to return null when either haystack or needle is null.
-                    (Some(haystack), Some(needle)) => match case_insensitive {
-                        true => Some(
-                            memchr::memmem::find(
-                                haystack.to_lowercase().as_bytes(),
-                                needle.to_lowercase().as_bytes(),
-                            )
-                            .is_some(),
-                        ),
-                        false => Some(
-                            memchr::memmem::find(haystack.as_bytes(), needle.as_bytes()).is_some(),
-                        ),
-                    },
+                    (Some(haystack), Some(needle)) => Some(matches(haystack, needle)),
                     _ => None,
                 }
             })
             .collect::<BooleanArray>();
+        let array = handle_nulls(array, nulls);
 
         // `Ok` because no error occurred during the calculation
         // `Arc` because arrays are immutable, thread-safe, trait objects.
         Ok(Arc::new(array) as ArrayRef)
     };
 
-    make_scalar_function(func)
+    let per_row = make_scalar_function(func);
+    if case_insensitive {
+        return per_row;
+    }
+    let literal: Mutex<Option<Arc<memchr::memmem::Finder<'static>>>> = Mutex::new(None);
+    Arc::new(move |args: &[ColumnarValue]| {
+        let [ColumnarValue::Array(haystack), ColumnarValue::Scalar(ScalarValue::Utf8(Some(needle)))] =
+            args
+        else {
+            return per_row(args);
+        };
+        if !matches!(
+            haystack.data_type(),
+            DataType::Utf8 | DataType::LargeUtf8 | DataType::Binary | DataType::LargeBinary
+        ) {
+            return per_row(args);
+        }
+        let finder = {
+            let mut literal = literal.lock().unwrap_or_else(|e| e.into_inner());
+            match literal.as_ref() {
+                Some(f) if f.needle() == needle.as_bytes() => f.clone(),
+                _ => {
+                    #[cfg(test)]
+                    tests::FINDERS_BUILT.with(|built| built.set(built.get() + 1));
+                    literal
+                        .insert(Arc::new(memchr::memmem::Finder::new(needle).into_owned()))
+                        .clone()
+                }
+            }
+        };
+        let array = match haystack.data_type() {
+            DataType::Utf8 => kernels::match_utf8_with(as_string_arg(haystack)?, &finder),
+            DataType::LargeUtf8 => {
+                kernels::match_utf8_with(as_large_string_arg(haystack)?, &finder)
+            }
+            DataType::Binary => {
+                kernels::match_binary_with(as_binary_arg::<i32>(haystack)?, &finder)
+            }
+            _ => kernels::match_binary_with(as_binary_arg::<i64>(haystack)?, &finder),
+        };
+        Ok(ColumnarValue::Array(
+            Arc::new(handle_nulls(array, nulls)) as ArrayRef
+        ))
+    })
+}
+
+/// Replaces the nulls of a match result by no match if `nulls` says so.
+fn handle_nulls(array: BooleanArray, nulls: Nulls) -> BooleanArray {
+    match nulls {
+        Nulls::AsNoMatch if array.null_count() > 0 => array
+            .iter()
+            .map(|matched| Some(matched.unwrap_or(false)))
+            .collect(),
+        _ => array,
+    }
+}
+
+/// Matches the values of a dictionary of strings.  Each distinct value is
+/// only searched once if the needle is the same for all rows, which it
+/// usually is, and the result mapped back through the keys.
+fn match_dictionary(
+    haystack: &ArrayRef,
+    needle: &StringArray,
+    matches: impl Fn(&[u8], &str) -> bool,
+) -> datafusion::error::Result<BooleanArray> {
+    downcast_dictionary_array!(
+        haystack => {
+            let values = as_string_arg(haystack.values())?;
+            let first = (needle.null_count() == 0 && !needle.is_empty()).then(|| needle.value(0));
+            match first.filter(|first| needle.iter().all(|n| n == Some(first))) {
+                Some(needle) => {
+                    let matched: Vec<_> = values
+                        .iter()
+                        .map(|value| value.map(|value| matches(value.as_bytes(), needle)))
+                        .collect();
+                    Ok(haystack
+                        .keys_iter()
+                        .map(|key| key.and_then(|key| matched[key]))
+                        .collect())
+                }
+                None => Ok(haystack
+                    .keys_iter()
+                    .zip(needle.iter())
+                    .map(|(key, needle)| {
+                        let (key, needle) = (key?, needle?);
+                        values
+                            .is_valid(key)
+                            .then(|| matches(values.value(key).as_bytes(), needle))
+                    })
+                    .collect()),
+            }
+        },
+        t => Err(DataFusionError::Execution(format!(
+            "match UDF expects a dictionary argument, got {t}"
+        ))),
+    )
+}
+
+/// Downcasts a UDF argument to [`StringArray`], reporting an error instead of
+/// panicking if DataFusion hands over an array of some other type.
+pub(crate) fn as_string_arg(arg: &ArrayRef) -> datafusion::error::Result<&StringArray> {
+    arg.as_any().downcast_ref::<StringArray>().ok_or_else(|| {
+        DataFusionError::Execution(format!(
+            "match UDF expects Utf8 arguments, got {}",
+            arg.data_type()
+        ))
+    })
+}
+
+type ByteArgs<'a> = Box<dyn Iterator<Item = Option<&'a [u8]>> + 'a>;
+
+/// Returns the values of a text or binary haystack argument as bytes.
+fn haystack_arg(arg: &ArrayRef) -> datafusion::error::Result<ByteArgs<'_>> {
+    Ok(match arg.data_type() {
+        DataType::LargeUtf8 => Box::new(as_large_string_arg(arg)?.iter().map(bytes)),
+        DataType::Binary => Box::new(as_binary_arg::<i32>(arg)?.iter()),
+        DataType::LargeBinary => Box::new(as_binary_arg::<i64>(arg)?.iter()),
+        _ => Box::new(as_string_arg(arg)?.iter().map(bytes)),
+    })
+}
+
+fn bytes(s: Option<&str>) -> Option<&[u8]> {
+    s.map(str::as_bytes)
+}
+
+fn as_binary_arg<O: OffsetSizeTrait>(
+    arg: &ArrayRef,
+) -> datafusion::error::Result<&GenericBinaryArray<O>> {
+    arg.as_any()
+        .downcast_ref::<GenericBinaryArray<O>>()
+        .ok_or_else(|| {
+            DataFusionError::Execution(format!(
+                "match UDF expects binary arguments, got {}",
+                arg.data_type()
+            ))
+        })
+}
+
+fn as_large_string_arg(arg: &ArrayRef) -> datafusion::error::Result<&LargeStringArray> {
+    arg.as_any()
+        .downcast_ref::<LargeStringArray>()
+        .ok_or_else(|| {
+            DataFusionError::Execution(format!(
+                "match UDF expects LargeUtf8 arguments, got {}",
+                arg.data_type()
+            ))
+        })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use datafusion::arrow::array::{Int64Array, StringArray};
-    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::array::{
+        as_list_array, as_struct_array, BinaryArray, DictionaryArray, Int64Array, LargeStringArray,
+        StringArray,
+    };
+    use datafusion::arrow::datatypes::{DataType, Field, Int32Type, Schema};
     use datafusion::arrow::record_batch::RecordBatch;
     use datafusion::datasource::MemTable;
     use datafusion::from_slice::FromSlice;
+    use datafusion::physical_plan::ColumnarValue;
     use datafusion::prelude::SessionContext;
-    use std::sync::Arc;
+    use std::{cell::Cell, sync::Arc};
+
+    thread_local! {
+        /// The number of literal finders the match UDF compiled on this thread.
+        pub(super) static FINDERS_BUILT: Cell<usize> = const { Cell::new(0) };
+    }
 
     #[tokio::test]
     async fn test_match_udf() {
@@ -123,17 +1905,16 @@ mod tests {
         let batch = RecordBatch::try_new(
             schema.clone(),
             vec![
-                Arc::new(StringArray::from_slice(&["a", "b", "c", "d"])),
-                Arc::new(Int64Array::from_slice(&[1, 2, 3, 4])),
-                Arc::new(StringArray::from_slice(&["NY", "Pune", "SF", "Beijing"])),
+                Arc::new(StringArray::from_slice(["a", "b", "c", "d"])),
+                Arc::new(Int64Array::from_slice([1, 2, 3, 4])),
+                Arc::new(StringArray::from_slice(["NY", "Pune", "SF", "Beijing"])),
             ],
         )
         .unwrap();
 
         // declare a new context. In spark API, this corresponds to a new spark SQLsession
         let ctx = SessionContext::new();
-        ctx.register_udf(MATCH_UDF.clone());
-        ctx.register_udf(MATCH_NO_CASE_UDF.clone());
+        register_all(&ctx);
 
         // declare a table in memory. In spark API, this corresponds to createDataFrame(...).
         let provider = MemTable::try_new(schema, vec![vec![batch]]).unwrap();
@@ -144,4 +1925,953 @@ mod tests {
         let count = result.iter().map(|batch| batch.num_rows()).sum::<usize>();
         assert_eq!(count, 1);
     }
+
+    /// Returns a session with every UDF registered and a table `t` of the
+    /// cities NY, Pune, SF and Beijing with the ids 1 to 4.
+    fn cities() -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("city", DataType::Utf8, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from_slice([1, 2, 3, 4])),
+                Arc::new(StringArray::from_slice(["NY", "Pune", "SF", "Beijing"])),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new();
+        register_all(&ctx);
+        let provider = MemTable::try_new(schema, vec![vec![batch]]).unwrap();
+        ctx.register_table("t", Arc::new(provider)).unwrap();
+        ctx
+    }
+
+    /// Returns the ids that the `sql` on [`cities`] selects.
+    async fn ids(ctx: &SessionContext, sql: &str) -> Vec<i64> {
+        let result = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+        result
+            .iter()
+            .flat_map(|batch| {
+                let ids = batch.column(0).as_any().downcast_ref::<Int64Array>();
+                ids.unwrap().values().to_vec()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_re_match_udf() {
+        let ctx = cities();
+        assert_eq!(
+            ids(
+                &ctx,
+                "select id from t where re_match(city, '^[A-Z][a-z]+$') order by id"
+            )
+            .await,
+            [2, 4]
+        );
+        let df = ctx
+            .sql("select id from t where re_match(city, '(')")
+            .await
+            .unwrap();
+        assert!(df.collect().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_match_any_udf() {
+        let ctx = cities();
+        assert_eq!(
+            ids(
+                &ctx,
+                "select id from t where str_match_any(city, 'SF', 'jin', NULL) order by id"
+            )
+            .await,
+            [3, 4]
+        );
+
+        // The needles change from row to row, and null ones are left out.
+        let haystack: ArrayRef = Arc::new(StringArray::from(vec![
+            Some("k8s pod"),
+            Some("node down"),
+            None,
+            Some("pod"),
+            Some("pod"),
+        ]));
+        let first: ArrayRef = Arc::new(StringArray::from(vec![
+            Some("k8s"),
+            Some("k8s"),
+            Some("pod"),
+            Some("down"),
+            None,
+        ]));
+        let second: ArrayRef = Arc::new(StringArray::from(vec![
+            None,
+            Some("down"),
+            Some("pod"),
+            None,
+            None,
+        ]));
+        let result = match_any_impl()(&[
+            ColumnarValue::Array(haystack.clone()),
+            ColumnarValue::Array(first),
+            ColumnarValue::Array(second),
+        ])
+        .unwrap()
+        .into_array(haystack.len());
+        let result = result.as_any().downcast_ref::<BooleanArray>().unwrap();
+        assert_eq!(
+            result.iter().collect::<Vec<_>>(),
+            [Some(true), Some(true), None, Some(false), Some(false)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_affix_match_udf() {
+        let ctx = cities();
+        let sql = "select id from t \
+            where starts_with_match(city, 'P') or ends_with_match(city, 'jing') order by id";
+        assert_eq!(ids(&ctx, sql).await, [2, 4]);
+
+        let haystack: ArrayRef = Arc::new(StringArray::from(vec![
+            Some("k8s pod"),
+            Some("pod k8s"),
+            None,
+        ]));
+        let needle: ArrayRef = Arc::new(StringArray::from(vec!["k8s"; 3]));
+        let matched = |suffix| {
+            let result = affix_match_impl(suffix)(&[
+                ColumnarValue::Array(haystack.clone()),
+                ColumnarValue::Array(needle.clone()),
+            ])
+            .unwrap()
+            .into_array(haystack.len());
+            let result = result.as_any().downcast_ref::<BooleanArray>().unwrap();
+            result.iter().collect::<Vec<_>>()
+        };
+        assert_eq!(matched(false), [Some(true), Some(false), None]);
+        assert_eq!(matched(true), [Some(false), Some(true), None]);
+    }
+
+    #[tokio::test]
+    async fn test_match_all_columns_udf() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("log", DataType::Utf8, true),
+            Field::new("id", DataType::Int64, false),
+            Field::new("city", DataType::Utf8, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(StringArray::from(vec![
+                    Some("GET /"),
+                    None,
+                    Some("POST /"),
+                    None,
+                ])),
+                Arc::new(Int64Array::from_slice([1, 2, 3, 4])),
+                Arc::new(StringArray::from_slice(["NY", "Pune", "SF", "Beijing"])),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new();
+        register_all(&ctx);
+        let provider = MemTable::try_new(schema, vec![vec![batch.clone()]]).unwrap();
+        ctx.register_table("t", Arc::new(provider)).unwrap();
+        let provider = MemTable::try_new(
+            Arc::new(batch.schema().project(&[1]).unwrap()),
+            vec![vec![batch.project(&[1]).unwrap()]],
+        )
+        .unwrap();
+        ctx.register_table("ids", Arc::new(provider)).unwrap();
+
+        let matched = |table: &'static str, needle: &'static str| {
+            let ctx = ctx.clone();
+            async move {
+                let df = ctx.table(table).await.unwrap();
+                let filter = match_all_columns(df.schema(), needle);
+                let result = df.filter(filter).unwrap().collect().await.unwrap();
+                result.iter().map(|batch| batch.num_rows()).sum::<usize>()
+            }
+        };
+        assert_eq!(matched("t", "N").await, 1);
+        assert_eq!(matched("t", "P").await, 2);
+        assert_eq!(matched("t", "/").await, 2);
+        assert_eq!(matched("t", "missing").await, 0);
+
+        // Without text columns, no row matches.
+        let df = ctx.table("ids").await.unwrap();
+        assert_eq!(match_all_columns(df.schema(), "1"), lit(false));
+        assert_eq!(matched("ids", "1").await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_find_udf() {
+        let ctx = cities();
+        let sql = "select str_find(city, 'e') as offset from t order by id";
+        let result = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+        let offsets = result[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(
+            offsets.iter().collect::<Vec<_>>(),
+            [None, Some(3), None, Some(1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_match_spans_udf() {
+        fn spans(list: &ArrayRef, row: usize) -> Vec<(i64, i64)> {
+            let spans = as_list_array(list).value(row);
+            let spans = as_struct_array(&spans);
+            let offsets = |i| {
+                let column = spans.column(i).as_any().downcast_ref::<Int64Array>();
+                column.unwrap().values().to_vec()
+            };
+            offsets(0).into_iter().zip(offsets(1)).collect()
+        }
+
+        let ctx = cities();
+        let sql = "select str_match_spans(city, 'i') as spans from t where id = 4";
+        let result = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+        assert_eq!(spans(result[0].column(0), 0), [(2, 3), (4, 5)]);
+
+        let haystack: ArrayRef = Arc::new(StringArray::from(vec![
+            Some("abab"),
+            None,
+            Some("abab"),
+            Some("xy"),
+            Some("xy"),
+        ]));
+        let needle: ArrayRef = Arc::new(StringArray::from(vec![
+            Some("ab"),
+            Some("ab"),
+            None,
+            Some("ab"),
+            Some(""),
+        ]));
+        let result = match_spans_impl()(&[
+            ColumnarValue::Array(haystack.clone()),
+            ColumnarValue::Array(needle),
+        ])
+        .unwrap()
+        .into_array(haystack.len());
+        assert_eq!(spans(&result, 0), [(0, 2), (2, 4)]);
+        assert!(result.is_null(1));
+        assert!(result.is_null(2));
+        assert_eq!(spans(&result, 3), []);
+        // An empty needle matches once, at the start.
+        assert_eq!(spans(&result, 4), [(0, 0)]);
+    }
+
+    #[tokio::test]
+    async fn test_count_matches_udaf() {
+        let ctx = cities();
+        let sql = "select count_matches(city, 'i'), count_occurrences(city, 'i') from t";
+        let result = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+        let counts: Vec<_> = result[0]
+            .columns()
+            .iter()
+            .map(|c| c.as_any().downcast_ref::<Int64Array>().unwrap().value(0))
+            .collect();
+        assert_eq!(counts, [1, 2]);
+
+        // Null values and needles are not counted.
+        let haystack: DictionaryArray<Int32Type> =
+            vec![Some("k8s k8s"), None, Some("k8s"), Some("pod")]
+                .into_iter()
+                .collect();
+        let haystack: ArrayRef = Arc::new(haystack);
+        let needle: ArrayRef = Arc::new(StringArray::from(vec![
+            Some("k8s"),
+            Some("k8s"),
+            None,
+            Some("pod"),
+        ]));
+        let count = |counting| {
+            let mut accumulator = CountMatches { counting, count: 0 };
+            accumulator
+                .update_batch(&[haystack.clone(), needle.clone()])
+                .unwrap();
+            accumulator.count
+        };
+        assert_eq!(count(Counting::Rows), 2);
+        assert_eq!(count(Counting::Occurrences), 3);
+
+        let mut accumulator = CountMatches {
+            counting: Counting::Rows,
+            count: 1,
+        };
+        let states: ArrayRef = Arc::new(Int64Array::from(vec![Some(2), None, Some(3)]));
+        accumulator.merge_batch(&[states]).unwrap();
+        assert_eq!(accumulator.evaluate().unwrap(), ScalarValue::Int64(Some(6)));
+    }
+
+    #[test]
+    fn test_match_udf_nulls() {
+        let values = vec![Some("k8s"), None, Some("k8s"), Some("pod")];
+        let strings: ArrayRef = Arc::new(StringArray::from(values.clone()));
+        let dictionary: DictionaryArray<Int32Type> = values.into_iter().collect();
+        let dictionary: ArrayRef = Arc::new(dictionary);
+        let needles = ColumnarValue::Array(Arc::new(StringArray::from(vec![
+            Some("k8s"),
+            Some("k8s"),
+            None,
+            Some("k8s"),
+        ])));
+        let literal = ColumnarValue::Scalar(ScalarValue::Utf8(Some("k8s".to_owned())));
+        let matched = |case_insensitive, nulls, haystack: &ArrayRef, needle: &ColumnarValue| {
+            let udf = match_udf(case_insensitive, nulls);
+            let result = (udf.fun)(&[ColumnarValue::Array(haystack.clone()), needle.clone()])
+                .unwrap()
+                .into_array(haystack.len());
+            let result = result.as_any().downcast_ref::<BooleanArray>().unwrap();
+            result.iter().collect::<Vec<_>>()
+        };
+
+        for case_insensitive in [false, true] {
+            for haystack in [&strings, &dictionary] {
+                assert_eq!(
+                    matched(case_insensitive, Nulls::Propagate, haystack, &needles),
+                    [Some(true), None, None, Some(false)]
+                );
+                assert_eq!(
+                    matched(case_insensitive, Nulls::AsNoMatch, haystack, &needles),
+                    [Some(true), Some(false), Some(false), Some(false)]
+                );
+                assert_eq!(
+                    matched(case_insensitive, Nulls::Propagate, haystack, &literal),
+                    [Some(true), None, Some(true), Some(false)]
+                );
+                assert_eq!(
+                    matched(case_insensitive, Nulls::AsNoMatch, haystack, &literal),
+                    [Some(true), Some(false), Some(true), Some(false)]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_not_match_udf() {
+        let haystack: ArrayRef =
+            Arc::new(StringArray::from(vec![Some("k8s pod"), Some("node"), None]));
+        let needle: ArrayRef = Arc::new(StringArray::from(vec!["k8s"; 3]));
+        let not_matched = |nulls| {
+            let result = not_match_impl(nulls)(&[
+                ColumnarValue::Array(haystack.clone()),
+                ColumnarValue::Array(needle.clone()),
+            ])
+            .unwrap()
+            .into_array(haystack.len());
+            let result = result.as_any().downcast_ref::<BooleanArray>().unwrap();
+            result.iter().collect::<Vec<_>>()
+        };
+        assert_eq!(
+            not_matched(Nulls::Propagate),
+            [Some(false), Some(true), None]
+        );
+        assert_eq!(
+            not_matched(Nulls::AsNoMatch),
+            [Some(false), Some(true), Some(true)]
+        );
+
+        let matched = |nulls| {
+            let result = match_expr_impl_with_nulls(false, nulls)(&[
+                ColumnarValue::Array(haystack.clone()),
+                ColumnarValue::Array(needle.clone()),
+            ])
+            .unwrap()
+            .into_array(haystack.len());
+            let result = result.as_any().downcast_ref::<BooleanArray>().unwrap();
+            result.iter().collect::<Vec<_>>()
+        };
+        assert_eq!(matched(Nulls::Propagate), [Some(true), Some(false), None]);
+        let literal = match_expr_impl(false)(&[
+            ColumnarValue::Array(haystack.clone()),
+            ColumnarValue::Scalar(ScalarValue::Utf8(Some("k8s".to_owned()))),
+        ])
+        .unwrap()
+        .into_array(haystack.len());
+        let literal = literal.as_any().downcast_ref::<BooleanArray>().unwrap();
+        assert_eq!(
+            literal.iter().collect::<Vec<_>>(),
+            [Some(true), Some(false), None]
+        );
+
+        // The finder of a literal needle is compiled once, and reused for
+        // later batches, with or without nulls.
+        let udf = match_expr_impl(false);
+        let built = FINDERS_BUILT.with(Cell::get);
+        let batches: [ArrayRef; 3] = [
+            Arc::new(StringArray::from(vec![Some("k8s pod"), None])),
+            Arc::new(StringArray::from(vec!["node", "k8s"])),
+            Arc::new(LargeStringArray::from(vec![None, Some("k8s")])),
+        ];
+        for batch in &batches {
+            let result = udf(&[
+                ColumnarValue::Array(batch.clone()),
+                ColumnarValue::Scalar(ScalarValue::Utf8(Some("k8s".to_owned()))),
+            ])
+            .unwrap()
+            .into_array(batch.len());
+            let result = result.as_any().downcast_ref::<BooleanArray>().unwrap();
+            assert_eq!(result.true_count(), 1);
+        }
+        assert_eq!(FINDERS_BUILT.with(Cell::get), built + 1);
+        assert_eq!(
+            matched(Nulls::AsNoMatch),
+            [Some(true), Some(false), Some(false)]
+        );
+    }
+
+    #[test]
+    fn test_token_match_udf() {
+        let values = [
+            "error_count=3",
+            "err: disk full",
+            "fatal err",
+            "GET /api/v1",
+            "xab ab ab",
+            "",
+        ];
+        let haystack: ArrayRef = Arc::new(StringArray::from(values.to_vec()));
+        let token_match = |term: &str| {
+            let result = token_match_impl()(&[
+                ColumnarValue::Array(haystack.clone()),
+                ColumnarValue::Array(Arc::new(StringArray::from(vec![term; values.len()]))),
+            ])
+            .unwrap()
+            .into_array(values.len());
+            let result = result.as_any().downcast_ref::<BooleanArray>().unwrap();
+            result.iter().map(Option::unwrap).collect::<Vec<_>>()
+        };
+        assert_eq!(token_match("err"), [false, true, true, false, false, false]);
+        assert_eq!(
+            token_match("GET /api"),
+            [false, false, false, true, false, false]
+        );
+        assert_eq!(
+            token_match("ab ab"),
+            [false, false, false, false, true, false]
+        );
+        assert_eq!(
+            token_match("/api/"),
+            [false, false, false, true, false, false]
+        );
+    }
+
+    #[test]
+    fn test_ip_in_cidr_udf() {
+        let values = vec![
+            Some("10.1.2.3"),
+            Some(" 192.168.0.1 "),
+            Some("fd00::1"),
+            Some("::ffff:10.0.0.1"),
+            Some("pod-7f9c"),
+            None,
+        ];
+        let haystack: ArrayRef = Arc::new(StringArray::from(values.clone()));
+        let ip_in_cidr = |cidr: &str| {
+            ip_in_cidr_impl()(&[
+                ColumnarValue::Array(haystack.clone()),
+                ColumnarValue::Array(Arc::new(StringArray::from(vec![cidr; values.len()]))),
+            ])
+            .map(|result| {
+                let result = result.into_array(values.len());
+                let result = result.as_any().downcast_ref::<BooleanArray>().unwrap();
+                result.iter().collect::<Vec<_>>()
+            })
+        };
+        let (t, f) = (Some(true), Some(false));
+        assert_eq!(ip_in_cidr("10.0.0.0/8").unwrap(), [t, f, f, t, f, None]);
+        assert_eq!(ip_in_cidr("192.168.0.1").unwrap(), [f, t, f, f, f, None]);
+        assert_eq!(ip_in_cidr("fd00::/8").unwrap(), [f, f, t, f, f, None]);
+        assert_eq!(ip_in_cidr("0.0.0.0/0").unwrap(), [t, t, f, t, f, None]);
+        assert!(ip_in_cidr("10.0.0.0/33").is_err());
+        assert!(ip_in_cidr("pod").is_err());
+    }
+
+    #[test]
+    fn test_kv_extract_udf() {
+        let values = vec![
+            Some(r#"level=info msg="pod \"web\" started" pod=web-1"#),
+            Some("level=warn mylevel=x"),
+            Some("xlevel=debug level=error msg=\"a b"),
+            Some("msg=none"),
+            None,
+        ];
+        let haystack: ArrayRef = Arc::new(StringArray::from(values.clone()));
+        let kv_extract = |key: &str| {
+            let result = kv_extract_impl()(&[
+                ColumnarValue::Array(haystack.clone()),
+                ColumnarValue::Array(Arc::new(StringArray::from(vec![key; values.len()]))),
+            ])
+            .unwrap()
+            .into_array(values.len());
+            let result = result.as_any().downcast_ref::<StringArray>().unwrap();
+            result
+                .iter()
+                .map(|value| value.map(str::to_owned))
+                .collect::<Vec<_>>()
+        };
+        let some = |s: &str| Some(s.to_owned());
+        assert_eq!(
+            kv_extract("level"),
+            [some("info"), some("warn"), some("error"), None, None]
+        );
+        assert_eq!(
+            kv_extract("msg"),
+            [
+                some(r#"pod "web" started"#),
+                None,
+                some("a b"),
+                some("none"),
+                None
+            ]
+        );
+        assert_eq!(kv_extract(""), [None, None, None, None, None]);
+    }
+
+    #[test]
+    fn test_phrase_match_udf() {
+        let values = [
+            "connection refused",
+            "connection to db:5432   refused",
+            "refused connection",
+            "reconnection refused",
+            "connection  refused, connection refused",
+        ];
+        let haystack: ArrayRef = Arc::new(StringArray::from(values.to_vec()));
+        let phrase_match = |phrase: &str, max_gap: Option<i64>| {
+            let mut args = vec![
+                ColumnarValue::Array(haystack.clone()),
+                ColumnarValue::Array(Arc::new(StringArray::from(vec![phrase; values.len()]))),
+            ];
+            if let Some(gap) = max_gap {
+                args.push(ColumnarValue::Array(Arc::new(Int64Array::from(vec![
+                    gap;
+                    values
+                        .len(
+                        )
+                ]))));
+            }
+            phrase_match_impl()(&args).map(|result| {
+                let result = result.into_array(values.len());
+                let result = result.as_any().downcast_ref::<BooleanArray>().unwrap();
+                result.iter().map(Option::unwrap).collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(
+            phrase_match("connection refused", None).unwrap(),
+            [true, true, false, false, true]
+        );
+        assert_eq!(
+            phrase_match(" connection   refused ", Some(1)).unwrap(),
+            [true, false, false, false, true]
+        );
+        assert_eq!(
+            phrase_match("connection refused", Some(3)).unwrap(),
+            [true, false, false, false, true]
+        );
+        assert_eq!(phrase_match("", None).unwrap(), [true; 5]);
+        assert!(phrase_match("refused", Some(-1)).is_err());
+    }
+
+    #[test]
+    fn test_near_match_udf() {
+        let values = vec![
+            Some("timeout after 30s, retrying upstream"),
+            Some("upstream: timeout"),
+            Some("timeout ............................................ upstream"),
+            Some("timeouts upstreams"),
+            None,
+        ];
+        let haystack: ArrayRef = Arc::new(StringArray::from(values.clone()));
+        let near_match = |a: &str, b: &str, max_dist: i64| {
+            near_match_impl()(&[
+                ColumnarValue::Array(haystack.clone()),
+                ColumnarValue::Array(Arc::new(StringArray::from(vec![a; values.len()]))),
+                ColumnarValue::Array(Arc::new(StringArray::from(vec![b; values.len()]))),
+                ColumnarValue::Array(Arc::new(Int64Array::from(vec![max_dist; values.len()]))),
+            ])
+            .map(|result| {
+                let result = result.into_array(values.len());
+                let result = result.as_any().downcast_ref::<BooleanArray>().unwrap();
+                result.iter().collect::<Vec<_>>()
+            })
+        };
+        let (t, f) = (Some(true), Some(false));
+        assert_eq!(
+            near_match("timeout", "upstream", 30).unwrap(),
+            [t, t, f, f, None]
+        );
+        assert_eq!(
+            near_match("upstream", "timeout", 2).unwrap(),
+            [f, t, f, f, None]
+        );
+        assert_eq!(
+            near_match("timeout", "upstream", 100).unwrap(),
+            [t, t, t, f, None]
+        );
+        assert!(near_match("timeout", "upstream", -1).is_err());
+    }
+
+    #[test]
+    fn test_glob_match_udf() {
+        let values = [
+            "app-web-prod",
+            "app--prod",
+            "app-web-staging",
+            "my-app-web-prod",
+            "app-é-prod",
+            "app-*-prod",
+            "",
+        ];
+        let haystack: ArrayRef = Arc::new(StringArray::from(values.to_vec()));
+        let glob_match = |pattern: &str| {
+            let result = glob_match_impl()(&[
+                ColumnarValue::Array(haystack.clone()),
+                ColumnarValue::Array(Arc::new(StringArray::from(vec![pattern; values.len()]))),
+            ])
+            .unwrap()
+            .into_array(values.len());
+            let result = result.as_any().downcast_ref::<BooleanArray>().unwrap();
+            result.iter().map(Option::unwrap).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            glob_match("app-*-prod"),
+            [true, true, false, false, true, true, false]
+        );
+        assert_eq!(
+            glob_match("app-?-prod"),
+            [false, false, false, false, true, true, false]
+        );
+        assert_eq!(
+            glob_match("*app*web*"),
+            [true, false, true, true, false, false, false]
+        );
+        assert_eq!(
+            glob_match("app-\\*-prod"),
+            [false, false, false, false, false, true, false]
+        );
+        assert_eq!(
+            glob_match("*-?????"),
+            [false, true, false, false, false, false, false]
+        );
+        assert_eq!(
+            glob_match("*-???????"),
+            [false, false, true, false, false, false, false]
+        );
+        assert_eq!(glob_match("*"), [true; 7]);
+        assert_eq!(
+            glob_match(""),
+            [false, false, false, false, false, false, true]
+        );
+    }
+
+    #[test]
+    fn test_regexp_extract_group_udf() {
+        let values = vec![
+            Some("GET /api status=200 took=12ms"),
+            Some("POST /login status=500"),
+            Some("GET /health"),
+            None,
+        ];
+        let haystack: ArrayRef = Arc::new(StringArray::from(values.clone()));
+        let extract = |pattern: &str, group: i64| {
+            regexp_extract_group_impl()(&[
+                ColumnarValue::Array(haystack.clone()),
+                ColumnarValue::Array(Arc::new(StringArray::from(vec![pattern; values.len()]))),
+                ColumnarValue::Array(Arc::new(Int64Array::from(vec![group; values.len()]))),
+            ])
+            .map(|result| {
+                let result = result.into_array(values.len());
+                let result = result.as_any().downcast_ref::<StringArray>().unwrap();
+                result
+                    .iter()
+                    .map(|v| v.map(str::to_owned))
+                    .collect::<Vec<_>>()
+            })
+        };
+        let some = |v: &str| Some(v.to_owned());
+        assert_eq!(
+            extract(r"status=(\d+)", 1).unwrap(),
+            [some("200"), some("500"), None, None]
+        );
+        assert_eq!(
+            extract(r"^(\w+) (\S+)", 0).unwrap(),
+            [
+                some("GET /api"),
+                some("POST /login"),
+                some("GET /health"),
+                None
+            ]
+        );
+        assert_eq!(
+            extract(r"^GET (\S+)(?: status=(\d+))?", 2).unwrap(),
+            [some("200"), None, None, None]
+        );
+        assert!(extract(r"status=(\d+)", 2).is_err());
+        assert!(extract(r"status=(\d+)", -1).is_err());
+        assert!(extract("(", 0).is_err());
+    }
+
+    #[test]
+    fn test_log_level_udf() {
+        let haystack: ArrayRef = Arc::new(StringArray::from(vec![
+            Some("2023-01-01T00:00:00Z INFO retrying after error"),
+            Some("[warning] disk 91% full"),
+            Some("level=error msg=\"connection refused\""),
+            Some("informational: error_count=0 debugging"),
+            Some("WARN"),
+            None,
+        ]));
+        let result = log_level_impl()(&[ColumnarValue::Array(haystack)])
+            .unwrap()
+            .into_array(6);
+        assert_eq!(
+            result.data_type(),
+            &DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8))
+        );
+        let levels = cast(&result, &DataType::Utf8).unwrap();
+        let levels = as_string_arg(&levels).unwrap();
+        assert_eq!(
+            levels.iter().collect::<Vec<_>>(),
+            [
+                Some("INFO"),
+                Some("WARN"),
+                Some("ERROR"),
+                None,
+                Some("WARN"),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_duration_udf() {
+        let haystack: ArrayRef = Arc::new(StringArray::from(vec![
+            Some("153ms"),
+            Some(" 2.5s "),
+            Some("1m30s"),
+            Some("250us"),
+            Some("12µs"),
+            Some("1500000ns"),
+            Some("1h"),
+            Some("153"),
+            Some("fast"),
+            Some("1.2.3s"),
+            Some("5 s"),
+            Some(""),
+            None,
+        ]));
+        let result = parse_duration_impl()(&[ColumnarValue::Array(haystack)])
+            .unwrap()
+            .into_array(13);
+        let result = result.as_any().downcast_ref::<Float64Array>().unwrap();
+        assert_eq!(
+            result.iter().collect::<Vec<_>>(),
+            [
+                Some(153.0),
+                Some(2500.0),
+                Some(90000.0),
+                Some(0.25),
+                Some(0.012),
+                Some(1.5),
+                Some(3600000.0),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn test_match_query_udf() {
+        let values = vec![
+            Some("error: connection timeout"),
+            Some("error: connection refused"),
+            Some("warn: connection timeout"),
+            None,
+        ];
+        let haystack: ArrayRef = Arc::new(StringArray::from(values.clone()));
+        let match_query = |query: &str| {
+            match_query_impl()(&[
+                ColumnarValue::Array(haystack.clone()),
+                ColumnarValue::Array(Arc::new(StringArray::from(vec![query; values.len()]))),
+            ])
+            .map(|result| {
+                let result = result.into_array(values.len());
+                let result = result.as_any().downcast_ref::<BooleanArray>().unwrap();
+                result.iter().collect::<Vec<_>>()
+            })
+        };
+        let (t, f) = (Some(true), Some(false));
+        assert_eq!(
+            match_query("error AND (timeout OR refused)").unwrap(),
+            [t, t, f, None]
+        );
+        assert_eq!(match_query("timeout -warn").unwrap(), [t, f, f, None]);
+        assert!(match_query("error AND (timeout").is_err());
+    }
+
+    #[test]
+    fn test_fuzzy_match_udf() {
+        let long = "x".repeat(70) + "payment-service";
+        let values = [
+            "GET payment-servcie/health",
+            "GET paymnt-service",
+            "GET shipping-service",
+            "pay",
+            long.as_str(),
+        ];
+        let haystack: ArrayRef = Arc::new(StringArray::from(values.to_vec()));
+        let fuzzy_match = |needle: &str, max_dist: i64| {
+            let result = fuzzy_match_impl()(&[
+                ColumnarValue::Array(haystack.clone()),
+                ColumnarValue::Array(Arc::new(StringArray::from(vec![needle; values.len()]))),
+                ColumnarValue::Array(Arc::new(Int64Array::from(vec![max_dist; values.len()]))),
+            ])
+            .unwrap()
+            .into_array(values.len());
+            let result = result.as_any().downcast_ref::<BooleanArray>().unwrap();
+            result.iter().map(Option::unwrap).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            fuzzy_match("payment-service", 0),
+            [false, false, false, false, true]
+        );
+        assert_eq!(
+            fuzzy_match("payment-service", 1),
+            [false, true, false, false, true]
+        );
+        assert_eq!(
+            fuzzy_match("payment-service", 2),
+            [true, true, false, false, true]
+        );
+        let needle = "x".repeat(60) + "payment-servcie";
+        assert_eq!(fuzzy_match(&needle, 1), [false; 5]);
+        assert_eq!(fuzzy_match(&needle, 2), [false, false, false, false, true]);
+    }
+
+    #[tokio::test]
+    async fn test_match_dictionary() {
+        let cities: DictionaryArray<Int32Type> =
+            vec![Some("NY"), None, Some("Pune"), Some("NY"), Some("SF")]
+                .into_iter()
+                .collect();
+        let cities: ArrayRef = Arc::new(cities);
+        let matched = |needle: StringArray| {
+            let result = match_expr_impl(false)(&[
+                ColumnarValue::Array(cities.clone()),
+                ColumnarValue::Array(Arc::new(needle)),
+            ])
+            .unwrap()
+            .into_array(cities.len());
+            let result = result.as_any().downcast_ref::<BooleanArray>().unwrap();
+            result.iter().collect::<Vec<_>>()
+        };
+        assert_eq!(
+            matched(StringArray::from(vec!["Y"; 5])),
+            [Some(true), None, Some(false), Some(true), Some(false)]
+        );
+        assert_eq!(
+            matched(StringArray::from(vec![
+                Some("Y"),
+                Some("Y"),
+                Some("P"),
+                None,
+                Some("S")
+            ])),
+            [Some(true), None, Some(true), None, Some(true)]
+        );
+
+        let large: ArrayRef = Arc::new(LargeStringArray::from(vec![Some("NY"), None]));
+        let result = match_expr_impl(false)(&[
+            ColumnarValue::Array(large),
+            ColumnarValue::Array(Arc::new(StringArray::from(vec!["Y"; 2]))),
+        ])
+        .unwrap()
+        .into_array(2);
+        let result = result.as_any().downcast_ref::<BooleanArray>().unwrap();
+        assert_eq!(result.iter().collect::<Vec<_>>(), [Some(true), None]);
+
+        let payloads: Vec<Option<&[u8]>> = vec![Some(b"\xffNY"), Some(b"\xfeny"), None];
+        let payloads: ArrayRef = Arc::new(BinaryArray::from(payloads));
+        let matched = |case_insensitive| {
+            let result = match_expr_impl(case_insensitive)(&[
+                ColumnarValue::Array(payloads.clone()),
+                ColumnarValue::Array(Arc::new(StringArray::from(vec!["NY"; 3]))),
+            ])
+            .unwrap()
+            .into_array(3);
+            let result = result.as_any().downcast_ref::<BooleanArray>().unwrap();
+            result.iter().collect::<Vec<_>>()
+        };
+        assert_eq!(matched(false), [Some(true), Some(false), None]);
+        assert_eq!(matched(true), [Some(true), Some(true), None]);
+
+        // The dictionary is passed as is rather than cast.
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "city",
+            cities.data_type().clone(),
+            true,
+        )]));
+        let batch = RecordBatch::try_new(schema.clone(), vec![cities.clone()]).unwrap();
+        let ctx = SessionContext::new();
+        ctx.register_udf(MATCH_UDF.clone());
+        let provider = MemTable::try_new(schema, vec![vec![batch]]).unwrap();
+        ctx.register_table("t", Arc::new(provider)).unwrap();
+        let df = ctx
+            .sql("select * from t where str_match(city, 'NY')")
+            .await
+            .unwrap();
+        let plan = format!("{:?}", df.clone().into_optimized_plan().unwrap());
+        assert!(!plan.contains("CAST"), "{plan}");
+        let result = df.collect().await.unwrap();
+        let count = result.iter().map(|batch| batch.num_rows()).sum::<usize>();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn test_match_ignore_case_udf() {
+        let haystack: ArrayRef = Arc::new(StringArray::from(vec![
+            Some("pod K8S-1 restarted"),
+            Some("k8"),
+            None,
+            Some("ÉCOLE k8s"),
+            Some("école"),
+        ]));
+        let needle: ArrayRef = Arc::new(StringArray::from(vec![
+            Some("k8s"),
+            Some("k8s"),
+            Some("k8s"),
+            Some("k8S"),
+            Some("ÉCOLE"),
+        ]));
+        let matched = |folding| {
+            let f = match_ignore_case_impl(folding);
+            let result = f(&[
+                ColumnarValue::Array(haystack.clone()),
+                ColumnarValue::Array(needle.clone()),
+            ])
+            .unwrap()
+            .into_array(haystack.len());
+            let result = result.as_any().downcast_ref::<BooleanArray>().unwrap();
+            result.iter().collect::<Vec<_>>()
+        };
+        assert_eq!(
+            matched(CaseFolding::Ascii),
+            [Some(true), Some(false), None, Some(true), Some(false)]
+        );
+        assert_eq!(
+            matched(CaseFolding::Unicode),
+            [Some(true), Some(false), None, Some(true), Some(true)]
+        );
+    }
 }
@@ -1,14 +1,58 @@
-use crate::ZnResult;
+use crate::{storage::RangeReader, ZnError, ZnResult};
+use arrow::datatypes::Schema;
 use parquet::{
+    arrow::parquet_to_arrow_schema,
     basic::Type as PhysicalType,
-    file::{footer, reader::ChunkReader},
+    file::{
+        footer::{decode_footer, decode_metadata},
+        metadata::ParquetMetaData,
+    },
 };
 use std::collections::HashMap;
 
+/// Length of the parquet footer: metadata length and magic.
+const FOOTER_SIZE: u64 = 8;
+
+/// Reads the metadata of a parquet file with two range reads: the footer, and
+/// then the metadata it points to.
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+pub fn read_metadata<R: RangeReader + ?Sized>(reader: &R) -> ZnResult<ParquetMetaData> {
+    let size = reader.size()?;
+    if size < FOOTER_SIZE {
+        return Err(ZnError::invalid_metadata(format!(
+            "file of {size} bytes is too small to be parquet"
+        )));
+    }
+    let footer = reader.read_range(size - FOOTER_SIZE..size)?;
+    let footer: &[u8; FOOTER_SIZE as usize] = footer[..]
+        .try_into()
+        .map_err(|_| ZnError::invalid_metadata("short footer"))?;
+    let metadata_len = decode_footer(footer)? as u64;
+    if metadata_len > size - FOOTER_SIZE {
+        return Err(ZnError::invalid_metadata(format!(
+            "metadata of {metadata_len} bytes is larger than the file"
+        )));
+    }
+    let metadata_start = size - FOOTER_SIZE - metadata_len;
+    let metadata = reader.read_range(metadata_start..size - FOOTER_SIZE)?;
+    Ok(decode_metadata(&metadata)?)
+}
+
+/// Returns the arrow schema of a parquet file, as the arrow reader decodes
+/// it; see [`read_metadata`].
+pub fn arrow_schema<R: RangeReader + ?Sized>(reader: &R) -> ZnResult<Schema> {
+    let metadata = read_metadata(reader)?;
+    let file_metadata = metadata.file_metadata();
+    Ok(parquet_to_arrow_schema(
+        file_metadata.schema_descr(),
+        file_metadata.key_value_metadata(),
+    )?)
+}
+
 /// Returns names and uncompressed data sizes (in bytes) of columns that are of
 /// [`PhysicalType::BYTE_ARRAY`] or [`PhysicalType::FIXED_LEN_BYTE_ARRAY`] type.
-pub fn text_columns<R: ChunkReader>(chunk_reader: &R) -> ZnResult<Vec<(String, u64)>> {
-    let metadata = footer::parse_metadata(chunk_reader)?;
+pub fn text_columns<R: RangeReader + ?Sized>(reader: &R) -> ZnResult<Vec<(String, u64)>> {
+    let metadata = read_metadata(reader)?;
 
     let mut col_sizes = HashMap::new();
     for row_group in metadata.row_groups() {
@@ -18,9 +62,12 @@ pub fn text_columns<R: ChunkReader>(chunk_reader: &R) -> ZnResult<Vec<(String, u
                 column_descr.physical_type(),
                 PhysicalType::BYTE_ARRAY | PhysicalType::FIXED_LEN_BYTE_ARRAY
             ) {
-                let size: i64 = column.uncompressed_size();
-                assert!(size >= 0);
-                let size = size as u64;
+                let size: u64 = column.uncompressed_size().try_into().map_err(|_| {
+                    ZnError::invalid_metadata(format!(
+                        "negative uncompressed size of column {:?}",
+                        column_descr.name()
+                    ))
+                })?;
                 col_sizes
                     .entry(column_descr.name().to_owned())
                     .and_modify(|n| *n += size)
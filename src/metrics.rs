@@ -0,0 +1,321 @@
+//! Search metrics
+//!
+//! The [`file`](crate::file), [`arrow`](crate::arrow), and
+//! [`datafusion`](crate::datafusion) search paths and the caches update a
+//! process-wide [`Registry`] of counters and latency histograms, so the
+//! performance of searches in production is observable without wrapping every
+//! call.  [`Registry::render`] formats the metrics in the Prometheus text
+//! exposition format, for serving on a `/metrics` endpoint:
+//!
+//! ```
+//! let text = zn_perf::metrics::registry().render();
+//! assert!(text.contains("zn_bytes_scanned_total"));
+//! ```
+
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// A monotonically increasing count.
+#[derive(Debug)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn inc_by(&self, n: u64) {
+        if n > 0 {
+            self.0.fetch_add(n, Ordering::Relaxed);
+        }
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Upper bounds of the latency buckets, in seconds.
+pub const LATENCY_BUCKETS: [f64; 10] =
+    [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+/// A distribution of durations over [`LATENCY_BUCKETS`].
+#[derive(Debug)]
+pub struct Histogram {
+    /// Observations per bucket, not cumulative; the last one is `+Inf`.
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; LATENCY_BUCKETS.len() + 1],
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        let i = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| secs <= bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.sum_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    /// Returns a guard observing the time until it is dropped.
+    pub fn start_timer(&self) -> Timer<'_> {
+        Timer {
+            histogram: self,
+            start: now(),
+        }
+    }
+
+    /// Number of observations.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).sum()
+    }
+
+    /// Sum of the observed durations.
+    pub fn sum(&self) -> Duration {
+        Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed))
+    }
+}
+
+/// Observes the time since [`Histogram::start_timer`] when dropped.
+#[must_use = "the timer observes when it is dropped"]
+pub struct Timer<'a> {
+    histogram: &'a Histogram,
+    start: Option<Instant>,
+}
+
+impl Drop for Timer<'_> {
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            self.histogram.observe(start.elapsed());
+        }
+    }
+}
+
+/// Returns the current time, or `None` on wasm32-unknown-unknown, where
+/// [`Instant::now`] panics for want of a clock.
+fn now() -> Option<Instant> {
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        None
+    } else {
+        Some(Instant::now())
+    }
+}
+
+/// The search path a query latency is recorded for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchPath {
+    /// [`crate::file`]
+    File,
+    /// [`crate::arrow`]
+    Arrow,
+    /// [`crate::datafusion`]
+    DataFusion,
+}
+
+impl SearchPath {
+    const ALL: [SearchPath; 3] = [SearchPath::File, SearchPath::Arrow, SearchPath::DataFusion];
+
+    fn label(self) -> &'static str {
+        match self {
+            SearchPath::File => "file",
+            SearchPath::Arrow => "arrow",
+            SearchPath::DataFusion => "datafusion",
+        }
+    }
+}
+
+/// A cache whose lookups are counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cache {
+    /// [`ChunkCache`](crate::cache::ChunkCache)
+    Chunk,
+    /// [`DiskCachedStore`](crate::disk_cache::DiskCachedStore)
+    Disk,
+    /// [`ColumnCache`](crate::column_cache::ColumnCache)
+    Column,
+}
+
+impl Cache {
+    const ALL: [Cache; 3] = [Cache::Chunk, Cache::Disk, Cache::Column];
+
+    fn label(self) -> &'static str {
+        match self {
+            Cache::Chunk => "chunk",
+            Cache::Disk => "disk",
+            Cache::Column => "column",
+        }
+    }
+}
+
+/// The metrics of the process; see [`registry`].
+#[derive(Debug)]
+pub struct Registry {
+    bytes_scanned: Counter,
+    rows_matched: Counter,
+    row_groups_pruned: Counter,
+    cache_hits: [Counter; Cache::ALL.len()],
+    cache_misses: [Counter; Cache::ALL.len()],
+    query_latency: [Histogram; SearchPath::ALL.len()],
+}
+
+static REGISTRY: Registry = Registry {
+    bytes_scanned: Counter::new(),
+    rows_matched: Counter::new(),
+    row_groups_pruned: Counter::new(),
+    cache_hits: [const { Counter::new() }; Cache::ALL.len()],
+    cache_misses: [const { Counter::new() }; Cache::ALL.len()],
+    query_latency: [const { Histogram::new() }; SearchPath::ALL.len()],
+};
+
+/// Returns the process-wide registry.
+pub fn registry() -> &'static Registry {
+    &REGISTRY
+}
+
+impl Registry {
+    /// Bytes of text values searched for a needle.
+    pub fn bytes_scanned(&self) -> &Counter {
+        &self.bytes_scanned
+    }
+
+    /// Rows containing a needle in some searched column.
+    pub fn rows_matched(&self) -> &Counter {
+        &self.rows_matched
+    }
+
+    /// Row groups skipped because an index ruled them out.
+    pub fn row_groups_pruned(&self) -> &Counter {
+        &self.row_groups_pruned
+    }
+
+    pub fn cache_hits(&self, cache: Cache) -> &Counter {
+        &self.cache_hits[cache as usize]
+    }
+
+    pub fn cache_misses(&self, cache: Cache) -> &Counter {
+        &self.cache_misses[cache as usize]
+    }
+
+    /// Durations of searches and DataFusion scans.
+    pub fn query_latency(&self, path: SearchPath) -> &Histogram {
+        &self.query_latency[path as usize]
+    }
+
+    /// Formats the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        counter(
+            &mut out,
+            "zn_bytes_scanned_total",
+            "Bytes of text values searched for a needle.",
+            &[("", &self.bytes_scanned)],
+        );
+        counter(
+            &mut out,
+            "zn_rows_matched_total",
+            "Rows containing a needle in some searched column.",
+            &[("", &self.rows_matched)],
+        );
+        counter(
+            &mut out,
+            "zn_row_groups_pruned_total",
+            "Row groups skipped because an index ruled them out.",
+            &[("", &self.row_groups_pruned)],
+        );
+        counter(
+            &mut out,
+            "zn_cache_hits_total",
+            "Cache lookups served from the cache.",
+            &Cache::ALL.map(|cache| (cache.label(), self.cache_hits(cache))),
+        );
+        counter(
+            &mut out,
+            "zn_cache_misses_total",
+            "Cache lookups that had to read through.",
+            &Cache::ALL.map(|cache| (cache.label(), self.cache_misses(cache))),
+        );
+
+        let name = "zn_query_latency_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Durations of searches and DataFusion scans."
+        );
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        for path in SearchPath::ALL {
+            let histogram = self.query_latency(path);
+            let label = path.label();
+            let mut cumulative = 0;
+            for (i, bucket) in histogram.buckets.iter().enumerate() {
+                cumulative += bucket.load(Ordering::Relaxed);
+                let le = LATENCY_BUCKETS
+                    .get(i)
+                    .map_or_else(|| "+Inf".to_owned(), |bound| bound.to_string());
+                let _ = writeln!(
+                    out,
+                    "{name}_bucket{{path=\"{label}\",le=\"{le}\"}} {cumulative}"
+                );
+            }
+            let sum = histogram.sum().as_secs_f64();
+            let _ = writeln!(out, "{name}_sum{{path=\"{label}\"}} {sum}");
+            let _ = writeln!(out, "{name}_count{{path=\"{label}\"}} {cumulative}");
+        }
+        out
+    }
+}
+
+/// Writes a counter with one sample per `(cache label, counter)`; an empty
+/// label writes an unlabeled sample.
+fn counter(out: &mut String, name: &str, help: &str, samples: &[(&str, &Counter)]) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} counter");
+    for (label, counter) in samples {
+        let value = counter.get();
+        if label.is_empty() {
+            let _ = writeln!(out, "{name} {value}");
+        } else {
+            let _ = writeln!(out, "{name}{{cache=\"{label}\"}} {value}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        // Other tests update the registry concurrently, so check local
+        // histograms and only lower bounds of the global counters.
+        let histogram = Histogram::new();
+        histogram.observe(Duration::from_millis(2));
+        histogram.observe(Duration::from_secs(10));
+        assert_eq!(histogram.count(), 2);
+        assert_eq!(histogram.buckets[1].load(Ordering::Relaxed), 1);
+        assert_eq!(
+            histogram.buckets[LATENCY_BUCKETS.len()].load(Ordering::Relaxed),
+            1
+        );
+        assert_eq!(histogram.sum(), Duration::from_millis(10_002));
+
+        registry().cache_hits(Cache::Disk).inc_by(3);
+        drop(registry().query_latency(SearchPath::Arrow).start_timer());
+        assert!(registry().cache_hits(Cache::Disk).get() >= 3);
+        let text = registry().render();
+        assert!(text.contains("# TYPE zn_cache_hits_total counter\n"));
+        assert!(text.contains("zn_cache_misses_total{cache=\"chunk\"} "));
+        assert!(text.contains("zn_query_latency_seconds_bucket{path=\"arrow\",le=\"+Inf\"} "));
+        assert!(!text.contains("zn_query_latency_seconds_count{path=\"arrow\"} 0\n"));
+    }
+}
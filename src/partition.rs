@@ -0,0 +1,250 @@
+//! Time-partitioned file layouts
+//!
+//! Log files are usually laid out in one directory per hour or day of the
+//! rows they hold, e.g. `logs/2023/01/31/12/*.parquet`.  A
+//! [`PartitionLayout`] resolves a query time range to the files of the
+//! partitions overlapping it, which then are searched with
+//! [`count_occurrences`](PartitionLayout::count_occurrences) or registered
+//! with DataFusion with `register`, with the `datafusion` feature.
+//!
+//! Timestamps are microseconds since the Unix epoch, in UTC.
+
+use crate::{file::count_occurrences_in_files, ZnError, ZnResult};
+use chrono::{Datelike, Duration, NaiveDateTime, Timelike};
+#[cfg(feature = "datafusion")]
+use datafusion::{
+    datasource::{
+        file_format::parquet::ParquetFormat,
+        listing::{ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl},
+    },
+    prelude::SessionContext,
+};
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+const PARQUET_EXTENSION: &str = "parquet";
+
+/// Time span of a partition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Granularity {
+    /// `root/YYYY/MM/DD/HH`
+    Hour,
+    /// `root/YYYY/MM/DD`
+    Day,
+}
+
+impl Granularity {
+    fn duration(self) -> Duration {
+        match self {
+            Granularity::Hour => Duration::hours(1),
+            Granularity::Day => Duration::days(1),
+        }
+    }
+
+    /// Start of the partition containing `t`.
+    fn truncate(self, t: NaiveDateTime) -> NaiveDateTime {
+        let date = t.date();
+        match self {
+            Granularity::Hour => date.and_hms_opt(t.hour(), 0, 0),
+            Granularity::Day => date.and_hms_opt(0, 0, 0),
+        }
+        .expect("the start of an hour is a valid time")
+    }
+}
+
+/// Parquet files partitioned by time into directories below a root.
+#[derive(Debug, Clone)]
+pub struct PartitionLayout {
+    root: PathBuf,
+    granularity: Granularity,
+}
+
+impl PartitionLayout {
+    pub fn new(root: impl Into<PathBuf>, granularity: Granularity) -> Self {
+        Self {
+            root: root.into(),
+            granularity,
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the directory of the partition holding rows of `timestamp`.
+    pub fn partition_dir(&self, timestamp: i64) -> ZnResult<PathBuf> {
+        Ok(self.dir_of(datetime(timestamp)?))
+    }
+
+    /// Returns the directories of the partitions overlapping the time
+    /// `range`, in time order, whether they exist or not.
+    pub fn partition_dirs(&self, range: Range<i64>) -> ZnResult<Vec<PathBuf>> {
+        if range.is_empty() {
+            return Ok(Vec::new());
+        }
+        let end = datetime(range.end)?;
+        let mut t = self.granularity.truncate(datetime(range.start)?);
+        let mut dirs = Vec::new();
+        while t < end {
+            dirs.push(self.dir_of(t));
+            t += self.granularity.duration();
+        }
+        Ok(dirs)
+    }
+
+    /// Returns the parquet files of the partitions overlapping the time
+    /// `range`, in time order and by name within a partition.
+    pub fn files(&self, range: Range<i64>) -> ZnResult<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for dir in self.partition_dirs(range)? {
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let mut partition_files = Vec::new();
+            for entry in entries {
+                let path = entry?.path();
+                if path.is_file() && path.extension().is_some_and(|ext| ext == PARQUET_EXTENSION) {
+                    partition_files.push(path);
+                }
+            }
+            partition_files.sort();
+            files.extend(partition_files);
+        }
+        Ok(files)
+    }
+
+    /// Counts the occurrences of the `needle` in the files of the time
+    /// `range`; see [`file::count_occurrences`](crate::file::count_occurrences).
+    pub fn count_occurrences(&self, range: Range<i64>, needle: &[u8]) -> ZnResult<usize> {
+        count_occurrences_in_files(&self.files(range)?, needle)
+    }
+
+    /// Registers the files of the time `range` as the table `table` of `ctx`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::InvalidArgument`] if there is no file in the range,
+    /// as the schema of the table can't be inferred then.
+    #[cfg(feature = "datafusion")]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, ctx), fields(root = ?self.root))
+    )]
+    pub async fn register(
+        &self,
+        ctx: &SessionContext,
+        table: &str,
+        range: Range<i64>,
+    ) -> ZnResult<()> {
+        let files = self.files(range.clone())?;
+        if files.is_empty() {
+            return Err(ZnError::invalid_argument(format!(
+                "no files for the time range {range:?}"
+            )));
+        }
+        let urls = files
+            .iter()
+            .map(|path| ListingTableUrl::parse(path.to_string_lossy()))
+            .collect::<Result<_, _>>()?;
+        let options = ListingOptions::new(std::sync::Arc::new(ParquetFormat::default()))
+            .with_file_extension(format!(".{PARQUET_EXTENSION}"));
+        let config = ListingTableConfig::new_with_multi_paths(urls)
+            .with_listing_options(options)
+            .infer_schema(&ctx.state())
+            .await?;
+        ctx.register_table(table, std::sync::Arc::new(ListingTable::try_new(config)?))?;
+        Ok(())
+    }
+
+    fn dir_of(&self, t: NaiveDateTime) -> PathBuf {
+        let mut dir = self
+            .root
+            .join(format!("{:04}/{:02}/{:02}", t.year(), t.month(), t.day()));
+        if self.granularity == Granularity::Hour {
+            dir.push(format!("{:02}", t.hour()));
+        }
+        dir
+    }
+}
+
+fn datetime(timestamp: i64) -> ZnResult<NaiveDateTime> {
+    let secs = timestamp.div_euclid(1_000_000);
+    let nanos = timestamp.rem_euclid(1_000_000) as u32 * 1000;
+    NaiveDateTime::from_timestamp_opt(secs, nanos)
+        .ok_or_else(|| ZnError::invalid_argument(format!("timestamp {timestamp} is out of range")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::parquet_bytes;
+
+    const HOUR: i64 = 3_600_000_000;
+
+    #[tokio::test]
+    async fn test_partition_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = PartitionLayout::new(dir.path(), Granularity::Hour);
+        // 2023-01-31T12:00:00Z
+        let noon = 1_675_166_400_000_000;
+        assert_eq!(
+            layout.partition_dir(noon + 1).unwrap(),
+            dir.path().join("2023/01/31/12")
+        );
+        assert_eq!(
+            layout.partition_dirs(noon - 1..noon + HOUR).unwrap(),
+            [
+                dir.path().join("2023/01/31/11"),
+                dir.path().join("2023/01/31/12")
+            ]
+        );
+
+        for (hour, logs) in [(0, ["k8s pod", "GET /"]), (1, ["k8s node", "POST /"])] {
+            let partition = layout.partition_dir(noon + hour * HOUR).unwrap();
+            std::fs::create_dir_all(&partition).unwrap();
+            std::fs::write(partition.join("1.parquet"), parquet_bytes(&logs, 1)).unwrap();
+            std::fs::write(partition.join("1.parquet.bloom"), b"").unwrap();
+        }
+        assert_eq!(layout.files(noon..noon + 2 * HOUR).unwrap().len(), 2);
+        assert_eq!(layout.files(noon + HOUR..noon + HOUR + 1).unwrap().len(), 1);
+        assert!(layout.files(noon - HOUR..noon).unwrap().is_empty());
+        assert_eq!(
+            PartitionLayout::new(dir.path(), Granularity::Day)
+                .partition_dirs(noon..noon + 1)
+                .unwrap(),
+            [dir.path().join("2023/01/31")]
+        );
+
+        assert_eq!(
+            layout
+                .count_occurrences(noon..noon + 2 * HOUR, b"k8s")
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            layout
+                .count_occurrences(noon + HOUR..noon + 2 * HOUR, b"k8s")
+                .unwrap(),
+            1
+        );
+
+        let ctx = SessionContext::new();
+        layout
+            .register(&ctx, "logs", noon..noon + 2 * HOUR)
+            .await
+            .unwrap();
+        let batches = ctx
+            .sql("select id from logs")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 4);
+        assert!(layout.register(&ctx, "none", 0..1).await.is_err());
+    }
+}
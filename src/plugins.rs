@@ -0,0 +1,204 @@
+//! Registry of user-defined matchers
+//!
+//! Applications register named [`Matcher`] factories at runtime, each
+//! building a matcher from a pattern, e.g. a tokenizer-aware matcher for
+//! their log format.  A registered matcher is then available by name to the
+//! [`file`](crate::file::count_matches) and
+//! [`arrow`](crate::arrow::count_matches) searches through [`matcher`], and,
+//! with the `datafusion` feature, to DataFusion as a UDF of the same name taking
+//! the text and the pattern, like [`str_match`](crate::match_udf):
+//!
+//! ```sql
+//! select * from logs where my_matcher(log, 'pattern')
+//! ```
+
+use crate::{str::Matcher, ZnError, ZnResult};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+/// Builds a matcher from a pattern.
+pub type MatcherFactory = Arc<dyn Fn(&str) -> ZnResult<Arc<dyn Matcher>> + Send + Sync>;
+
+static MATCHERS: RwLock<BTreeMap<String, MatcherFactory>> = RwLock::new(BTreeMap::new());
+
+/// Registers the `factory` under `name`, replacing the factory previously
+/// registered under it.
+pub fn register_matcher(name: impl Into<String>, factory: MatcherFactory) {
+    let mut matchers = MATCHERS.write().unwrap_or_else(|e| e.into_inner());
+    matchers.insert(name.into(), factory);
+}
+
+/// Removes the factory registered under `name`; returns `false` if there was
+/// none.
+pub fn unregister_matcher(name: &str) -> bool {
+    let mut matchers = MATCHERS.write().unwrap_or_else(|e| e.into_inner());
+    matchers.remove(name).is_some()
+}
+
+/// Returns the names of the registered matchers, in ascending order.
+pub fn matcher_names() -> Vec<String> {
+    let matchers = MATCHERS.read().unwrap_or_else(|e| e.into_inner());
+    matchers.keys().cloned().collect()
+}
+
+/// Builds the matcher registered under `name` for the `pattern`.
+///
+/// # Errors
+///
+/// Returns [`ZnError::InvalidArgument`] if no matcher is registered under
+/// `name`, and the errors of its factory.
+pub fn matcher(name: &str, pattern: &str) -> ZnResult<Arc<dyn Matcher>> {
+    let factory = MATCHERS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(name)
+        .cloned()
+        .ok_or_else(|| ZnError::invalid_argument(format!("no matcher {name:?} is registered")))?;
+    factory(pattern)
+}
+
+/// Returns the DataFusion UDF of the matcher registered under `name`, which
+/// returns null if the text or the pattern is null.
+///
+/// # Errors
+///
+/// Returns [`ZnError::InvalidArgument`] if no matcher is registered under
+/// `name`.
+#[cfg(feature = "datafusion")]
+pub fn udf(name: &str) -> ZnResult<datafusion::logical_expr::ScalarUDF> {
+    use crate::match_udf::as_string_arg;
+    use datafusion::{
+        arrow::{
+            array::{Array, ArrayRef, BooleanArray},
+            datatypes::DataType,
+        },
+        error::DataFusionError,
+        logical_expr::Volatility,
+        physical_plan::functions::make_scalar_function,
+        prelude::create_udf,
+    };
+
+    if !matcher_names().iter().any(|n| n == name) {
+        return Err(ZnError::invalid_argument(format!(
+            "no matcher {name:?} is registered"
+        )));
+    }
+    let matcher_name = name.to_owned();
+    let func = move |args: &[ArrayRef]| -> datafusion::error::Result<ArrayRef> {
+        if args.len() != 2 {
+            return Err(DataFusionError::Execution(format!(
+                "{matcher_name} expects the text and the pattern"
+            )));
+        }
+        let haystack = as_string_arg(&args[0])?;
+        let patterns = as_string_arg(&args[1])?;
+        // The pattern is typically a literal: build its matcher once.
+        let mut built: Option<(&str, Arc<dyn Matcher>)> = None;
+        let mut array = Vec::with_capacity(haystack.len());
+        for (haystack, pattern) in haystack.iter().zip(patterns.iter()) {
+            let (Some(haystack), Some(pattern)) = (haystack, pattern) else {
+                array.push(None);
+                continue;
+            };
+            let current = match &built {
+                Some((p, current)) if *p == pattern => current.clone(),
+                _ => {
+                    let new = matcher(&matcher_name, pattern)
+                        .map_err(|e| DataFusionError::External(Box::new(e)))?;
+                    built = Some((pattern, new.clone()));
+                    new
+                }
+            };
+            array.push(Some(current.is_match(haystack.as_bytes())));
+        }
+        Ok(Arc::new(BooleanArray::from(array)) as ArrayRef)
+    };
+    Ok(create_udf(
+        name,
+        vec![DataType::Utf8, DataType::Utf8],
+        Arc::new(DataType::Boolean),
+        Volatility::Stable,
+        make_scalar_function(func),
+    ))
+}
+
+/// Registers the [`udf`] of every registered matcher with `ctx`.
+#[cfg(feature = "datafusion")]
+pub fn register_udfs(ctx: &datafusion::prelude::SessionContext) -> ZnResult<()> {
+    for name in matcher_names() {
+        ctx.register_udf(udf(&name)?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        file,
+        str::{tokens, Substring},
+        test_util::{parquet_bytes, parquet_file},
+    };
+    use datafusion::{datasource::MemTable, prelude::SessionContext};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    /// Matches the values having the pattern as one of their tokens.
+    struct Token(Vec<u8>);
+
+    impl Matcher for Token {
+        fn is_match(&self, haystack: &[u8]) -> bool {
+            tokens(haystack).any(|t| t == self.0)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_plugins() {
+        register_matcher(
+            "test_token",
+            Arc::new(|pattern: &str| Ok(Arc::new(Token(pattern.into())) as Arc<dyn Matcher>)),
+        );
+        assert!(matcher_names().contains(&"test_token".to_owned()));
+        assert!(matcher("test_unknown", "x").is_err());
+
+        let logs = ["k8s pod", "k8spod", "pod k8s_x"];
+        let token = matcher("test_token", "pod").unwrap();
+        assert_eq!(
+            file::count_matches(&parquet_file(&logs, 2), &*token).unwrap(),
+            2
+        );
+        let reader = ParquetRecordBatchReaderBuilder::try_new(parquet_bytes(&logs, 2))
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(
+            crate::arrow::count_matches(reader, &Substring::new(b"pod")).unwrap(),
+            3
+        );
+
+        let ctx = SessionContext::new();
+        register_udfs(&ctx).unwrap();
+        let data = parquet_bytes(&logs, 2);
+        let reader = ParquetRecordBatchReaderBuilder::try_new(data).unwrap();
+        let schema = reader.schema().clone();
+        let batches = reader
+            .build()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let table = MemTable::try_new(schema, vec![batches]).unwrap();
+        ctx.register_table("t", Arc::new(table)).unwrap();
+        let batches = ctx
+            .sql("select id from t where test_token(log, 'k8s')")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 1);
+
+        assert!(unregister_matcher("test_token"));
+        assert!(udf("test_token").is_err());
+    }
+}
@@ -0,0 +1,188 @@
+//! Thread pool of the parallel scans
+//!
+//! The parallel searches, e.g.
+//! [`count_occurrences_in_files`](crate::file::count_occurrences_in_files),
+//! run on a single rayon pool.  By default it has a thread per
+//! [tuned](crate::tune) scan and is built when first used.  Applications
+//! embedding the crate size, name, and pin its threads with [`configure`], or
+//! share a pool of their own with [`set_pool`], so the crate never runs more
+//! scan threads than they allow.
+
+use crate::{tune, ZnError, ZnResult};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::sync::{Arc, RwLock};
+
+/// Settings of the scan pool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolOptions {
+    /// Number of threads; the [tuned](crate::tune) parallelism if 0.
+    pub threads: usize,
+    /// Prefix of the thread names, which are numbered from 0, e.g.
+    /// `zn-scan-0`.
+    pub thread_name: String,
+    /// Pins the `i`-th thread to the `i`-th core, modulo the core count.
+    /// Only supported on Linux; ignored elsewhere.
+    pub pin_threads: bool,
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        Self {
+            threads: 0,
+            thread_name: "zn-scan".to_owned(),
+            pin_threads: false,
+        }
+    }
+}
+
+impl PoolOptions {
+    /// Builds a pool with these settings.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::InvalidArgument`] if the threads cannot be spawned.
+    pub fn build(&self) -> ZnResult<ThreadPool> {
+        let threads = match self.threads {
+            0 => tune::tuning().parallelism.max(1),
+            n => n,
+        };
+        let name = self.thread_name.clone();
+        let mut builder = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(move |i| format!("{name}-{i}"));
+        if self.pin_threads {
+            builder = builder.start_handler(pin_to_core);
+        }
+        builder.build().map_err(|e| {
+            ZnError::invalid_argument(format!("cannot build the scan thread pool: {e}"))
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn pin_to_core(thread: usize) {
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    // SAFETY: `set` is a plain bit set, initialized by zeroing it.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(thread % cores, &mut set);
+        // Pinning is an optimization: the thread still runs if it fails.
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_to_core(_thread: usize) {}
+
+static POOL: RwLock<Option<Arc<ThreadPool>>> = RwLock::new(None);
+
+/// Builds a pool with the `options` and installs it, replacing the
+/// previously installed one.  Scans running on the previous pool finish
+/// there.
+pub fn configure(options: &PoolOptions) -> ZnResult<()> {
+    set_pool(Arc::new(options.build()?));
+    Ok(())
+}
+
+/// Installs the `pool`, e.g. one shared with the application, replacing the
+/// previously installed one.
+pub fn set_pool(pool: Arc<ThreadPool>) {
+    *POOL.write().unwrap_or_else(|e| e.into_inner()) = Some(pool);
+}
+
+/// Removes the installed pool; the next scan builds a default one.
+pub fn clear_pool() {
+    *POOL.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Returns the installed pool, building and installing a default one if
+/// there is none.
+///
+/// # Errors
+///
+/// Returns [`ZnError::InvalidArgument`] if the default pool cannot be built.
+pub fn pool() -> ZnResult<Arc<ThreadPool>> {
+    if let Some(pool) = POOL.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        return Ok(pool.clone());
+    }
+    let mut installed = POOL.write().unwrap_or_else(|e| e.into_inner());
+    match installed.as_ref() {
+        Some(pool) => Ok(pool.clone()),
+        None => {
+            let pool = Arc::new(PoolOptions::default().build()?);
+            *installed = Some(pool.clone());
+            Ok(pool)
+        }
+    }
+}
+
+/// Runs `op` on the [`pool`], so that the rayon iterators it uses are
+/// scheduled on the pool's threads.
+pub fn install<R: Send>(op: impl FnOnce() -> R + Send) -> ZnResult<R> {
+    Ok(pool()?.install(op))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::parquet_bytes;
+
+    #[test]
+    fn test_pool() {
+        let options = PoolOptions {
+            threads: 2,
+            thread_name: "zn-test".to_owned(),
+            pin_threads: true,
+        };
+        let pool = Arc::new(options.build().unwrap());
+        let (threads, name) = pool.install(|| {
+            (
+                rayon::current_num_threads(),
+                std::thread::current().name().map(str::to_owned),
+            )
+        });
+        assert_eq!(threads, 2);
+        assert!(name.unwrap().starts_with("zn-test-"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let files: Vec<_> = (0..3)
+            .map(|i| {
+                let path = dir.path().join(format!("{i}.parquet"));
+                std::fs::write(&path, parquet_bytes(&["k8s pod", "node", "k8s"], 2)).unwrap();
+                path
+            })
+            .collect();
+        set_pool(pool.clone());
+        assert!(Arc::ptr_eq(&super::pool().unwrap(), &pool));
+        assert_eq!(
+            crate::file::count_occurrences_in_files(&files, b"k8s").unwrap(),
+            6
+        );
+        clear_pool();
+        assert!(!Arc::ptr_eq(&super::pool().unwrap(), &pool));
+    }
+
+    #[test]
+    fn test_file_count_occurrences_parallel() {
+        use crate::file::{count_occurrences_parallel, open_bytes, ScanUnit};
+        use parquet::file::reader::FileReader;
+
+        let mut logs = vec!["k8s pod", "node", "k8s", "vm"];
+        logs.extend(["k8s k8s"; 20]);
+        let file = open_bytes(parquet_bytes(&logs, 3)).unwrap();
+        assert!(file.num_row_groups() > 1);
+        for unit in [ScanUnit::RowGroup, ScanUnit::ColumnChunk] {
+            for threads in [0, 1, 4] {
+                assert_eq!(
+                    count_occurrences_parallel(&file, b"k8s", threads, unit).unwrap(),
+                    22
+                );
+                assert_eq!(
+                    count_occurrences_parallel(&file, b"pod", threads, unit).unwrap(),
+                    1
+                );
+            }
+        }
+        assert!(count_occurrences_parallel(&file, b"", 0, ScanUnit::RowGroup).is_err());
+    }
+}
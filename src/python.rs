@@ -0,0 +1,110 @@
+//! Python bindings
+//!
+//! Exposes the search paths to Python as the `zn_perf` extension module:
+//!
+//! ```python
+//! import zn_perf
+//! zn_perf.count_occurrences("logs.parquet", "us-west-2")
+//! zn_perf.text_columns("logs.parquet")
+//! session = zn_perf.Session(batch_size=8192)
+//! session.register_parquet("logs", "logs.parquet")
+//! batches = session.sql("select count(*) from logs")  # pyarrow.RecordBatch
+//! ```
+//!
+//! Record batches are handed over through the Arrow C data interface, without
+//! copying.  The GIL is released while searching.
+//!
+//! Only available with the `python` feature; build the module with
+//! `maturin build --features python,pyo3/extension-module`.
+
+use crate::{datafusion::new_session_context, redact, ZnError};
+use arrow::pyarrow::PyArrowConvert;
+use datafusion::prelude::{ParquetReadOptions, SessionContext};
+use pyo3::{
+    exceptions::{PyOSError, PyRuntimeError, PyValueError},
+    prelude::*,
+};
+use std::{fs::File, sync::Arc};
+use tokio::runtime::Runtime;
+
+fn py_err(e: impl Into<ZnError>) -> PyErr {
+    match e.into() {
+        e @ (ZnError::EmptyNeedle | ZnError::InvalidArgument(_)) => {
+            PyValueError::new_err(e.to_string())
+        }
+        e @ ZnError::Io(_) => PyOSError::new_err(e.to_string()),
+        e => PyRuntimeError::new_err(e.to_string()),
+    }
+}
+
+/// Counts the cells of the byte array columns of the parquet file at `path`
+/// that contain `needle`; see [`crate::file::count_occurrences`].
+#[pyfunction]
+fn count_occurrences(py: Python<'_>, path: &str, needle: &str) -> PyResult<usize> {
+    py.allow_threads(|| {
+        let file = crate::file::open(Arc::new(File::open(path)?))?;
+        crate::file::count_occurrences(&file, needle.as_bytes())
+    })
+    .map_err(py_err)
+}
+
+/// Returns `(name, uncompressed size)` of the text columns of the parquet
+/// file at `path`; see [`crate::metadata::text_columns`].
+#[pyfunction]
+fn text_columns(py: Python<'_>, path: &str) -> PyResult<Vec<(String, u64)>> {
+    py.allow_threads(|| crate::metadata::text_columns(&File::open(path)?))
+        .map_err(py_err)
+}
+
+/// A DataFusion session running SQL over registered parquet files.
+#[pyclass]
+struct Session {
+    ctx: SessionContext,
+    runtime: Runtime,
+}
+
+#[pymethods]
+impl Session {
+    #[new]
+    #[args(batch_size = "8192", optimized = "false")]
+    fn new(batch_size: usize, optimized: bool) -> PyResult<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .map_err(py_err)?;
+        Ok(Self {
+            ctx: new_session_context(batch_size, optimized),
+            runtime,
+        })
+    }
+
+    /// Registers the parquet file or directory at `path` as the table `name`.
+    fn register_parquet(&self, py: Python<'_>, name: &str, path: &str) -> PyResult<()> {
+        py.allow_threads(|| {
+            self.runtime.block_on(self.ctx.register_parquet(
+                name,
+                path,
+                ParquetReadOptions::default(),
+            ))
+        })
+        .map_err(py_err)
+    }
+
+    /// Runs the SQL `query` and returns the result as `pyarrow.RecordBatch`es.
+    fn sql(&self, py: Python<'_>, query: &str) -> PyResult<Vec<PyObject>> {
+        let batches = py
+            .allow_threads(|| {
+                self.runtime
+                    .block_on(async { redact::collect(self.ctx.sql(query).await?).await })
+            })
+            .map_err(py_err)?;
+        batches.iter().map(|batch| batch.to_pyarrow(py)).collect()
+    }
+}
+
+#[pymodule]
+fn zn_perf(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(count_occurrences, m)?)?;
+    m.add_function(wrap_pyfunction!(text_columns, m)?)?;
+    m.add_class::<Session>()?;
+    Ok(())
+}
@@ -0,0 +1,811 @@
+//! Log search query language
+//!
+//! A Lucene-like syntax for searching logs, parsed by [`parse`] into a
+//! [`Query`], which then turns into a DataFusion [`Expr`] with
+//! [`Query::to_expr`] or into a [`ScanPlan`] for the [`file`](crate::file)
+//! API with [`Query::into_scan_plan`]:
+//!
+//! | Syntax                        | Matches rows …                                   |
+//! |-------------------------------|--------------------------------------------------|
+//! | `k8s`                         | containing `k8s` in some default column          |
+//! | `"GET /api"`                  | containing the phrase                            |
+//! | `level:error`                 | whose `level` contains `error`                   |
+//! | `host:(web-1 OR web-2)`       | whose `host` contains `web-1` or `web-2`         |
+//! | `pod*-7?`                     | matching the wildcards somewhere in a column     |
+//! | `status:[500 TO 599]`         | with `status` in the range, bounds included      |
+//! | `status:{400 TO *}`           | with `status` above 400                          |
+//! | `host:*`                      | with a `host`                                    |
+//! | `a AND b`, `a b`, `a && b`    | matching both                                    |
+//! | `a OR b`, `a \|\| b`          | matching either                                  |
+//! | `NOT a`, `-a`, `!a`           | not matching                                     |
+//!
+//! Terms and phrases match anywhere in a cell, like
+//! [`count_occurrences`](crate::file::count_occurrences), rather than whole
+//! tokens.  `AND` binds tighter than `OR`; a backslash escapes the next
+//! character.
+
+use crate::{
+    file::byte_array_columns, index::RowGroupPruner, match_udf::MATCH_UDF, ZnError, ZnResult,
+};
+use datafusion::{
+    common::Column,
+    logical_expr::{expr::BinaryExpr, Operator},
+    prelude::{lit, Expr},
+};
+use memchr::memmem;
+use parquet::{
+    file::reader::FileReader,
+    record::{Field, Row},
+};
+use std::{borrow::Cow, cmp::Ordering, ops::Bound};
+
+/// Part of a wildcard pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WildcardPiece {
+    Literal(String),
+    /// `*`
+    AnyString,
+    /// `?`
+    AnyChar,
+}
+
+/// A parsed query.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    /// Every row (`*`).
+    All,
+    /// A term or phrase, in `field` or any default column.
+    Match {
+        field: Option<String>,
+        text: String,
+    },
+    /// A term with wildcards, in `field` or any default column.
+    Wildcard {
+        field: Option<String>,
+        pattern: Vec<WildcardPiece>,
+    },
+    /// A range of the values of `field`, compared as numbers if both bounds
+    /// are numbers and as strings otherwise.
+    Range {
+        field: String,
+        low: Bound<String>,
+        high: Bound<String>,
+    },
+    /// Rows with a value in `field` (`field:*`).
+    Exists {
+        field: String,
+    },
+    And(Vec<Query>),
+    Or(Vec<Query>),
+    Not(Box<Query>),
+}
+
+/// Parses a query.
+///
+/// # Errors
+///
+/// Returns [`ZnError::InvalidArgument`] describing the first syntax error.
+pub fn parse(input: &str) -> ZnResult<Query> {
+    let tokens = lex(input)?;
+    let mut parser = Parser { tokens, pos: 0 };
+    if parser.tokens.is_empty() {
+        return Err(ZnError::invalid_argument("empty query"));
+    }
+    let query = parser.parse_or(None)?;
+    match parser.peek() {
+        None => Ok(query),
+        Some(token) => Err(ZnError::invalid_argument(format!(
+            "unexpected {token:?} in query"
+        ))),
+    }
+}
+
+impl Query {
+    /// Converts the query into a DataFusion filter; unfielded terms search
+    /// the `default_columns`.  Nulls match no term, so that `NOT` selects
+    /// the rows a [`ScanPlan`] selects.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::InvalidArgument`] if the query has an unfielded
+    /// term but there are no `default_columns`.
+    pub fn to_expr(&self, default_columns: &[&str]) -> ZnResult<Expr> {
+        let columns = |field: &Option<String>| -> ZnResult<Vec<Expr>> {
+            match field {
+                Some(field) => Ok(vec![column(field)]),
+                None if default_columns.is_empty() => Err(ZnError::invalid_argument(
+                    "unfielded term but no default columns",
+                )),
+                None => Ok(default_columns.iter().map(|c| column(c)).collect()),
+            }
+        };
+        Ok(match self {
+            Query::All => lit(true),
+            Query::Match { field, text } => any(columns(field)?
+                .into_iter()
+                .map(|c| MATCH_UDF.call(vec![c, lit(text.as_str())]).is_true())),
+            Query::Wildcard { field, pattern } => {
+                let regex = lit(wildcard_regex(pattern));
+                any(columns(field)?.into_iter().map(|c| {
+                    Expr::BinaryExpr(BinaryExpr::new(
+                        Box::new(c),
+                        Operator::RegexMatch,
+                        Box::new(regex.clone()),
+                    ))
+                    .is_true()
+                }))
+            }
+            Query::Range { field, low, high } => {
+                let numeric = bounds_numeric(low, high);
+                let bound = |value: &String| match numeric {
+                    true => lit(value.parse::<f64>().expect("checked by bounds_numeric")),
+                    false => lit(value.as_str()),
+                };
+                let mut conditions = Vec::new();
+                match low {
+                    Bound::Included(v) => conditions.push(column(field).gt_eq(bound(v))),
+                    Bound::Excluded(v) => conditions.push(column(field).gt(bound(v))),
+                    Bound::Unbounded => (),
+                }
+                match high {
+                    Bound::Included(v) => conditions.push(column(field).lt_eq(bound(v))),
+                    Bound::Excluded(v) => conditions.push(column(field).lt(bound(v))),
+                    Bound::Unbounded => (),
+                }
+                if conditions.is_empty() {
+                    column(field).is_not_null()
+                } else {
+                    all(conditions.into_iter()).is_true()
+                }
+            }
+            Query::Exists { field } => column(field).is_not_null(),
+            Query::And(queries) => all(queries
+                .iter()
+                .map(|q| q.to_expr(default_columns))
+                .collect::<ZnResult<Vec<_>>>()?
+                .into_iter()),
+            Query::Or(queries) => any(queries
+                .iter()
+                .map(|q| q.to_expr(default_columns))
+                .collect::<ZnResult<Vec<_>>>()?
+                .into_iter()),
+            Query::Not(query) => query.to_expr(default_columns)?.not(),
+        })
+    }
+
+    /// Plans a row-by-row scan evaluating the query; unfielded terms search
+    /// the byte array columns, like
+    /// [`count_occurrences`](crate::file::count_occurrences).
+    pub fn into_scan_plan(self) -> ScanPlan {
+        let mut needles = Vec::new();
+        self.required_needles(&mut needles);
+        ScanPlan {
+            query: self,
+            needles,
+        }
+    }
+
+    /// Collects substrings every matching row contains.
+    fn required_needles(&self, needles: &mut Vec<Vec<u8>>) {
+        match self {
+            Query::Match { text, .. } => needles.push(text.as_bytes().to_vec()),
+            Query::Wildcard { pattern, .. } => {
+                needles.extend(pattern.iter().filter_map(|piece| match piece {
+                    WildcardPiece::Literal(s) => Some(s.as_bytes().to_vec()),
+                    WildcardPiece::AnyString | WildcardPiece::AnyChar => None,
+                }))
+            }
+            Query::And(queries) => queries.iter().for_each(|q| q.required_needles(needles)),
+            Query::All
+            | Query::Range { .. }
+            | Query::Exists { .. }
+            | Query::Or(_)
+            | Query::Not(_) => (),
+        }
+    }
+
+    fn matches<'a>(&'a self, row: &'a Row, searched: &'a [String]) -> bool {
+        let cells = |field: Option<&'a str>| cells(row, field, searched);
+        match self {
+            Query::All => true,
+            Query::Match { field, text } => cells(field.as_deref())
+                .filter_map(text_of)
+                .any(|s| memmem::find(s.as_bytes(), text.as_bytes()).is_some()),
+            Query::Wildcard { field, pattern } => cells(field.as_deref())
+                .filter_map(text_of)
+                .any(|s| wildcard_matches(pattern, &s)),
+            Query::Range { field, low, high } => {
+                let numeric = bounds_numeric(low, high);
+                cells(Some(field)).any(|value| {
+                    let above = |bound: &String| compare(value, bound, numeric);
+                    let low_ok = match low {
+                        Bound::Included(v) => above(v).is_some_and(|o| o.is_ge()),
+                        Bound::Excluded(v) => above(v).is_some_and(|o| o.is_gt()),
+                        Bound::Unbounded => !matches!(value, Field::Null),
+                    };
+                    let high_ok = match high {
+                        Bound::Included(v) => above(v).is_some_and(|o| o.is_le()),
+                        Bound::Excluded(v) => above(v).is_some_and(|o| o.is_lt()),
+                        Bound::Unbounded => !matches!(value, Field::Null),
+                    };
+                    low_ok && high_ok
+                })
+            }
+            Query::Exists { field } => {
+                cells(Some(field)).any(|value| !matches!(value, Field::Null))
+            }
+            Query::And(queries) => queries.iter().all(|q| q.matches(row, searched)),
+            Query::Or(queries) => queries.iter().any(|q| q.matches(row, searched)),
+            Query::Not(query) => !query.matches(row, searched),
+        }
+    }
+}
+
+/// A [`Query`] evaluated row by row over parquet files.
+#[derive(Debug, Clone)]
+pub struct ScanPlan {
+    query: Query,
+    needles: Vec<Vec<u8>>,
+}
+
+impl ScanPlan {
+    pub fn query(&self) -> &Query {
+        &self.query
+    }
+
+    /// Substrings every matching row contains, for pruning row groups.
+    pub fn needles(&self) -> &[Vec<u8>] {
+        &self.needles
+    }
+
+    /// Counts the rows of `file` that match the query.
+    pub fn count_rows<R: FileReader>(&self, file: &R) -> ZnResult<usize> {
+        self.count_rows_in(file, 0..file.num_row_groups())
+    }
+
+    /// Like [`count_rows`](Self::count_rows), but only scans the row groups
+    /// the `index` reports as candidates for all [needles](Self::needles).
+    pub fn count_rows_with_index<R: FileReader, P: RowGroupPruner>(
+        &self,
+        file: &R,
+        index: &P,
+    ) -> ZnResult<usize> {
+        if index.num_row_groups() != file.num_row_groups() {
+            return Err(ZnError::invalid_index(format!(
+                "index covers {} row group(s), file has {}",
+                index.num_row_groups(),
+                file.num_row_groups()
+            )));
+        }
+        let mut candidates: Vec<_> = (0..file.num_row_groups()).collect();
+        for needle in self.needles.iter().filter(|n| !n.is_empty()) {
+            let matching = index.candidates(needle);
+            candidates.retain(|i| matching.binary_search(i).is_ok());
+        }
+        self.count_rows_in(file, candidates)
+    }
+
+    fn count_rows_in<R: FileReader>(
+        &self,
+        file: &R,
+        row_groups: impl IntoIterator<Item = usize>,
+    ) -> ZnResult<usize> {
+        let searched: Vec<_> = byte_array_columns(file.metadata())?
+            .get_fields()
+            .iter()
+            .map(|t| t.name().to_owned())
+            .collect();
+        let mut count = 0;
+        for i in row_groups {
+            for row in file.get_row_group(i)?.get_row_iter(None)? {
+                count += usize::from(self.query.matches(&row, &searched));
+            }
+        }
+        Ok(count)
+    }
+}
+
+/// Returns the cells of `field`, or of the `searched` columns for no field.
+fn cells<'a>(
+    row: &'a Row,
+    field: Option<&'a str>,
+    searched: &'a [String],
+) -> impl Iterator<Item = &'a Field> {
+    row.get_column_iter()
+        .filter(move |(name, _)| match field {
+            Some(field) => *name == field,
+            None => searched.contains(name),
+        })
+        .map(|(_, value)| value)
+}
+
+fn column(name: &str) -> Expr {
+    // Not `col`, which would take dots for qualifiers.
+    Expr::Column(Column::from_name(name))
+}
+
+fn any(exprs: impl Iterator<Item = Expr>) -> Expr {
+    exprs.reduce(Expr::or).unwrap_or_else(|| lit(false))
+}
+
+fn all(exprs: impl Iterator<Item = Expr>) -> Expr {
+    exprs.reduce(Expr::and).unwrap_or_else(|| lit(true))
+}
+
+fn is_number(bound: &Bound<String>) -> bool {
+    match bound {
+        Bound::Included(v) | Bound::Excluded(v) => v.parse::<f64>().is_ok(),
+        Bound::Unbounded => true,
+    }
+}
+
+fn bounds_numeric(low: &Bound<String>, high: &Bound<String>) -> bool {
+    is_number(low) && is_number(high)
+}
+
+/// Compares a cell with a bound, or returns `None` if they are incomparable.
+fn compare(value: &Field, bound: &str, numeric: bool) -> Option<Ordering> {
+    if numeric {
+        let bound: f64 = bound.parse().ok()?;
+        let value = match *value {
+            Field::Byte(v) => v.into(),
+            Field::Short(v) => v.into(),
+            Field::Int(v) => v.into(),
+            Field::Long(v) => v as f64,
+            Field::UByte(v) => v.into(),
+            Field::UShort(v) => v.into(),
+            Field::UInt(v) => v.into(),
+            Field::ULong(v) => v as f64,
+            Field::Float(v) => v.into(),
+            Field::Double(v) => v,
+            Field::TimestampMillis(v) | Field::TimestampMicros(v) => v as f64,
+            _ => text_of(value)?.parse().ok()?,
+        };
+        value.partial_cmp(&bound)
+    } else {
+        Some(text_of(value)?.as_ref().cmp(bound))
+    }
+}
+
+/// Returns the text a term is searched in.
+fn text_of(value: &Field) -> Option<Cow<'_, str>> {
+    match value {
+        Field::Str(s) => Some(Cow::Borrowed(s)),
+        Field::Bytes(b) => Some(String::from_utf8_lossy(b.data())),
+        Field::Null | Field::Group(_) | Field::ListInternal(_) | Field::MapInternal(_) => None,
+        value => Some(Cow::Owned(value.to_string())),
+    }
+}
+
+fn wildcard_regex(pattern: &[WildcardPiece]) -> String {
+    let mut regex = String::new();
+    for piece in pattern {
+        match piece {
+            WildcardPiece::Literal(s) => {
+                for c in s.chars() {
+                    if "\\.+*?()|[]{}^$#&-~".contains(c) {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+            }
+            WildcardPiece::AnyString => regex.push_str(".*"),
+            WildcardPiece::AnyChar => regex.push('.'),
+        }
+    }
+    regex
+}
+
+/// Whether the `pattern` matches some substring of `s`.
+fn wildcard_matches(pattern: &[WildcardPiece], s: &str) -> bool {
+    let chars: Vec<char> = s.chars().collect();
+    (0..=chars.len()).any(|start| matches_at(pattern, &chars[start..]))
+}
+
+/// Whether the `pattern` matches a prefix of `s`.
+fn matches_at(pattern: &[WildcardPiece], s: &[char]) -> bool {
+    let Some((piece, rest)) = pattern.split_first() else {
+        return true;
+    };
+    match piece {
+        WildcardPiece::Literal(lit) => {
+            let lit: Vec<char> = lit.chars().collect();
+            s.starts_with(&lit) && matches_at(rest, &s[lit.len()..])
+        }
+        WildcardPiece::AnyChar => !s.is_empty() && matches_at(rest, &s[1..]),
+        WildcardPiece::AnyString => (0..=s.len()).any(|skip| matches_at(rest, &s[skip..])),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Colon,
+    Minus,
+    And,
+    Or,
+    Not,
+    Quoted(String),
+    Word(Vec<WildcardPiece>),
+}
+
+fn lex(input: &str) -> ZnResult<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        let single = match c {
+            '(' => Some(Token::LParen),
+            ')' => Some(Token::RParen),
+            '[' => Some(Token::LBracket),
+            ']' => Some(Token::RBracket),
+            '{' => Some(Token::LBrace),
+            '}' => Some(Token::RBrace),
+            ':' => Some(Token::Colon),
+            '-' | '!' => Some(if c == '-' { Token::Minus } else { Token::Not }),
+            _ => None,
+        };
+        if c.is_whitespace() {
+            chars.next();
+        } else if let Some(token) = single {
+            chars.next();
+            tokens.push(token);
+        } else if c == '"' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    None => return Err(ZnError::invalid_argument("unterminated phrase in query")),
+                    Some('"') => break,
+                    Some('\\') => text.extend(chars.next()),
+                    Some(c) => text.push(c),
+                }
+            }
+            tokens.push(Token::Quoted(text));
+        } else {
+            let mut pieces = Vec::new();
+            let mut literal = String::new();
+            let mut raw = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || "()[]{}\":".contains(c) {
+                    break;
+                }
+                chars.next();
+                raw.push(c);
+                match c {
+                    '\\' => literal.extend(chars.next()),
+                    '*' | '?' => {
+                        if !literal.is_empty() {
+                            pieces.push(WildcardPiece::Literal(std::mem::take(&mut literal)));
+                        }
+                        pieces.push(match c {
+                            '*' => WildcardPiece::AnyString,
+                            _ => WildcardPiece::AnyChar,
+                        });
+                    }
+                    c => literal.push(c),
+                }
+            }
+            if !literal.is_empty() {
+                pieces.push(WildcardPiece::Literal(literal));
+            }
+            tokens.push(match raw.as_str() {
+                "AND" | "&&" => Token::And,
+                "OR" | "||" => Token::Or,
+                "NOT" => Token::Not,
+                _ => Token::Word(pieces),
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect_more(&mut self) -> ZnResult<Token> {
+        self.next()
+            .ok_or_else(|| ZnError::invalid_argument("unexpected end of query"))
+    }
+
+    fn parse_or(&mut self, field: Option<&str>) -> ZnResult<Query> {
+        let mut queries = vec![self.parse_and(field)?];
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            queries.push(self.parse_and(field)?);
+        }
+        Ok(flatten(queries, Query::Or))
+    }
+
+    fn parse_and(&mut self, field: Option<&str>) -> ZnResult<Query> {
+        let mut queries = vec![self.parse_unary(field)?];
+        loop {
+            match self.peek() {
+                Some(Token::And) => {
+                    self.next();
+                }
+                None | Some(Token::Or | Token::RParen) => break,
+                Some(_) => (), // implicit AND
+            }
+            queries.push(self.parse_unary(field)?);
+        }
+        Ok(flatten(queries, Query::And))
+    }
+
+    fn parse_unary(&mut self, field: Option<&str>) -> ZnResult<Query> {
+        if matches!(self.peek(), Some(Token::Not | Token::Minus)) {
+            self.next();
+            return Ok(Query::Not(Box::new(self.parse_unary(field)?)));
+        }
+        self.parse_primary(field)
+    }
+
+    fn parse_primary(&mut self, field: Option<&str>) -> ZnResult<Query> {
+        match self.expect_more()? {
+            Token::LParen => {
+                let query = self.parse_or(field)?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(query),
+                    _ => Err(ZnError::invalid_argument("unclosed parenthesis in query")),
+                }
+            }
+            Token::Quoted(text) => Ok(Query::Match {
+                field: field.map(str::to_owned),
+                text,
+            }),
+            Token::Word(pieces) if self.peek() == Some(&Token::Colon) => {
+                let name = literal(&pieces)
+                    .ok_or_else(|| ZnError::invalid_argument("wildcard in field name"))?;
+                if field.is_some() {
+                    return Err(ZnError::invalid_argument(format!(
+                        "nested field {name:?} in query"
+                    )));
+                }
+                self.next();
+                self.parse_fielded(name)
+            }
+            Token::Word(pieces) => Ok(term(field, pieces)),
+            token @ (Token::LBracket | Token::LBrace) => match field {
+                Some(field) => self.parse_range(field, token == Token::LBracket),
+                None => Err(ZnError::invalid_argument("range without a field in query")),
+            },
+            token => Err(ZnError::invalid_argument(format!(
+                "unexpected {token:?} in query"
+            ))),
+        }
+    }
+
+    fn parse_fielded(&mut self, field: String) -> ZnResult<Query> {
+        match self.peek() {
+            Some(Token::Word(pieces)) if pieces == &[WildcardPiece::AnyString] => {
+                self.next();
+                Ok(Query::Exists { field })
+            }
+            _ => self.parse_primary(Some(&field)),
+        }
+    }
+
+    fn parse_range(&mut self, field: &str, inclusive_low: bool) -> ZnResult<Query> {
+        let low = self.parse_bound(inclusive_low)?;
+        match self.expect_more()? {
+            Token::Word(pieces) if literal(&pieces).as_deref() == Some("TO") => (),
+            token => {
+                return Err(ZnError::invalid_argument(format!(
+                    "expected TO in range, found {token:?}"
+                )))
+            }
+        }
+        let high_token = self.tokens.get(self.pos + 1).cloned();
+        let inclusive_high = match high_token {
+            Some(Token::RBracket) => true,
+            Some(Token::RBrace) => false,
+            // A negative upper bound.
+            _ if self.peek() == Some(&Token::Minus) => {
+                matches!(self.tokens.get(self.pos + 2), Some(Token::RBracket))
+            }
+            _ => return Err(ZnError::invalid_argument("unclosed range in query")),
+        };
+        let high = self.parse_bound(inclusive_high)?;
+        match self.next() {
+            Some(Token::RBracket | Token::RBrace) => Ok(Query::Range {
+                field: field.to_owned(),
+                low,
+                high,
+            }),
+            _ => Err(ZnError::invalid_argument("unclosed range in query")),
+        }
+    }
+
+    fn parse_bound(&mut self, inclusive: bool) -> ZnResult<Bound<String>> {
+        let negative = self.peek() == Some(&Token::Minus);
+        if negative {
+            self.next();
+        }
+        let value = match self.expect_more()? {
+            Token::Quoted(text) => text,
+            Token::Word(pieces) if pieces == [WildcardPiece::AnyString] && !negative => {
+                return Ok(Bound::Unbounded)
+            }
+            Token::Word(pieces) => literal(&pieces)
+                .ok_or_else(|| ZnError::invalid_argument("wildcard in range bound"))?,
+            token => {
+                return Err(ZnError::invalid_argument(format!(
+                    "unexpected {token:?} in range"
+                )))
+            }
+        };
+        let value = if negative { format!("-{value}") } else { value };
+        Ok(match inclusive {
+            true => Bound::Included(value),
+            false => Bound::Excluded(value),
+        })
+    }
+}
+
+fn flatten(mut queries: Vec<Query>, combine: fn(Vec<Query>) -> Query) -> Query {
+    if queries.len() == 1 {
+        queries.pop().expect("one query")
+    } else {
+        combine(queries)
+    }
+}
+
+/// Returns the word if it has no wildcards.
+fn literal(pieces: &[WildcardPiece]) -> Option<String> {
+    match pieces {
+        [WildcardPiece::Literal(s)] => Some(s.clone()),
+        _ => None,
+    }
+}
+
+fn term(field: Option<&str>, pieces: Vec<WildcardPiece>) -> Query {
+    let field = field.map(str::to_owned);
+    match literal(&pieces) {
+        Some(text) => Query::Match { field, text },
+        None if pieces == [WildcardPiece::AnyString] && field.is_none() => Query::All,
+        None => Query::Wildcard {
+            field,
+            pattern: pieces,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{datafusion::new_session_context, index::TrigramIndex};
+    use arrow::{
+        array::{Int64Array, StringArray},
+        datatypes::{DataType, Schema},
+        record_batch::RecordBatch,
+    };
+    use arrow_schema::Field as ArrowField;
+    use bytes::Bytes;
+    use parquet::{arrow::ArrowWriter, file::serialized_reader::SerializedFileReader};
+    use std::sync::Arc;
+
+    fn logs() -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![
+            ArrowField::new("log", DataType::Utf8, true),
+            ArrowField::new("level", DataType::Utf8, true),
+            ArrowField::new("status", DataType::Int64, true),
+        ]));
+        RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(vec![
+                    Some("GET /api k8s pod-7a"),
+                    Some("GET /index.html"),
+                    Some("POST /api"),
+                    None,
+                ])),
+                Arc::new(StringArray::from(vec![
+                    Some("info"),
+                    Some("error"),
+                    None,
+                    Some("error"),
+                ])),
+                Arc::new(Int64Array::from(vec![
+                    Some(200),
+                    Some(404),
+                    Some(503),
+                    None,
+                ])),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_query() {
+        assert_eq!(
+            parse(r#"level:error AND NOT "GET /" OR status:[500 TO *]"#).unwrap(),
+            Query::Or(vec![
+                Query::And(vec![
+                    Query::Match {
+                        field: Some("level".to_owned()),
+                        text: "error".to_owned()
+                    },
+                    Query::Not(Box::new(Query::Match {
+                        field: None,
+                        text: "GET /".to_owned()
+                    })),
+                ]),
+                Query::Range {
+                    field: "status".to_owned(),
+                    low: Bound::Included("500".to_owned()),
+                    high: Bound::Unbounded,
+                },
+            ])
+        );
+        for bad in ["", "(a", "a:", "[1 TO 2]", "s:[1 2]", "\"a", "a OR"] {
+            assert!(parse(bad).is_err(), "{bad:?}");
+        }
+
+        let batch = logs();
+        let mut buf = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut buf, batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        let file = SerializedFileReader::new(Bytes::from(buf)).unwrap();
+        let index = TrigramIndex::build(&file).unwrap();
+
+        let ctx = new_session_context(8192, false);
+        ctx.register_batch("logs", batch).unwrap();
+        let cases = [
+            ("api", 2),
+            ("api -k8s", 1),
+            ("level:(info OR error)", 3),
+            ("pod-?a", 1),
+            ("pod*a", 1),
+            ("status:[404 TO 503}", 1),
+            ("status:{-1 TO 404]", 2),
+            ("level:* && !log:*", 1),
+            ("*", 4),
+            (r#""/index.html" OR post"#, 1),
+        ];
+        for (query, expected) in cases {
+            let query = parse(query).unwrap();
+            let expr = query.to_expr(&["log", "level"]).unwrap();
+            let rows: usize = ctx
+                .table("logs")
+                .await
+                .unwrap()
+                .filter(expr)
+                .unwrap()
+                .collect()
+                .await
+                .unwrap()
+                .iter()
+                .map(|b| b.num_rows())
+                .sum();
+            assert_eq!(rows, expected, "{query:?}");
+
+            let plan = query.into_scan_plan();
+            assert_eq!(plan.count_rows(&file).unwrap(), expected, "{plan:?}");
+            assert_eq!(
+                plan.count_rows_with_index(&file, &index).unwrap(),
+                expected,
+                "{plan:?}"
+            );
+        }
+        assert!(parse("k8s").unwrap().to_expr(&[]).is_err());
+    }
+}
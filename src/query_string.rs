@@ -0,0 +1,250 @@
+//! Boolean text queries in a small Lucene-like syntax
+//!
+//! [`QueryString::parse`] turns a query like `error AND (timeout OR
+//! refused)` into a tree of [terms](Term) that is evaluated against each
+//! value in one pass, e.g. by
+//! [`match_query`](crate::match_udf::MATCH_QUERY_UDF), so simple boolean
+//! text queries don't have to be rewritten into SQL by clients.
+
+use crate::{
+    str::{Matcher, Term},
+    ZnError, ZnResult,
+};
+
+#[derive(Debug, Clone)]
+enum Node {
+    Term(Term),
+    Not(Box<Node>),
+    And(Vec<Node>),
+    Or(Vec<Node>),
+}
+
+impl Node {
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        match self {
+            Node::Term(term) => term.is_match(haystack),
+            Node::Not(node) => !node.is_match(haystack),
+            Node::And(nodes) => nodes.iter().all(|node| node.is_match(haystack)),
+            Node::Or(nodes) => nodes.iter().any(|node| node.is_match(haystack)),
+        }
+    }
+}
+
+/// A parsed boolean text query.
+#[derive(Debug, Clone)]
+pub struct QueryString {
+    root: Node,
+}
+
+impl QueryString {
+    /// Parses a query of whole-token [terms](Term), e.g. `timeout`, and
+    /// quoted terms spanning several tokens, e.g. `"connection refused"`,
+    /// combined with `AND`, `OR`, `NOT` or a leading `-`, and parentheses.
+    /// Terms next to each other are implicitly joined with `AND`, which
+    /// binds tighter than `OR`; operators are only recognized in upper case.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::InvalidArgument`] if the query is empty or
+    /// malformed.
+    pub fn parse(query: &str) -> ZnResult<Self> {
+        let invalid = || ZnError::invalid_argument(format!("invalid query: {query}"));
+        let tokens = lex(query).ok_or_else(invalid)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+        };
+        let root = parser.or().ok_or_else(invalid)?;
+        if parser.pos != tokens.len() {
+            return Err(invalid());
+        }
+        Ok(Self { root })
+    }
+}
+
+impl Matcher for QueryString {
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        self.root.is_match(haystack)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Term(String),
+}
+
+/// Splits `query` into tokens, or returns `None` if a quote is not closed.
+fn lex(query: &str) -> Option<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '-' if chars.peek().is_some_and(|c| !c.is_whitespace()) => tokens.push(Token::Not),
+            '"' => {
+                let mut term = String::new();
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => term.push(chars.next()?),
+                        c => term.push(c),
+                    }
+                }
+                tokens.push(Token::Term(term));
+            }
+            c => {
+                let mut word = String::from(c);
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '(' | ')' | '"') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(match word.as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => Token::Term(word),
+                });
+            }
+        }
+    }
+    Some(tokens)
+}
+
+/// A recursive descent parser returning `None` on syntax errors.
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let found = self.peek() == Some(token);
+        self.pos += usize::from(found);
+        found
+    }
+
+    fn or(&mut self) -> Option<Node> {
+        let mut nodes = vec![self.and()?];
+        while self.eat(&Token::Or) {
+            nodes.push(self.and()?);
+        }
+        Some(match nodes.len() {
+            1 => nodes.pop().unwrap(),
+            _ => Node::Or(nodes),
+        })
+    }
+
+    fn and(&mut self) -> Option<Node> {
+        let mut nodes = vec![self.unary()?];
+        loop {
+            match self.peek() {
+                Some(Token::And) => self.pos += 1,
+                Some(Token::Not | Token::Open | Token::Term(_)) => {}
+                _ => break,
+            }
+            nodes.push(self.unary()?);
+        }
+        Some(match nodes.len() {
+            1 => nodes.pop().unwrap(),
+            _ => Node::And(nodes),
+        })
+    }
+
+    fn unary(&mut self) -> Option<Node> {
+        let node = match self.tokens.get(self.pos)? {
+            Token::Not => {
+                self.pos += 1;
+                return Some(Node::Not(Box::new(self.unary()?)));
+            }
+            Token::Open => {
+                self.pos += 1;
+                let node = self.or()?;
+                return self.eat(&Token::Close).then_some(node);
+            }
+            Token::Term(term) if !term.is_empty() => Node::Term(Term::new(term.as_bytes())),
+            _ => return None,
+        };
+        self.pos += 1;
+        Some(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_string() {
+        let values = [
+            "error: connection timeout",
+            "error: connection refused by upstream",
+            "warn: connection timeout",
+            "error_count=3 timeout",
+            "ERROR: disk full",
+        ];
+        let matches = |query: &str| {
+            let query = QueryString::parse(query).unwrap();
+            values
+                .iter()
+                .map(|value| query.is_match(value.as_bytes()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            matches("error AND (timeout OR refused)"),
+            [true, true, false, false, false]
+        );
+        assert_eq!(
+            matches("error (timeout OR refused)"),
+            [true, true, false, false, false]
+        );
+        assert_eq!(
+            matches("error timeout OR warn"),
+            [true, false, true, false, false]
+        );
+        assert_eq!(matches("timeout -error"), [false, false, true, true, false]);
+        assert_eq!(
+            matches("NOT error AND NOT warn"),
+            [false, false, false, true, true]
+        );
+        assert_eq!(
+            matches("\"connection refused\""),
+            [false, true, false, false, false]
+        );
+        assert_eq!(
+            matches("\"disk full\" OR ERROR"),
+            [false, false, false, false, true]
+        );
+        assert_eq!(matches("error and"), [false; 5]);
+        assert_eq!(matches("error_count=3"), [false, false, false, true, false]);
+
+        for query in [
+            "",
+            "  ",
+            "(error",
+            "error)",
+            "error AND",
+            "OR error",
+            "NOT",
+            "\"error",
+            "\"\"",
+            "()",
+        ] {
+            assert!(QueryString::parse(query).is_err(), "{query}");
+        }
+    }
+}
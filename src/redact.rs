@@ -0,0 +1,314 @@
+//! Redaction of sensitive data in search results
+//!
+//! A [`RedactionPolicy`] drops or masks sensitive columns, and masks
+//! sensitive [`Pattern`]s like email addresses or bearer tokens in the other
+//! text columns.  Once installed with [`set_policy`], it applies to the
+//! [`Hit`](crate::results::Hit)s of the [`results`](crate::results) searches,
+//! the rows of the Flight server's filter searches, the results of
+//! `fulltext::hybrid_sql`, and DataFrames run with [`collect`], so consumers
+//! of these APIs never see the raw values.
+//!
+//! Redaction only changes what is returned: searches still match, count,
+//! and aggregate the raw values.
+
+use crate::ZnResult;
+use arrow::{
+    array::{Array, ArrayRef, StringArray},
+    datatypes::{DataType, Field, Schema},
+    record_batch::{RecordBatch, RecordBatchOptions},
+};
+use arrow_array::cast::as_string_array;
+use memchr::memmem;
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    ops::Range,
+    sync::{Arc, RwLock},
+};
+
+/// What a [`RedactionPolicy`] does with a whole column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnRule {
+    /// Removes the column from the results.
+    Drop,
+    /// Replaces every non-null value with the mask, as text.
+    Mask,
+}
+
+/// Sensitive text masked wherever it occurs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pattern {
+    /// Email addresses like `alice@example.com`.
+    Email,
+    /// The value following the prefix, up to the next whitespace, quote,
+    /// `,`, `;`, or `&`, e.g. the token of `Bearer ` or `token=`.
+    Prefixed(String),
+}
+
+/// Columns and patterns to redact; see the [module docs](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedactionPolicy {
+    columns: BTreeMap<String, ColumnRule>,
+    patterns: Vec<Pattern>,
+    mask: String,
+}
+
+impl Default for RedactionPolicy {
+    /// A policy redacting nothing, masking with `***`.
+    fn default() -> Self {
+        Self {
+            columns: BTreeMap::new(),
+            patterns: Vec::new(),
+            mask: "***".to_owned(),
+        }
+    }
+}
+
+impl RedactionPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Removes the column `name` from the results.
+    pub fn drop_column(mut self, name: impl Into<String>) -> Self {
+        self.columns.insert(name.into(), ColumnRule::Drop);
+        self
+    }
+
+    /// Masks every value of the column `name`.
+    pub fn mask_column(mut self, name: impl Into<String>) -> Self {
+        self.columns.insert(name.into(), ColumnRule::Mask);
+        self
+    }
+
+    /// Masks the `pattern` in the text columns without a [`ColumnRule`].
+    pub fn mask_pattern(mut self, pattern: Pattern) -> Self {
+        self.patterns.push(pattern);
+        self
+    }
+
+    /// Replaces the masked text with `mask` instead of `***`.
+    pub fn with_mask(mut self, mask: impl Into<String>) -> Self {
+        self.mask = mask.into();
+        self
+    }
+
+    pub fn mask(&self) -> &str {
+        &self.mask
+    }
+
+    pub fn column_rule(&self, name: &str) -> Option<ColumnRule> {
+        self.columns.get(name).copied()
+    }
+
+    /// Returns `true` if the policy redacts nothing.
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty() && self.patterns.is_empty()
+    }
+
+    /// Masks the [patterns](Pattern) of the policy in `text`.
+    pub fn redact_text<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut spans = Vec::new();
+        for pattern in &self.patterns {
+            match pattern {
+                Pattern::Email => email_spans(text.as_bytes(), &mut spans),
+                Pattern::Prefixed(prefix) => prefixed_spans(text.as_bytes(), prefix, &mut spans),
+            }
+        }
+        if spans.is_empty() {
+            return Cow::Borrowed(text);
+        }
+        spans.sort_unstable_by_key(|span| span.start);
+        let mut redacted = String::with_capacity(text.len());
+        let mut pos = 0;
+        for span in spans {
+            if span.end <= pos {
+                continue;
+            }
+            if span.start >= pos {
+                redacted.push_str(&text[pos..span.start]);
+                redacted.push_str(&self.mask);
+            }
+            // An overlapping span extends the previous mask.
+            pos = span.end;
+        }
+        redacted.push_str(&text[pos..]);
+        Cow::Owned(redacted)
+    }
+
+    /// Redacts the `value` of the column `name`, as text; `None` if the
+    /// column is dropped.
+    pub fn redact_value<'a>(&'a self, name: &str, value: &'a str) -> Option<Cow<'a, str>> {
+        match self.column_rule(name) {
+            Some(ColumnRule::Drop) => None,
+            Some(ColumnRule::Mask) => Some(Cow::Borrowed(&self.mask)),
+            None => Some(self.redact_text(value)),
+        }
+    }
+
+    /// Redacts the columns of the `batch`: drops and masks whole columns,
+    /// masked ones becoming [`DataType::Utf8`], and masks the patterns in
+    /// the other [`DataType::Utf8`] columns.
+    pub fn redact_batch(&self, batch: &RecordBatch) -> ZnResult<RecordBatch> {
+        if self.is_empty() {
+            return Ok(batch.clone());
+        }
+        let schema = batch.schema();
+        let mut fields = Vec::with_capacity(schema.fields().len());
+        let mut columns = Vec::with_capacity(schema.fields().len());
+        for (field, array) in schema.fields().iter().zip(batch.columns()) {
+            let redacted: ArrayRef = match self.column_rule(field.name()) {
+                Some(ColumnRule::Drop) => continue,
+                Some(ColumnRule::Mask) => {
+                    let masked: StringArray = (0..array.len())
+                        .map(|i| array.is_valid(i).then_some(self.mask.as_str()))
+                        .collect();
+                    fields.push(Field::new(
+                        field.name(),
+                        DataType::Utf8,
+                        field.is_nullable(),
+                    ));
+                    columns.push(Arc::new(masked) as ArrayRef);
+                    continue;
+                }
+                None if field.data_type() == &DataType::Utf8 && !self.patterns.is_empty() => {
+                    let redacted: StringArray = as_string_array(array)
+                        .iter()
+                        .map(|value| value.map(|v| self.redact_text(v)))
+                        .collect();
+                    Arc::new(redacted)
+                }
+                None => array.clone(),
+            };
+            fields.push(field.clone());
+            columns.push(redacted);
+        }
+        let schema = Schema::new_with_metadata(fields, schema.metadata().clone());
+        let options = RecordBatchOptions::new().with_row_count(Some(batch.num_rows()));
+        Ok(RecordBatch::try_new_with_options(
+            Arc::new(schema),
+            columns,
+            &options,
+        )?)
+    }
+}
+
+/// Adds the spans of the email addresses of `text`: a local part of
+/// alphanumerics and `._%+-`, and a domain of alphanumerics, `.` and `-`
+/// with at least one inner `.`.
+fn email_spans(text: &[u8], spans: &mut Vec<Range<usize>>) {
+    let is_local = |b: u8| b.is_ascii_alphanumeric() || b"._%+-".contains(&b);
+    let is_domain = |b: u8| b.is_ascii_alphanumeric() || b".-".contains(&b);
+    for at in memchr::memchr_iter(b'@', text) {
+        let start = text[..at]
+            .iter()
+            .rposition(|&b| !is_local(b))
+            .map_or(0, |i| i + 1);
+        let mut end = text[at + 1..]
+            .iter()
+            .position(|&b| !is_domain(b))
+            .map_or(text.len(), |i| at + 1 + i);
+        // Not the period ending a sentence.
+        while end > at + 1 && text[end - 1] == b'.' {
+            end -= 1;
+        }
+        let domain = &text[at + 1..end];
+        if start < at && domain.first() != Some(&b'.') && domain.contains(&b'.') {
+            spans.push(start..end);
+        }
+    }
+}
+
+/// Adds the spans of the values following `prefix` in `text`.
+fn prefixed_spans(text: &[u8], prefix: &str, spans: &mut Vec<Range<usize>>) {
+    if prefix.is_empty() {
+        return;
+    }
+    for found in memmem::find_iter(text, prefix.as_bytes()) {
+        let start = found + prefix.len();
+        let end = text[start..]
+            .iter()
+            .position(|&b| b.is_ascii_whitespace() || b"\"',;&".contains(&b))
+            .map_or(text.len(), |i| start + i);
+        if end > start {
+            spans.push(start..end);
+        }
+    }
+}
+
+static POLICY: RwLock<Option<Arc<RedactionPolicy>>> = RwLock::new(None);
+
+/// Installs a process-wide policy, replacing the previously installed one.
+pub fn set_policy(policy: Arc<RedactionPolicy>) {
+    *POLICY.write().unwrap_or_else(|e| e.into_inner()) = Some(policy);
+}
+
+/// Removes the policy installed with [`set_policy`].
+pub fn clear_policy() {
+    *POLICY.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Returns the installed policy, if it redacts anything.
+pub fn policy() -> Option<Arc<RedactionPolicy>> {
+    POLICY
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .filter(|policy| !policy.is_empty())
+}
+
+/// Runs the DataFrame `df` and returns its batches, redacted by the
+/// installed policy.
+#[cfg(feature = "datafusion")]
+pub async fn collect(df: datafusion::dataframe::DataFrame) -> ZnResult<Vec<RecordBatch>> {
+    let batches = df.collect().await?;
+    match policy() {
+        Some(policy) => batches.iter().map(|b| policy.redact_batch(b)).collect(),
+        None => Ok(batches),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::Int64Array;
+
+    #[test]
+    fn test_redaction() {
+        let policy = RedactionPolicy::new()
+            .drop_column("password")
+            .mask_column("id")
+            .mask_pattern(Pattern::Email)
+            .mask_pattern(Pattern::Prefixed("Bearer ".to_owned()));
+        assert_eq!(
+            policy.redact_text("mail alice.b@example.com. Bearer abc123, x@y"),
+            "mail ***. Bearer ***, x@y"
+        );
+        assert_eq!(policy.redact_text("no PII"), "no PII");
+        assert_eq!(policy.redact_value("password", "hunter2"), None);
+        assert_eq!(policy.redact_value("id", "1").unwrap(), "***");
+
+        let schema = Schema::new(vec![
+            Field::new("log", DataType::Utf8, true),
+            Field::new("id", DataType::Int64, true),
+            Field::new("password", DataType::Utf8, true),
+        ]);
+        let batch = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(StringArray::from(vec![Some("to bob@example.org"), None])),
+                Arc::new(Int64Array::from(vec![Some(1), None])),
+                Arc::new(StringArray::from(vec!["a", "b"])),
+            ],
+        )
+        .unwrap();
+        let redacted = policy.redact_batch(&batch).unwrap();
+        let schema = redacted.schema();
+        let names: Vec<_> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+        assert_eq!(names, ["log", "id"]);
+        let log = as_string_array(redacted.column(0));
+        assert_eq!((log.value(0), log.is_null(1)), ("to ***", true));
+        let id = as_string_array(redacted.column(1));
+        assert_eq!((id.value(0), id.is_null(1)), ("***", true));
+    }
+}
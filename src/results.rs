@@ -0,0 +1,640 @@
+//! Search results of all search paths
+//!
+//! [`search_file`], [`search_arrow`] and [`search_datafusion`] answer the
+//! same search, the rows containing a needle in some text column, each with
+//! its own engine, and return the same [`SearchResult`]: the number of
+//! matching rows, the first [`SearchOptions::max_hits`] of them as [`Hit`]s
+//! with a snippet around the match, and the [`SearchStats`] of the scan.
+//! Consumers get one shape regardless of which engine answered.
+//! [`stream_file`] and [`stream_datafusion`] stream the hits instead, as the
+//! scan finds them.
+//!
+//! The hits are redacted by the installed [`RedactionPolicy`], and the
+//! searches abort once their [`SearchOptions::cancel`] token is cancelled.
+
+use crate::{
+    cancel::CancelToken,
+    file::{byte_array_value, is_byte_array},
+    match_udf::match_all_columns,
+    metrics::{registry, SearchPath, Timer},
+    redact::{self, RedactionPolicy},
+    runtime, ZnError, ZnResult,
+};
+use arrow::{
+    array::{Array, ArrayRef, Int64Array},
+    compute::cast,
+    datatypes::{DataType, TimeUnit},
+    record_batch::RecordBatch,
+    util::display::array_value_to_string,
+};
+use arrow_array::cast::as_string_array;
+use datafusion::{physical_plan::SendableRecordBatchStream, prelude::SessionContext};
+use futures::{
+    channel::mpsc,
+    stream::{self, Stream, StreamExt, TryStreamExt},
+    SinkExt,
+};
+use memchr::memmem;
+use parquet::{
+    arrow::arrow_reader::ParquetRecordBatchReader,
+    file::reader::FileReader,
+    record::{Field, Row},
+};
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    pub needle: String,
+    /// Maximum number of [`SearchResult::hits`]; the total counts all
+    /// matching rows regardless.
+    pub max_hits: usize,
+    /// Column holding the time of a row, as microseconds since the Unix
+    /// epoch or as an Arrow timestamp.
+    pub timestamp_column: Option<String>,
+    /// Characters of context on either side of the match in a snippet.
+    pub snippet_context: usize,
+    /// Aborts the search, checked before every row group or batch.
+    pub cancel: CancelToken,
+}
+
+impl SearchOptions {
+    pub fn new(needle: impl Into<String>) -> Self {
+        Self {
+            needle: needle.into(),
+            max_hits: 100,
+            timestamp_column: Some("_timestamp".to_owned()),
+            snippet_context: 40,
+            cancel: CancelToken::new(),
+        }
+    }
+}
+
+/// A matching row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hit {
+    /// The file, or the table for [`search_datafusion`].
+    pub file: String,
+    /// Number of the row in the file, counted from 0; `None` for
+    /// [`search_datafusion`], which does not keep track of it.
+    pub row: Option<u64>,
+    /// Value of the [timestamp column](SearchOptions::timestamp_column), in
+    /// microseconds since the Unix epoch.
+    pub time: Option<i64>,
+    /// The first match with its context, `…` marking cut off text.
+    pub snippet: String,
+    /// The non-null cells of the row, formatted as text.
+    pub fields: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchStats {
+    pub engine: SearchPath,
+    /// Rows searched, or `None` if the engine filtered them itself.
+    pub rows_scanned: Option<u64>,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResult {
+    /// Number of matching rows.
+    pub total: u64,
+    /// The first matching rows, in scan order.
+    pub hits: Vec<Hit>,
+    pub stats: SearchStats,
+}
+
+/// Searches the rows of the parquet `haystack` using the
+/// [`parquet::file`] API, matching in [byte array] columns like
+/// [`crate::file::count_occurrences`].
+///
+/// # Errors
+///
+/// Returns [`ZnError::EmptyNeedle`] if the needle is empty,
+/// [`ZnError::UnsupportedType`] if the timestamp column is not an integer
+/// or timestamp column, and [`ZnError::Cancelled`] if the search is
+/// [cancelled](SearchOptions::cancel).
+///
+/// [byte array]: crate::file
+pub fn search_file<R: FileReader>(
+    file: &str,
+    haystack: &R,
+    options: &SearchOptions,
+) -> ZnResult<SearchResult> {
+    let mut collector = Collector::try_new(SearchPath::File, options.clone())?;
+    let rows_scanned = scan_file(file, haystack, &mut collector, |_| true)?;
+    Ok(collector.finish(Some(rows_scanned)))
+}
+
+/// Streams the hits of [`search_file`], at most [`SearchOptions::max_hits`]
+/// of them, scanning the `haystack` on a blocking thread of the
+/// [current](crate::runtime::current) runtime.  The scan buffers a few hits
+/// ahead of the consumer and waits while the buffer is full, so a slow
+/// consumer throttles it; dropping the stream stops it.
+pub fn stream_file<R: FileReader + 'static>(
+    file: impl Into<String>,
+    haystack: Arc<R>,
+    options: SearchOptions,
+) -> impl Stream<Item = ZnResult<Hit>> + Send + 'static {
+    let (mut tx, rx) = mpsc::channel(STREAM_BUFFER);
+    let file = file.into();
+    runtime::current().spawn_blocking(Box::new(move || {
+        let mut send = |item| futures::executor::block_on(tx.send(item)).is_ok();
+        let scan = Collector::try_new(SearchPath::File, options).and_then(|mut collector| {
+            scan_file(&file, &*haystack, &mut collector, |collector| {
+                let hits = collector.take_hits();
+                hits.into_iter().all(|hit| send(Ok(hit))) && !collector.full()
+            })
+        });
+        if let Err(e) = scan {
+            send(Err(e));
+        }
+    }));
+    rx
+}
+
+/// Number of hits a [`stream_file`] scan buffers ahead of the consumer.
+const STREAM_BUFFER: usize = 64;
+
+/// Scans the rows of `haystack` into the `collector`, calling `emit` after
+/// every row group until it returns `false`; returns the number of rows
+/// scanned.
+fn scan_file<R: FileReader + ?Sized>(
+    file: &str,
+    haystack: &R,
+    collector: &mut Collector,
+    mut emit: impl FnMut(&mut Collector) -> bool,
+) -> ZnResult<u64> {
+    let searched: HashSet<_> = haystack
+        .metadata()
+        .file_metadata()
+        .schema()
+        .get_fields()
+        .iter()
+        .filter(|t| t.is_primitive() && is_byte_array(t.get_physical_type()))
+        .map(|t| t.name().to_owned())
+        .collect();
+    let mut rows_scanned = 0;
+    for i in 0..haystack.num_row_groups() {
+        collector.options.cancel.check()?;
+        for row in haystack.get_row_group(i)?.get_row_iter(None)? {
+            collector.push_file_row(file, rows_scanned, &row, &searched)?;
+            rows_scanned += 1;
+        }
+        if !emit(collector) {
+            break;
+        }
+    }
+    Ok(rows_scanned)
+}
+
+/// Searches the batches of `haystack`, which must read `file` from its first
+/// row on, matching in [`DataType::Utf8`] columns like
+/// [`crate::arrow::count_occurrences`].
+///
+/// # Errors
+///
+/// Returns [`ZnError::EmptyNeedle`] if the needle is empty,
+/// [`ZnError::UnsupportedType`] if the timestamp column is not an integer
+/// or timestamp column, and [`ZnError::Cancelled`] if the search is
+/// [cancelled](SearchOptions::cancel).
+pub fn search_arrow(
+    file: &str,
+    haystack: ParquetRecordBatchReader,
+    options: &SearchOptions,
+) -> ZnResult<SearchResult> {
+    let mut collector = Collector::try_new(SearchPath::Arrow, options.clone())?;
+    let mut rows_scanned = 0;
+    for batch in haystack {
+        options.cancel.check()?;
+        let batch = batch?;
+        collector.push_batch(file, Some(rows_scanned), &batch)?;
+        rows_scanned += batch.num_rows() as u64;
+    }
+    Ok(collector.finish(Some(rows_scanned)))
+}
+
+/// Searches the `table` of `ctx` with a DataFusion query filtering by
+/// [`match_all_columns`] on its [`DataType::Utf8`] columns.
+///
+/// # Errors
+///
+/// Returns [`ZnError::EmptyNeedle`] if the needle is empty,
+/// [`ZnError::UnsupportedType`] if the timestamp column is not an integer
+/// or timestamp column, and [`ZnError::Cancelled`] if the search is
+/// [cancelled](SearchOptions::cancel).
+pub async fn search_datafusion(
+    ctx: &SessionContext,
+    table: &str,
+    options: &SearchOptions,
+) -> ZnResult<SearchResult> {
+    let mut collector = Collector::try_new(SearchPath::DataFusion, options.clone())?;
+    let mut stream = match_table(ctx, table, &options.needle).await?;
+    while let Some(batch) = stream.next().await {
+        options.cancel.check()?;
+        collector.push_batch(table, None, &batch?)?;
+    }
+    Ok(collector.finish(None))
+}
+
+/// Streams the hits of [`search_datafusion`], at most
+/// [`SearchOptions::max_hits`] of them.  Batches are only executed as the
+/// consumer polls for hits, so a slow consumer throttles the query.
+pub fn stream_datafusion(
+    ctx: SessionContext,
+    table: impl Into<String>,
+    options: SearchOptions,
+) -> impl Stream<Item = ZnResult<Hit>> + Send + 'static {
+    let table = table.into();
+    stream::once(async move {
+        let collector = Collector::try_new(SearchPath::DataFusion, options)?;
+        let batches = match_table(&ctx, &table, &collector.options.needle).await?;
+        let hits = stream::try_unfold(
+            (collector, batches, table),
+            |(mut collector, mut batches, table)| async move {
+                while !collector.full() {
+                    let Some(batch) = batches.next().await else {
+                        break;
+                    };
+                    collector.options.cancel.check()?;
+                    collector.push_batch(&table, None, &batch?)?;
+                    let hits = collector.take_hits();
+                    if !hits.is_empty() {
+                        let hits = stream::iter(hits.into_iter().map(Ok));
+                        return Ok(Some((hits, (collector, batches, table))));
+                    }
+                }
+                Ok::<_, ZnError>(None)
+            },
+        );
+        Ok::<_, ZnError>(hits.try_flatten())
+    })
+    .try_flatten()
+}
+
+/// Executes a query of the rows of the `table` of `ctx` matching the
+/// `needle` in some [`DataType::Utf8`] column.
+async fn match_table(
+    ctx: &SessionContext,
+    table: &str,
+    needle: &str,
+) -> ZnResult<SendableRecordBatchStream> {
+    let df = ctx.table(table).await?;
+    let filter = match_all_columns(df.schema(), needle);
+    Ok(df.filter(filter)?.execute_stream().await?)
+}
+
+/// Builds the [`SearchResult`] of a scan.
+struct Collector {
+    options: SearchOptions,
+    finder: memmem::Finder<'static>,
+    total: u64,
+    hits: Vec<Hit>,
+    /// Number of hits handed out by [`Collector::take_hits`].
+    taken: usize,
+    engine: SearchPath,
+    policy: Option<Arc<RedactionPolicy>>,
+    start: Instant,
+    _timer: Timer<'static>,
+}
+
+impl Collector {
+    fn try_new(engine: SearchPath, options: SearchOptions) -> ZnResult<Self> {
+        if options.needle.is_empty() {
+            return Err(ZnError::empty_needle());
+        }
+        Ok(Self {
+            finder: memmem::Finder::new(options.needle.as_bytes()).into_owned(),
+            options,
+            total: 0,
+            hits: Vec::new(),
+            taken: 0,
+            engine,
+            policy: redact::policy(),
+            start: Instant::now(),
+            _timer: registry().query_latency(engine).start_timer(),
+        })
+    }
+
+    fn full(&self) -> bool {
+        self.taken + self.hits.len() >= self.options.max_hits
+    }
+
+    /// Hands out the hits collected so far.
+    fn take_hits(&mut self) -> Vec<Hit> {
+        self.taken += self.hits.len();
+        std::mem::take(&mut self.hits)
+    }
+
+    fn push_file_row(
+        &mut self,
+        file: &str,
+        row_number: u64,
+        row: &Row,
+        searched: &HashSet<String>,
+    ) -> ZnResult<()> {
+        let mut matched = None;
+        for (name, value) in row.get_column_iter() {
+            if !searched.contains(name) {
+                continue;
+            }
+            if let Some(s) = byte_array_value(name, value)? {
+                if self.finder.find(s).is_some() {
+                    matched = Some((name, String::from_utf8_lossy(s)));
+                    break;
+                }
+            }
+        }
+        let Some((column, text)) = matched else {
+            return Ok(());
+        };
+        self.total += 1;
+        if self.full() {
+            return Ok(());
+        }
+
+        let mut time = None;
+        let mut fields = BTreeMap::new();
+        for (name, value) in row.get_column_iter() {
+            if Some(name) == self.options.timestamp_column.as_ref() {
+                time = match *value {
+                    Field::Null => None,
+                    Field::Long(t) | Field::TimestampMicros(t) => Some(t),
+                    Field::TimestampMillis(t) => Some(t.saturating_mul(1000)),
+                    _ => return Err(timestamp_type_error(name)),
+                };
+            }
+            let formatted = match value {
+                Field::Null => continue,
+                Field::Str(s) => s.clone(),
+                Field::Bytes(b) => String::from_utf8_lossy(b.data()).into_owned(),
+                value => value.to_string(),
+            };
+            self.insert_field(&mut fields, name, &formatted);
+        }
+        let snippet = self.snippet(column, &text);
+        self.hits.push(Hit {
+            file: file.to_owned(),
+            row: Some(row_number),
+            time,
+            snippet,
+            fields,
+        });
+        Ok(())
+    }
+
+    /// Adds the matching rows of the `batch`, whose first row has the
+    /// number `first_row` in the file, if known.
+    fn push_batch(
+        &mut self,
+        file: &str,
+        first_row: Option<u64>,
+        batch: &RecordBatch,
+    ) -> ZnResult<()> {
+        let schema = batch.schema();
+        let texts: Vec<_> = schema
+            .fields()
+            .iter()
+            .zip(batch.columns())
+            .filter(|(_, array)| array.data_type() == &DataType::Utf8)
+            .map(|(field, array)| (field.name(), as_string_array(array)))
+            .collect();
+        let times = self.time_column(batch)?;
+        for row in 0..batch.num_rows() {
+            let Some((column, text)) = texts
+                .iter()
+                .filter(|(_, array)| array.is_valid(row))
+                .map(|(name, array)| (name, array.value(row)))
+                .find(|(_, s)| self.finder.find(s.as_bytes()).is_some())
+            else {
+                continue;
+            };
+            self.total += 1;
+            if self.full() {
+                continue;
+            }
+            let mut fields = BTreeMap::new();
+            for (field, array) in schema.fields().iter().zip(batch.columns()) {
+                if array.is_valid(row) {
+                    let formatted = array_value_to_string(array, row)?;
+                    self.insert_field(&mut fields, field.name(), &formatted);
+                }
+            }
+            let snippet = self.snippet(column, text);
+            self.hits.push(Hit {
+                file: file.to_owned(),
+                row: first_row.map(|first| first + row as u64),
+                time: times
+                    .as_ref()
+                    .filter(|times| times.is_valid(row))
+                    .map(|times| times.value(row)),
+                snippet,
+                fields,
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the timestamp column of the `batch` in microseconds, if the
+    /// batch has it.
+    fn time_column(&self, batch: &RecordBatch) -> ZnResult<Option<Int64Array>> {
+        let Some(name) = &self.options.timestamp_column else {
+            return Ok(None);
+        };
+        let Some(array) = batch
+            .schema()
+            .index_of(name)
+            .ok()
+            .map(|i| batch.column(i).clone())
+        else {
+            return Ok(None);
+        };
+        let array: ArrayRef = match array.data_type() {
+            DataType::Int64 => array,
+            DataType::Timestamp(_, tz) => cast(
+                &cast(
+                    &array,
+                    &DataType::Timestamp(TimeUnit::Microsecond, tz.clone()),
+                )?,
+                &DataType::Int64,
+            )?,
+            _ => return Err(timestamp_type_error(name)),
+        };
+        Ok(Some(
+            array
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .expect("cast to Int64")
+                .clone(),
+        ))
+    }
+
+    /// Adds the cell `value` of the column `name` to the `fields` of a hit,
+    /// unless redacted away.
+    fn insert_field(&self, fields: &mut BTreeMap<String, String>, name: &str, value: &str) {
+        let value = match &self.policy {
+            Some(policy) => match policy.redact_value(name, value) {
+                Some(value) => value.into_owned(),
+                None => return,
+            },
+            None => value.to_owned(),
+        };
+        fields.insert(name.to_owned(), value);
+    }
+
+    /// Cuts the context of the first match out of the redacted `text` of the
+    /// `column`, which contains the needle before redaction.
+    fn snippet(&self, column: &str, text: &str) -> String {
+        match &self.policy {
+            Some(policy) if policy.column_rule(column).is_some() => policy.mask().to_owned(),
+            Some(policy) => self.cut_snippet(&policy.redact_text(text)),
+            None => self.cut_snippet(text),
+        }
+    }
+
+    fn cut_snippet(&self, text: &str) -> String {
+        let context = self.options.snippet_context;
+        let (start, end) = match text.find(&self.options.needle) {
+            Some(start) => (start, start + self.options.needle.len()),
+            // Redaction masked the match; show the start of the text.
+            None => (0, 0),
+        };
+        let from = text[..start]
+            .char_indices()
+            .rev()
+            .take(context)
+            .last()
+            .map_or(start, |(i, _)| i);
+        let rest = &text[end..];
+        let to = end
+            + rest
+                .char_indices()
+                .nth(context)
+                .map_or(rest.len(), |(i, _)| i);
+
+        let mut snippet = String::new();
+        if from > 0 {
+            snippet.push('…');
+        }
+        snippet.push_str(&text[from..to]);
+        if to < text.len() {
+            snippet.push('…');
+        }
+        snippet
+    }
+
+    fn finish(self, rows_scanned: Option<u64>) -> SearchResult {
+        SearchResult {
+            total: self.total,
+            hits: self.hits,
+            stats: SearchStats {
+                engine: self.engine,
+                rows_scanned,
+                elapsed: self.start.elapsed(),
+            },
+        }
+    }
+}
+
+fn timestamp_type_error(name: &str) -> ZnError {
+    ZnError::unsupported_type(format!(
+        "timestamp column {name:?} is neither an integer nor a timestamp column"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        datafusion::CachedParquetTable,
+        test_util::{parquet_bytes, parquet_file},
+    };
+    use parquet::{
+        arrow::arrow_reader::ParquetRecordBatchReaderBuilder,
+        file::serialized_reader::SerializedFileReader,
+    };
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_search_results() {
+        let logs = [
+            "k8s pod started",
+            "GET /",
+            "a very long line mentioning k8s",
+        ];
+        let data = parquet_bytes(&logs, 2);
+        let options = SearchOptions {
+            max_hits: 1,
+            timestamp_column: Some("id".to_owned()),
+            snippet_context: 5,
+            ..SearchOptions::new("k8s")
+        };
+
+        let file = SerializedFileReader::new(data.clone()).unwrap();
+        let by_file = search_file("logs", &file, &options).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(data.clone())
+            .unwrap()
+            .with_batch_size(1)
+            .build()
+            .unwrap();
+        let by_arrow = search_arrow("logs", reader, &options).unwrap();
+        let ctx = SessionContext::new();
+        let table = CachedParquetTable::try_new(Arc::new(file), 2).unwrap();
+        ctx.register_table("logs", Arc::new(table)).unwrap();
+        let by_datafusion = search_datafusion(&ctx, "logs", &options).await.unwrap();
+
+        let hit = Hit {
+            file: "logs".to_owned(),
+            row: Some(0),
+            time: Some(0),
+            snippet: "k8s pod …".to_owned(),
+            fields: BTreeMap::from([
+                ("id".to_owned(), "0".to_owned()),
+                ("log".to_owned(), logs[0].to_owned()),
+            ]),
+        };
+        for result in [&by_file, &by_arrow, &by_datafusion] {
+            assert_eq!(result.total, 2, "{:?}", result.stats.engine);
+        }
+        assert_eq!(by_file.hits, std::slice::from_ref(&hit));
+        assert_eq!(by_arrow.hits, by_file.hits);
+        assert_eq!(by_datafusion.hits, [Hit { row: None, ..hit }]);
+        assert_eq!(by_arrow.stats.rows_scanned, Some(3));
+        assert_eq!(by_datafusion.stats.rows_scanned, None);
+
+        let options = SearchOptions {
+            max_hits: 2,
+            ..options
+        };
+        let by_file = search_file("logs", &parquet_file(&logs, 2), &options).unwrap();
+        assert_eq!(by_file.hits[1].snippet, "…ning k8s");
+        assert_eq!(by_file.hits[1].row, Some(2));
+
+        let streamed: Vec<_> =
+            stream_file("logs", Arc::new(parquet_file(&logs, 1)), options.clone())
+                .try_collect()
+                .await
+                .unwrap();
+        assert_eq!(streamed, by_file.hits);
+        let streamed: Vec<_> = stream_datafusion(
+            ctx,
+            "logs",
+            SearchOptions {
+                max_hits: 1,
+                ..options
+            },
+        )
+        .try_collect()
+        .await
+        .unwrap();
+        assert_eq!(streamed.len(), 1);
+
+        assert!(matches!(
+            search_file("logs", &parquet_file(&logs, 2), &SearchOptions::new("")),
+            Err(ZnError::EmptyNeedle)
+        ));
+    }
+}
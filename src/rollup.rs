@@ -0,0 +1,468 @@
+//! Pre-aggregated rollups
+//!
+//! Dashboards ask the same aggregate queries over and over, e.g. the number
+//! of rows per service, and each of them scans all raw rows.  A
+//! [`RollupSpec`] describes a rollup of the rows, their counts per time
+//! bucket and group of key columns (e.g. per minute per service), and
+//! [`merge_with_rollups`] computes the rollups of the files that compaction
+//! writes, so they stay up to date as files come and go.
+//!
+//! A [`RollupRule`] is a DataFusion optimizer rule answering matching
+//! queries from a table of the rollups instead of the raw rows.  A query
+//! matches if it counts rows (`count(*)`) of the raw table without a filter,
+//! grouped by key columns of the rollup or by the timestamp divided by a
+//! multiple of the interval, e.g.
+//!
+//! ```sql
+//! select service, _timestamp / 3600000000, count(*) from logs group by 1, 2
+//! ```
+//!
+//! for a rollup per minute per service.  Timestamps must not precede the
+//! Unix epoch, as SQL's division truncates them towards it, while buckets
+//! start before them.
+
+use crate::{
+    compact::merge,
+    storage::{RangeChunkReader, RangeReader},
+    writer::{properties, DEFAULT_ROW_GROUP_SIZE},
+    ZnError, ZnResult,
+};
+use arrow::{
+    array::{Array, ArrayRef, Int64Array},
+    compute::cast,
+    datatypes::{DataType, Field, Schema, TimeUnit},
+    record_batch::RecordBatch,
+    row::{OwnedRow, RowConverter, SortField},
+};
+use bytes::Bytes;
+use datafusion::{
+    common::{Column, ScalarValue},
+    datasource::{provider_as_source, TableProvider},
+    error::Result as DataFusionResult,
+    logical_expr::{
+        aggregate_function::AggregateFunction as AggregateKind, expr::AggregateFunction,
+        BinaryExpr, LogicalPlan, LogicalPlanBuilder, Operator,
+    },
+    optimizer::{optimizer::ApplyOrder, OptimizerConfig, OptimizerRule},
+    prelude::{Expr, SessionContext},
+};
+use parquet::arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter, ProjectionMask};
+use std::{collections::BTreeMap, sync::Arc};
+
+/// Name of the column of the start of the time bucket of a rollup row.
+pub const BUCKET_COLUMN: &str = "bucket";
+
+/// Name of the column of the number of rows of a rollup row.
+pub const COUNT_COLUMN: &str = "count";
+
+/// A rollup: numbers of rows per time bucket and group of key columns.
+///
+/// Its rows have the [`BUCKET_COLUMN`], the start of the bucket in
+/// microseconds since the Unix epoch (null for rows without timestamp), the
+/// key columns, and the [`COUNT_COLUMN`], sorted by bucket and keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RollupSpec {
+    /// Int64 or microsecond timestamp column holding the time of a row, in
+    /// microseconds since the Unix epoch.
+    pub timestamp_column: String,
+    /// Length of a bucket, in microseconds.
+    pub interval: i64,
+    pub group_by: Vec<String>,
+}
+
+impl RollupSpec {
+    pub fn new(interval: i64, group_by: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            timestamp_column: "_timestamp".to_owned(),
+            interval,
+            group_by: group_by.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Computes the rollup of the parquet `files` as a parquet file.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::InvalidArgument`] if the interval is not positive,
+    /// a file lacks the timestamp or a key column, or a key column has
+    /// different types in different files.
+    pub fn compute<R: RangeReader + ?Sized + 'static>(&self, files: &[Arc<R>]) -> ZnResult<Bytes> {
+        if self.interval <= 0 {
+            return Err(ZnError::invalid_argument("interval must be positive"));
+        }
+        let mut counts = BTreeMap::<OwnedRow, i64>::new();
+        let mut key_fields: Option<Vec<Field>> = None;
+        let mut converter = None;
+        for file in files {
+            let builder =
+                ParquetRecordBatchReaderBuilder::try_new(RangeChunkReader::try_new(file.clone())?)?;
+            let schema = builder.schema().clone();
+            let columns = std::iter::once(&self.timestamp_column)
+                .chain(&self.group_by)
+                .map(|name| {
+                    schema.index_of(name).map_err(|_| {
+                        ZnError::invalid_argument(format!("no rollup column {name:?}"))
+                    })
+                })
+                .collect::<ZnResult<Vec<_>>>()?;
+            let fields: Vec<_> = columns[1..]
+                .iter()
+                .map(|&i| schema.field(i).clone().with_nullable(true))
+                .collect();
+            match &key_fields {
+                Some(key_fields) if *key_fields != fields => {
+                    return Err(ZnError::invalid_argument(
+                        "key columns have different types in different files",
+                    ))
+                }
+                Some(_) => (),
+                None => {
+                    let sort_fields = std::iter::once(DataType::Int64)
+                        .chain(fields.iter().map(|f| f.data_type().clone()))
+                        .map(SortField::new)
+                        .collect();
+                    converter = Some(RowConverter::new(sort_fields)?);
+                    key_fields = Some(fields);
+                }
+            }
+            let converter = converter.as_mut().expect("set with the key fields");
+
+            let mask = ProjectionMask::roots(
+                builder.metadata().file_metadata().schema_descr(),
+                columns.clone(),
+            );
+            for batch in builder.with_projection(mask).build()? {
+                let batch = batch?;
+                let schema = batch.schema();
+                let mut keys: Vec<ArrayRef> = Vec::with_capacity(columns.len());
+                keys.push(Arc::new(self.buckets(
+                    batch.column(schema.index_of(&self.timestamp_column)?),
+                )?));
+                for name in &self.group_by {
+                    keys.push(batch.column(schema.index_of(name)?).clone());
+                }
+                for row in converter.convert_columns(&keys)?.iter() {
+                    *counts.entry(row.owned()).or_default() += 1;
+                }
+            }
+        }
+
+        let key_fields = key_fields.unwrap_or_default();
+        let mut fields = vec![Field::new(BUCKET_COLUMN, DataType::Int64, true)];
+        fields.extend(key_fields.iter().cloned());
+        fields.push(Field::new(COUNT_COLUMN, DataType::Int64, false));
+        let schema = Arc::new(Schema::new(fields));
+        let mut columns = match &converter {
+            Some(converter) => converter.convert_rows(counts.keys().map(|row| row.row()))?,
+            None => Vec::new(),
+        };
+        columns.push(Arc::new(Int64Array::from_iter_values(
+            counts.values().copied(),
+        )));
+        let props = properties(DEFAULT_ROW_GROUP_SIZE, Some(0), std::iter::empty()).build();
+        let mut buf = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut buf, schema.clone(), Some(props))?;
+        if converter.is_some() {
+            writer.write(&RecordBatch::try_new(schema, columns)?)?;
+        }
+        writer.close()?;
+        Ok(buf.into())
+    }
+
+    /// Returns the starts of the buckets of the `timestamps`.
+    fn buckets(&self, timestamps: &ArrayRef) -> ZnResult<Int64Array> {
+        match timestamps.data_type() {
+            DataType::Int64 | DataType::Timestamp(TimeUnit::Microsecond, _) => (),
+            t => {
+                return Err(ZnError::unsupported_type(format!(
+                    "timestamp column {:?} is of type {t}",
+                    self.timestamp_column
+                )))
+            }
+        }
+        let timestamps = cast(timestamps, &DataType::Int64)?;
+        let timestamps = timestamps
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .expect("cast to Int64");
+        Ok(timestamps
+            .iter()
+            .map(|t| t.map(|t| t.div_euclid(self.interval) * self.interval))
+            .collect())
+    }
+}
+
+/// A file written by [`merge_with_rollups`] with its rollups.
+#[derive(Debug, Clone)]
+pub struct Compacted {
+    pub file: Bytes,
+    /// The rollup of the file of each spec, in order.
+    pub rollups: Vec<Bytes>,
+}
+
+/// Like [`merge`], but also computes the rollups of every merged file.
+///
+/// The rollups of a file replace those of the files merged into it.
+pub fn merge_with_rollups<R: RangeReader + ?Sized + 'static>(
+    files: &[Arc<R>],
+    target_size: u64,
+    sort_by: Option<&str>,
+    specs: &[RollupSpec],
+) -> ZnResult<Vec<Compacted>> {
+    merge(files, target_size, sort_by)?
+        .into_iter()
+        .map(|file| {
+            let merged = [Arc::new(file.clone())];
+            let rollups = specs
+                .iter()
+                .map(|spec| spec.compute(&merged))
+                .collect::<ZnResult<_>>()?;
+            Ok(Compacted { file, rollups })
+        })
+        .collect()
+}
+
+struct Rollup {
+    table: String,
+    spec: RollupSpec,
+    provider: Arc<dyn TableProvider>,
+}
+
+/// Optimizer rule answering matching aggregate queries from rollups; see
+/// the [module docs](self).
+#[derive(Default)]
+pub struct RollupRule {
+    rollups: Vec<Rollup>,
+}
+
+impl RollupRule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the rollup of `table` described by `spec`, whose rows `provider`
+    /// provides, e.g. a parquet table of the rollups of all files of the
+    /// table.
+    pub fn with_rollup(
+        mut self,
+        table: impl Into<String>,
+        spec: RollupSpec,
+        provider: Arc<dyn TableProvider>,
+    ) -> Self {
+        self.rollups.push(Rollup {
+            table: table.into(),
+            spec,
+            provider,
+        });
+        self
+    }
+
+    /// Returns a context sharing the tables and settings of `ctx`, with the
+    /// rule added to its optimizer.
+    pub fn register(self, ctx: &SessionContext) -> SessionContext {
+        SessionContext::with_state(ctx.state().add_optimizer_rule(Arc::new(self)))
+    }
+}
+
+impl OptimizerRule for RollupRule {
+    fn try_optimize(
+        &self,
+        plan: &LogicalPlan,
+        _config: &dyn OptimizerConfig,
+    ) -> DataFusionResult<Option<LogicalPlan>> {
+        let LogicalPlan::Aggregate(aggregate) = plan else {
+            return Ok(None);
+        };
+        let LogicalPlan::TableScan(scan) = aggregate.input.as_ref() else {
+            return Ok(None);
+        };
+        if !scan.filters.is_empty() || scan.fetch.is_some() {
+            return Ok(None);
+        }
+        if !aggregate.aggr_expr.iter().all(is_count_star) {
+            return Ok(None);
+        }
+        // The rollup is scanned under the name of the table, so the columns
+        // of the rewritten plan have the same qualifiers.
+        let alias = scan.table_name.as_str();
+        let Some((rollup, group_expr)) = self
+            .rollups
+            .iter()
+            .filter(|rollup| rollup.table == scan.table_name)
+            .find_map(|rollup| {
+                let group_expr = aggregate
+                    .group_expr
+                    .iter()
+                    .map(|e| rollup_group_expr(&rollup.spec, alias, e))
+                    .collect::<Option<Vec<_>>>()?;
+                Some((rollup, group_expr))
+            })
+        else {
+            return Ok(None);
+        };
+
+        let counts = Expr::Column(Column::new(Some(alias), COUNT_COLUMN));
+        let aggr_expr = aggregate.aggr_expr.iter().map(|_| {
+            Expr::AggregateFunction(AggregateFunction::new(
+                AggregateKind::Sum,
+                vec![counts.clone()],
+                false,
+                None,
+            ))
+        });
+        let rewritten = LogicalPlanBuilder::scan(
+            format!("{alias}_rollup"),
+            provider_as_source(rollup.provider.clone()),
+            None,
+        )?
+        .alias(alias)?
+        .aggregate(group_expr, aggr_expr)?
+        .build()?;
+        // Restores the names and types of the output of the aggregate.
+        let projection: Vec<_> = aggregate
+            .schema
+            .fields()
+            .iter()
+            .zip(rewritten.schema().fields())
+            .map(|(old, new)| {
+                let column = Expr::Column(new.qualified_column());
+                match old.qualifier() {
+                    Some(_) => column,
+                    None => Expr::Cast(datafusion::logical_expr::Cast::new(
+                        Box::new(column),
+                        old.data_type().clone(),
+                    ))
+                    .alias(old.name()),
+                }
+            })
+            .collect();
+        Ok(Some(
+            LogicalPlanBuilder::from(rewritten)
+                .project(projection)?
+                .build()?,
+        ))
+    }
+
+    fn name(&self) -> &str {
+        "rollup"
+    }
+
+    fn apply_order(&self) -> Option<ApplyOrder> {
+        Some(ApplyOrder::BottomUp)
+    }
+}
+
+/// Whether `expr` counts all rows, like `count(*)`.
+fn is_count_star(expr: &Expr) -> bool {
+    match expr {
+        Expr::AggregateFunction(AggregateFunction {
+            fun: AggregateKind::Count,
+            args,
+            distinct: false,
+            filter: None,
+        }) => matches!(&args[..], [Expr::Literal(value)] if !value.is_null()),
+        _ => false,
+    }
+}
+
+/// Returns the expression over the rollup, scanned as `alias`, grouping like
+/// the group expression `expr` over the raw rows, if there is one.
+fn rollup_group_expr(spec: &RollupSpec, alias: &str, expr: &Expr) -> Option<Expr> {
+    let column = |name: &str| Expr::Column(Column::new(Some(alias), name));
+    match expr {
+        Expr::Column(c) if spec.group_by.contains(&c.name) => Some(column(&c.name)),
+        Expr::BinaryExpr(BinaryExpr {
+            left,
+            op: Operator::Divide,
+            right,
+        }) => match (left.as_ref(), right.as_ref()) {
+            (Expr::Column(c), Expr::Literal(ScalarValue::Int64(Some(divisor))))
+                if c.name == spec.timestamp_column
+                    && *divisor > 0
+                    && divisor % spec.interval == 0 =>
+            {
+                Some(Expr::BinaryExpr(BinaryExpr::new(
+                    Box::new(column(BUCKET_COLUMN)),
+                    Operator::Divide,
+                    right.clone(),
+                )))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        datafusion::CachedParquetTable,
+        writer::{write_ndjson, WriterOptions},
+    };
+    use arrow::util::pretty::pretty_format_batches;
+    use parquet::file::serialized_reader::SerializedFileReader;
+
+    fn table(data: Bytes) -> Arc<CachedParquetTable> {
+        let reader = SerializedFileReader::new(data).unwrap();
+        Arc::new(CachedParquetTable::try_new(Arc::new(reader), 1024).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_rollup() {
+        let minute = 60_000_000;
+        let files: Vec<_> = [
+            [(0, "api"), (minute + 1, "api")],
+            [(minute + 2, "db"), (3 * minute, "api")],
+        ]
+        .iter()
+        .map(|rows| {
+            let input: String = rows
+                .iter()
+                .map(|(t, service)| {
+                    format!("{{\"_timestamp\": {t}, \"service\": \"{service}\", \"log\": \"x\"}}\n")
+                })
+                .collect();
+            let mut buf = Vec::new();
+            write_ndjson(input.as_bytes(), &mut buf, &WriterOptions::default()).unwrap();
+            Arc::new(Bytes::from(buf))
+        })
+        .collect();
+        let spec = RollupSpec::new(minute, ["service"]);
+        let compacted =
+            merge_with_rollups(&files, u64::MAX, None, std::slice::from_ref(&spec)).unwrap();
+        assert_eq!(compacted.len(), 1);
+        let Compacted { file, rollups } = compacted.into_iter().next().unwrap();
+
+        let ctx = SessionContext::new();
+        ctx.register_table("logs", table(file)).unwrap();
+        let with_rollups = RollupRule::new()
+            .with_rollup("logs", spec.clone(), table(rollups[0].clone()))
+            .register(&ctx);
+        for sql in [
+            "select service, count(*) from logs group by service order by service",
+            "select _timestamp / 120000000 as t, count(1) from logs group by 1 order by t",
+            "select count(*) from logs",
+            // Not answered from the rollup.
+            "select service, count(*) from logs where log = 'x' group by service order by 1",
+        ] {
+            let expected = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+            let df = with_rollups.sql(sql).await.unwrap();
+            let plan = df.clone().into_optimized_plan().unwrap();
+            let batches = df.collect().await.unwrap();
+            assert_eq!(
+                pretty_format_batches(&batches).unwrap().to_string(),
+                pretty_format_batches(&expected).unwrap().to_string(),
+                "{sql}"
+            );
+            assert_eq!(
+                format!("{plan:?}").contains("logs_rollup"),
+                !sql.contains("where"),
+                "{sql}: {plan:?}"
+            );
+        }
+
+        assert!(RollupSpec::new(0, ["service"]).compute(&files).is_err());
+        assert!(RollupSpec::new(minute, ["host"]).compute(&files).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+//! Executors of the async APIs
+//!
+//! The asynchronous reads of a [`RangeChunkReader`](crate::storage::RangeChunkReader)
+//! and of the `DiskCachedStore` run blocking code off the async threads, and
+//! the blocking reads of an `ObjectStoreReader` drive the store's futures.
+//! They do both through a [`Runtime`], so they work on any executor:
+//!
+//! * [`TokioRuntime`], with the `native` feature, uses tokio's blocking
+//!   thread pool;
+//! * [`ThreadRuntime`] runs blocking tasks on threads of its own and drives
+//!   futures on the calling thread, for async-std, smol, or custom
+//!   executors.
+//!
+//! [`current`] returns the runtime installed with [`set_runtime`], else the
+//! tokio runtime of the caller, if any, else a [`ThreadRuntime`].
+
+use crate::ZnResult;
+use futures::{channel::oneshot, future::BoxFuture, Future};
+use std::sync::{Arc, RwLock};
+
+/// Blocking code run by [`Runtime::spawn_blocking`].
+pub type BlockingTask = Box<dyn FnOnce() + Send>;
+
+pub trait Runtime: Send + Sync {
+    /// Runs the `task` on a thread where it may block.  Dropping the task
+    /// without running it, or a panic, fails the waiting [`spawn_blocking`].
+    fn spawn_blocking(&self, task: BlockingTask);
+
+    /// Runs the `future` to completion on the calling thread, which must not
+    /// be one of the executor's own threads.
+    fn block_on(&self, future: BoxFuture<'_, ()>);
+}
+
+/// Runs blocking tasks on the blocking thread pool of a tokio runtime.
+#[cfg(feature = "native")]
+#[derive(Debug, Clone)]
+pub struct TokioRuntime {
+    handle: tokio::runtime::Handle,
+}
+
+#[cfg(feature = "native")]
+impl TokioRuntime {
+    pub fn new(handle: tokio::runtime::Handle) -> Self {
+        Self { handle }
+    }
+
+    /// Returns the tokio runtime the caller runs on, if any.
+    pub fn current() -> Option<Self> {
+        tokio::runtime::Handle::try_current().ok().map(Self::new)
+    }
+}
+
+#[cfg(feature = "native")]
+impl Runtime for TokioRuntime {
+    fn spawn_blocking(&self, task: BlockingTask) {
+        // Dropping the join handle detaches the task.
+        drop(self.handle.spawn_blocking(task));
+    }
+
+    fn block_on(&self, future: BoxFuture<'_, ()>) {
+        self.handle.block_on(future)
+    }
+}
+
+/// Runs every blocking task on a new thread and drives futures with
+/// [`futures::executor::block_on`]; needs no executor, but futures relying
+/// on one, like those of HTTP-based object stores, may not make progress.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadRuntime;
+
+impl Runtime for ThreadRuntime {
+    fn spawn_blocking(&self, task: BlockingTask) {
+        // If no thread can be spawned, the task is dropped, which fails the
+        // waiting `spawn_blocking`.
+        let _ = std::thread::Builder::new()
+            .name("zn-blocking".to_owned())
+            .spawn(task);
+    }
+
+    fn block_on(&self, future: BoxFuture<'_, ()>) {
+        futures::executor::block_on(future)
+    }
+}
+
+/// Runs the blocking `f` on the `runtime` and returns its result.
+///
+/// # Errors
+///
+/// Returns [`ZnError::Io`](crate::ZnError::Io) if `f` panicked or the
+/// runtime dropped it.
+pub async fn spawn_blocking<T: Send + 'static>(
+    runtime: &dyn Runtime,
+    f: impl FnOnce() -> T + Send + 'static,
+) -> ZnResult<T> {
+    let (tx, rx) = oneshot::channel();
+    runtime.spawn_blocking(Box::new(move || {
+        let _ = tx.send(f());
+    }));
+    Ok(rx
+        .await
+        .map_err(|_| std::io::Error::other("blocking task panicked or was cancelled"))?)
+}
+
+/// Runs the `future` to completion on the `runtime` from the calling
+/// thread; see [`Runtime::block_on`].
+pub fn block_on<F>(runtime: &dyn Runtime, future: F) -> F::Output
+where
+    F: Future + Send,
+    F::Output: Send,
+{
+    let mut output = None;
+    runtime.block_on(Box::pin(async {
+        output = Some(future.await);
+    }));
+    output.expect("block_on runs the future to completion")
+}
+
+static RUNTIME: RwLock<Option<Arc<dyn Runtime>>> = RwLock::new(None);
+
+/// Installs a process-wide runtime, replacing the previously installed one.
+pub fn set_runtime(runtime: Arc<dyn Runtime>) {
+    *RUNTIME.write().unwrap_or_else(|e| e.into_inner()) = Some(runtime);
+}
+
+/// Removes the runtime installed with [`set_runtime`].
+pub fn clear_runtime() {
+    *RUNTIME.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Returns the runtime the async APIs use when called from here; see the
+/// [module docs](self).
+pub fn current() -> Arc<dyn Runtime> {
+    if let Some(runtime) = RUNTIME.read().unwrap_or_else(|e| e.into_inner()).clone() {
+        return runtime;
+    }
+    #[cfg(feature = "native")]
+    if let Some(runtime) = TokioRuntime::current() {
+        return Arc::new(runtime);
+    }
+    Arc::new(ThreadRuntime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{storage::RangeChunkReader, test_util::parquet_bytes};
+    use futures::TryStreamExt;
+    use parquet::arrow::ParquetRecordBatchStreamBuilder;
+
+    #[test]
+    fn test_thread_runtime() {
+        let runtime = ThreadRuntime;
+        let answer = block_on(&runtime, spawn_blocking(&runtime, || 6 * 7));
+        assert_eq!(answer.unwrap(), 42);
+        assert!(block_on(&runtime, spawn_blocking(&runtime, || panic!("boom"))).is_err());
+
+        // Without a tokio runtime, the async reader falls back to threads.
+        let reader = RangeChunkReader::try_new(Arc::new(parquet_bytes(&["a", "b"], 1))).unwrap();
+        let rows = futures::executor::block_on(async {
+            let stream = ParquetRecordBatchStreamBuilder::new(reader)
+                .await
+                .unwrap()
+                .build()
+                .unwrap();
+            let batches: Vec<_> = stream.try_collect().await.unwrap();
+            batches.iter().map(|b| b.num_rows()).sum::<usize>()
+        });
+        assert_eq!(rows, 2);
+    }
+}
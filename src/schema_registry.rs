@@ -0,0 +1,266 @@
+//! Schemas of log streams over time
+//!
+//! Log streams evolve: services add fields, drop them, or start logging a
+//! number as a string.  A [`SchemaRegistry`] keeps the versions of the schema
+//! of every stream, each with its [`SchemaDiff`] to the previous version, as
+//! it observes the files ingested or registered for the stream.  Queries can
+//! be validated against the [union](SchemaRegistry::union_schema) of all
+//! versions, and a [drift callback](SchemaRegistry::on_drift) alerts on new
+//! versions.
+//!
+//! Only the fields count; schema-level metadata changes no version.
+
+use crate::{
+    compact::merged_schema, metadata::arrow_schema, storage::RangeReader, ZnError, ZnResult,
+};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// Differences between two versions of a schema.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SchemaDiff {
+    pub added: Vec<Field>,
+    /// Names of the removed fields.
+    pub removed: Vec<String>,
+    /// Fields whose type or nullability changed: name, old field, new field.
+    pub changed: Vec<(String, Field, Field)>,
+}
+
+impl SchemaDiff {
+    /// Compares the fields of `old` and `new`, by name.
+    pub fn between(old: &Schema, new: &Schema) -> Self {
+        let mut diff = SchemaDiff::default();
+        for field in new.fields() {
+            match old.field_with_name(field.name()) {
+                Err(_) => diff.added.push(field.clone()),
+                Ok(old_field) if old_field != field => {
+                    diff.changed
+                        .push((field.name().clone(), old_field.clone(), field.clone()))
+                }
+                Ok(_) => (),
+            }
+        }
+        for field in old.fields() {
+            if new.field_with_name(field.name()).is_err() {
+                diff.removed.push(field.name().clone());
+            }
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// A version of the schema of a stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaVersion {
+    /// Counted from 1.
+    pub version: u32,
+    pub schema: SchemaRef,
+    /// When the version was first observed, in microseconds since the Unix
+    /// epoch.
+    pub observed_at: i64,
+    /// Difference to the previous version; everything is added for the first.
+    pub diff: SchemaDiff,
+}
+
+type DriftCallback = Arc<dyn Fn(&str, &SchemaVersion) + Send + Sync>;
+
+/// Schema versions of log streams; see the [module docs](self).
+#[derive(Default)]
+pub struct SchemaRegistry {
+    streams: Mutex<HashMap<String, Vec<SchemaVersion>>>,
+    on_drift: Option<DriftCallback>,
+}
+
+impl SchemaRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Calls `callback` with the stream name and the new version whenever
+    /// the schema of a known stream changes.
+    pub fn on_drift(
+        mut self,
+        callback: impl Fn(&str, &SchemaVersion) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_drift = Some(Arc::new(callback));
+        self
+    }
+
+    /// Records the `schema` of data of `stream` seen at `timestamp` and
+    /// returns its version.
+    pub fn observe(&self, stream: &str, schema: &Schema, timestamp: i64) -> SchemaVersion {
+        let schema = Schema::new(schema.fields().clone());
+        let mut streams = self.lock();
+        let versions = streams.entry(stream.to_owned()).or_default();
+        if let Some(latest) = versions.last() {
+            if latest.schema.fields() == schema.fields() {
+                return latest.clone();
+            }
+        }
+        let diff = match versions.last() {
+            Some(latest) => SchemaDiff::between(&latest.schema, &schema),
+            None => SchemaDiff::between(&Schema::empty(), &schema),
+        };
+        let version = SchemaVersion {
+            version: versions.len() as u32 + 1,
+            schema: Arc::new(schema),
+            observed_at: timestamp,
+            diff,
+        };
+        let drifted = !versions.is_empty();
+        versions.push(version.clone());
+        drop(streams);
+        if drifted {
+            if let Some(callback) = &self.on_drift {
+                callback(stream, &version);
+            }
+        }
+        version
+    }
+
+    /// Records the schema of the parquet file read by `reader`; see
+    /// [`observe`](Self::observe).
+    pub fn observe_file<R: RangeReader + ?Sized>(
+        &self,
+        stream: &str,
+        reader: &R,
+        timestamp: i64,
+    ) -> ZnResult<SchemaVersion> {
+        Ok(self.observe(stream, &arrow_schema(reader)?, timestamp))
+    }
+
+    /// Returns the versions of the schema of `stream`, oldest first.
+    pub fn versions(&self, stream: &str) -> Vec<SchemaVersion> {
+        self.lock().get(stream).cloned().unwrap_or_default()
+    }
+
+    pub fn latest(&self, stream: &str) -> Option<SchemaVersion> {
+        self.lock().get(stream).and_then(|v| v.last().cloned())
+    }
+
+    /// Returns the fields of all versions of the schema of `stream`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::InvalidArgument`] if the stream is unknown, and an
+    /// error if a field changed its type.
+    pub fn union_schema(&self, stream: &str) -> ZnResult<SchemaRef> {
+        let schemas: Vec<_> = self
+            .versions(stream)
+            .iter()
+            .map(|v| v.schema.as_ref().clone())
+            .collect();
+        if schemas.is_empty() {
+            return Err(ZnError::invalid_argument(format!(
+                "unknown stream {stream:?}"
+            )));
+        }
+        merged_schema(&schemas)
+    }
+
+    /// Checks that every one of `columns` is a field of some version of the
+    /// schema of `stream`, and returns their types in the latest version
+    /// having them.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::InvalidArgument`] naming the unknown columns.
+    pub fn validate(&self, stream: &str, columns: &[&str]) -> ZnResult<Vec<DataType>> {
+        let versions = self.versions(stream);
+        let mut types = Vec::with_capacity(columns.len());
+        let mut unknown = Vec::new();
+        for column in columns {
+            match versions
+                .iter()
+                .rev()
+                .find_map(|v| v.schema.field_with_name(column).ok())
+            {
+                Some(field) => types.push(field.data_type().clone()),
+                None => unknown.push(*column),
+            }
+        }
+        if unknown.is_empty() {
+            Ok(types)
+        } else {
+            Err(ZnError::invalid_argument(format!(
+                "unknown column(s) {unknown:?} of stream {stream:?}"
+            )))
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Vec<SchemaVersion>>> {
+        // Versions are pushed in a single statement, so a poisoned lock is
+        // still consistent.
+        self.streams.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::parquet_bytes;
+
+    #[test]
+    fn test_schema_registry() {
+        let drifts = Arc::new(Mutex::new(Vec::new()));
+        let sink = drifts.clone();
+        let registry = SchemaRegistry::new()
+            .on_drift(move |stream, v| sink.lock().unwrap().push((stream.to_owned(), v.version)));
+
+        let first = registry
+            .observe_file("app", &parquet_bytes(&["a"], 1), 10)
+            .unwrap();
+        assert_eq!(first.version, 1);
+        assert_eq!(first.diff.added.len(), 2);
+        let again = registry
+            .observe_file("app", &parquet_bytes(&["b"], 1), 20)
+            .unwrap();
+        assert_eq!((again.version, again.observed_at), (1, 10));
+
+        let schema = Schema::new(vec![
+            Field::new("log", DataType::Utf8, true),
+            Field::new("id", DataType::Utf8, false),
+            Field::new("host", DataType::Utf8, true),
+        ]);
+        let second = registry.observe("app", &schema, 30);
+        assert_eq!(second.version, 2);
+        assert_eq!(
+            second.diff.added,
+            [Field::new("host", DataType::Utf8, true)]
+        );
+        assert_eq!(second.diff.changed.len(), 1);
+        assert!(second.diff.removed.is_empty());
+        assert_eq!(*drifts.lock().unwrap(), [("app".to_owned(), 2)]);
+
+        assert_eq!(registry.versions("app").len(), 2);
+        assert_eq!(
+            registry.validate("app", &["host", "id"]).unwrap(),
+            [DataType::Utf8, DataType::Utf8]
+        );
+        assert!(registry.validate("app", &["level"]).is_err());
+        // `id` changed from Int64 to Utf8.
+        assert!(registry.union_schema("app").is_err());
+        assert!(registry.latest("web").is_none());
+        assert!(registry.union_schema("web").is_err());
+        registry.observe(
+            "web",
+            &Schema::new(vec![Field::new("a", DataType::Utf8, false)]),
+            0,
+        );
+        registry.observe(
+            "web",
+            &Schema::new(vec![Field::new("b", DataType::Int64, false)]),
+            1,
+        );
+        let union = registry.union_schema("web").unwrap();
+        assert_eq!(union.fields().len(), 2);
+        assert!(union.fields().iter().all(|f| f.is_nullable()));
+    }
+}
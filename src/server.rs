@@ -0,0 +1,470 @@
+//! Arrow Flight search service
+//!
+//! [`SearchService`] answers searches over the files of a [`PartitionLayout`]
+//! through Arrow Flight `DoGet` calls, so the crate can run as a standalone
+//! search sidecar.  The ticket of a call is a [`SearchRequest`] encoded as
+//! JSON.  Results stream back as record batches while the scan progresses,
+//! redacted by the installed [`RedactionPolicy`](crate::redact::RedactionPolicy),
+//! and the scan stops as soon as the client drops the stream.  Searches are
+//! recorded in the installed [audit log](crate::audit).  A
+//! [`Coordinator`](crate::distributed::Coordinator) restricts the search of
+//! each of its workers to a shard of the files.
+//!
+//! Only available with the `flight` feature.
+
+use crate::{
+    arrow::match_mask,
+    audit::{self, AuditRecord},
+    compact::{adapt, merged_schema},
+    partition::PartitionLayout,
+    redact, tune, ZnError, ZnResult,
+};
+use arrow::{
+    array::{BooleanArray, Int64Array, UInt64Array},
+    compute::{and, cast, filter_record_batch},
+    datatypes::{DataType, Field, Schema},
+    record_batch::RecordBatch,
+};
+use arrow_flight::{
+    encode::FlightDataEncoderBuilder,
+    error::FlightError,
+    flight_service_server::{FlightService, FlightServiceServer},
+    Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightInfo,
+    HandshakeRequest, HandshakeResponse, PutResult, SchemaResult, Ticket,
+};
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fs::File, net::SocketAddr, ops::Range, path::Path, sync::Arc};
+use tokio::sync::mpsc;
+use tonic::{Request, Response, Status, Streaming};
+
+/// Request header naming the principal of a search in the audit log.
+pub const PRINCIPAL_HEADER: &str = "x-zn-principal";
+
+/// Upper bound of the number of buckets of a histogram.
+const MAX_BUCKETS: i64 = 1 << 20;
+
+type BatchSender = mpsc::Sender<Result<RecordBatch, FlightError>>;
+
+/// A search, as carried by the ticket of a `DoGet` call, e.g.
+/// `{"op": "count", "needle": "error", "start": 0, "end": 3600000000}`.
+///
+/// Each search matches the rows of the time range `start..end`, in
+/// microseconds since the Unix epoch, that contain the `needle` in some
+/// string column.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum SearchRequest {
+    /// Number of matching rows, as a single `count` row.
+    Count {
+        needle: String,
+        start: i64,
+        end: i64,
+    },
+    /// The matching rows, with the columns of all files of the range.
+    Filter {
+        needle: String,
+        start: i64,
+        end: i64,
+    },
+    /// Number of matching rows per `interval` microseconds, as rows of the
+    /// `bucket` start and its `count`.
+    Histogram {
+        needle: String,
+        start: i64,
+        end: i64,
+        interval: i64,
+    },
+}
+
+/// The ticket of a `DoGet` call: a [`SearchRequest`], and optionally the
+/// paths, relative to the root of the layout, of the files to search.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SearchTicket {
+    #[serde(flatten)]
+    pub request: SearchRequest,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub files: Option<Vec<String>>,
+}
+
+impl SearchRequest {
+    pub(crate) fn needle(&self) -> &str {
+        match self {
+            SearchRequest::Count { needle, .. }
+            | SearchRequest::Filter { needle, .. }
+            | SearchRequest::Histogram { needle, .. } => needle,
+        }
+    }
+
+    pub(crate) fn range(&self) -> Range<i64> {
+        match *self {
+            SearchRequest::Count { start, end, .. }
+            | SearchRequest::Filter { start, end, .. }
+            | SearchRequest::Histogram { start, end, .. } => start..end,
+        }
+    }
+}
+
+/// Flight service searching the files of a [`PartitionLayout`].
+#[derive(Debug, Clone)]
+pub struct SearchService {
+    layout: PartitionLayout,
+    timestamp_column: String,
+}
+
+impl SearchService {
+    /// Serves the files of `layout`, whose rows hold their time in the
+    /// `timestamp_column`, as microseconds since the Unix epoch.
+    pub fn new(layout: PartitionLayout, timestamp_column: impl Into<String>) -> Self {
+        Self {
+            layout,
+            timestamp_column: timestamp_column.into(),
+        }
+    }
+
+    pub fn into_server(self) -> FlightServiceServer<Self> {
+        FlightServiceServer::new(self)
+    }
+
+    /// Runs the search over the `files` of the ticket's range, or all of
+    /// them, sending the results to `tx`, until done or the receiver is
+    /// dropped.  Adds the files searched and the rows returned to `audit`.
+    fn search(
+        &self,
+        ticket: &SearchTicket,
+        tx: &BatchSender,
+        audit: &mut AuditRecord,
+    ) -> ZnResult<()> {
+        let request = &ticket.request;
+        let needle = request.needle();
+        if needle.is_empty() {
+            return Err(ZnError::empty_needle());
+        }
+        let range = request.range();
+        let mut buckets = match *request {
+            SearchRequest::Histogram { interval, .. } => {
+                if interval <= 0 {
+                    return Err(ZnError::invalid_argument("interval must be positive"));
+                }
+                let num_buckets = (range.end.saturating_sub(range.start).max(0) + interval - 1)
+                    .checked_div(interval)
+                    .unwrap_or(0);
+                if num_buckets > MAX_BUCKETS {
+                    return Err(ZnError::invalid_argument(format!(
+                        "histogram of {num_buckets} buckets, at most {MAX_BUCKETS} are allowed"
+                    )));
+                }
+                vec![0; num_buckets as usize]
+            }
+            _ => Vec::new(),
+        };
+
+        let mut builders = Vec::new();
+        let mut schemas = Vec::new();
+        let files: Option<HashSet<_>> = ticket.files.as_ref().map(|f| f.iter().collect());
+        for path in self.layout.files(range.clone())? {
+            if let Some(files) = &files {
+                if !relative_path(self.layout.root(), &path).is_some_and(|p| files.contains(&p)) {
+                    continue;
+                }
+            }
+            let file = File::open(&path)?;
+            audit.bytes_scanned += file.metadata()?.len();
+            audit.files.push(path.display().to_string());
+            let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+            schemas.push(Schema::new(builder.schema().fields().clone()));
+            builders.push(builder);
+        }
+        let schema = merged_schema(&schemas)?;
+        let policy = redact::policy();
+
+        let mut count = 0;
+        for builder in builders {
+            for batch in builder.with_batch_size(tune::tuning().batch_size).build()? {
+                if tx.is_closed() {
+                    return Ok(());
+                }
+                let batch = batch?;
+                let times = self.times(&batch)?;
+                let in_range: BooleanArray = times
+                    .iter()
+                    .map(|t| Some(t.is_some_and(|t| range.contains(&t))))
+                    .collect();
+                let mask = and(&match_mask(&batch, needle)?, &in_range)?;
+                match request {
+                    SearchRequest::Count { .. } => count += mask.true_count() as u64,
+                    SearchRequest::Filter { .. } => {
+                        let mut batch = filter_record_batch(&adapt(&batch, &schema)?, &mask)?;
+                        if let Some(policy) = &policy {
+                            batch = policy.redact_batch(&batch)?;
+                        }
+                        if batch.num_rows() == 0 {
+                            continue;
+                        }
+                        audit.rows_returned += batch.num_rows() as u64;
+                        if tx.blocking_send(Ok(batch)).is_err() {
+                            return Ok(());
+                        }
+                    }
+                    SearchRequest::Histogram { interval, .. } => {
+                        for (t, matched) in times.iter().zip(mask.iter()) {
+                            if let (Some(t), Some(true)) = (t, matched) {
+                                buckets[((t - range.start) / interval) as usize] += 1;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let result = match request {
+            SearchRequest::Count { .. } => {
+                let schema = Schema::new(vec![Field::new("count", DataType::UInt64, false)]);
+                let counts = UInt64Array::from(vec![count]);
+                RecordBatch::try_new(Arc::new(schema), vec![Arc::new(counts)])?
+            }
+            SearchRequest::Filter { .. } => return Ok(()),
+            SearchRequest::Histogram { interval, .. } => {
+                let schema = Schema::new(vec![
+                    Field::new("bucket", DataType::Int64, false),
+                    Field::new("count", DataType::UInt64, false),
+                ]);
+                let starts: Int64Array = (0..buckets.len() as i64)
+                    .map(|i| range.start + i * interval)
+                    .collect();
+                let counts = UInt64Array::from(buckets);
+                RecordBatch::try_new(Arc::new(schema), vec![Arc::new(starts), Arc::new(counts)])?
+            }
+        };
+        audit.rows_returned += result.num_rows() as u64;
+        // The client may be gone already, which is fine.
+        let _ = tx.blocking_send(Ok(result));
+        Ok(())
+    }
+
+    fn times(&self, batch: &RecordBatch) -> ZnResult<Int64Array> {
+        let i = batch
+            .schema()
+            .index_of(&self.timestamp_column)
+            .map_err(|_| {
+                ZnError::invalid_argument(format!(
+                    "no timestamp column {:?}",
+                    self.timestamp_column
+                ))
+            })?;
+        let times = cast(batch.column(i), &DataType::Int64)?;
+        Ok(arrow::array::as_primitive_array(&times).clone())
+    }
+}
+
+/// Returns the `path` relative to the `root`, with `/` separators.
+pub(crate) fn relative_path(root: &Path, path: &Path) -> Option<String> {
+    let components: Option<Vec<_>> = path
+        .strip_prefix(root)
+        .ok()?
+        .components()
+        .map(|c| c.as_os_str().to_str())
+        .collect();
+    Some(components?.join("/"))
+}
+
+/// Serves the `service` on `addr` until the server fails.
+pub async fn serve(service: SearchService, addr: SocketAddr) -> ZnResult<()> {
+    tonic::transport::Server::builder()
+        .add_service(service.into_server())
+        .serve(addr)
+        .await?;
+    Ok(())
+}
+
+fn status(e: ZnError) -> Status {
+    match e {
+        ZnError::EmptyNeedle | ZnError::InvalidArgument(_) => {
+            Status::invalid_argument(e.to_string())
+        }
+        ZnError::Overloaded(_) => Status::resource_exhausted(e.to_string()),
+        ZnError::Cancelled => Status::cancelled(e.to_string()),
+        e => Status::internal(e.to_string()),
+    }
+}
+
+#[tonic::async_trait]
+impl FlightService for SearchService {
+    type HandshakeStream = BoxStream<'static, Result<HandshakeResponse, Status>>;
+    type ListFlightsStream = BoxStream<'static, Result<FlightInfo, Status>>;
+    type DoGetStream = BoxStream<'static, Result<FlightData, Status>>;
+    type DoPutStream = BoxStream<'static, Result<PutResult, Status>>;
+    type DoExchangeStream = BoxStream<'static, Result<FlightData, Status>>;
+    type DoActionStream = BoxStream<'static, Result<arrow_flight::Result, Status>>;
+    type ListActionsStream = BoxStream<'static, Result<ActionType, Status>>;
+
+    async fn do_get(
+        &self,
+        request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+        let principal = request
+            .metadata()
+            .get(PRINCIPAL_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("anonymous")
+            .to_owned();
+        let ticket = request.into_inner().ticket;
+        let search: SearchTicket = serde_json::from_slice(&ticket)
+            .map_err(|e| Status::invalid_argument(format!("invalid search request: {e}")))?;
+        let mut audit = AuditRecord::new(principal, String::from_utf8_lossy(&ticket));
+        // Few result batches are buffered per call, so a slow client
+        // throttles the scan instead of filling the server's memory.
+        let (tx, rx) = mpsc::channel(tune::tuning().prefetch_depth.max(1));
+        let service = self.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = service.search(&search, &tx, &mut audit) {
+                let _ = tx.blocking_send(Err(FlightError::Tonic(status(e))));
+            }
+            if let Err(e) = audit::record(&audit) {
+                let _ = tx.blocking_send(Err(FlightError::Tonic(status(e))));
+            }
+        });
+        let batches = futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|batch| (batch, rx))
+        });
+        let flight_data = FlightDataEncoderBuilder::new()
+            .build(batches)
+            .map_err(Status::from);
+        Ok(Response::new(flight_data.boxed()))
+    }
+
+    async fn handshake(
+        &self,
+        _request: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        Err(Status::unimplemented("no authentication"))
+    }
+
+    async fn list_flights(
+        &self,
+        _request: Request<Criteria>,
+    ) -> Result<Response<Self::ListFlightsStream>, Status> {
+        Err(Status::unimplemented("searches are requested with DoGet"))
+    }
+
+    async fn get_flight_info(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        Err(Status::unimplemented("searches are requested with DoGet"))
+    }
+
+    async fn get_schema(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<SchemaResult>, Status> {
+        Err(Status::unimplemented("searches are requested with DoGet"))
+    }
+
+    async fn do_put(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoPutStream>, Status> {
+        Err(Status::unimplemented("the search service is read-only"))
+    }
+
+    async fn do_exchange(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        Err(Status::unimplemented("searches are requested with DoGet"))
+    }
+
+    async fn do_action(
+        &self,
+        _request: Request<Action>,
+    ) -> Result<Response<Self::DoActionStream>, Status> {
+        Err(Status::unimplemented("no actions"))
+    }
+
+    async fn list_actions(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::ListActionsStream>, Status> {
+        Ok(Response::new(futures::stream::empty().boxed()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        partition::Granularity,
+        writer::{write_ndjson, WriterOptions},
+    };
+    use arrow::array::as_primitive_array;
+    use arrow::datatypes::UInt64Type;
+    use arrow_flight::decode::FlightRecordBatchStream;
+
+    async fn get(
+        service: &SearchService,
+        request: &SearchRequest,
+    ) -> Result<Vec<RecordBatch>, FlightError> {
+        let ticket = Ticket {
+            ticket: serde_json::to_vec(request).unwrap().into(),
+        };
+        let stream = service.do_get(Request::new(ticket)).await?.into_inner();
+        FlightRecordBatchStream::new_from_flight_data(stream.map_err(FlightError::Tonic))
+            .try_collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_search_service() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = PartitionLayout::new(dir.path(), Granularity::Day);
+        let lines = [
+            r#"{"_timestamp": 10, "log": "error: timeout"}"#,
+            r#"{"_timestamp": 20, "log": "ok"}"#,
+            r#"{"_timestamp": 30, "log": "error: refused"}"#,
+            r#"{"_timestamp": 99000000000, "log": "error: later"}"#,
+        ];
+        let partition = layout.partition_dir(0).unwrap();
+        std::fs::create_dir_all(&partition).unwrap();
+        let file = File::create(partition.join("1.parquet")).unwrap();
+        write_ndjson(lines.join("\n").as_bytes(), file, &WriterOptions::default()).unwrap();
+        let service = SearchService::new(layout, "_timestamp");
+
+        let needle = "error".to_owned();
+        let request = SearchRequest::Count {
+            needle: needle.clone(),
+            start: 0,
+            end: 100,
+        };
+        let batches = get(&service, &request).await.unwrap();
+        let counts: &UInt64Array = as_primitive_array::<UInt64Type>(batches[0].column(0));
+        assert_eq!(counts.value(0), 2);
+
+        let request = SearchRequest::Filter {
+            needle: needle.clone(),
+            start: 0,
+            end: 100,
+        };
+        let batches = get(&service, &request).await.unwrap();
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+        assert_eq!(batches[0].num_columns(), 2);
+
+        let request = SearchRequest::Histogram {
+            needle: needle.clone(),
+            start: 0,
+            end: 40,
+            interval: 15,
+        };
+        let batches = get(&service, &request).await.unwrap();
+        let counts: &UInt64Array = as_primitive_array::<UInt64Type>(batches[0].column(1));
+        assert_eq!(counts.values(), &[1, 0, 1]);
+
+        let request = SearchRequest::Count {
+            needle: String::new(),
+            start: 0,
+            end: 100,
+        };
+        assert!(get(&service, &request).await.is_err());
+    }
+}
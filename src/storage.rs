@@ -0,0 +1,409 @@
+//! Byte range access to parquet files on any backend
+//!
+//! A [`RangeReader`] reads byte ranges of a single object: a local [`File`], a
+//! memory map ([`MmapFile`]), an object of an [`ObjectStore`]
+//! ([`ObjectStoreReader`], which also reads from plain HTTP servers with the
+//! `http` feature), or a buffer in memory.  [`RangeChunkReader`] adapts any of
+//! them to parquet's [`ChunkReader`] and [`AsyncFileReader`], so
+//! [`file::open`](crate::file::open), the [`metadata`](crate::metadata)
+//! functions, the indexes, and the caches work the same on every backend.
+//! The buffers in memory and the memory maps are read as slices rather than
+//! copies, so searching them never duplicates the file.  The memory maps, the
+//! object stores, and the asynchronous adapter need the `native` feature.
+
+#[cfg(feature = "native")]
+use crate::{
+    metadata::read_metadata,
+    runtime::{self, Runtime},
+};
+use crate::{ZnError, ZnResult};
+use bytes::{Buf, Bytes};
+#[cfg(feature = "native")]
+use futures::{future::BoxFuture, FutureExt};
+#[cfg(feature = "native")]
+use object_store::{path::Path as ObjectPath, ObjectStore};
+#[cfg(feature = "native")]
+use parquet::{arrow::async_reader::AsyncFileReader, file::metadata::ParquetMetaData};
+use parquet::{
+    errors::{ParquetError, Result as ParquetResult},
+    file::reader::{ChunkReader, Length},
+};
+#[cfg(feature = "native")]
+use std::path::Path;
+use std::{fs::File, ops::Range, sync::Arc};
+
+/// Random access to the bytes of an object.
+pub trait RangeReader: Send + Sync {
+    /// Size of the object in bytes.
+    fn size(&self) -> ZnResult<u64>;
+
+    /// Reads the bytes in `range`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the range extends past the end of the object.
+    fn read_range(&self, range: Range<u64>) -> ZnResult<Bytes>;
+}
+
+impl<R: RangeReader + ?Sized> RangeReader for Arc<R> {
+    fn size(&self) -> ZnResult<u64> {
+        (**self).size()
+    }
+
+    fn read_range(&self, range: Range<u64>) -> ZnResult<Bytes> {
+        (**self).read_range(range)
+    }
+}
+
+impl<R: RangeReader + ?Sized> RangeReader for &R {
+    fn size(&self) -> ZnResult<u64> {
+        (**self).size()
+    }
+
+    fn read_range(&self, range: Range<u64>) -> ZnResult<Bytes> {
+        (**self).read_range(range)
+    }
+}
+
+impl RangeReader for Bytes {
+    fn size(&self) -> ZnResult<u64> {
+        Ok(self.len() as u64)
+    }
+
+    fn read_range(&self, range: Range<u64>) -> ZnResult<Bytes> {
+        let range = checked_range(range, self.len())?;
+        Ok(self.slice(range))
+    }
+}
+
+/// Reads with positioned reads, so concurrent readers don't share a cursor.
+impl RangeReader for File {
+    fn size(&self) -> ZnResult<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn read_range(&self, range: Range<u64>) -> ZnResult<Bytes> {
+        let len = usize::try_from(range.end.saturating_sub(range.start))
+            .map_err(|_| ZnError::invalid_argument(format!("range {range:?} is too long")))?;
+        let mut buf = vec![0; len];
+        read_exact_at(self, &mut buf, range.start)?;
+        Ok(buf.into())
+    }
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn read_exact_at(_file: &File, _buf: &mut [u8], _offset: u64) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// A memory-mapped local file.
+///
+/// The ranges read are slices of the map, which stays mapped while any of
+/// them is alive.
+#[cfg(feature = "native")]
+pub struct MmapFile {
+    map: Bytes,
+}
+
+#[cfg(feature = "native")]
+impl MmapFile {
+    pub fn open(path: impl AsRef<Path>) -> ZnResult<Self> {
+        let file = File::open(path)?;
+        // SAFETY: like every user of memory maps we rely on the file not
+        // being truncated while mapped; parquet files are immutable once
+        // written.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(Self {
+            map: Bytes::from_owner(map),
+        })
+    }
+}
+
+#[cfg(feature = "native")]
+impl RangeReader for MmapFile {
+    fn size(&self) -> ZnResult<u64> {
+        Ok(self.map.len() as u64)
+    }
+
+    fn read_range(&self, range: Range<u64>) -> ZnResult<Bytes> {
+        let range = checked_range(range, self.map.len())?;
+        Ok(self.map.slice(range))
+    }
+}
+
+/// An object of an [`ObjectStore`].
+///
+/// The store is asynchronous, so the blocking [`read_range`] runs its
+/// requests on a [`Runtime`] and must not be called from one of the
+/// runtime's own threads; read from async code through [`RangeChunkReader`]'s
+/// [`AsyncFileReader`] implementation, or from within `spawn_blocking`.
+///
+/// [`read_range`]: RangeReader::read_range
+#[cfg(feature = "native")]
+pub struct ObjectStoreReader {
+    store: Arc<dyn ObjectStore>,
+    location: ObjectPath,
+    size: u64,
+    runtime: Arc<dyn Runtime>,
+}
+
+#[cfg(feature = "native")]
+impl ObjectStoreReader {
+    /// Looks up the size of the object at `location` and returns a reader
+    /// that sends its requests to the [current](runtime::current) runtime.
+    pub async fn try_new(store: Arc<dyn ObjectStore>, location: ObjectPath) -> ZnResult<Self> {
+        let size = store.head(&location).await?.size as u64;
+        Ok(Self::with_size(store, location, size, runtime::current()))
+    }
+
+    /// Returns a reader of the object at `location`, whose `size` is already
+    /// known (e.g. from a listing), that sends its requests to `runtime`.
+    pub fn with_size(
+        store: Arc<dyn ObjectStore>,
+        location: ObjectPath,
+        size: u64,
+        runtime: Arc<dyn Runtime>,
+    ) -> Self {
+        Self {
+            store,
+            location,
+            size,
+            runtime,
+        }
+    }
+
+    /// Reads the file at `url` from an HTTP server supporting range requests.
+    #[cfg(feature = "http")]
+    pub async fn http(url: &str) -> ZnResult<Self> {
+        let path_start = url
+            .find("://")
+            .and_then(|scheme_end| url[scheme_end + 3..].find('/').map(|i| scheme_end + 3 + i))
+            .ok_or_else(|| ZnError::invalid_argument(format!("no file path in URL {url:?}")))?;
+        let store = object_store::http::HttpBuilder::new()
+            .with_url(&url[..path_start])
+            .build()?;
+        let location = ObjectPath::parse(&url[path_start + 1..])
+            .map_err(|e| ZnError::invalid_argument(e.to_string()))?;
+        Self::try_new(Arc::new(store), location).await
+    }
+}
+
+#[cfg(feature = "native")]
+impl RangeReader for ObjectStoreReader {
+    fn size(&self) -> ZnResult<u64> {
+        Ok(self.size)
+    }
+
+    fn read_range(&self, range: Range<u64>) -> ZnResult<Bytes> {
+        let size = usize::try_from(self.size)
+            .map_err(|_| ZnError::invalid_argument("object is too large"))?;
+        let range = checked_range(range, size)?;
+        Ok(runtime::block_on(
+            &*self.runtime,
+            self.store.get_range(&self.location, range),
+        )?)
+    }
+}
+
+/// Adapts a [`RangeReader`] to parquet's synchronous [`ChunkReader`] and,
+/// with the `native` feature, asynchronous `AsyncFileReader`.
+pub struct RangeChunkReader<R: ?Sized> {
+    inner: Arc<R>,
+    size: u64,
+}
+
+impl<R: RangeReader + ?Sized> RangeChunkReader<R> {
+    pub fn try_new(inner: Arc<R>) -> ZnResult<Self> {
+        let size = inner.size()?;
+        Ok(Self { inner, size })
+    }
+
+    pub fn inner(&self) -> &Arc<R> {
+        &self.inner
+    }
+}
+
+impl<R: ?Sized> Clone for RangeChunkReader<R> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            size: self.size,
+        }
+    }
+}
+
+impl<R: RangeReader + ?Sized> Length for RangeChunkReader<R> {
+    fn len(&self) -> u64 {
+        self.size
+    }
+}
+
+impl<R: RangeReader + ?Sized> ChunkReader for RangeChunkReader<R> {
+    type T = bytes::buf::Reader<Bytes>;
+
+    fn get_read(&self, start: u64, length: usize) -> ParquetResult<Self::T> {
+        Ok(self.get_bytes(start, length)?.reader())
+    }
+
+    fn get_bytes(&self, start: u64, length: usize) -> ParquetResult<Bytes> {
+        self.inner
+            .read_range(start..start + length as u64)
+            .map_err(into_parquet_error)
+    }
+}
+
+/// Runs the blocking reads on the [current](runtime::current) runtime.
+#[cfg(feature = "native")]
+impl<R: RangeReader + ?Sized + 'static> AsyncFileReader for RangeChunkReader<R> {
+    fn get_bytes(&mut self, range: Range<usize>) -> BoxFuture<'_, ParquetResult<Bytes>> {
+        let inner = self.inner.clone();
+        spawn_read(move || inner.read_range(range.start as u64..range.end as u64)).boxed()
+    }
+
+    fn get_byte_ranges(
+        &mut self,
+        ranges: Vec<Range<usize>>,
+    ) -> BoxFuture<'_, ParquetResult<Vec<Bytes>>> {
+        let inner = self.inner.clone();
+        spawn_read(move || {
+            ranges
+                .into_iter()
+                .map(|range| inner.read_range(range.start as u64..range.end as u64))
+                .collect()
+        })
+        .boxed()
+    }
+
+    fn get_metadata(&mut self) -> BoxFuture<'_, ParquetResult<Arc<ParquetMetaData>>> {
+        let inner = self.inner.clone();
+        spawn_read(move || read_metadata(&*inner).map(Arc::new)).boxed()
+    }
+}
+
+#[cfg(feature = "native")]
+async fn spawn_read<T: Send + 'static>(
+    f: impl FnOnce() -> ZnResult<T> + Send + 'static,
+) -> ParquetResult<T> {
+    runtime::spawn_blocking(&*runtime::current(), f)
+        .await
+        .map_err(into_parquet_error)?
+        .map_err(into_parquet_error)
+}
+
+fn into_parquet_error(e: ZnError) -> ParquetError {
+    match e {
+        ZnError::Parquet(e) => e,
+        e => ParquetError::General(e.to_string()),
+    }
+}
+
+fn checked_range(range: Range<u64>, size: usize) -> ZnResult<Range<usize>> {
+    match (usize::try_from(range.start), usize::try_from(range.end)) {
+        (Ok(start), Ok(end)) if start <= end && end <= size => Ok(start..end),
+        _ => Err(ZnError::invalid_argument(format!(
+            "range {range:?} is out of bounds of {size} bytes"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::parquet_bytes;
+    use futures::TryStreamExt;
+    use object_store::memory::InMemory;
+    use parquet::arrow::ParquetRecordBatchStreamBuilder;
+
+    /// Uses the blocking API, which readers of object stores don't support on
+    /// runtime threads.
+    fn text_columns_and_count(reader: Arc<dyn RangeReader>) -> (Vec<(String, u64)>, usize) {
+        let text_columns = crate::metadata::text_columns(&reader).unwrap();
+        let file = crate::file::open(reader).unwrap();
+        let n = crate::file::count_occurrences(&file, b"k8s").unwrap();
+        (text_columns, n)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_storage_backends() {
+        let data = parquet_bytes(&["GET /index.html", "k8s pod", "POST /api", "k8s node"], 2);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("logs.parquet");
+        std::fs::write(&path, &data).unwrap();
+        let store = Arc::new(InMemory::new());
+        let location = ObjectPath::from("logs.parquet");
+        store.put(&location, data.clone()).await.unwrap();
+
+        // Buffers and maps are read without copying.
+        let mmap = MmapFile::open(&path).unwrap();
+        assert_eq!(data.read_range(4..8).unwrap().as_ptr(), data[4..].as_ptr());
+        assert_eq!(
+            mmap.read_range(0..8).unwrap()[4..].as_ptr(),
+            mmap.read_range(4..8).unwrap().as_ptr()
+        );
+        let file = crate::file::open_bytes(data.clone()).unwrap();
+        assert_eq!(crate::file::count_occurrences(&file, b"k8s").unwrap(), 2);
+
+        let readers: Vec<Arc<dyn RangeReader>> = vec![
+            Arc::new(data.clone()),
+            Arc::new(File::open(&path).unwrap()),
+            Arc::new(MmapFile::open(&path).unwrap()),
+            Arc::new(ObjectStoreReader::try_new(store, location).await.unwrap()),
+        ];
+        for reader in readers {
+            let len = data.len() as u64;
+            let blocking = reader.clone();
+            let (magic, past_end) = tokio::task::spawn_blocking(move || {
+                (
+                    blocking.read_range(0..4).unwrap(),
+                    blocking.read_range(0..len + 1),
+                )
+            })
+            .await
+            .unwrap();
+            assert_eq!(magic, &b"PAR1"[..]);
+            assert!(past_end.is_err());
+
+            let blocking_reader = reader.clone();
+            let (text_columns, n) =
+                tokio::task::spawn_blocking(move || text_columns_and_count(blocking_reader))
+                    .await
+                    .unwrap();
+            assert_eq!(text_columns.len(), 1);
+            assert_eq!(text_columns[0].0, "log");
+            assert_eq!(n, 2);
+
+            let batches: Vec<_> =
+                ParquetRecordBatchStreamBuilder::new(RangeChunkReader::try_new(reader).unwrap())
+                    .await
+                    .unwrap()
+                    .build()
+                    .unwrap()
+                    .try_collect()
+                    .await
+                    .unwrap();
+            assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 4);
+        }
+    }
+}
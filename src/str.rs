@@ -0,0 +1,574 @@
+//! Byte string helpers shared by the search paths
+
+use memchr::memmem;
+use std::borrow::Cow;
+
+/// Returns `true` if `b` separates tokens.
+///
+/// Everything except ASCII alphanumerics, `_`, and non-ASCII bytes (which
+/// belong to multi-byte UTF-8 characters) is a separator.
+pub fn is_token_separator(b: u8) -> bool {
+    !(b.is_ascii_alphanumeric() || b == b'_' || !b.is_ascii())
+}
+
+/// Splits `s` into non-empty tokens at [separator] bytes.
+///
+/// [separator]: is_token_separator()
+pub fn tokens(s: &[u8]) -> impl Iterator<Item = &[u8]> {
+    s.split(|&b| is_token_separator(b))
+        .filter(|token| !token.is_empty())
+}
+
+/// Decides whether a byte string matches, e.g. contains a needle.
+///
+/// The search paths take matchers where they take needles, so custom
+/// matching, like a tokenizer aware of a log format, can be
+/// [registered](crate::plugins) and plugged into them.
+pub trait Matcher: Send + Sync {
+    fn is_match(&self, haystack: &[u8]) -> bool;
+}
+
+/// Matches byte strings containing a needle, like the needle-based search
+/// paths.
+#[derive(Debug, Clone)]
+pub struct Substring {
+    finder: memmem::Finder<'static>,
+}
+
+impl Substring {
+    pub fn new(needle: &[u8]) -> Self {
+        Self {
+            finder: memmem::Finder::new(needle).into_owned(),
+        }
+    }
+
+    pub fn needle(&self) -> &[u8] {
+        self.finder.needle()
+    }
+}
+
+impl Matcher for Substring {
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        self.finder.find(haystack).is_some()
+    }
+}
+
+/// Matches byte strings containing a term as whole tokens: the match must
+/// not continue a token before or after it, so `err` matches `err: 5` and
+/// `fatal err` but not `error_count`.  Terms may span several tokens, e.g.
+/// `GET /api`.
+#[derive(Debug, Clone)]
+pub struct Term {
+    finder: memmem::Finder<'static>,
+}
+
+impl Term {
+    pub fn new(term: &[u8]) -> Self {
+        Self {
+            finder: memmem::Finder::new(term).into_owned(),
+        }
+    }
+
+    pub fn term(&self) -> &[u8] {
+        self.finder.needle()
+    }
+
+    /// Returns the position of the first match in `haystack`.
+    pub fn find(&self, haystack: &[u8]) -> Option<usize> {
+        self.find_from(haystack, 0)
+    }
+
+    /// Returns the position of the first match in `haystack` that starts at
+    /// or after `from`; the bytes before `from` still delimit tokens.
+    pub fn find_from(&self, haystack: &[u8], mut from: usize) -> Option<usize> {
+        let term = self.finder.needle();
+        let (Some(&first), Some(&last)) = (term.first(), term.last()) else {
+            return (from <= haystack.len()).then_some(from);
+        };
+        // Matches may overlap a rejected one, so the search resumes right
+        // after the start of each.
+        while let Some(found) = self.finder.find(haystack.get(from..)?) {
+            let start = from + found;
+            let end = start + term.len();
+            let starts_token = is_token_separator(first)
+                || haystack[..start]
+                    .last()
+                    .is_none_or(|&b| is_token_separator(b));
+            let ends_token = is_token_separator(last)
+                || haystack.get(end).is_none_or(|&b| is_token_separator(b));
+            if starts_token && ends_token {
+                return Some(start);
+            }
+            from = start + 1;
+        }
+        None
+    }
+}
+
+impl Matcher for Term {
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        self.find(haystack).is_some()
+    }
+}
+
+/// Matches byte strings containing the whitespace-separated terms of a
+/// phrase as whole [terms](Term), in order, e.g. `connection refused` matches
+/// `connection to 10.0.0.1 refused`.  With a maximum gap, consecutive terms
+/// must be at most that many bytes apart, so a gap of 1 only matches them
+/// separated by a single byte, like a space.
+#[derive(Debug, Clone)]
+pub struct Phrase {
+    terms: Vec<Term>,
+    max_gap: Option<usize>,
+}
+
+impl Phrase {
+    pub fn new(phrase: &[u8], max_gap: Option<usize>) -> Self {
+        let terms = phrase
+            .split(u8::is_ascii_whitespace)
+            .filter(|term| !term.is_empty())
+            .map(Term::new)
+            .collect();
+        Self { terms, max_gap }
+    }
+
+    /// Returns the position of the first term of the first match in
+    /// `haystack`.
+    pub fn find(&self, haystack: &[u8]) -> Option<usize> {
+        let Some((first, rest)) = self.terms.split_first() else {
+            return Some(0);
+        };
+        let mut from = 0;
+        while let Some(start) = first.find_from(haystack, from) {
+            // Taking the earliest match of each term leaves the most room
+            // for the next one.
+            let mut end = start + first.term().len();
+            let chained = rest.iter().all(|term| match term.find_from(haystack, end) {
+                Some(next) if self.max_gap.is_none_or(|gap| next - end <= gap) => {
+                    end = next + term.term().len();
+                    true
+                }
+                _ => false,
+            });
+            if chained {
+                return Some(start);
+            }
+            // Without a bound on the gaps, a later start cannot chain the
+            // terms this one could not.
+            self.max_gap?;
+            from = start + 1;
+        }
+        None
+    }
+}
+
+impl Matcher for Phrase {
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        self.find(haystack).is_some()
+    }
+}
+
+/// Matches byte strings containing two [terms](Term), in either order, at
+/// most a distance apart: the number of bytes between the end of one and the
+/// start of the other, 0 if they overlap.
+#[derive(Debug, Clone)]
+pub struct Near {
+    first: Term,
+    second: Term,
+    max_dist: usize,
+}
+
+impl Near {
+    pub fn new(first: &[u8], second: &[u8], max_dist: usize) -> Self {
+        Self {
+            first: Term::new(first),
+            second: Term::new(second),
+            max_dist,
+        }
+    }
+}
+
+impl Matcher for Near {
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        let (first, second) = (self.first.term().len(), self.second.term().len());
+        let mut from = 0;
+        while let Some(start) = self.first.find_from(haystack, from) {
+            // The second term is close enough iff it starts in this window.
+            let window =
+                start.saturating_sub(self.max_dist + second)..=start + first + self.max_dist;
+            if self
+                .second
+                .find_from(haystack, *window.start())
+                .is_some_and(|found| window.contains(&found))
+            {
+                return true;
+            }
+            from = start + 1;
+        }
+        false
+    }
+}
+
+/// Matches byte strings containing a needle, ignoring the case of ASCII
+/// letters, without lowercasing the haystack.
+#[derive(Debug, Clone)]
+pub struct IgnoreAsciiCase {
+    needle: Vec<u8>,
+}
+
+impl IgnoreAsciiCase {
+    pub fn new(needle: &[u8]) -> Self {
+        Self {
+            needle: needle.to_ascii_lowercase(),
+        }
+    }
+
+    /// The needle, lowercased.
+    pub fn needle(&self) -> &[u8] {
+        &self.needle
+    }
+
+    /// Returns the position of the first match in `haystack`.
+    pub fn find(&self, haystack: &[u8]) -> Option<usize> {
+        let Some((&first, rest)) = self.needle.split_first() else {
+            return Some(0);
+        };
+        let last_start = haystack.len().checked_sub(self.needle.len())?;
+        // Candidates start with either case of the first byte; the rest of
+        // the needle is only compared there.
+        memchr::memchr2_iter(first, first.to_ascii_uppercase(), &haystack[..=last_start])
+            .find(|&i| haystack[i + 1..i + self.needle.len()].eq_ignore_ascii_case(rest))
+    }
+}
+
+impl Matcher for IgnoreAsciiCase {
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        self.find(haystack).is_some()
+    }
+}
+
+/// Matches byte strings containing a substring at most `max_dist` byte
+/// insertions, deletions, or substitutions away from a needle.
+#[derive(Debug, Clone)]
+pub struct Fuzzy {
+    needle: Vec<u8>,
+    max_dist: usize,
+    /// For each byte, the bit set of its positions in the needle, if the
+    /// needle fits in a word.
+    peq: Option<Box<[u64; 256]>>,
+}
+
+impl Fuzzy {
+    pub fn new(needle: &[u8], max_dist: usize) -> Self {
+        let peq = (needle.len() <= 64).then(|| {
+            let mut peq = Box::new([0; 256]);
+            for (i, &b) in needle.iter().enumerate() {
+                peq[b as usize] |= 1 << i;
+            }
+            peq
+        });
+        Self {
+            needle: needle.to_vec(),
+            max_dist,
+            peq,
+        }
+    }
+
+    /// Myers' bit-parallel search, tracking the distance of the best
+    /// substring ending at every byte of the haystack.
+    fn is_match_bit_parallel(&self, peq: &[u64; 256], haystack: &[u8]) -> bool {
+        let m = self.needle.len();
+        let last = 1 << (m - 1);
+        let (mut pv, mut mv) = (!0u64, 0u64);
+        let mut dist = m;
+        for &b in haystack {
+            let eq = peq[b as usize];
+            let xv = eq | mv;
+            let xh = ((eq & pv).wrapping_add(pv) ^ pv) | eq;
+            let ph = mv | !(xh | pv);
+            let mh = pv & xh;
+            if ph & last != 0 {
+                dist += 1;
+            } else if mh & last != 0 {
+                dist -= 1;
+            }
+            let (ph, mh) = (ph << 1, mh << 1);
+            pv = mh | !(xv | ph);
+            mv = ph & xv;
+            if dist <= self.max_dist {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Sellers' dynamic programming search, a column of distances per byte
+    /// of the haystack.
+    fn is_match_dynamic(&self, haystack: &[u8]) -> bool {
+        let mut column: Vec<usize> = (0..=self.needle.len()).collect();
+        for &b in haystack {
+            let mut diagonal = 0;
+            for (i, &n) in self.needle.iter().enumerate() {
+                let dist = (diagonal + usize::from(n != b))
+                    .min(column[i] + 1)
+                    .min(column[i + 1] + 1);
+                diagonal = column[i + 1];
+                column[i + 1] = dist;
+            }
+            if column[self.needle.len()] <= self.max_dist {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl Matcher for Fuzzy {
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        if self.needle.len() <= self.max_dist {
+            return true;
+        }
+        match &self.peq {
+            Some(peq) => self.is_match_bit_parallel(peq, haystack),
+            None => self.is_match_dynamic(haystack),
+        }
+    }
+}
+
+/// Extracts the value of a key from logfmt-style lines of `key=value` pairs
+/// separated by whitespace, e.g. `level=info msg="pod started" pod=web-1`.
+#[derive(Debug, Clone)]
+pub struct LogfmtKey {
+    /// Finds `key=`.
+    finder: memmem::Finder<'static>,
+}
+
+impl LogfmtKey {
+    pub fn new(key: &[u8]) -> Self {
+        let mut pattern = key.to_vec();
+        pattern.push(b'=');
+        Self {
+            finder: memmem::Finder::new(&pattern).into_owned(),
+        }
+    }
+
+    pub fn key(&self) -> &[u8] {
+        let pattern = self.finder.needle();
+        &pattern[..pattern.len() - 1]
+    }
+
+    /// Returns the value of the first pair with the key in `line`, or `None`
+    /// if there is none.  Double-quoted values are unquoted, with `\"` and
+    /// `\\` unescaped; an unterminated one runs to the end of the line.
+    pub fn value<'a>(&self, line: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+        if self.key().is_empty() {
+            return None;
+        }
+        let start = self
+            .finder
+            .find_iter(line)
+            .find(|&start| line[..start].last().is_none_or(|b| b.is_ascii_whitespace()))?;
+        let value = &line[start + self.finder.needle().len()..];
+        match value.strip_prefix(b"\"") {
+            Some(quoted) => Some(unquote(quoted)),
+            None => {
+                let end = value
+                    .iter()
+                    .position(u8::is_ascii_whitespace)
+                    .unwrap_or(value.len());
+                Some(Cow::Borrowed(&value[..end]))
+            }
+        }
+    }
+}
+
+/// Returns the start of `quoted` up to the closing quote, unescaped.
+fn unquote(quoted: &[u8]) -> Cow<'_, [u8]> {
+    // Only values with escapes are copied.
+    let mut unescaped: Option<Vec<u8>> = None;
+    let mut rest = quoted;
+    while let Some(i) = memchr::memchr2(b'"', b'\\', rest) {
+        if rest[i] == b'"' {
+            return match unescaped {
+                Some(mut unescaped) => {
+                    unescaped.extend_from_slice(&rest[..i]);
+                    Cow::Owned(unescaped)
+                }
+                None => Cow::Borrowed(&rest[..i]),
+            };
+        }
+        let unescaped = unescaped.get_or_insert_with(Vec::new);
+        unescaped.extend_from_slice(&rest[..i]);
+        unescaped.extend(rest.get(i + 1));
+        rest = rest.get(i + 2..).unwrap_or_default();
+    }
+    match unescaped {
+        Some(mut unescaped) => {
+            unescaped.extend_from_slice(rest);
+            Cow::Owned(unescaped)
+        }
+        None => Cow::Borrowed(rest),
+    }
+}
+
+/// Matches whole byte strings against a shell-style glob, e.g. `app-*-prod`:
+/// `*` matches any run of characters, `?` any single character, and `\`
+/// makes the next character literal.
+///
+/// The pattern is split at the stars into a prefix, a suffix, and the
+/// segments in between, which are searched for with memmem from left to
+/// right, instead of being translated into a regex.
+#[derive(Debug, Clone)]
+pub struct Glob {
+    /// Never empty; more than one if the pattern has stars.
+    segments: Vec<Segment>,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Self {
+        let mut segments = vec![Segment::default()];
+        let mut literal = Vec::new();
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            let segment = segments.last_mut().unwrap();
+            match c {
+                '*' => {
+                    segment.push_literal(&mut literal);
+                    segments.push(Segment::default());
+                }
+                '?' => {
+                    segment.push_literal(&mut literal);
+                    segment.atoms.push(Atom::AnyChar);
+                }
+                c => {
+                    let c = match c {
+                        '\\' => chars.next().unwrap_or('\\'),
+                        c => c,
+                    };
+                    literal.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+            }
+        }
+        segments.last_mut().unwrap().push_literal(&mut literal);
+        Self { segments }
+    }
+
+    fn matches(&self, haystack: &[u8]) -> Option<()> {
+        let (first, rest) = self.segments.split_first()?;
+        let Some((last, middle)) = rest.split_last() else {
+            return (first.match_at(haystack, 0)? == haystack.len()).then_some(());
+        };
+        let mut at = first.match_at(haystack, 0)?;
+        for segment in middle {
+            at = segment.find_from(haystack, at)?.1;
+        }
+        let ends_at = |start| last.match_at(haystack, start) == Some(haystack.len());
+        match last.literal_len() {
+            Some(len) => haystack
+                .len()
+                .checked_sub(len)
+                .filter(|&start| start >= at && ends_at(start))
+                .map(drop),
+            None => (at..=haystack.len())
+                .rev()
+                .filter(|&start| is_char_boundary(haystack, start))
+                .any(ends_at)
+                .then_some(()),
+        }
+    }
+}
+
+impl Matcher for Glob {
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        self.matches(haystack).is_some()
+    }
+}
+
+/// The literals and `?`s between two stars of a [`Glob`].
+#[derive(Debug, Clone, Default)]
+struct Segment {
+    atoms: Vec<Atom>,
+}
+
+#[derive(Debug, Clone)]
+enum Atom {
+    Literal(memmem::Finder<'static>),
+    AnyChar,
+}
+
+impl Segment {
+    /// Moves the pending `literal`, if any, into the segment.
+    fn push_literal(&mut self, literal: &mut Vec<u8>) {
+        if !literal.is_empty() {
+            let finder = memmem::Finder::new(literal.as_slice()).into_owned();
+            self.atoms.push(Atom::Literal(finder));
+            literal.clear();
+        }
+    }
+
+    /// Returns the length of the segment if it has no `?`.
+    fn literal_len(&self) -> Option<usize> {
+        self.atoms
+            .iter()
+            .map(|atom| match atom {
+                Atom::Literal(literal) => Some(literal.needle().len()),
+                Atom::AnyChar => None,
+            })
+            .sum()
+    }
+
+    /// Returns the end of the segment if it matches `haystack` at `at`.
+    fn match_at(&self, haystack: &[u8], mut at: usize) -> Option<usize> {
+        for atom in &self.atoms {
+            match atom {
+                Atom::Literal(literal) => {
+                    let literal = literal.needle();
+                    if !haystack.get(at..)?.starts_with(literal) {
+                        return None;
+                    }
+                    at += literal.len();
+                }
+                Atom::AnyChar => {
+                    at += char_len(*haystack.get(at)?);
+                    if at > haystack.len() {
+                        return None;
+                    }
+                }
+            }
+        }
+        Some(at)
+    }
+
+    /// Returns the start and end of the first match in `haystack` that
+    /// starts at or after `from`.
+    fn find_from(&self, haystack: &[u8], mut from: usize) -> Option<(usize, usize)> {
+        let Some(Atom::Literal(literal)) = self.atoms.first() else {
+            return (from..=haystack.len())
+                .filter(|&start| is_char_boundary(haystack, start))
+                .find_map(|start| Some((start, self.match_at(haystack, start)?)));
+        };
+        while let Some(found) = literal.find(haystack.get(from..)?) {
+            let start = from + found;
+            if let Some(end) = self.match_at(haystack, start) {
+                return Some((start, end));
+            }
+            from = start + 1;
+        }
+        None
+    }
+}
+
+/// Returns the length of the UTF-8 character starting with `lead`; a byte
+/// that cannot start one is a character of its own.
+fn char_len(lead: u8) -> usize {
+    match lead {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    }
+}
+
+pub(crate) fn is_char_boundary(s: &[u8], i: usize) -> bool {
+    s.get(i).is_none_or(|&b| !(0x80..0xc0).contains(&b))
+}
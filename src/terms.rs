@@ -0,0 +1,154 @@
+//! Term dictionary for exact-token lookup
+//!
+//! A [`TermIndex`] stores every [token] of every text column of a parquet file
+//! in a finite state transducer keyed by `column \0 token`, mapping it to the
+//! sorted list of rows containing the token.  Constraints like `level:error`
+//! then resolve to a [`RowSelection`] without scanning the data.
+//!
+//! [token]: crate::str::tokens
+
+use crate::{
+    file::{byte_array_columns, byte_array_value},
+    str::tokens,
+    ZnError, ZnResult,
+};
+use fst::{IntoStreamer, Map, MapBuilder, Streamer};
+use parquet::{arrow::arrow_reader::RowSelection, file::reader::FileReader};
+use std::collections::BTreeMap;
+
+const KEY_SEPARATOR: u8 = 0;
+
+/// FST-backed dictionary of `(column, token)` pairs of a parquet file.
+pub struct TermIndex {
+    terms: Map<Vec<u8>>,
+    /// Sorted row numbers (counted from the start of the file) per term; the
+    /// FST maps a term to its index here.
+    postings: Vec<Vec<u64>>,
+    num_rows: u64,
+}
+
+impl TermIndex {
+    /// Tokenizes the [byte array] columns of the file and builds the index.
+    ///
+    /// [byte array]: crate::file::byte_array_columns_uncompressed_size
+    pub fn build<R: FileReader>(reader: &R) -> ZnResult<Self> {
+        let projection = byte_array_columns(reader.metadata())?;
+        let mut terms: BTreeMap<Vec<u8>, Vec<u64>> = BTreeMap::new();
+        let mut num_rows = 0;
+        for row in reader.get_row_iter(Some(projection))? {
+            for (column_name, value) in row.get_column_iter() {
+                let Some(s) = byte_array_value(column_name, value)? else {
+                    continue;
+                };
+                for token in tokens(s) {
+                    let rows = terms.entry(key(column_name, token)).or_default();
+                    if rows.last() != Some(&num_rows) {
+                        rows.push(num_rows);
+                    }
+                }
+            }
+            num_rows += 1;
+        }
+
+        let mut builder = MapBuilder::memory();
+        let mut postings = Vec::with_capacity(terms.len());
+        for (term, rows) in terms {
+            builder
+                .insert(term, postings.len() as u64)
+                .map_err(|e| ZnError::invalid_index(e.to_string()))?;
+            postings.push(rows);
+        }
+        Ok(Self {
+            terms: builder.into_map(),
+            postings,
+            num_rows,
+        })
+    }
+
+    /// Number of distinct `(column, token)` pairs.
+    pub fn num_terms(&self) -> usize {
+        self.postings.len()
+    }
+
+    /// Total number of rows of the indexed file.
+    pub fn num_rows(&self) -> u64 {
+        self.num_rows
+    }
+
+    /// Returns the sorted numbers of rows whose `column` contains the
+    /// token `term`.
+    pub fn lookup(&self, column: &str, term: &str) -> &[u64] {
+        match self.terms.get(key(column, term.as_bytes())) {
+            Some(i) => &self.postings[i as usize],
+            None => &[],
+        }
+    }
+
+    /// Returns the sorted numbers of rows whose `column` contains a token
+    /// starting with `prefix`.
+    pub fn lookup_prefix(&self, column: &str, prefix: &str) -> Vec<u64> {
+        let start = key(column, prefix.as_bytes());
+        let mut stream = self.terms.range().ge(&start).into_stream();
+        let mut rows = Vec::new();
+        while let Some((term, i)) = stream.next() {
+            if !term.starts_with(&start) {
+                break;
+            }
+            rows.extend_from_slice(&self.postings[i as usize]);
+        }
+        rows.sort_unstable();
+        rows.dedup();
+        rows
+    }
+
+    /// Converts sorted row numbers, e.g. returned by [`lookup`](Self::lookup),
+    /// into a selection of rows of the indexed file; see
+    /// [`row_selection`](crate::arrow::row_selection).
+    pub fn row_selection(&self, rows: &[u64]) -> RowSelection {
+        crate::arrow::row_selection(rows, self.num_rows)
+    }
+}
+
+fn key(column: &str, token: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(column.len() + 1 + token.len());
+    key.extend_from_slice(column.as_bytes());
+    key.push(KEY_SEPARATOR);
+    key.extend_from_slice(token);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::parquet_file;
+    use parquet::arrow::arrow_reader::RowSelector;
+
+    #[test]
+    fn test_term_index() {
+        let file = parquet_file(
+            &[
+                "level=error msg=timeout",
+                "level=info msg=ok",
+                "level=error msg=refused",
+                "level=errors",
+            ],
+            2,
+        );
+        let index = TermIndex::build(&file).unwrap();
+        assert_eq!(index.num_rows(), 4);
+        assert_eq!(index.lookup("log", "error"), [0, 2]);
+        assert_eq!(index.lookup("log", "err"), [] as [u64; 0]);
+        assert_eq!(index.lookup("id", "error"), [] as [u64; 0]);
+        assert_eq!(index.lookup_prefix("log", "err"), [0, 2, 3]);
+
+        let selection: Vec<RowSelector> = index.row_selection(&[0, 2, 3]).into();
+        assert_eq!(
+            selection,
+            [
+                RowSelector::select(1),
+                RowSelector::skip(1),
+                RowSelector::select(2)
+            ]
+        );
+    }
+}
@@ -0,0 +1,41 @@
+use arrow::{
+    array::{Int64Array, StringArray},
+    datatypes::{DataType, Field, Schema},
+    record_batch::RecordBatch,
+};
+use bytes::Bytes;
+use parquet::{
+    arrow::ArrowWriter,
+    file::{properties::WriterProperties, serialized_reader::SerializedFileReader},
+};
+use std::sync::Arc;
+
+/// Opens the file written by [`parquet_bytes`].
+pub(crate) fn parquet_file(logs: &[&str], row_group_size: usize) -> SerializedFileReader<Bytes> {
+    SerializedFileReader::new(parquet_bytes(logs, row_group_size)).unwrap()
+}
+
+/// Writes a parquet file with a `log` column holding `logs` and an `id`
+/// column numbering them, `row_group_size` rows per row group.
+pub(crate) fn parquet_bytes(logs: &[&str], row_group_size: usize) -> Bytes {
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("log", DataType::Utf8, true),
+        Field::new("id", DataType::Int64, false),
+    ]));
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(StringArray::from_iter_values(logs)),
+            Arc::new(Int64Array::from_iter_values(0..logs.len() as i64)),
+        ],
+    )
+    .unwrap();
+    let props = WriterProperties::builder()
+        .set_max_row_group_size(row_group_size)
+        .build();
+    let mut buf = Vec::new();
+    let mut writer = ArrowWriter::try_new(&mut buf, schema, Some(props)).unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+    Bytes::from(buf)
+}
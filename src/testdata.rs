@@ -0,0 +1,305 @@
+//! Synthetic log-shaped parquet files
+//!
+//! A [`LogSpec`] describes a file of log records: a `_timestamp` column, a
+//! free-text `log` column, and label columns of bounded cardinality, with a
+//! needle planted in a given fraction of the `log` values.  Files are
+//! generated from a seed, so the same spec always yields the same bytes:
+//! benchmarks don't need private data, and tests get reproducible fixtures.
+//!
+//! ```
+//! use zn_perf::testdata::LogSpec;
+//!
+//! let spec = LogSpec {
+//!     rows: 1000,
+//!     ..LogSpec::default()
+//! };
+//! let generated = spec.generate()?;
+//! let file = zn_perf::file::open_bytes(generated.data)?;
+//! assert_eq!(
+//!     zn_perf::file::count_occurrences(&file, spec.needle.as_bytes())?,
+//!     generated.matching_cells
+//! );
+//! # Ok::<(), zn_perf::ZnError>(())
+//! ```
+
+use crate::{ZnError, ZnResult};
+use arrow::{
+    array::{ArrayRef, Int64Array, StringArray},
+    datatypes::{DataType, Field, Schema, SchemaRef},
+    record_batch::RecordBatch,
+};
+use bytes::Bytes;
+use memchr::memmem;
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use std::{path::Path, sync::Arc};
+
+/// Words the `log` values and the labels are made of.
+const WORDS: &[&str] = &[
+    "GET",
+    "POST",
+    "pod",
+    "node",
+    "container",
+    "started",
+    "stopped",
+    "request",
+    "response",
+    "error",
+    "warning",
+    "timeout",
+    "user",
+    "session",
+    "cache",
+    "disk",
+    "network",
+    "ingress",
+    "service",
+    "deployment",
+    "replica",
+    "volume",
+    "queue",
+    "worker",
+];
+
+const LEVELS: &[&str] = &["DEBUG", "INFO", "INFO", "INFO", "WARN", "ERROR"];
+
+/// First timestamp of the generated files, in microseconds since the Unix
+/// epoch (2023-01-01T00:00:00Z).
+const START_MICROS: i64 = 1_672_531_200_000_000;
+
+/// The shape of a generated file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogSpec {
+    pub rows: usize,
+    /// Number of label columns, named `label_0`, `label_1`, ...
+    pub label_columns: usize,
+    /// Number of distinct values of each label column.
+    pub cardinality: usize,
+    /// Maximum number of rows per row group.
+    pub row_group_size: usize,
+    /// Text planted in the `log` values.
+    pub needle: String,
+    /// Fraction of the `log` values holding the needle, between 0 and 1.
+    pub needle_density: f64,
+    pub seed: u64,
+}
+
+impl Default for LogSpec {
+    fn default() -> Self {
+        Self {
+            rows: 100_000,
+            label_columns: 4,
+            cardinality: 16,
+            row_group_size: 8192,
+            needle: "search_string".to_owned(),
+            needle_density: 0.01,
+            seed: 0,
+        }
+    }
+}
+
+/// A generated parquet file.
+#[derive(Debug, Clone)]
+pub struct TestData {
+    pub data: Bytes,
+    /// Number of rows with a text value containing the needle, as counted by
+    /// the [`arrow`](crate::arrow::count_occurrences) search.
+    pub matching_rows: usize,
+    /// Number of text values containing the needle, as counted by the
+    /// [`file`](crate::file::count_occurrences) search.
+    pub matching_cells: usize,
+}
+
+impl LogSpec {
+    /// Generates the file.
+    ///
+    /// The needle may happen to occur in generated text other than the
+    /// planted values, e.g. if it is a common word; the counts of the
+    /// returned [`TestData`] include these occurrences.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ZnError::EmptyNeedle`] if the needle is empty, and
+    /// [`ZnError::InvalidArgument`] if the row group size is zero, the label
+    /// columns have no values, or the density is not between 0 and 1.
+    pub fn generate(&self) -> ZnResult<TestData> {
+        if self.needle.is_empty() {
+            return Err(ZnError::empty_needle());
+        }
+        if self.row_group_size == 0 {
+            return Err(ZnError::invalid_argument("row group size must be positive"));
+        }
+        if self.label_columns > 0 && self.cardinality == 0 {
+            return Err(ZnError::invalid_argument(
+                "label columns need a positive cardinality",
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.needle_density) {
+            return Err(ZnError::invalid_argument(format!(
+                "needle density {} is not between 0 and 1",
+                self.needle_density
+            )));
+        }
+
+        let schema = self.schema();
+        let props = WriterProperties::builder()
+            .set_max_row_group_size(self.row_group_size)
+            .set_compression(Compression::ZSTD)
+            .build();
+        let mut buf = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut buf, schema.clone(), Some(props))?;
+        let mut rng = SplitMix64(self.seed);
+        let finder = memmem::Finder::new(self.needle.as_bytes());
+        let (mut matching_rows, mut matching_cells) = (0, 0);
+        for start in (0..self.rows).step_by(self.row_group_size) {
+            let end = self.rows.min(start + self.row_group_size);
+            let mut columns: Vec<Vec<String>> = vec![Vec::new(); 1 + self.label_columns];
+            for row in start..end {
+                columns[0].push(self.log_value(&mut rng));
+                for (i, labels) in columns[1..].iter_mut().enumerate() {
+                    let value = rng.below(self.cardinality as u64) as usize;
+                    labels.push(label_value(i, value));
+                }
+                let cells = columns
+                    .iter()
+                    .filter(|column| finder.find(column[row - start].as_bytes()).is_some())
+                    .count();
+                matching_cells += cells;
+                matching_rows += usize::from(cells > 0);
+            }
+            let timestamps = Int64Array::from_iter_values(
+                (start..end).map(|row| START_MICROS + row as i64 * 1000),
+            );
+            let mut arrays = vec![Arc::new(timestamps) as ArrayRef];
+            arrays.extend(
+                columns
+                    .iter()
+                    .map(|c| Arc::new(StringArray::from_iter_values(c)) as ArrayRef),
+            );
+            writer.write(&RecordBatch::try_new(schema.clone(), arrays)?)?;
+        }
+        writer.close()?;
+        Ok(TestData {
+            data: buf.into(),
+            matching_rows,
+            matching_cells,
+        })
+    }
+
+    /// Generates the file and writes it to `path`.
+    pub fn write(&self, path: impl AsRef<Path>) -> ZnResult<TestData> {
+        let generated = self.generate()?;
+        std::fs::write(path, &generated.data)?;
+        Ok(generated)
+    }
+
+    fn schema(&self) -> SchemaRef {
+        let mut fields = vec![
+            Field::new("_timestamp", DataType::Int64, false),
+            Field::new("log", DataType::Utf8, false),
+        ];
+        fields.extend(
+            (0..self.label_columns)
+                .map(|i| Field::new(format!("label_{i}"), DataType::Utf8, false)),
+        );
+        Arc::new(Schema::new(fields))
+    }
+
+    fn log_value(&self, rng: &mut SplitMix64) -> String {
+        let mut words: Vec<&str> = (0..4)
+            .map(|_| WORDS[rng.below(WORDS.len() as u64) as usize])
+            .collect();
+        // Compared to the density scaled to the whole range of u64.
+        if (rng.next() as f64) < self.needle_density * u64::MAX as f64 {
+            let i = rng.below(words.len() as u64) as usize;
+            words[i] = &self.needle;
+        }
+        format!(
+            "{} {} took {}ms",
+            LEVELS[rng.below(LEVELS.len() as u64) as usize],
+            words.join(" "),
+            rng.below(2000)
+        )
+    }
+}
+
+/// The `value`-th value of the label column `column`.
+fn label_value(column: usize, value: usize) -> String {
+    format!("{}-{value}", WORDS[(column * 7 + value) % WORDS.len()])
+}
+
+/// The SplitMix64 generator, for reproducible files without a dependency on
+/// a random number crate.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number below `n`, which must be positive.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::{arrow::arrow_reader::ParquetRecordBatchReaderBuilder, file::reader::FileReader};
+
+    #[test]
+    fn test_generate() {
+        let spec = LogSpec {
+            rows: 2500,
+            label_columns: 2,
+            cardinality: 3,
+            row_group_size: 1000,
+            needle_density: 0.1,
+            ..LogSpec::default()
+        };
+        let generated = spec.generate().unwrap();
+        assert_eq!(generated.data, spec.generate().unwrap().data);
+        assert!((150..350).contains(&generated.matching_rows));
+
+        let file = crate::file::open_bytes(generated.data.clone()).unwrap();
+        let metadata = file.metadata();
+        assert_eq!(metadata.num_row_groups(), 3);
+        assert_eq!(metadata.file_metadata().num_rows(), 2500);
+        assert_eq!(metadata.file_metadata().schema_descr().num_columns(), 4);
+        assert_eq!(
+            crate::file::count_occurrences(&file, b"search_string").unwrap(),
+            generated.matching_cells
+        );
+        let reader = ParquetRecordBatchReaderBuilder::try_new(generated.data)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(
+            crate::arrow::count_occurrences(reader, "search_string").unwrap(),
+            generated.matching_rows
+        );
+
+        // Searches the labels only, of which `label_0` has 3 values.
+        let label = |needle: &str| LogSpec {
+            needle: needle.to_owned(),
+            needle_density: 0.0,
+            ..spec.clone()
+        };
+        assert_eq!(
+            label(&label_value(0, 3)).generate().unwrap().matching_rows,
+            0
+        );
+        assert!(label(&label_value(0, 2)).generate().unwrap().matching_rows > 0);
+        assert!(matches!(label("").generate(), Err(ZnError::EmptyNeedle)));
+        let dense = LogSpec {
+            needle_density: 1.5,
+            ..spec
+        };
+        assert!(matches!(dense.generate(), Err(ZnError::InvalidArgument(_))));
+    }
+}
@@ -0,0 +1,250 @@
+//! Defaults derived from the hardware
+//!
+//! The best batch size, parallelism, and prefetch depth of a search depend on
+//! the machine: batches should fit the CPU cache, every core should scan, and
+//! slow storage needs more reads in flight to stay busy.  [`Hardware::probe`]
+//! finds the core count and cache sizes, [`probe_throughput`] measures a
+//! storage backend, and [`Tuning::for_hardware`] derives the defaults from
+//! them.
+//!
+//! Once installed with [`set_tuning`], typically by [`auto_tune`] at
+//! startup, the defaults are used by
+//! [`count_occurrences_in_files`](crate::file::count_occurrences_in_files),
+//! [`new_session_context`](crate::datafusion::new_session_context), the
+//! ND-JSON [`writer`](crate::writer), and the Flight server; without, they
+//! are [`Tuning::default`].
+
+use crate::{storage::RangeReader, ZnResult};
+use std::{
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+/// Bytes read by [`probe_throughput`] at most.
+const PROBE_BYTES: u64 = 16 << 20;
+
+/// Bytes read at a time by [`probe_throughput`].
+const PROBE_READ_BYTES: u64 = 1 << 20;
+
+/// Expected bytes per row of a text column, for sizing batches.
+pub(crate) const ROW_BYTES: u64 = 128;
+
+/// What [`Tuning::for_hardware`] knows of the machine.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hardware {
+    pub cores: usize,
+    /// Size of the L2 cache of a core in bytes, if known.
+    pub l2_cache_bytes: Option<u64>,
+    /// Size of the last level cache in bytes, if known.
+    pub last_level_cache_bytes: Option<u64>,
+    /// Read throughput of the storage in bytes per second, if measured.
+    pub storage_bytes_per_second: Option<f64>,
+}
+
+impl Hardware {
+    /// Finds the core count and, on Linux, the cache sizes of the machine;
+    /// the storage throughput is left to [`probe_throughput`].
+    pub fn probe() -> Self {
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let caches = cache_sizes();
+        let level = |l| {
+            caches
+                .iter()
+                .find(|&&(level, _)| level == l)
+                .map(|&(_, s)| s)
+        };
+        Self {
+            cores,
+            l2_cache_bytes: level(2),
+            last_level_cache_bytes: caches.iter().max().map(|&(_, s)| s),
+            storage_bytes_per_second: None,
+        }
+    }
+}
+
+/// Defaults of the search paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tuning {
+    /// Number of rows decoded at a time.
+    pub batch_size: usize,
+    /// Number of files or partitions scanned at the same time.
+    pub parallelism: usize,
+    /// Number of reads or result batches in flight ahead of the consumer.
+    pub prefetch_depth: usize,
+}
+
+impl Default for Tuning {
+    /// The constants used before tuning: 8192 rows per batch, one scan per
+    /// core, and two batches in flight.
+    fn default() -> Self {
+        Self {
+            batch_size: 8192,
+            parallelism: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            prefetch_depth: 2,
+        }
+    }
+}
+
+impl Tuning {
+    /// Derives the defaults for `hardware`:
+    ///
+    /// * batches of text fill half the L2 cache, in powers of two between 1024
+    ///   and 65536 rows;
+    /// * one scan per core;
+    /// * 2 reads in flight on storage faster than 1 GB/s, 4 down to
+    ///   100 MB/s, and 8 below, as slow storage is typically remote.
+    ///
+    /// Unknown properties keep their [defaults](Tuning::default).
+    pub fn for_hardware(hardware: &Hardware) -> Self {
+        let default = Self::default();
+        let batch_size = hardware
+            .l2_cache_bytes
+            .map_or(default.batch_size, |l2| batch_rows(l2 / 2, ROW_BYTES));
+        let prefetch_depth = match hardware.storage_bytes_per_second {
+            None => default.prefetch_depth,
+            Some(bps) if bps >= 1e9 => 2,
+            Some(bps) if bps >= 1e8 => 4,
+            Some(_) => 8,
+        };
+        Self {
+            batch_size,
+            parallelism: hardware.cores.max(1),
+            prefetch_depth,
+        }
+    }
+}
+
+/// Returns the number of rows of `row_bytes` each filling `batch_bytes`, in
+/// powers of two between 1024 and 65536.
+pub(crate) fn batch_rows(batch_bytes: u64, row_bytes: u64) -> usize {
+    let rows = (batch_bytes / row_bytes.max(1)).max(1);
+    // The largest power of two not above `rows`.
+    let rows = 1 << (63 - rows.leading_zeros());
+    rows.clamp(1024, 65536) as usize
+}
+
+/// Measures the read throughput of `reader` in bytes per second, reading up
+/// to 16 MiB of it in 1 MiB ranges.
+///
+/// The first reads of a local file may be served from the page cache, which
+/// overestimates the throughput of cold files.
+pub fn probe_throughput<R: RangeReader + ?Sized>(reader: &R) -> ZnResult<f64> {
+    let size = reader.size()?.min(PROBE_BYTES);
+    let start = Instant::now();
+    let mut offset = 0;
+    while offset < size {
+        let end = (offset + PROBE_READ_BYTES).min(size);
+        reader.read_range(offset..end)?;
+        offset = end;
+    }
+    let elapsed = start.elapsed().max(Duration::from_nanos(1));
+    Ok(size as f64 / elapsed.as_secs_f64())
+}
+
+static TUNING: RwLock<Option<Tuning>> = RwLock::new(None);
+
+/// Installs process-wide defaults, replacing the previously installed ones.
+pub fn set_tuning(tuning: Tuning) {
+    *TUNING.write().unwrap_or_else(|e| e.into_inner()) = Some(tuning);
+}
+
+/// Removes the defaults installed with [`set_tuning`].
+pub fn clear_tuning() {
+    *TUNING.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Returns the installed defaults, or [`Tuning::default`].
+pub fn tuning() -> Tuning {
+    TUNING
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .unwrap_or_default()
+}
+
+/// Probes the hardware and, if given, the throughput of the `storage`,
+/// installs the derived defaults, and returns them.
+pub fn auto_tune<R: RangeReader + ?Sized>(storage: Option<&R>) -> ZnResult<Tuning> {
+    let mut hardware = Hardware::probe();
+    if let Some(storage) = storage {
+        hardware.storage_bytes_per_second = Some(probe_throughput(storage)?);
+    }
+    let tuning = Tuning::for_hardware(&hardware);
+    set_tuning(tuning);
+    Ok(tuning)
+}
+
+/// Returns the level and size in bytes of the data and unified caches of the
+/// first CPU, as listed by Linux's sysfs; empty elsewhere.
+fn cache_sizes() -> Vec<(u8, u64)> {
+    let Ok(dir) = std::fs::read_dir("/sys/devices/system/cpu/cpu0/cache") else {
+        return Vec::new();
+    };
+    let read = |path: &std::path::Path, name| std::fs::read_to_string(path.join(name)).ok();
+    dir.flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            if read(&path, "type")?.trim() == "Instruction" {
+                return None;
+            }
+            let level = read(&path, "level")?.trim().parse().ok()?;
+            Some((level, parse_cache_size(&read(&path, "size")?)?))
+        })
+        .collect()
+}
+
+/// Parses a sysfs cache size like `512K` or `32M`.
+fn parse_cache_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let (digits, unit) = match size.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => size.split_at(i),
+        None => (size, ""),
+    };
+    let multiplier = match unit {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return None,
+    };
+    digits.parse::<u64>().ok().map(|n| n * multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    #[test]
+    fn test_tuning() {
+        assert_eq!(parse_cache_size("512K\n"), Some(512 << 10));
+        assert_eq!(parse_cache_size("32M"), Some(32 << 20));
+        assert_eq!(parse_cache_size("1x"), None);
+
+        let hardware = Hardware {
+            cores: 16,
+            l2_cache_bytes: Some(2 << 20),
+            last_level_cache_bytes: Some(32 << 20),
+            storage_bytes_per_second: Some(5e7),
+        };
+        let tuning = Tuning::for_hardware(&hardware);
+        assert_eq!(
+            tuning,
+            Tuning {
+                batch_size: 8192,
+                parallelism: 16,
+                prefetch_depth: 8,
+            }
+        );
+        // A tiny L2 still gets usable batches, unknown storage the default.
+        let tuning = Tuning::for_hardware(&Hardware {
+            l2_cache_bytes: Some(64 << 10),
+            storage_bytes_per_second: None,
+            ..hardware
+        });
+        assert_eq!((tuning.batch_size, tuning.prefetch_depth), (1024, 2));
+
+        assert!(Hardware::probe().cores >= 1);
+        let storage = Bytes::from(vec![0; 3 << 20]);
+        assert!(probe_throughput(&storage).unwrap() > 0.0);
+    }
+}
@@ -0,0 +1,276 @@
+//! Consistency of the search paths
+//!
+//! [`consistent`] runs the [`file`](crate::file), [`arrow`](crate::arrow), and
+//! DataFusion searches for a needle on the same parquet file and compares the
+//! rows each of them finds, catching semantic drift between the paths as new
+//! options land:
+//!
+//! ```
+//! use zn_perf::{testdata::LogSpec, verify};
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let spec = LogSpec {
+//!     rows: 10_000,
+//!     ..LogSpec::default()
+//! };
+//! let generated = spec.generate()?;
+//! let consistency = verify::consistent(generated.data, "search_string").await?;
+//! assert!(consistency.is_consistent(), "{consistency}");
+//! # Ok::<(), zn_perf::ZnError>(())
+//! # }).unwrap();
+//! ```
+
+use crate::{
+    arrow::match_mask,
+    bench::{search_sql, SqlOp},
+    file::{byte_array_columns, byte_array_value, open_bytes},
+    str::{Matcher, Substring},
+    ZnError, ZnResult,
+};
+use arrow::{
+    array::{ArrayRef, Int64Array},
+    datatypes::{DataType, Field, Schema},
+    record_batch::RecordBatch,
+};
+use arrow_array::cast::as_primitive_array;
+use bytes::Bytes;
+use datafusion::{datasource::MemTable, prelude::SessionContext};
+use parquet::{arrow::arrow_reader::ParquetRecordBatchReaderBuilder, file::reader::FileReader};
+use std::{fmt, sync::Arc};
+
+/// Column numbering the rows of the table the DataFusion path searches.
+const ROW_COLUMN: &str = "__zn_row";
+
+/// A row that the search paths disagree on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RowDiff {
+    /// Row number, counted from the start of the file.
+    pub row: u64,
+    pub row_group: usize,
+    /// Row number, counted from the start of the row group.
+    pub row_in_group: u64,
+    /// Whether the [`file`](crate::file) path found the row.
+    pub file: bool,
+    /// Whether the [`arrow`](crate::arrow) path found the row.
+    pub arrow: bool,
+    /// Whether the DataFusion path found the row.
+    pub datafusion: bool,
+}
+
+impl fmt::Display for RowDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let found = |found: bool| if found { "found" } else { "missed" };
+        write!(
+            f,
+            "row {} (row group {}, row {}): file {}, arrow {}, datafusion {}",
+            self.row,
+            self.row_group,
+            self.row_in_group,
+            found(self.file),
+            found(self.arrow),
+            found(self.datafusion)
+        )
+    }
+}
+
+/// The rows found by each search path, as returned by [`consistent`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Consistency {
+    pub file_rows: usize,
+    pub arrow_rows: usize,
+    pub datafusion_rows: usize,
+    /// The rows found by some paths but not all, in order.
+    pub diffs: Vec<RowDiff>,
+}
+
+impl Consistency {
+    /// Returns `true` if every path found the same rows.
+    pub fn is_consistent(&self) -> bool {
+        self.diffs.is_empty()
+    }
+}
+
+impl fmt::Display for Consistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rows found: file {}, arrow {}, datafusion {}",
+            self.file_rows, self.arrow_rows, self.datafusion_rows
+        )?;
+        for diff in &self.diffs {
+            write!(f, "\n{diff}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Searches the parquet `file` for the `needle` with the
+/// [`file`](crate::file) path, the [`arrow`](crate::arrow) path, and
+/// DataFusion (`strpos` over the text columns), and compares the rows they
+/// find.
+///
+/// # Errors
+///
+/// Returns [`ZnError::EmptyNeedle`] if `needle` is empty, and the errors of
+/// the searches.
+pub async fn consistent(file: Bytes, needle: &str) -> ZnResult<Consistency> {
+    if needle.is_empty() {
+        return Err(ZnError::empty_needle());
+    }
+    let file_hits = file_rows(file.clone(), needle)?;
+    let mut arrow_hits = Vec::with_capacity(file_hits.len());
+    let mut batches = Vec::new();
+    let reader = ParquetRecordBatchReaderBuilder::try_new(file.clone())?;
+    let schema = reader.schema().clone();
+    for batch in reader.build()? {
+        let batch = batch?;
+        let mask = match_mask(&batch, needle)?;
+        arrow_hits.extend((0..mask.len()).map(|i| mask.value(i)));
+        batches.push(batch);
+    }
+    let datafusion_hits = datafusion_rows(&schema, batches, needle, file_hits.len()).await?;
+
+    let metadata = open_bytes(file)?.metadata().clone();
+    let mut group_starts = Vec::with_capacity(metadata.num_row_groups());
+    let mut start = 0;
+    for row_group in metadata.row_groups() {
+        group_starts.push(start);
+        start += row_group.num_rows() as u64;
+    }
+    let mut diffs = Vec::new();
+    for (row, ((&file, &arrow), &datafusion)) in file_hits
+        .iter()
+        .zip(&arrow_hits)
+        .zip(&datafusion_hits)
+        .enumerate()
+    {
+        if file == arrow && arrow == datafusion {
+            continue;
+        }
+        let row = row as u64;
+        let row_group = group_starts.partition_point(|&start| start <= row) - 1;
+        diffs.push(RowDiff {
+            row,
+            row_group,
+            row_in_group: row - group_starts[row_group],
+            file,
+            arrow,
+            datafusion,
+        });
+    }
+    let count = |hits: &[bool]| hits.iter().filter(|&&hit| hit).count();
+    Ok(Consistency {
+        file_rows: count(&file_hits),
+        arrow_rows: count(&arrow_hits),
+        datafusion_rows: count(&datafusion_hits),
+        diffs,
+    })
+}
+
+/// Returns which rows of the `file` have a byte array value containing the
+/// `needle`, like [`crate::file::count_occurrences`] but row by row.
+fn file_rows(file: Bytes, needle: &str) -> ZnResult<Vec<bool>> {
+    let file = open_bytes(file)?;
+    let matcher = Substring::new(needle.as_bytes());
+    let projection = byte_array_columns(file.metadata())?;
+    let mut hits = Vec::with_capacity(file.metadata().file_metadata().num_rows() as usize);
+    for row in file.get_row_iter(Some(projection))? {
+        let mut hit = false;
+        for (column_name, value) in row.get_column_iter() {
+            if let Some(s) = byte_array_value(column_name, value)? {
+                hit |= matcher.is_match(s);
+            }
+        }
+        hits.push(hit);
+    }
+    Ok(hits)
+}
+
+/// Returns which of the `num_rows` rows of the `batches` DataFusion finds
+/// with `strpos(column, needle) > 0` over the [`DataType::Utf8`] columns.
+async fn datafusion_rows(
+    schema: &Schema,
+    batches: Vec<RecordBatch>,
+    needle: &str,
+    num_rows: usize,
+) -> ZnResult<Vec<bool>> {
+    let text_columns: Vec<_> = schema
+        .fields()
+        .iter()
+        .filter(|f| f.data_type() == &DataType::Utf8)
+        .map(|f| f.name().clone())
+        .collect();
+    let mut fields = schema.fields().clone();
+    fields.push(Field::new(ROW_COLUMN, DataType::Int64, false));
+    let schema = Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone()));
+    let mut start = 0;
+    let mut numbered = Vec::with_capacity(batches.len());
+    for batch in batches {
+        let end = start + batch.num_rows() as i64;
+        let mut columns = batch.columns().to_vec();
+        columns.push(Arc::new(Int64Array::from_iter_values(start..end)) as ArrayRef);
+        numbered.push(RecordBatch::try_new(schema.clone(), columns)?);
+        start = end;
+    }
+
+    let ctx = SessionContext::new();
+    ctx.register_table("t", Arc::new(MemTable::try_new(schema, vec![numbered])?))?;
+    let sql = search_sql("t", &text_columns, SqlOp::Strpos, needle);
+    let mut hits = vec![false; num_rows];
+    for batch in ctx.sql(&sql).await?.collect().await? {
+        let index = batch.schema().index_of(ROW_COLUMN)?;
+        let rows: &Int64Array = as_primitive_array(batch.column(index));
+        for row in rows.values() {
+            hits[*row as usize] = true;
+        }
+    }
+    Ok(hits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util::parquet_bytes, testdata::LogSpec};
+
+    #[tokio::test]
+    async fn test_consistent() {
+        let spec = LogSpec {
+            rows: 3000,
+            row_group_size: 1000,
+            needle_density: 0.05,
+            ..LogSpec::default()
+        };
+        let generated = spec.generate().unwrap();
+        let consistency = consistent(generated.data, "search_string").await.unwrap();
+        assert!(consistency.is_consistent(), "{consistency}");
+        assert_eq!(consistency.file_rows, generated.matching_rows);
+
+        let data = parquet_bytes(&["k8s", "pod", "x", "k8s pod"], 3);
+        let consistency = consistent(data, "k8s").await.unwrap();
+        assert_eq!(
+            (
+                consistency.file_rows,
+                consistency.arrow_rows,
+                consistency.datafusion_rows
+            ),
+            (2, 2, 2)
+        );
+        assert!(matches!(
+            consistent(Bytes::new(), "").await,
+            Err(ZnError::EmptyNeedle)
+        ));
+
+        let diff = RowDiff {
+            row: 3,
+            row_group: 1,
+            row_in_group: 0,
+            file: true,
+            arrow: true,
+            datafusion: false,
+        };
+        assert_eq!(
+            diff.to_string(),
+            "row 3 (row group 1, row 0): file found, arrow found, datafusion missed"
+        );
+    }
+}
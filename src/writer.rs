@@ -0,0 +1,279 @@
+//! Write ND-JSON logs to parquet
+//!
+//! [`write_ndjson`] converts ND-JSON log lines into a parquet file laid out
+//! the way the search side of the crate likes it: rows sorted by timestamp,
+//! zstd compression, dictionary encoding for the low-cardinality label
+//! columns only, and a bounded number of rows per row group.  The schema is
+//! inferred from the records.
+//!
+//! [`train_dictionary`] trains zstd dictionaries on samples of such columns.
+//! Parquet's ZSTD codec has no way to reference a dictionary, so neither
+//! [`write_ndjson`] nor [`crate::compact`] can use them in the files they
+//! write; the label columns get parquet's own dictionary encoding instead.
+//! Trained dictionaries suit sidecars and caches that compress many small,
+//! repetitive values one by one.
+
+use crate::{tune, ZnError, ZnResult};
+use arrow::{
+    compute::{concat_batches, sort_to_indices, take, SortOptions},
+    json::reader::{infer_json_schema_from_iterator, Decoder, DecoderOptions},
+    record_batch::RecordBatch,
+};
+use parquet::{
+    arrow::ArrowWriter,
+    basic::Compression,
+    file::properties::{WriterProperties, WriterPropertiesBuilder},
+    format::SortingColumn,
+    schema::types::ColumnPath,
+};
+use serde_json::Value;
+use std::{
+    io::{BufRead, Write},
+    sync::Arc,
+};
+
+/// Default maximum number of rows per row group, the same as parquet's.
+pub(crate) const DEFAULT_ROW_GROUP_SIZE: usize = 1024 * 1024;
+
+/// Settings of [`write_ndjson`].
+#[derive(Debug, Clone)]
+pub struct WriterOptions {
+    /// Maximum number of rows per row group.
+    pub row_group_size: usize,
+    /// Column to sort the rows by, ascending, with rows lacking it last.
+    pub timestamp_column: Option<String>,
+    /// Columns to dictionary-encode, typically labels like `level` or `host`.
+    /// Other columns are plain-encoded, as dictionaries of free text grow
+    /// about as large as the text itself.
+    pub label_columns: Vec<String>,
+    /// Number of records the schema is inferred from; all of them if `None`.
+    /// Fields first seen after these records are dropped.
+    pub infer_schema_records: Option<usize>,
+}
+
+impl Default for WriterOptions {
+    fn default() -> Self {
+        Self {
+            row_group_size: DEFAULT_ROW_GROUP_SIZE,
+            timestamp_column: Some("_timestamp".to_owned()),
+            label_columns: Vec::new(),
+            infer_schema_records: None,
+        }
+    }
+}
+
+/// Reads ND-JSON records from `input` and writes them to `output` as a
+/// parquet file.  Returns the number of rows written.
+///
+/// The records are buffered in memory, as sorting needs all of them.  Blank
+/// lines are skipped.
+///
+/// # Errors
+///
+/// Returns [`ZnError::InvalidArgument`] if a line is not a JSON object or
+/// the `timestamp_column` is not a field of the records.
+pub fn write_ndjson<R: BufRead, W: Write + Send>(
+    input: R,
+    output: W,
+    options: &WriterOptions,
+) -> ZnResult<usize> {
+    let mut records = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Value = serde_json::from_str(&line)
+            .map_err(|e| ZnError::invalid_argument(format!("line {}: {e}", i + 1)))?;
+        if !record.is_object() {
+            return Err(ZnError::invalid_argument(format!(
+                "line {}: not a JSON object",
+                i + 1
+            )));
+        }
+        records.push(record);
+    }
+
+    let mut batch = decode_records(records, options.infer_schema_records)?;
+    let schema = batch.schema();
+
+    let mut sort_column = None;
+    if let Some(name) = &options.timestamp_column {
+        let i = schema.index_of(name).map_err(|_| {
+            ZnError::invalid_argument(format!("no timestamp column {name:?} in the records"))
+        })?;
+        batch = sort_by_column(&batch, i)?;
+        sort_column = Some(i);
+    }
+
+    let dictionary_columns: Vec<_> = options
+        .label_columns
+        .iter()
+        .map(|label| ColumnPath::from(label.as_str()))
+        .collect();
+    let props = properties(options.row_group_size, sort_column, dictionary_columns).build();
+    let mut writer = ArrowWriter::try_new(output, schema, Some(props))?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(batch.num_rows())
+}
+
+/// Decodes JSON object `records` into one batch, with the schema inferred
+/// from the first `infer_schema_records` of them, or all if `None`.
+pub(crate) fn decode_records(
+    records: Vec<Value>,
+    infer_schema_records: Option<usize>,
+) -> ZnResult<RecordBatch> {
+    let num_inferred = infer_schema_records.unwrap_or(records.len());
+    let schema = Arc::new(infer_json_schema_from_iterator(
+        records.iter().take(num_inferred).cloned().map(Ok),
+    )?);
+    let decoder = Decoder::new(
+        schema.clone(),
+        DecoderOptions::new().with_batch_size(tune::tuning().batch_size),
+    );
+    let mut values = records.into_iter().map(Ok);
+    let mut batches = Vec::new();
+    while let Some(batch) = decoder.next_batch(&mut values)? {
+        batches.push(batch);
+    }
+    Ok(concat_batches(&schema, &batches)?)
+}
+
+/// Returns the writer settings shared by everything that writes files for
+/// searching: zstd, dictionary encoding of the `dictionary_columns` only, and
+/// the sort order recorded in the metadata.
+pub(crate) fn properties(
+    row_group_size: usize,
+    sort_column: Option<usize>,
+    dictionary_columns: impl IntoIterator<Item = ColumnPath>,
+) -> WriterPropertiesBuilder {
+    let sorting_columns = sort_column.map(|i| {
+        vec![SortingColumn {
+            column_idx: i as i32,
+            descending: false,
+            nulls_first: false,
+        }]
+    });
+    let mut props = WriterProperties::builder()
+        .set_max_row_group_size(row_group_size.max(1))
+        .set_compression(Compression::ZSTD)
+        .set_dictionary_enabled(false)
+        .set_sorting_columns(sorting_columns);
+    for column in dictionary_columns {
+        props = props.set_column_dictionary_enabled(column, true);
+    }
+    props
+}
+
+/// Trains a zstd dictionary of at most `max_size` bytes on the `samples`,
+/// e.g. the values of a label column.
+///
+/// # Errors
+///
+/// Returns [`ZnError::InvalidArgument`] if zstd cannot train a dictionary
+/// on the samples, typically because there are too few of them.
+pub fn train_dictionary<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> ZnResult<Vec<u8>> {
+    zstd::dict::from_samples(samples, max_size)
+        .map_err(|e| ZnError::invalid_argument(format!("cannot train a zstd dictionary: {e}")))
+}
+
+/// Sorts the rows of the `batch` by a column, ascending, nulls last.
+pub(crate) fn sort_by_column(batch: &RecordBatch, column: usize) -> ZnResult<RecordBatch> {
+    let options = SortOptions {
+        descending: false,
+        nulls_first: false,
+    };
+    let indices = sort_to_indices(batch.column(column), Some(options), None)?;
+    let columns = batch
+        .columns()
+        .iter()
+        .map(|c| take(c, &indices, None))
+        .collect::<Result<_, _>>()?;
+    Ok(RecordBatch::try_new(batch.schema(), columns)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use parquet::{
+        file::{reader::FileReader, serialized_reader::SerializedFileReader},
+        record::RowAccessor,
+    };
+
+    #[test]
+    fn test_write_ndjson() {
+        let input = r#"
+{"_timestamp": 30, "level": "info", "log": "third"}
+{"_timestamp": 10, "level": "error", "log": "first", "host": "a"}
+
+{"level": "info", "log": "no timestamp"}
+{"_timestamp": 20, "level": "info", "log": "second"}
+"#;
+        let options = WriterOptions {
+            row_group_size: 2,
+            label_columns: vec!["level".to_owned()],
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        assert_eq!(
+            write_ndjson(input.as_bytes(), &mut buf, &options).unwrap(),
+            4
+        );
+
+        let file = SerializedFileReader::new(Bytes::from(buf)).unwrap();
+        assert_eq!(file.num_row_groups(), 2);
+        let schema = file.metadata().file_metadata().schema_descr();
+        let column = |name| {
+            (0..schema.num_columns())
+                .find(|&i| schema.column(i).name() == name)
+                .unwrap()
+        };
+        let row_group = file.metadata().row_group(0);
+        let level = row_group.column(column("level"));
+        assert_eq!(level.compression(), Compression::ZSTD);
+        assert!(level.dictionary_page_offset().is_some());
+        assert!(row_group
+            .column(column("log"))
+            .dictionary_page_offset()
+            .is_none());
+
+        let log = column("log");
+        let logs: Vec<_> = file
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.get_string(log).unwrap().clone())
+            .collect();
+        assert_eq!(logs, ["first", "second", "third", "no timestamp"]);
+
+        let options = WriterOptions {
+            timestamp_column: Some("time".to_owned()),
+            ..Default::default()
+        };
+        assert!(write_ndjson(input.as_bytes(), Vec::new(), &options).is_err());
+        assert!(write_ndjson(&b"[1, 2]"[..], Vec::new(), &options).is_err());
+    }
+
+    #[test]
+    fn test_train_dictionary() {
+        let samples: Vec<_> = (0..1000)
+            .map(|i| format!("level=info host=web-{} service=checkout", i % 10))
+            .collect();
+        let dictionary = train_dictionary(&samples, 4096).unwrap();
+        assert!(!dictionary.is_empty() && dictionary.len() <= 4096);
+
+        let sample = samples[3].as_bytes();
+        let plain = zstd::bulk::compress(sample, 3).unwrap();
+        let mut compressor = zstd::bulk::Compressor::with_dictionary(3, &dictionary).unwrap();
+        let compressed = compressor.compress(sample).unwrap();
+        assert!(compressed.len() < plain.len());
+        let mut decompressor = zstd::bulk::Decompressor::with_dictionary(&dictionary).unwrap();
+        assert_eq!(
+            decompressor.decompress(&compressed, sample.len()).unwrap(),
+            sample
+        );
+
+        assert!(train_dictionary(&["x"], 4096).is_err());
+    }
+}